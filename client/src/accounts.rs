@@ -0,0 +1,192 @@
+//! Fetch and decode helpers for `Market`, `BetLog`, `BetLedgerPage`, `UserPosition`
+//! and `ResolutionJob`.
+
+use std::fmt;
+
+use anchor_lang::{prelude::Pubkey, AccountDeserialize, Discriminator};
+use market_factory::{BetLedgerPage, BetLog, LedgerEntry, Market, ResolutionJob, UserPosition};
+use solana_account_decoder::UiAccountEncoding;
+use solana_banks_client::BanksClient;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The RPC or banks transport failed
+    Transport(String),
+    /// Account data did not decode as the expected account type
+    Decode(anchor_lang::error::Error),
+    /// A ledger page the market says exists was not found
+    MissingLedgerPage(u32),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "transport error: {}", e),
+            ClientError::Decode(e) => write!(f, "decode error: {}", e),
+            ClientError::MissingLedgerPage(i) => write!(f, "ledger page {} not found", i),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+// ========== DECODING ==========
+
+fn decode<T: AccountDeserialize>(mut data: &[u8]) -> Result<T, ClientError> {
+    T::try_deserialize(&mut data).map_err(ClientError::Decode)
+}
+
+pub fn decode_market(data: &[u8]) -> Result<Market, ClientError> {
+    decode(data)
+}
+
+pub fn decode_bet_log(data: &[u8]) -> Result<BetLog, ClientError> {
+    decode(data)
+}
+
+pub fn decode_position(data: &[u8]) -> Result<UserPosition, ClientError> {
+    decode(data)
+}
+
+/// Zero-copy accounts skip Borsh; check the discriminator and copy the bytes out
+pub fn decode_ledger_page(data: &[u8]) -> Result<BetLedgerPage, ClientError> {
+    let size = std::mem::size_of::<BetLedgerPage>();
+    if data.len() < 8 + size || data[..8] != BetLedgerPage::DISCRIMINATOR {
+        return Err(ClientError::Decode(
+            anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch.into(),
+        ));
+    }
+    Ok(bytemuck::pod_read_unaligned(&data[8..8 + size]))
+}
+
+pub fn decode_resolution_job(data: &[u8]) -> Result<ResolutionJob, ClientError> {
+    decode(data)
+}
+
+// ========== FETCHING ==========
+
+/// A source of raw account data, implemented for the RPC client and for
+/// `solana-program-test`'s `BanksClient`.
+#[allow(async_fn_in_trait)]
+pub trait AccountFetcher {
+    async fn account_data(&mut self, address: &Pubkey) -> Result<Option<Vec<u8>>, ClientError>;
+}
+
+impl AccountFetcher for RpcClient {
+    async fn account_data(&mut self, address: &Pubkey) -> Result<Option<Vec<u8>>, ClientError> {
+        let response = self
+            .get_account_with_commitment(address, self.commitment())
+            .await
+            .map_err(|e| ClientError::Transport(e.to_string()))?;
+        Ok(response.value.map(|account| account.data))
+    }
+}
+
+impl AccountFetcher for BanksClient {
+    async fn account_data(&mut self, address: &Pubkey) -> Result<Option<Vec<u8>>, ClientError> {
+        let account = self
+            .get_account(*address)
+            .await
+            .map_err(|e| ClientError::Transport(e.to_string()))?;
+        Ok(account.map(|account| account.data))
+    }
+}
+
+async fn fetch<T: AccountDeserialize>(
+    fetcher: &mut impl AccountFetcher,
+    address: &Pubkey,
+) -> Result<Option<T>, ClientError> {
+    match fetcher.account_data(address).await? {
+        Some(data) => decode(&data).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub async fn fetch_market(
+    fetcher: &mut impl AccountFetcher,
+    address: &Pubkey,
+) -> Result<Option<Market>, ClientError> {
+    fetch(fetcher, address).await
+}
+
+pub async fn fetch_bet_log(
+    fetcher: &mut impl AccountFetcher,
+    address: &Pubkey,
+) -> Result<Option<BetLog>, ClientError> {
+    fetch(fetcher, address).await
+}
+
+pub async fn fetch_position(
+    fetcher: &mut impl AccountFetcher,
+    address: &Pubkey,
+) -> Result<Option<UserPosition>, ClientError> {
+    fetch(fetcher, address).await
+}
+
+pub async fn fetch_ledger_page(
+    fetcher: &mut impl AccountFetcher,
+    address: &Pubkey,
+) -> Result<Option<BetLedgerPage>, ClientError> {
+    match fetcher.account_data(address).await? {
+        Some(data) => decode_ledger_page(&data).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Every bet of a `BetStorage::Ledger` market in deposit order, read from
+/// `market.ledger_page_count` accounts rather than one account per bet.
+pub async fn fetch_ledger_entries(
+    fetcher: &mut impl AccountFetcher,
+    market_address: &Pubkey,
+    market: &Market,
+) -> Result<Vec<LedgerEntry>, ClientError> {
+    let mut entries = Vec::with_capacity(market.bet_count as usize);
+    for page_index in 0..market.ledger_page_count {
+        let (address, _) = crate::pda::ledger_page(market_address, page_index);
+        let page = fetch_ledger_page(fetcher, &address)
+            .await?
+            .ok_or(ClientError::MissingLedgerPage(page_index))?;
+        entries.extend_from_slice(&page.entries[..page.entry_count as usize]);
+    }
+    Ok(entries)
+}
+
+pub async fn fetch_resolution_job(
+    fetcher: &mut impl AccountFetcher,
+    address: &Pubkey,
+) -> Result<Option<ResolutionJob>, ClientError> {
+    fetch(fetcher, address).await
+}
+
+/// All `BetLog`s for a market, found by filtering on the discriminator and the
+/// `market` field (the first field after the discriminator).
+pub async fn fetch_market_bet_logs(
+    rpc: &RpcClient,
+    market: &Pubkey,
+) -> Result<Vec<(Pubkey, BetLog)>, ClientError> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, BetLog::DISCRIMINATOR.to_vec())),
+            RpcFilterType::Memcmp(Memcmp::new_raw_bytes(8, market.to_bytes().to_vec())),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let accounts = rpc
+        .get_program_accounts_with_config(&market_factory::ID, config)
+        .await
+        .map_err(|e| ClientError::Transport(e.to_string()))?;
+
+    accounts
+        .into_iter()
+        .map(|(address, account)| Ok((address, decode_bet_log(&account.data)?)))
+        .collect()
+}
@@ -0,0 +1,55 @@
+//! Merkle allowlists for private markets.
+//!
+//! Leaves and inner nodes are hashed with the program's `allowlist_leaf` and
+//! `allowlist_parent`. Pairs are sorted before hashing, so a proof is just the
+//! sibling hashes from the leaf up. An odd node at the end of a level is
+//! carried up unhashed. Receipt payout trees share the inner nodes.
+
+use anchor_lang::prelude::Pubkey;
+use market_factory::{allowlist_leaf, allowlist_parent};
+
+/// Every level of the tree, leaves first
+fn levels(leaves: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => allowlist_parent(a, b),
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+pub(crate) fn root(leaves: Vec<[u8; 32]>) -> Option<[u8; 32]> {
+    levels(leaves).last().unwrap().first().copied()
+}
+
+pub(crate) fn proof(leaves: Vec<[u8; 32]>, mut index: usize) -> Vec<[u8; 32]> {
+    let levels = levels(leaves);
+    let mut proof = Vec::new();
+    for level in &levels[..levels.len() - 1] {
+        if let Some(sibling) = level.get(index ^ 1) {
+            proof.push(*sibling);
+        }
+        index /= 2;
+    }
+    proof
+}
+
+/// Root to pass as `allowlist_root`; `None` for an empty list
+pub fn allowlist_root(wallets: &[Pubkey]) -> Option<[u8; 32]> {
+    root(wallets.iter().map(allowlist_leaf).collect())
+}
+
+/// Proof for `wallet`'s deposits; `None` if it is not in `wallets`
+pub fn allowlist_proof(wallets: &[Pubkey], wallet: &Pubkey) -> Option<Vec<[u8; 32]>> {
+    let index = wallets.iter().position(|w| w == wallet)?;
+    Some(proof(wallets.iter().map(allowlist_leaf).collect(), index))
+}
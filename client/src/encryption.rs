@@ -0,0 +1,46 @@
+//! Client-side bet payload in the format the MXE decrypts.
+//!
+//! Layout: `[choice: u8][stake: u64 LE]`, matching the backend's
+//! `encryptBetData` and the MXE's `decrypt_and_parse`.
+//!
+//! NOTE: Until the Arcium Arcis SDK is wired in, the payload is only
+//! serialized, not encrypted. Swap the body of `encrypt_bet` for the Arcis
+//! call once it is available; the layout inside the ciphertext stays the same.
+
+pub const BET_PAYLOAD_LEN: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BetPayload {
+    pub choice: u8, // 0 = NO, 1 = YES
+    pub stake: u64,
+}
+
+/// Build the `encrypted_blob` for `deposit_bet`
+pub fn encrypt_bet(choice: u8, stake: u64) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(BET_PAYLOAD_LEN);
+    blob.push(choice);
+    blob.extend_from_slice(&stake.to_le_bytes());
+    blob
+}
+
+/// SHA-256 commitment stored on chain for off-chain bet storage
+pub fn blob_commitment(blob: &[u8]) -> [u8; 32] {
+    solana_program::hash::hash(blob).to_bytes()
+}
+
+/// Where the MXE looks for a committed blob: `<blob_store_uri>/<hex(blob_hash)>`
+pub fn blob_locator(blob_store_uri: &str, blob_hash: &[u8; 32]) -> String {
+    let hex: String = blob_hash.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}/{}", blob_store_uri.trim_end_matches('/'), hex)
+}
+
+/// Inverse of `encrypt_bet` (for testing/debugging only)
+pub fn decrypt_bet(blob: &[u8]) -> Option<BetPayload> {
+    if blob.len() < BET_PAYLOAD_LEN {
+        return None;
+    }
+    Some(BetPayload {
+        choice: blob[0],
+        stake: u64::from_le_bytes(blob[1..BET_PAYLOAD_LEN].try_into().ok()?),
+    })
+}
@@ -0,0 +1,1106 @@
+//! Instruction builders for every `market_factory` instruction.
+
+use anchor_lang::{
+    prelude::{AccountMeta, Pubkey},
+    solana_program::instruction::Instruction,
+    system_program, InstructionData, ToAccountMetas,
+};
+use anchor_spl::{
+    associated_token::{self, get_associated_token_address_with_program_id},
+    token::spl_token,
+    token_2022::spl_token_2022,
+};
+use solana_program::sysvar;
+
+use market_factory::Collateral;
+
+use crate::pda;
+
+fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+pub use market_factory::instruction::CreateMarket as CreateMarketArgs;
+pub use market_factory::instruction::CreateMarketTemplate as CreateMarketTemplateArgs;
+
+/// What a market escrows: a mint and the token program that owns it, or
+/// native SOL
+///
+/// Builders that take a wallet's token account use the wallet itself in its
+/// place for native SOL markets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketToken {
+    Token { mint: Pubkey, program: Pubkey },
+    NativeSol,
+}
+
+/// The optional collateral accounts, filled in for one side only
+struct CollateralAccounts {
+    escrow_vault: Option<Pubkey>,
+    token_mint: Option<Pubkey>,
+    wallet_tokens: Option<Pubkey>,
+    sol_vault: Option<Pubkey>,
+    token_program: Option<Pubkey>,
+}
+
+impl MarketToken {
+    pub fn spl(mint: Pubkey) -> Self {
+        Self::Token {
+            mint,
+            program: spl_token::ID,
+        }
+    }
+
+    pub fn token_2022(mint: Pubkey) -> Self {
+        Self::Token {
+            mint,
+            program: spl_token_2022::ID,
+        }
+    }
+
+    pub fn collateral(&self) -> Collateral {
+        match self {
+            Self::Token { .. } => Collateral::Token,
+            Self::NativeSol => Collateral::NativeSol,
+        }
+    }
+
+    /// The account stored as the market's `escrow_vault`
+    pub fn vault(&self, market: &Pubkey) -> Pubkey {
+        match self {
+            Self::Token { .. } => pda::escrow(market).0,
+            Self::NativeSol => pda::sol_vault(market).0,
+        }
+    }
+
+    fn accounts(&self, market: &Pubkey, wallet_tokens: &Pubkey) -> CollateralAccounts {
+        match *self {
+            Self::Token { mint, program } => CollateralAccounts {
+                escrow_vault: Some(pda::escrow(market).0),
+                token_mint: Some(mint),
+                wallet_tokens: Some(*wallet_tokens),
+                sol_vault: None,
+                token_program: Some(program),
+            },
+            Self::NativeSol => CollateralAccounts {
+                escrow_vault: None,
+                token_mint: None,
+                wallet_tokens: None,
+                sol_vault: Some(pda::sol_vault(market).0),
+                token_program: None,
+            },
+        }
+    }
+}
+
+fn create_market_accounts(
+    creator: &Pubkey,
+    token: &MarketToken,
+    creator_token_account: Option<&Pubkey>,
+    args: &CreateMarketArgs,
+) -> market_factory::accounts::CreateMarket {
+    let (market, _) = pda::market(creator);
+    let collateral = token.accounts(&market, creator_token_account.unwrap_or(creator));
+    market_factory::accounts::CreateMarket {
+        market,
+        escrow_vault: collateral.escrow_vault,
+        token_mint: collateral.token_mint,
+        sol_vault: collateral.sol_vault,
+        creator_token_account: creator_token_account.and(collateral.wallet_tokens),
+        creator: *creator,
+        sponsor: None,
+        parent_market: args.condition.map(|condition| condition.market),
+        parent_creator: None,
+        system_program: system_program::ID,
+        token_program: collateral.token_program,
+        rent: sysvar::rent::ID,
+    }
+}
+
+/// `args.collateral` must match `token`; conditional markets go through
+/// `create_conditional_market`
+pub fn create_market(creator: &Pubkey, token: &MarketToken, args: CreateMarketArgs) -> Instruction {
+    instruction(create_market_accounts(creator, token, None, &args), args)
+}
+
+/// Market conditioned on the parent named in `args.condition`. The parent's
+/// creator must sign too, since the parent cannot close until this market is
+/// final.
+pub fn create_conditional_market(
+    creator: &Pubkey,
+    token: &MarketToken,
+    parent_creator: &Pubkey,
+    args: CreateMarketArgs,
+) -> Instruction {
+    let mut accounts = create_market_accounts(creator, token, None, &args);
+    accounts.parent_creator = Some(*parent_creator);
+    instruction(accounts, args)
+}
+
+/// LMSR market with liquidity `b`; the creator funds `lmsr_funding(b)` from
+/// `creator_token_account`. Other `args` must leave the bet-log features off.
+pub fn create_lmsr_market(
+    creator: &Pubkey,
+    token: &MarketToken,
+    creator_token_account: &Pubkey,
+    mut args: CreateMarketArgs,
+    liquidity: u64,
+) -> Instruction {
+    args.lmsr_liquidity = Some(liquidity);
+    instruction(
+        create_market_accounts(creator, token, Some(creator_token_account), &args),
+        args,
+    )
+}
+
+/// Market seeded with `args.subsidy` from `funder_token_account` (the
+/// funder's wallet for native SOL). The funder is `sponsor` when given, who
+/// must then sign, else the creator; either way it gets back any subsidy the
+/// settlement leaves undistributed through `reclaim_subsidy`.
+pub fn create_subsidized_market(
+    creator: &Pubkey,
+    token: &MarketToken,
+    sponsor: Option<&Pubkey>,
+    funder_token_account: &Pubkey,
+    args: CreateMarketArgs,
+) -> Instruction {
+    let mut accounts = create_market_accounts(creator, token, Some(funder_token_account), &args);
+    accounts.sponsor = sponsor.copied();
+    instruction(accounts, args)
+}
+
+/// `args.collateral` must match `token`
+pub fn create_market_template(
+    creator: &Pubkey,
+    token: &MarketToken,
+    args: CreateMarketTemplateArgs,
+) -> Instruction {
+    let token_mint = match token {
+        MarketToken::Token { mint, .. } => Some(*mint),
+        MarketToken::NativeSol => None,
+    };
+    let accounts = market_factory::accounts::CreateMarketTemplate {
+        template: pda::template(creator, args.template_id).0,
+        token_mint,
+        creator: *creator,
+        system_program: system_program::ID,
+    };
+    instruction(accounts, args)
+}
+
+/// Open instance `index` of `template`, which must be its `spawned` count.
+/// Anyone can send it; `payer` covers the rent.
+pub fn spawn_market(
+    template: &Pubkey,
+    index: u32,
+    token: &MarketToken,
+    payer: &Pubkey,
+) -> Instruction {
+    let (market, _) = pda::template_market(template, index);
+    let collateral = token.accounts(&market, payer);
+    let accounts = market_factory::accounts::SpawnMarket {
+        template: *template,
+        market,
+        escrow_vault: collateral.escrow_vault,
+        token_mint: collateral.token_mint,
+        sol_vault: collateral.sol_vault,
+        payer: *payer,
+        system_program: system_program::ID,
+        token_program: collateral.token_program,
+    };
+    instruction(accounts, market_factory::instruction::SpawnMarket {})
+}
+
+/// Who signs a deposit besides the bettor's wallet
+///
+/// A relayer can pay the rent for a wallet that only holds the stake token,
+/// and, once the wallet has approved it as a delegate on its token account,
+/// move the stake without the wallet signing at all. A session key bets
+/// through the session the wallet opened with `create_session`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepositSigners {
+    /// Signs the stake transfer: the wallet, its token account's delegate or
+    /// a session key
+    pub authority: Pubkey,
+    /// Pays the bet log and position rent
+    pub payer: Pubkey,
+    /// The session PDA when `authority` is a session key
+    pub session: Option<Pubkey>,
+}
+
+impl DepositSigners {
+    /// The wallet signs and pays for itself
+    pub fn wallet(user: &Pubkey) -> Self {
+        Self {
+            authority: *user,
+            payer: *user,
+            session: None,
+        }
+    }
+
+    /// `session_key` signs and pays for a bet recorded for `owner`
+    pub fn session(owner: &Pubkey, session_key: &Pubkey) -> Self {
+        Self {
+            authority: *session_key,
+            payer: *session_key,
+            session: Some(pda::session(owner, session_key).0),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn deposit_bet_accounts(
+    market: &Pubkey,
+    token: &MarketToken,
+    user: &Pubkey,
+    signers: DepositSigners,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    referrer: Option<&Pubkey>,
+    receipt: bool,
+) -> market_factory::accounts::DepositBet {
+    let collateral = token.accounts(market, user_token_account);
+    let bet_log = pda::bet_log(market, user, bet_index).0;
+    let receipt_mint = pda::receipt_mint(&bet_log).0;
+    let receipt = |account| Some(account).filter(|_| receipt);
+    market_factory::accounts::DepositBet {
+        market: *market,
+        bet_log,
+        position: pda::position(market, user).0,
+        escrow_vault: collateral.escrow_vault,
+        token_mint: collateral.token_mint,
+        user_token_account: collateral.wallet_tokens,
+        sol_vault: collateral.sol_vault,
+        user: *user,
+        authority: signers.authority,
+        session: signers.session,
+        payer: signers.payer,
+        referrer: referrer.copied(),
+        referral: referrer.map(|referrer| pda::referral(market, referrer).0),
+        receipt_mint: receipt(receipt_mint),
+        receipt_account: collateral.token_program.and_then(|program| {
+            receipt(get_associated_token_address_with_program_id(
+                user,
+                &receipt_mint,
+                &program,
+            ))
+        }),
+        token_program: collateral.token_program,
+        associated_token_program: receipt(associated_token::ID),
+        system_program: system_program::ID,
+    }
+}
+
+/// `bet_index` must equal the market's current `bet_count`. `allowlist_proof`
+/// is empty unless the market has an allowlist (see `allowlist::allowlist_proof`).
+/// `referrer` earns the market's `referrer_fee_bps` of the stake at settlement.
+/// `receipt` must match the market's `receipt_tokens`; the receipt lands in
+/// the user's associated token account.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet(
+    market: &Pubkey,
+    token: &MarketToken,
+    user: &Pubkey,
+    signers: DepositSigners,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    encrypted_blob: Vec<u8>,
+    choice: u8,
+    amount: u64,
+    allowlist_proof: Vec<[u8; 32]>,
+    referrer: Option<&Pubkey>,
+    receipt: bool,
+) -> Instruction {
+    instruction(
+        deposit_bet_accounts(
+            market,
+            token,
+            user,
+            signers,
+            user_token_account,
+            bet_index,
+            referrer,
+            receipt,
+        ),
+        market_factory::instruction::DepositBet {
+            encrypted_blob,
+            choice,
+            amount,
+            allowlist_proof,
+        },
+    )
+}
+
+/// For off-chain storage markets; upload the blob to the market's store first
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_commitment(
+    market: &Pubkey,
+    token: &MarketToken,
+    user: &Pubkey,
+    signers: DepositSigners,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    blob_hash: [u8; 32],
+    choice: u8,
+    amount: u64,
+    allowlist_proof: Vec<[u8; 32]>,
+    referrer: Option<&Pubkey>,
+    receipt: bool,
+) -> Instruction {
+    instruction(
+        deposit_bet_accounts(
+            market,
+            token,
+            user,
+            signers,
+            user_token_account,
+            bet_index,
+            referrer,
+            receipt,
+        ),
+        market_factory::instruction::DepositBetCommitment {
+            blob_hash,
+            choice,
+            amount,
+            allowlist_proof,
+        },
+    )
+}
+
+/// Refunds the stake minus the market's withdrawal penalty and closes the bet
+/// log. `referrer` is the bet log's `referrer`.
+pub fn withdraw_bet(
+    market: &Pubkey,
+    token: &MarketToken,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    referrer: Option<&Pubkey>,
+) -> Instruction {
+    let collateral = token.accounts(market, depositor_token_account);
+    instruction(
+        market_factory::accounts::WithdrawBet {
+            market: *market,
+            bet_log: *bet_log,
+            position: pda::position(market, depositor).0,
+            referral: referrer.map(|referrer| pda::referral(market, referrer).0),
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            depositor_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            depositor: *depositor,
+            token_program: collateral.token_program,
+        },
+        market_factory::instruction::WithdrawBet {},
+    )
+}
+
+/// Redeem a bet's receipt from `holder`'s associated token account: a refund
+/// while betting is open, else `amount` proven by `proof` (see
+/// `receipts::payout_proof`). Funds go to `holder_token_account`. Receipt
+/// markets always escrow tokens, so this takes the mint and its program.
+/// `referrer` is the bet log's `referrer`, needed only while betting is open.
+#[allow(clippy::too_many_arguments)]
+pub fn redeem_receipt(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    token_program: &Pubkey,
+    depositor: &Pubkey,
+    bet_index: u64,
+    holder: &Pubkey,
+    holder_token_account: &Pubkey,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+    referrer: Option<&Pubkey>,
+) -> Instruction {
+    let bet_log = pda::bet_log(market, depositor, bet_index).0;
+    let receipt_mint = pda::receipt_mint(&bet_log).0;
+    instruction(
+        market_factory::accounts::RedeemReceipt {
+            market: *market,
+            bet_log,
+            position: pda::position(market, depositor).0,
+            referral: referrer.map(|referrer| pda::referral(market, referrer).0),
+            receipt_mint,
+            receipt_account: get_associated_token_address_with_program_id(
+                holder,
+                &receipt_mint,
+                token_program,
+            ),
+            holder: *holder,
+            depositor: *depositor,
+            escrow_vault: pda::escrow(market).0,
+            token_mint: *token_mint,
+            holder_token_account: *holder_token_account,
+            token_program: *token_program,
+        },
+        market_factory::instruction::RedeemReceipt {
+            bet_index,
+            amount,
+            proof,
+        },
+    )
+}
+
+fn amend_bet_accounts(
+    market: &Pubkey,
+    token: &MarketToken,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    referrer: Option<&Pubkey>,
+) -> market_factory::accounts::AmendBet {
+    let collateral = token.accounts(market, depositor_token_account);
+    market_factory::accounts::AmendBet {
+        market: *market,
+        bet_log: *bet_log,
+        position: pda::position(market, depositor).0,
+        referral: referrer.map(|referrer| pda::referral(market, referrer).0),
+        escrow_vault: collateral.escrow_vault,
+        token_mint: collateral.token_mint,
+        depositor_token_account: collateral.wallet_tokens,
+        sol_vault: collateral.sol_vault,
+        depositor: *depositor,
+        token_program: collateral.token_program,
+        system_program: system_program::ID,
+    }
+}
+
+/// `amount` is the new total stake, not a delta. `referrer` is the bet log's
+/// `referrer`.
+#[allow(clippy::too_many_arguments)]
+pub fn amend_bet(
+    market: &Pubkey,
+    token: &MarketToken,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    encrypted_blob: Vec<u8>,
+    choice: u8,
+    amount: u64,
+    referrer: Option<&Pubkey>,
+) -> Instruction {
+    instruction(
+        amend_bet_accounts(
+            market,
+            token,
+            bet_log,
+            depositor,
+            depositor_token_account,
+            referrer,
+        ),
+        market_factory::instruction::AmendBet {
+            encrypted_blob,
+            choice,
+            amount,
+        },
+    )
+}
+
+/// For off-chain storage markets; upload the new blob to the market's store
+/// first. `referrer` is the bet log's `referrer`.
+#[allow(clippy::too_many_arguments)]
+pub fn amend_bet_commitment(
+    market: &Pubkey,
+    token: &MarketToken,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    blob_hash: [u8; 32],
+    choice: u8,
+    amount: u64,
+    referrer: Option<&Pubkey>,
+) -> Instruction {
+    instruction(
+        amend_bet_accounts(
+            market,
+            token,
+            bet_log,
+            depositor,
+            depositor_token_account,
+            referrer,
+        ),
+        market_factory::instruction::AmendBetCommitment {
+            blob_hash,
+            choice,
+            amount,
+        },
+    )
+}
+
+/// Must be opened before the deposit that lands on `page_index`
+pub fn open_ledger_page(market: &Pubkey, payer: &Pubkey, page_index: u32) -> Instruction {
+    instruction(
+        market_factory::accounts::OpenLedgerPage {
+            market: *market,
+            ledger_page: pda::ledger_page(market, page_index).0,
+            payer: *payer,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::OpenLedgerPage { page_index },
+    )
+}
+
+/// For ledger markets; `page_index` is `bet_count / LEDGER_PAGE_CAPACITY`.
+/// `signers` and `referrer` work as for `deposit_bet`.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_ledger(
+    market: &Pubkey,
+    token: &MarketToken,
+    user: &Pubkey,
+    signers: DepositSigners,
+    user_token_account: &Pubkey,
+    page_index: u32,
+    blob_hash: [u8; 32],
+    amount: u64,
+    allowlist_proof: Vec<[u8; 32]>,
+    referrer: Option<&Pubkey>,
+) -> Instruction {
+    let collateral = token.accounts(market, user_token_account);
+    instruction(
+        market_factory::accounts::DepositBetLedger {
+            market: *market,
+            ledger_page: pda::ledger_page(market, page_index).0,
+            position: pda::position(market, user).0,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            user_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            user: *user,
+            authority: signers.authority,
+            session: signers.session,
+            payer: signers.payer,
+            referrer: referrer.copied(),
+            referral: referrer.map(|referrer| pda::referral(market, referrer).0),
+            token_program: collateral.token_program,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::DepositBetLedger {
+            blob_hash,
+            amount,
+            allowlist_proof,
+        },
+    )
+}
+
+pub fn enqueue_resolution(market: &Pubkey, payer: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::EnqueueResolution {
+            market: *market,
+            resolution_job: pda::resolution_job(market).0,
+            payer: *payer,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::EnqueueResolution {},
+    )
+}
+
+/// Signed by the market's `resolver`; `mxe_result` is the MXE's JSON
+/// `MarketResult`, as signed. Fee and payout accounts are token accounts
+/// (wallets for native SOL markets):
+/// `PROTOCOL_TREASURY`'s and the creator's, needed only for a nonzero cut,
+/// one per nonzero referral fee of the result, in order, given as the
+/// referrer and its fee account in `referrals`, then one per nonzero payout
+/// of the result, in order (none for LMSR and receipt markets).
+/// The winning outcome, fees and subsidy refund are read from the result
+/// itself. Conditional markets pass their parent as `parent_market`.
+#[allow(clippy::too_many_arguments)]
+pub fn callback_settle(
+    market: &Pubkey,
+    resolver: &Pubkey,
+    token: &MarketToken,
+    mxe_result: Vec<u8>,
+    result_signature: Vec<u8>,
+    protocol_fee_account: Option<Pubkey>,
+    creator_fee_account: Option<Pubkey>,
+    referrals: &[(Pubkey, Pubkey)],
+    payout_accounts: &[Pubkey],
+    parent_market: Option<Pubkey>,
+) -> Instruction {
+    let collateral = token.accounts(market, resolver);
+    let mut ix = instruction(
+        market_factory::accounts::CallbackSettle {
+            market: *market,
+            resolution_job: pda::resolution_job(market).0,
+            resolver: *resolver,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            sol_vault: collateral.sol_vault,
+            protocol_fee_account,
+            creator_fee_account,
+            token_program: collateral.token_program,
+            parent_market,
+        },
+        market_factory::instruction::CallbackSettle {
+            mxe_result,
+            result_signature,
+        },
+    );
+    for (referrer, fee_account) in referrals {
+        ix.accounts.push(AccountMeta::new_readonly(
+            pda::referral(market, referrer).0,
+            false,
+        ));
+        ix.accounts.push(AccountMeta::new(*fee_account, false));
+    }
+    ix.accounts.extend(
+        payout_accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    ix
+}
+
+/// Conditional markets pass their parent as `parent_market`
+pub fn cancel_market(
+    market: &Pubkey,
+    creator: &Pubkey,
+    authority: &Pubkey,
+    parent_market: Option<Pubkey>,
+) -> Instruction {
+    instruction(
+        market_factory::accounts::CancelMarket {
+            market: *market,
+            creator: *creator,
+            authority: *authority,
+            parent_market,
+        },
+        market_factory::instruction::CancelMarket {},
+    )
+}
+
+/// Cancel a market whose resolution job failed its last allowed attempt;
+/// conditional markets pass their parent as `parent_market`
+pub fn void_stuck_market(market: &Pubkey, parent_market: Option<Pubkey>) -> Instruction {
+    instruction(
+        market_factory::accounts::VoidStuckMarket {
+            market: *market,
+            resolution_job: pda::resolution_job(market).0,
+            parent_market,
+        },
+        market_factory::instruction::VoidStuckMarket {},
+    )
+}
+
+/// Refund a wallet's stake on a market voided by `void_stuck_market`
+pub fn refund_position(
+    market: &Pubkey,
+    token: &MarketToken,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+) -> Instruction {
+    let collateral = token.accounts(market, owner_token_account);
+    instruction(
+        market_factory::accounts::RefundPosition {
+            market: *market,
+            position: pda::position(market, owner).0,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            owner_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            owner: *owner,
+            token_program: collateral.token_program,
+        },
+        market_factory::instruction::RefundPosition {},
+    )
+}
+
+/// Buy `shares` of `outcome` in an LMSR market, paying at most `max_cost`
+/// (quote with `lmsr_buy_cost`)
+#[allow(clippy::too_many_arguments)]
+pub fn buy_shares(
+    market: &Pubkey,
+    token: &MarketToken,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    outcome: u8,
+    shares: u64,
+    max_cost: u64,
+    allowlist_proof: Vec<[u8; 32]>,
+) -> Instruction {
+    let collateral = token.accounts(market, user_token_account);
+    instruction(
+        market_factory::accounts::BuyShares {
+            market: *market,
+            position: pda::position(market, user).0,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            user_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            user: *user,
+            token_program: collateral.token_program,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::BuyShares {
+            outcome,
+            shares,
+            max_cost,
+            allowlist_proof,
+        },
+    )
+}
+
+/// Pays one unit per winning share once the LMSR market has settled
+pub fn redeem_shares(
+    market: &Pubkey,
+    token: &MarketToken,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+) -> Instruction {
+    let collateral = token.accounts(market, owner_token_account);
+    instruction(
+        market_factory::accounts::RedeemShares {
+            market: *market,
+            position: pda::position(market, owner).0,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            owner_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            owner: *owner,
+            token_program: collateral.token_program,
+        },
+        market_factory::instruction::RedeemShares {},
+    )
+}
+
+/// Returns what is left of an LMSR market's escrow to its creator once every
+/// winning share has been redeemed
+pub fn withdraw_liquidity(
+    market: &Pubkey,
+    token: &MarketToken,
+    creator: &Pubkey,
+    creator_token_account: &Pubkey,
+) -> Instruction {
+    let collateral = token.accounts(market, creator_token_account);
+    instruction(
+        market_factory::accounts::WithdrawLiquidity {
+            market: *market,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            creator_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            creator: *creator,
+            token_program: collateral.token_program,
+        },
+        market_factory::instruction::WithdrawLiquidity {},
+    )
+}
+
+/// Pays the sponsor the subsidy owed back once the market is settled or
+/// cancelled
+pub fn reclaim_subsidy(
+    market: &Pubkey,
+    token: &MarketToken,
+    sponsor: &Pubkey,
+    sponsor_token_account: &Pubkey,
+) -> Instruction {
+    let collateral = token.accounts(market, sponsor_token_account);
+    instruction(
+        market_factory::accounts::ReclaimSubsidy {
+            market: *market,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            sponsor_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            sponsor: *sponsor,
+            token_program: collateral.token_program,
+        },
+        market_factory::instruction::ReclaimSubsidy {},
+    )
+}
+
+/// Leg markets are appended as read-only accounts, in `legs` order
+fn with_parlay_legs(mut ix: Instruction, legs: &[Pubkey]) -> Instruction {
+    ix.accounts
+        .extend(legs.iter().map(|leg| AccountMeta::new(*leg, false)));
+    ix
+}
+
+/// Pool over `legs`, which must be open token markets of `token_mint` in
+/// ascending key order
+pub fn create_parlay_pool(
+    legs: &[Pubkey],
+    token_mint: &Pubkey,
+    token_program: &Pubkey,
+    payer: &Pubkey,
+) -> Instruction {
+    let pool = pda::parlay_pool(legs).0;
+    let ix = instruction(
+        market_factory::accounts::CreateParlayPool {
+            pool,
+            escrow_vault: pda::parlay_escrow(&pool).0,
+            token_mint: *token_mint,
+            payer: *payer,
+            token_program: *token_program,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::CreateParlayPool {
+            legs: legs.to_vec(),
+        },
+    );
+    with_parlay_legs(ix, legs)
+}
+
+/// Bit `i` of `outcomes` is the outcome picked on leg `i`
+pub fn place_parlay(
+    pool: &Pubkey,
+    token_mint: &Pubkey,
+    token_program: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    outcomes: u8,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        market_factory::accounts::PlaceParlay {
+            pool: *pool,
+            parlay: pda::parlay(pool, owner).0,
+            escrow_vault: pda::parlay_escrow(pool).0,
+            token_mint: *token_mint,
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: *token_program,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::PlaceParlay { outcomes, amount },
+    )
+}
+
+/// Permissionless once every leg is settled or cancelled
+pub fn settle_parlay_pool(pool: &Pubkey, legs: &[Pubkey]) -> Instruction {
+    let ix = instruction(
+        market_factory::accounts::SettleParlayPool { pool: *pool },
+        market_factory::instruction::SettleParlayPool {},
+    );
+    with_parlay_legs(ix, legs)
+}
+
+/// Pays the ticket's share or refund into `owner_token_account` and closes it
+pub fn claim_parlay(
+    pool: &Pubkey,
+    token_mint: &Pubkey,
+    token_program: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+) -> Instruction {
+    instruction(
+        market_factory::accounts::ClaimParlay {
+            pool: *pool,
+            parlay: pda::parlay(pool, owner).0,
+            escrow_vault: pda::parlay_escrow(pool).0,
+            token_mint: *token_mint,
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: *token_program,
+        },
+        market_factory::instruction::ClaimParlay {},
+    )
+}
+
+/// Replace or clear the allowlist root; only before the market's first bet
+pub fn set_allowlist_root(
+    market: &Pubkey,
+    creator: &Pubkey,
+    allowlist_root: Option<[u8; 32]>,
+) -> Instruction {
+    instruction(
+        market_factory::accounts::SetAllowlistRoot {
+            market: *market,
+            creator: *creator,
+        },
+        market_factory::instruction::SetAllowlistRoot { allowlist_root },
+    )
+}
+
+/// Approves the session PDA as delegate of `owner_token_account` for
+/// `spend_cap`; fails while another delegate has an unspent approval
+pub fn create_session(
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    token_program: &Pubkey,
+    session_key: &Pubkey,
+    expires_at: i64,
+    spend_cap: u64,
+    allowed_markets: Vec<Pubkey>,
+) -> Instruction {
+    instruction(
+        market_factory::accounts::CreateSession {
+            session: pda::session(owner, session_key).0,
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: *token_program,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::CreateSession {
+            session_key: *session_key,
+            expires_at,
+            spend_cap,
+            allowed_markets,
+        },
+    )
+}
+
+/// Also revokes the session's approval on `owner_token_account`, the account
+/// it was created with, unless another delegate has replaced it
+pub fn revoke_session(
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    token_program: &Pubkey,
+    session_key: &Pubkey,
+) -> Instruction {
+    instruction(
+        market_factory::accounts::RevokeSession {
+            session: pda::session(owner, session_key).0,
+            token_account: *owner_token_account,
+            owner: *owner,
+            token_program: *token_program,
+        },
+        market_factory::instruction::RevokeSession {},
+    )
+}
+
+/// Permissionless; rent always goes to `depositor`
+pub fn close_bet_log(market: &Pubkey, bet_log: &Pubkey, depositor: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::CloseBetLog {
+            market: *market,
+            bet_log: *bet_log,
+            depositor: *depositor,
+        },
+        market_factory::instruction::CloseBetLog {},
+    )
+}
+
+/// `payer` is whoever opened the page and receives its rent
+pub fn close_ledger_page(market: &Pubkey, page_index: u32, payer: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::CloseLedgerPage {
+            market: *market,
+            ledger_page: pda::ledger_page(market, page_index).0,
+            payer: *payer,
+        },
+        market_factory::instruction::CloseLedgerPage {},
+    )
+}
+
+pub fn close_position(market: &Pubkey, owner: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::ClosePosition {
+            market: *market,
+            position: pda::position(market, owner).0,
+            owner: *owner,
+        },
+        market_factory::instruction::ClosePosition {},
+    )
+}
+
+/// Rent goes to `payer`, who funded the referral on its first referred bet
+pub fn close_referral(market: &Pubkey, referrer: &Pubkey, payer: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::CloseReferral {
+            market: *market,
+            referral: pda::referral(market, referrer).0,
+            payer: *payer,
+        },
+        market_factory::instruction::CloseReferral {},
+    )
+}
+
+pub fn close_resolution_job(market: &Pubkey, creator: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::CloseResolutionJob {
+            market: *market,
+            resolution_job: pda::resolution_job(market).0,
+            creator: *creator,
+        },
+        market_factory::instruction::CloseResolutionJob {},
+    )
+}
+
+/// Any dust left in a token escrow is swept to `creator_token_account`.
+/// Token-2022 mints with a transfer fee need the escrow's withheld fees
+/// harvested to the mint first
+pub fn close_escrow(
+    market: &Pubkey,
+    token: &MarketToken,
+    creator: &Pubkey,
+    creator_token_account: &Pubkey,
+) -> Instruction {
+    let collateral = token.accounts(market, creator_token_account);
+    instruction(
+        market_factory::accounts::CloseEscrow {
+            market: *market,
+            escrow_vault: collateral.escrow_vault,
+            token_mint: collateral.token_mint,
+            creator_token_account: collateral.wallet_tokens,
+            sol_vault: collateral.sol_vault,
+            creator: *creator,
+            token_program: collateral.token_program,
+        },
+        market_factory::instruction::CloseEscrow {},
+    )
+}
+
+/// Requires every bet log, the resolution job and the escrow to be closed first
+pub fn close_market(market: &Pubkey, token: &MarketToken, creator: &Pubkey) -> Instruction {
+    instruction(
+        market_factory::accounts::CloseMarket {
+            market: *market,
+            escrow_vault: token.vault(market),
+            resolution_job: pda::resolution_job(market).0,
+            creator: *creator,
+        },
+        market_factory::instruction::CloseMarket {},
+    )
+}
+
+/// Bring a pre-versioning `market` and its other `accounts` (each bet log
+/// followed by its depositor's position, then the resolution job) to the
+/// current layout; `payer` covers the extra rent. Accounts already current
+/// are skipped.
+pub fn migrate_market(market: &Pubkey, accounts: &[Pubkey], payer: &Pubkey) -> Instruction {
+    let mut ix = instruction(
+        market_factory::accounts::MigrateMarket {
+            market: *market,
+            escrow_vault: Some(pda::escrow(market).0),
+            payer: *payer,
+            system_program: system_program::ID,
+        },
+        market_factory::instruction::MigrateMarket {},
+    );
+    ix.accounts.extend(
+        accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    ix
+}
+
+fn update_resolution_job_accounts(
+    market: &Pubkey,
+    authority: &Pubkey,
+) -> market_factory::accounts::UpdateResolutionJob {
+    market_factory::accounts::UpdateResolutionJob {
+        market: *market,
+        resolution_job: pda::resolution_job(market).0,
+        authority: *authority,
+    }
+}
+
+pub fn mark_job_running(market: &Pubkey, authority: &Pubkey) -> Instruction {
+    instruction(
+        update_resolution_job_accounts(market, authority),
+        market_factory::instruction::MarkJobRunning {},
+    )
+}
+
+pub fn mark_job_failed(market: &Pubkey, authority: &Pubkey, error_code: u32) -> Instruction {
+    instruction(
+        update_resolution_job_accounts(market, authority),
+        market_factory::instruction::MarkJobFailed { error_code },
+    )
+}
@@ -0,0 +1,31 @@
+//! Rust client SDK for the `market_factory` program.
+//!
+//! Wraps PDA derivation, instruction building, account decoding and the
+//! client-side bet payload so consumers don't re-derive them by hand.
+
+pub mod accounts;
+pub mod allowlist;
+pub mod encryption;
+pub mod instructions;
+pub mod pda;
+pub mod receipts;
+
+pub use accounts::{
+    decode_bet_log, decode_ledger_page, decode_market, decode_position, decode_resolution_job,
+    fetch_bet_log, fetch_ledger_entries, fetch_ledger_page, fetch_market, fetch_market_bet_logs,
+    fetch_position, fetch_resolution_job, AccountFetcher, ClientError,
+};
+pub use allowlist::{allowlist_proof, allowlist_root};
+pub use encryption::{
+    blob_commitment, blob_locator, decrypt_bet, encrypt_bet, BetPayload, BET_PAYLOAD_LEN,
+};
+pub use instructions::{CreateMarketArgs, CreateMarketTemplateArgs, DepositSigners, MarketToken};
+pub use market_factory::{
+    self, lmsr_buy_cost, lmsr_funding, parlay_legs_hash, template_market_seed, BetLedgerPage,
+    BetLog, BetStorage, Category, Collateral, FeeSplit, JobStatus, LedgerEntry, LmsrState, Market,
+    MarketMetadata, MarketState, MarketTemplate, MxePayout, MxeResult, OracleConfig,
+    ParentCondition, Parlay, ParlayPool, Payout, Referral, ResolutionJob, SessionAuthority,
+    SolVault, StakeLimits, TemplateInstance, TimeDecay, UserPosition, ACCOUNT_VERSION, ID,
+    LEDGER_PAGE_CAPACITY, PROTOCOL_TREASURY,
+};
+pub use receipts::{payout_proof, payout_root};
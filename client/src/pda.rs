@@ -0,0 +1,141 @@
+//! Program-derived addresses used by `market_factory`.
+//!
+//! ```text
+//! Market:        ["market", creator_pubkey]
+//!                ["market", template_market_seed(template, index)]
+//! Template:      ["template", creator_pubkey, template_id]
+//! Escrow:        ["escrow", market_pubkey]
+//! SolVault:      ["sol_vault", market_pubkey]
+//! BetLog:        ["bet", market_pubkey, user_pubkey, bet_count]
+//! LedgerPage:    ["ledger", market_pubkey, page_index]
+//! UserPosition:  ["position", market_pubkey, user_pubkey]
+//! Referral:      ["referral", market_pubkey, referrer_pubkey]
+//! ResolutionJob: ["rqueue", market_pubkey]
+//! Session:       ["session", owner_pubkey, session_key]
+//! ReceiptMint:   ["receipt", bet_log_pubkey]
+//! ParlayPool:    ["parlay_pool", parlay_legs_hash(legs)]
+//! ParlayEscrow:  ["parlay_escrow", pool_pubkey]
+//! Parlay:        ["parlay", pool_pubkey, owner_pubkey]
+//! ```
+
+use anchor_lang::prelude::Pubkey;
+
+pub const MARKET_SEED: &[u8] = b"market";
+pub const TEMPLATE_SEED: &[u8] = b"template";
+pub const ESCROW_SEED: &[u8] = b"escrow";
+pub const SOL_VAULT_SEED: &[u8] = b"sol_vault";
+pub const BET_SEED: &[u8] = b"bet";
+pub const LEDGER_SEED: &[u8] = b"ledger";
+pub const POSITION_SEED: &[u8] = b"position";
+pub const REFERRAL_SEED: &[u8] = b"referral";
+pub const RESOLUTION_JOB_SEED: &[u8] = b"rqueue";
+pub const SESSION_SEED: &[u8] = b"session";
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+pub const PARLAY_POOL_SEED: &[u8] = b"parlay_pool";
+pub const PARLAY_ESCROW_SEED: &[u8] = b"parlay_escrow";
+pub const PARLAY_SEED: &[u8] = b"parlay";
+
+pub fn market(creator: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MARKET_SEED, creator.as_ref()], &market_factory::ID)
+}
+
+/// Recurring market template `template_id` of `creator`
+pub fn template(creator: &Pubkey, template_id: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[TEMPLATE_SEED, creator.as_ref(), &template_id.to_le_bytes()],
+        &market_factory::ID,
+    )
+}
+
+/// Instance `index` of a template, opened by `spawn_market`
+pub fn template_market(template: &Pubkey, index: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            MARKET_SEED,
+            &market_factory::template_market_seed(template, index),
+        ],
+        &market_factory::ID,
+    )
+}
+
+pub fn escrow(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_SEED, market.as_ref()], &market_factory::ID)
+}
+
+/// Lamport vault for native SOL markets
+pub fn sol_vault(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SOL_VAULT_SEED, market.as_ref()], &market_factory::ID)
+}
+
+/// `bet_index` is the market's `bet_count` at the time the bet is deposited
+pub fn bet_log(market: &Pubkey, depositor: &Pubkey, bet_index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            BET_SEED,
+            market.as_ref(),
+            depositor.as_ref(),
+            &bet_index.to_le_bytes(),
+        ],
+        &market_factory::ID,
+    )
+}
+
+/// Page `n` holds bets `n * LEDGER_PAGE_CAPACITY ..`
+pub fn ledger_page(market: &Pubkey, page_index: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[LEDGER_SEED, market.as_ref(), &page_index.to_le_bytes()],
+        &market_factory::ID,
+    )
+}
+
+pub fn position(market: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[POSITION_SEED, market.as_ref(), owner.as_ref()],
+        &market_factory::ID,
+    )
+}
+
+/// Live stake `referrer` has referred on `market`, which caps its fee
+pub fn referral(market: &Pubkey, referrer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[REFERRAL_SEED, market.as_ref(), referrer.as_ref()],
+        &market_factory::ID,
+    )
+}
+
+pub fn resolution_job(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RESOLUTION_JOB_SEED, market.as_ref()], &market_factory::ID)
+}
+
+/// Session authority letting `session_key` bet for `owner`
+pub fn session(owner: &Pubkey, session_key: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SESSION_SEED, owner.as_ref(), session_key.as_ref()],
+        &market_factory::ID,
+    )
+}
+
+/// Receipt mint of a bet in a `receipt_tokens` market
+pub fn receipt_mint(bet_log: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECEIPT_SEED, bet_log.as_ref()], &market_factory::ID)
+}
+
+/// Parlay pool over `legs`, which are in ascending key order
+pub fn parlay_pool(legs: &[Pubkey]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PARLAY_POOL_SEED, &market_factory::parlay_legs_hash(legs)],
+        &market_factory::ID,
+    )
+}
+
+pub fn parlay_escrow(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PARLAY_ESCROW_SEED, pool.as_ref()], &market_factory::ID)
+}
+
+/// `owner`'s ticket in a parlay pool
+pub fn parlay(pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PARLAY_SEED, pool.as_ref(), owner.as_ref()],
+        &market_factory::ID,
+    )
+}
@@ -0,0 +1,28 @@
+//! Payout trees for receipt markets.
+//!
+//! Settlement commits each bet's payout as `payout_leaf(bet_index, amount)`,
+//! in bet order, under `Market.payout_root`. Whoever holds a bet's receipt
+//! redeems it with the amount and the proof from here.
+
+use market_factory::payout_leaf;
+
+use crate::allowlist::{proof, root};
+
+fn leaves(payouts: &[(u64, u64)]) -> Vec<[u8; 32]> {
+    payouts
+        .iter()
+        .map(|&(bet_index, amount)| payout_leaf(bet_index, amount))
+        .collect()
+}
+
+/// Root over `(bet_index, amount)` pairs; `None` for a market without bets
+pub fn payout_root(payouts: &[(u64, u64)]) -> Option<[u8; 32]> {
+    root(leaves(payouts))
+}
+
+/// Amount and proof to redeem `bet_index`'s receipt with; `None` if it has no
+/// entry in `payouts`
+pub fn payout_proof(payouts: &[(u64, u64)], bet_index: u64) -> Option<(u64, Vec<[u8; 32]>)> {
+    let index = payouts.iter().position(|&(i, _)| i == bet_index)?;
+    Some((payouts[index].1, proof(leaves(payouts), index)))
+}
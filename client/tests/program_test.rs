@@ -0,0 +1,501 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::token::spl_token;
+use market_factory_client::{
+    allowlist_proof, allowlist_root, blob_commitment, blob_locator, decrypt_bet, encrypt_bet,
+    fetch_bet_log, fetch_ledger_entries, fetch_market, fetch_position, fetch_resolution_job,
+    instructions, pda, BetPayload, BetStorage, Collateral, CreateMarketArgs, DepositSigners,
+    JobStatus, MarketMetadata, MarketState, MarketToken, MxeResult, StakeLimits,
+    LEDGER_PAGE_CAPACITY,
+};
+use resolve_market_mxe::{
+    resolve_market, resolve_market_with_store, EncryptedBet, FileSystemBlobStore, MXEInput,
+};
+use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program_pack::Pack,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    market_factory::entry(program_id, accounts, data)
+}
+
+async fn start() -> ProgramTestContext {
+    let mut program_test = ProgramTest::new(
+        "market_factory",
+        market_factory::ID,
+        processor!(process_instruction),
+    );
+    program_test.prefer_bpf(false);
+    program_test.start_with_context().await
+}
+
+async fn send(ctx: &mut ProgramTestContext, instructions: &[Instruction], signers: &[&Keypair]) {
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers: Vec<&Keypair> = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+async fn create_token_account(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let payer = ctx.payer.pubkey();
+    send(
+        ctx,
+        &[
+            system_instruction::create_account(
+                &payer,
+                &account.pubkey(),
+                rent.minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_account(
+                &spl_token::ID,
+                &account.pubkey(),
+                mint,
+                owner,
+            )
+            .unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::ID,
+                mint,
+                &account.pubkey(),
+                &payer,
+                &[],
+                1_000,
+            )
+            .unwrap(),
+        ],
+        &[&account],
+    )
+    .await;
+    account.pubkey()
+}
+
+async fn create_mint(ctx: &mut ProgramTestContext) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let payer = ctx.payer.pubkey();
+    send(
+        ctx,
+        &[
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer,
+                None,
+                6,
+            )
+            .unwrap(),
+        ],
+        &[&mint],
+    )
+    .await;
+    mint.pubkey()
+}
+
+#[tokio::test]
+async fn builders_and_fetchers_drive_a_market() {
+    let mut ctx = start().await;
+    let creator = Keypair::new();
+    let payer = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[system_instruction::transfer(
+            &payer,
+            &creator.pubkey(),
+            1_000_000_000,
+        )],
+        &[],
+    )
+    .await;
+
+    let mint = create_mint(&mut ctx).await;
+    let creator_tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+
+    let clock = ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let deadline = clock.unix_timestamp + 60;
+    let mxe_program_id = Pubkey::new_unique();
+    let resolver = Keypair::new();
+    let (market, bump) = pda::market(&creator.pubkey());
+    send(
+        &mut ctx,
+        &[instructions::create_market(
+            &creator.pubkey(),
+            &MarketToken::spl(mint),
+            CreateMarketArgs {
+                question: "Client SDK?".to_string(),
+                deadline,
+                mxe_program_id,
+                resolver: resolver.pubkey(),
+                bet_storage: BetStorage::OnChain,
+                blob_store_uri: String::new(),
+                withdrawal_penalty_bps: 0,
+                stake_limits: StakeLimits::default(),
+                open_time: None,
+                resolve_after: None,
+                collateral: Collateral::Token,
+                allowlist_root: None,
+                creator_fee_bps: 0,
+                referrer_fee_bps: 0,
+                receipt_tokens: false,
+                lmsr_liquidity: None,
+                subsidy: 0,
+                time_decay: None,
+                condition: None,
+                metadata: MarketMetadata::default(),
+            },
+        )],
+        &[&creator],
+    )
+    .await;
+
+    for (bet_index, (choice, stake)) in [(1u8, 40u64), (0, 60)].into_iter().enumerate() {
+        send(
+            &mut ctx,
+            &[instructions::deposit_bet(
+                &market,
+                &MarketToken::spl(mint),
+                &creator.pubkey(),
+                DepositSigners::wallet(&creator.pubkey()),
+                &creator_tokens,
+                bet_index as u64,
+                encrypt_bet(choice, stake),
+                choice,
+                stake,
+                Vec::new(),
+                None,
+                false,
+            )],
+            &[&creator],
+        )
+        .await;
+    }
+
+    let state = fetch_market(&mut ctx.banks_client, &market)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.bump, bump);
+    assert_eq!(state.escrow_vault, pda::escrow(&market).0);
+    assert_eq!(state.bet_count, 2);
+    assert_eq!(state.total_pool, 100);
+
+    let (bet_log, _) = pda::bet_log(&market, &creator.pubkey(), 1);
+    let bet = fetch_bet_log(&mut ctx.banks_client, &bet_log)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(bet.amount, 60);
+    assert_eq!(
+        decrypt_bet(&bet.encrypted_blob),
+        Some(BetPayload {
+            choice: 0,
+            stake: 60
+        })
+    );
+
+    let position = fetch_position(
+        &mut ctx.banks_client,
+        &pda::position(&market, &creator.pubkey()).0,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(position.total_staked, 100);
+
+    let (job, _) = pda::resolution_job(&market);
+    assert!(fetch_resolution_job(&mut ctx.banks_client, &job)
+        .await
+        .unwrap()
+        .is_none());
+
+    let mut clock = ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp = deadline;
+    ctx.set_sysvar(&clock);
+    send(
+        &mut ctx,
+        &[
+            instructions::enqueue_resolution(&market, &payer),
+            instructions::mark_job_running(&market, &resolver.pubkey()),
+        ],
+        &[&resolver],
+    )
+    .await;
+
+    let state = fetch_market(&mut ctx.banks_client, &market)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(state.state == MarketState::Enqueued);
+    let job = fetch_resolution_job(&mut ctx.banks_client, &job)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(job.status == JobStatus::Running);
+    assert_eq!(job.attempts, 1);
+}
+
+#[tokio::test]
+async fn ledger_entries_build_the_mxe_input() {
+    let mut ctx = start().await;
+    let creator = Keypair::new();
+    let payer = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[system_instruction::transfer(
+            &payer,
+            &creator.pubkey(),
+            1_000_000_000,
+        )],
+        &[],
+    )
+    .await;
+    let mint = create_mint(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+
+    let clock = ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let (market, _) = pda::market(&creator.pubkey());
+    send(
+        &mut ctx,
+        &[
+            instructions::create_market(
+                &creator.pubkey(),
+                &MarketToken::spl(mint),
+                CreateMarketArgs {
+                    question: "Ledger?".to_string(),
+                    deadline: clock.unix_timestamp + 60,
+                    mxe_program_id: Pubkey::new_unique(),
+                    resolver: Pubkey::new_unique(),
+                    bet_storage: BetStorage::Ledger,
+                    blob_store_uri: "ipfs://bets".to_string(),
+                    withdrawal_penalty_bps: 0,
+                    stake_limits: StakeLimits::default(),
+                    open_time: None,
+                    resolve_after: None,
+                    collateral: Collateral::Token,
+                    allowlist_root: None,
+                    creator_fee_bps: 0,
+                    referrer_fee_bps: 0,
+                    receipt_tokens: false,
+                    lmsr_liquidity: None,
+                    subsidy: 0,
+                    time_decay: None,
+                    condition: None,
+                    metadata: MarketMetadata::default(),
+                },
+            ),
+            instructions::open_ledger_page(&market, &payer, 0),
+        ],
+        &[&creator],
+    )
+    .await;
+
+    // Fill page 0 and spill one bet onto page 1
+    let stakes: Vec<u64> = (0..=LEDGER_PAGE_CAPACITY as u64)
+        .map(|i| i % 5 + 1)
+        .collect();
+    let blobs: Vec<Vec<u8>> = stakes.iter().map(|&s| encrypt_bet(1, s)).collect();
+    for (chunk_index, chunk) in stakes.chunks(10).enumerate() {
+        let first = chunk_index * 10;
+        let page_index = (first / LEDGER_PAGE_CAPACITY) as u32;
+        let mut ixs = Vec::new();
+        if first % LEDGER_PAGE_CAPACITY == 0 && page_index > 0 {
+            ixs.push(instructions::open_ledger_page(&market, &payer, page_index));
+        }
+        ixs.extend(chunk.iter().enumerate().map(|(i, &stake)| {
+            instructions::deposit_bet_ledger(
+                &market,
+                &MarketToken::spl(mint),
+                &creator.pubkey(),
+                DepositSigners::wallet(&creator.pubkey()),
+                &tokens,
+                page_index,
+                blob_commitment(&blobs[first + i]),
+                stake,
+                Vec::new(),
+                None,
+            )
+        }));
+        send(&mut ctx, &ixs, &[&creator]).await;
+    }
+
+    let state = fetch_market(&mut ctx.banks_client, &market)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.ledger_page_count, 2);
+    let entries = fetch_ledger_entries(&mut ctx.banks_client, &market, &state)
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), stakes.len());
+
+    let root = std::env::temp_dir().join(format!("client-ledger-store-{}", std::process::id()));
+    let store = FileSystemBlobStore::new(&root);
+    for blob in &blobs {
+        store.put(blob).unwrap();
+    }
+    let output = resolve_market_with_store(
+        MXEInput {
+            market_id: market.to_bytes().to_vec(),
+            encrypted_bets: entries
+                .iter()
+                .map(|entry| EncryptedBet {
+                    depositor_pubkey: entry.depositor.to_bytes().to_vec(),
+                    encrypted_blob: vec![],
+                    amount: entry.amount,
+                    blob_hash: Some(entry.blob_hash.to_vec()),
+                    bet_id: None,
+                    revision: 0,
+                    referrer: Some(entry.referrer)
+                        .filter(|referrer| *referrer != Pubkey::default())
+                        .map(|referrer| referrer.to_bytes().to_vec()),
+                    bet_index: None,
+                    timestamp: None,
+                })
+                .collect(),
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: state.penalty_pool,
+            time_decay: None,
+            void: false,
+        },
+        &store,
+    )
+    .unwrap();
+    assert_eq!(output.result.total_pool, state.total_pool);
+}
+
+#[test]
+fn encrypted_bets_resolve_in_the_mxe() {
+    let bet = |seed: u8, choice: u8, stake: u64| EncryptedBet {
+        depositor_pubkey: vec![seed; 32],
+        encrypted_blob: encrypt_bet(choice, stake),
+        amount: stake,
+        blob_hash: None,
+        bet_id: None,
+        revision: 0,
+        referrer: None,
+        bet_index: None,
+        timestamp: None,
+    };
+    let output = resolve_market(MXEInput {
+        market_id: vec![9; 32],
+        encrypted_bets: vec![bet(1, 1, 300), bet(2, 0, 100), bet(3, 1, 100)],
+        encrypted_oracle: None,
+        protocol_fee_bps: 0,
+        creator_fee_bps: 0,
+        referrer_fee_bps: 0,
+        subsidy: 0,
+        penalties: 0,
+        time_decay: None,
+        void: false,
+    })
+    .unwrap();
+
+    assert_eq!(output.result.total_pool, 500);
+    assert_eq!(output.result.winning_choice, 1);
+    let payouts: Vec<u64> = output.result.payouts.iter().map(|p| p.payout).collect();
+    assert_eq!(payouts, vec![375, 0, 125]);
+
+    // `callback_settle` reads the same JSON the MXE signs
+    let json = serde_json::to_vec(&output.result).unwrap();
+    let result: MxeResult = serde_json::from_slice(&json).unwrap();
+    assert_eq!(result.market_id, hex_string(&[9; 32]));
+    assert_eq!(result.payouts[2].recipient, hex_string(&[3; 32]));
+    assert_eq!(result.payouts[2].payout, 125);
+}
+
+#[test]
+fn blob_commitments_match_the_mxe_store() {
+    let root = std::env::temp_dir().join(format!("client-blob-store-{}", std::process::id()));
+    let store = FileSystemBlobStore::new(&root);
+    let blob = encrypt_bet(1, 70);
+    let commitment = blob_commitment(&blob);
+    assert_eq!(store.put(&blob).unwrap(), commitment.to_vec());
+
+    let locator = blob_locator(&format!("file://{}/", root.display()), &commitment);
+    assert!(locator.ends_with(&format!("/{}", hex_string(&commitment))));
+
+    let output = resolve_market_with_store(
+        MXEInput {
+            market_id: vec![9; 32],
+            encrypted_bets: vec![EncryptedBet {
+                depositor_pubkey: vec![1; 32],
+                encrypted_blob: vec![],
+                amount: 70,
+                blob_hash: Some(commitment.to_vec()),
+                bet_id: None,
+                revision: 0,
+                referrer: None,
+                bet_index: None,
+                timestamp: None,
+            }],
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: None,
+            void: false,
+        },
+        &store,
+    )
+    .unwrap();
+    assert_eq!(output.result.total_pool, 70);
+}
+
+#[test]
+fn allowlist_proofs_fold_to_the_root() {
+    let wallets: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+    let root = allowlist_root(&wallets).unwrap();
+    for wallet in &wallets {
+        let proof = allowlist_proof(&wallets, wallet).unwrap();
+        let node = proof
+            .iter()
+            .fold(market_factory::allowlist_leaf(wallet), |node, sibling| {
+                market_factory::allowlist_parent(&node, sibling)
+            });
+        assert_eq!(node, root);
+    }
+    assert_eq!(allowlist_proof(&wallets, &Pubkey::new_unique()), None);
+    assert_eq!(allowlist_root(&[]), None);
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,167 @@
+//! Off-chain storage for encrypted bet blobs.
+//!
+//! Markets created with off-chain bet storage keep only a SHA-256 commitment
+//! of each ciphertext on chain. The MXE fetches the blobs from a `BlobStore`
+//! and refuses any blob that does not match its commitment.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::resolve_market::EncryptedBet;
+
+/// Content-addressed blob storage, keyed by the blob's SHA-256 hash
+pub trait BlobStore {
+    fn fetch(&self, blob_hash: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Local filesystem stand-in for IPFS / object storage.
+/// Blob `h` lives at `<root>/<hex(h)>`, mirroring `<blob_store_uri>/<hex(h)>` on chain.
+pub struct FileSystemBlobStore {
+    root: PathBuf,
+}
+
+impl FileSystemBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Write a blob and return its hash
+    pub fn put(&self, blob: &[u8]) -> Result<Vec<u8>, String> {
+        let hash = blob_hash(blob);
+        fs::create_dir_all(&self.root)
+            .map_err(|e| format!("Blob store error: {}", e))?;
+        fs::write(self.root.join(hex::encode(&hash)), blob)
+            .map_err(|e| format!("Blob store error: {}", e))?;
+        Ok(hash)
+    }
+}
+
+impl BlobStore for FileSystemBlobStore {
+    fn fetch(&self, blob_hash: &[u8]) -> Result<Vec<u8>, String> {
+        fs::read(self.root.join(hex::encode(blob_hash)))
+            .map_err(|e| format!("Blob {} not found: {}", hex::encode(blob_hash), e))
+    }
+}
+
+/// SHA-256 of a ciphertext, as committed in `BetLog.blob_hash`
+pub fn blob_hash(blob: &[u8]) -> Vec<u8> {
+    Sha256::digest(blob).to_vec()
+}
+
+/// Fill in `encrypted_blob` for every bet that carries a commitment,
+/// verifying each fetched blob against its on-chain hash
+pub fn load_committed_blobs(
+    bets: &mut [EncryptedBet],
+    store: &dyn BlobStore,
+) -> Result<(), String> {
+    for bet in bets.iter_mut() {
+        let expected = match &bet.blob_hash {
+            Some(hash) => hash,
+            None => continue,
+        };
+        let blob = store.fetch(expected)?;
+        if blob_hash(&blob) != *expected {
+            return Err(format!(
+                "Blob hash mismatch for {}",
+                hex::encode(expected)
+            ));
+        }
+        bet.encrypted_blob = blob;
+    }
+    Ok(())
+}
+
+// ========== TESTS ==========
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve_market::{resolve_market, resolve_market_with_store, MXEInput};
+
+    fn temp_store(name: &str) -> FileSystemBlobStore {
+        let root = std::env::temp_dir().join(format!(
+            "mxe-blob-store-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        FileSystemBlobStore::new(root)
+    }
+
+    fn committed_bet(store: &FileSystemBlobStore, blob: Vec<u8>) -> EncryptedBet {
+        EncryptedBet {
+            depositor_pubkey: vec![0; 32],
+            encrypted_blob: vec![],
+            amount: 0,
+            blob_hash: Some(store.put(&blob).unwrap()),
+            bet_id: None,
+            revision: 0,
+            referrer: None,
+            bet_index: None,
+            timestamp: None,
+        }
+    }
+
+    fn input(bets: Vec<EncryptedBet>) -> MXEInput {
+        MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: bets,
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: None,
+            void: false,
+        }
+    }
+
+    #[test]
+    fn test_resolve_with_committed_blobs() {
+        let store = temp_store("ok");
+        let bets = vec![
+            committed_bet(&store, vec![1, 100, 0, 0, 0, 0, 0, 0, 0]),
+            committed_bet(&store, vec![0, 50, 0, 0, 0, 0, 0, 0, 0]),
+        ];
+
+        let result = resolve_market_with_store(input(bets), &store).unwrap();
+        assert_eq!(result.result.total_pool, 150);
+        assert_eq!(result.result.winning_choice, 1);
+    }
+
+    #[test]
+    fn test_tampered_blob_is_rejected() {
+        let store = temp_store("tampered");
+        let bet = committed_bet(&store, vec![1, 100, 0, 0, 0, 0, 0, 0, 0]);
+        let path = store.root.join(hex::encode(bet.blob_hash.as_ref().unwrap()));
+        fs::write(path, [1, 200, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+
+        let err = resolve_market_with_store(input(vec![bet]), &store).unwrap_err();
+        assert!(err.contains("mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn test_superseded_blob_is_not_fetched() {
+        let store = temp_store("superseded");
+        let mut original = committed_bet(&store, vec![1, 100, 0, 0, 0, 0, 0, 0, 0]);
+        let mut amended = committed_bet(&store, vec![0, 40, 0, 0, 0, 0, 0, 0, 0]);
+        original.bet_id = Some(vec![9; 32]);
+        amended.bet_id = Some(vec![9; 32]);
+        amended.revision = 1;
+        let path = store.root.join(hex::encode(original.blob_hash.as_ref().unwrap()));
+        fs::remove_file(path).unwrap();
+
+        let result = resolve_market_with_store(input(vec![original, amended]), &store).unwrap();
+        assert_eq!(result.result.total_pool, 40);
+    }
+
+    #[test]
+    fn test_unloaded_blob_is_rejected() {
+        let store = temp_store("unloaded");
+        let bet = committed_bet(&store, vec![1, 100, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(resolve_market(input(vec![bet])).is_err());
+    }
+}
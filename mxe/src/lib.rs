@@ -1,7 +1,17 @@
+mod blob_store;
 mod resolve_market;
 
+pub use blob_store::{
+    blob_hash,
+    load_committed_blobs,
+    BlobStore,
+    FileSystemBlobStore,
+};
+
 pub use resolve_market::{
+    drop_superseded_bets,
     resolve_market, 
+    resolve_market_with_store, 
     MXEInput, 
     MXEOutput, 
     MarketResult, 
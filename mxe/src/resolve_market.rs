@@ -1,39 +1,53 @@
 /// Arcium MXE: Encrypted Market Resolution
-/// 
+///
 /// This module runs inside Arcium's Multi-Party Execution Environment (MXE)
 /// to privately compute prediction market outcomes using encrypted bets.
-/// 
+///
 /// All computation happens under MPC - no single node sees cleartext data.
-
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+use crate::blob_store::{load_committed_blobs, BlobStore};
 
 // ========== DATA STRUCTURES ==========
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EncryptedBet {
-    pub depositor_pubkey: Vec<u8>,  // 32 bytes
-    pub encrypted_blob: Vec<u8>,    // Encrypted payload
-    pub amount: u64,                // Can be encrypted in blob
+    pub depositor_pubkey: Vec<u8>, // 32 bytes
+    pub encrypted_blob: Vec<u8>,   // Encrypted payload (empty until loaded for off-chain bets)
+    pub amount: u64,               // Can be encrypted in blob
+    #[serde(default)]
+    pub blob_hash: Option<Vec<u8>>, // On-chain commitment for off-chain blobs
+    #[serde(default)]
+    pub bet_id: Option<Vec<u8>>, // BetLog address, shared by every revision of a bet
+    #[serde(default)]
+    pub revision: u8, // BetLog.revision; amendments supersede lower revisions
+    #[serde(default)]
+    pub referrer: Option<Vec<u8>>, // BetLog.referrer; earns referrer_fee_bps of the stake
+    #[serde(default)]
+    pub bet_index: Option<u64>, // BetLog seed index; keys receipt-market payout leaves
+    #[serde(default)]
+    pub timestamp: Option<i64>, // BetLog.timestamp; required under time decay
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DecryptedBet {
     pub depositor_pubkey: Vec<u8>,
-    pub choice: u8,  // 0 = NO, 1 = YES
+    pub choice: u8, // 0 = NO, 1 = YES
     pub stake: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OracleReport {
-    pub outcome: u8,       // 0 or 1
+    pub outcome: u8, // 0 or 1
     pub timestamp: i64,
     pub source: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Payout {
-    pub recipient: String,  // Base58 encoded pubkey
+    pub recipient: String, // Base58 encoded pubkey
     pub payout: u64,
 }
 
@@ -41,18 +55,45 @@ pub struct Payout {
 pub struct MarketResult {
     pub market_id: String,
     pub winning_choice: u8,
-    pub total_pool: u64,
-    pub fee_amount: u64,
+    pub total_pool: u64, // Stakes, withdrawal penalties and the subsidy in play
+    #[serde(default)]
+    pub subsidy_refund: u64, // Subsidy left undistributed, owed to the sponsor
+    pub fee_amount: u64, // protocol_fee + creator_fee + all referral fees
+    pub protocol_fee: u64,
+    pub creator_fee: u64,
+    pub referral_fees: Vec<Payout>, // One per referrer, in first-seen order
     pub payouts: Vec<Payout>,
+    #[serde(default)]
+    pub payout_root: Option<String>, // Hex; receipt markets, when every bet has a bet_index
+    #[serde(default)]
+    pub void: bool, // Every stake refunded; settle with no winning outcome
     pub timestamp: i64,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeDecay {
+    pub open_time: i64,        // Market.open_time
+    pub deadline: i64,         // Market.deadline
+    pub full_weight_secs: u32, // Market.time_decay: full weight this long after open_time
+    pub min_weight_bps: u16,   // Market.time_decay: weight of a bet at the deadline
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MXEInput {
     pub market_id: Vec<u8>,
     pub encrypted_bets: Vec<EncryptedBet>,
     pub encrypted_oracle: Option<Vec<u8>>,
-    pub fee_bps: u16,  // Basis points (e.g., 50 = 0.5%)
+    pub protocol_fee_bps: u16, // Basis points of the pool (e.g., 50 = 0.5%)
+    pub creator_fee_bps: u16,  // Market.creator_fee_bps, of the pool
+    pub referrer_fee_bps: u16, // Market.referrer_fee_bps, of each referred stake
+    #[serde(default)]
+    pub subsidy: u64, // Market.subsidy, split evenly across outcomes
+    #[serde(default)]
+    pub penalties: u64, // Market.penalty_pool, forfeited by withdrawn and reduced stakes
+    #[serde(default)]
+    pub time_decay: Option<TimeDecay>, // Weights winners by how early they bet
+    #[serde(default)]
+    pub void: bool, // Conditional market whose parent condition failed
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -77,10 +118,10 @@ impl MPCContext {
     fn decrypt_and_parse(&self, encrypted_blob: &[u8]) -> DecryptedBet {
         // TODO: Use Arcium's MPC decrypt function
         // For now, simulate decryption (in real MXE, this stays encrypted to all parties)
-        
+
         // Expected format: borsh-serialized DecryptedBet
         // In production: arcium_mpc::decrypt(encrypted_blob)
-        
+
         // Placeholder parsing
         DecryptedBet {
             depositor_pubkey: vec![0; 32],
@@ -89,7 +130,7 @@ impl MPCContext {
                 encrypted_blob
                     .get(1..9)
                     .and_then(|s| s.try_into().ok())
-                    .unwrap_or([0; 8])
+                    .unwrap_or([0; 8]),
             ),
         }
     }
@@ -101,22 +142,33 @@ impl MPCContext {
 
     /// Secure multiplication with division (fixed-point)
     fn secure_mul_div(&self, value: u64, numerator: u64, denominator: u64) -> u64 {
-        if denominator == 0 {
+        self.secure_share(value, numerator, denominator as u128)
+    }
+
+    /// `value * weight / total_weight`, rounded down. Both factors fit in u64, so
+    /// the product cannot overflow u128; `weight <= total_weight` keeps the
+    /// result within `value`.
+    fn secure_share(&self, value: u64, weight: u64, total_weight: u128) -> u64 {
+        if total_weight == 0 {
             return 0;
         }
-        ((value as u128 * numerator as u128) / denominator as u128) as u64
+        ((value as u128 * weight as u128) / total_weight) as u64
     }
 
     /// Conditional within MPC
     fn secure_if_else<T: Clone>(&self, condition: bool, if_val: T, else_val: T) -> T {
-        if condition { if_val } else { else_val }
+        if condition {
+            if_val
+        } else {
+            else_val
+        }
     }
 
     /// Sign the result using MXE attestation keys
     fn sign_result(&self, data: &[u8]) -> Vec<u8> {
         // TODO: Use Arcium's signing mechanism
         // arcium_mpc::sign(data)
-        
+
         // Placeholder: Return mock signature
         vec![0x42; 64]
     }
@@ -124,7 +176,7 @@ impl MPCContext {
     fn now_unix(&self) -> i64 {
         // Get current timestamp
         // In MXE: use provided timestamp or system time
-        0  // Placeholder
+        0 // Placeholder
     }
 }
 
@@ -132,14 +184,31 @@ impl MPCContext {
 
 /// Main entry point for the MXE computation
 /// This function is called by Arcium nodes with encrypted inputs
-pub fn resolve_market(input: MXEInput) -> Result<MXEOutput, String> {
+pub fn resolve_market(mut input: MXEInput) -> Result<MXEOutput, String> {
     let mpc = MPCContext::new();
-    
+    drop_superseded_bets(&mut input.encrypted_bets);
+
     // Step 1: Decrypt all bets within MPC
     let mut decrypted_bets: Vec<DecryptedBet> = Vec::new();
+    let mut referrers: Vec<Option<Vec<u8>>> = Vec::new();
+    let mut bet_indexes: Vec<Option<u64>> = Vec::new();
+    let mut weights: Vec<u64> = Vec::new();
     for encrypted_bet in &input.encrypted_bets {
-        let bet = mpc.decrypt_and_parse(&encrypted_bet.encrypted_blob);
+        if encrypted_bet.blob_hash.is_some() && encrypted_bet.encrypted_blob.is_empty() {
+            return Err("Off-chain bet blob not loaded".to_string());
+        }
+        let mut bet = mpc.decrypt_and_parse(&encrypted_bet.encrypted_blob);
+        // Paid to the depositor the bet log records, which settlement checks
+        bet.depositor_pubkey = encrypted_bet.depositor_pubkey.clone();
+        let weight = match (&input.time_decay, encrypted_bet.timestamp) {
+            (None, _) => bet.stake,
+            (Some(decay), Some(timestamp)) => decayed_stake(decay, bet.stake, timestamp),
+            (Some(_), None) => return Err("Bet timestamp missing under time decay".to_string()),
+        };
+        weights.push(weight);
         decrypted_bets.push(bet);
+        referrers.push(encrypted_bet.referrer.clone());
+        bet_indexes.push(encrypted_bet.bet_index);
     }
 
     if decrypted_bets.is_empty() {
@@ -149,7 +218,7 @@ pub fn resolve_market(input: MXEInput) -> Result<MXEOutput, String> {
     // Step 2: Aggregate pools per choice
     let mut pool_yes: u64 = 0;
     let mut pool_no: u64 = 0;
-    
+
     for bet in &decrypted_bets {
         if bet.choice == 1 {
             pool_yes = mpc.secure_add(pool_yes, bet.stake);
@@ -158,7 +227,10 @@ pub fn resolve_market(input: MXEInput) -> Result<MXEOutput, String> {
         }
     }
 
-    let total_pool = mpc.secure_add(pool_yes, pool_no);
+    // The subsidy seeds each side with half; a side nobody bet on hands its
+    // half back, and with no winners the whole subsidy goes back
+    let seed_no = input.subsidy / 2;
+    let seed_yes = input.subsidy - seed_no;
 
     // Step 3: Determine winning outcome
     let winning_choice = if let Some(oracle_data) = &input.encrypted_oracle {
@@ -170,34 +242,75 @@ pub fn resolve_market(input: MXEInput) -> Result<MXEOutput, String> {
         mpc.secure_if_else(pool_yes > pool_no, 1, 0)
     };
 
-    // Step 4: Calculate fee
-    let fee_amount = mpc.secure_mul_div(total_pool, input.fee_bps as u64, 10000);
+    let winners_pool = if winning_choice == 1 {
+        pool_yes
+    } else {
+        pool_no
+    };
+    // A void market refunds like one nobody won
+    let refund_all = input.void || winners_pool == 0;
+    let subsidy_refund = mpc.secure_if_else(
+        refund_all,
+        input.subsidy,
+        mpc.secure_add(
+            mpc.secure_if_else(pool_no == 0, seed_no, 0),
+            mpc.secure_if_else(pool_yes == 0, seed_yes, 0),
+        ),
+    );
+    // Withdrawal penalties are pool money: winners share them, and a refund
+    // spreads them over the refunded stakes
+    let total_stake = mpc.secure_add(pool_yes, pool_no);
+    let total_pool = mpc.secure_add(
+        mpc.secure_add(total_stake, input.penalties),
+        input.subsidy - subsidy_refund,
+    );
+
+    // Step 4: Calculate fees (none when every bet is refunded)
+    let (protocol_fee, creator_fee, referral_fees) = if refund_all {
+        (0, 0, Vec::new())
+    } else {
+        (
+            mpc.secure_mul_div(total_pool, input.protocol_fee_bps as u64, 10000),
+            mpc.secure_mul_div(total_pool, input.creator_fee_bps as u64, 10000),
+            referral_fees(&mpc, &decrypted_bets, &referrers, input.referrer_fee_bps),
+        )
+    };
+    let fee_amount = referral_fees
+        .iter()
+        .fold(protocol_fee + creator_fee, |total, fee| total + fee.payout);
     let distributable = total_pool.saturating_sub(fee_amount);
 
     // Step 5: Compute payouts
-    let winners_pool = if winning_choice == 1 { pool_yes } else { pool_no };
-    
+
     let mut payouts = Vec::new();
-    
-    if winners_pool == 0 {
+
+    if refund_all {
         // No winners, refund all (or handle as draw)
         for bet in &decrypted_bets {
+            let penalty_share = mpc.secure_share(input.penalties, bet.stake, total_stake as u128);
             payouts.push(Payout {
                 recipient: encode_pubkey(&bet.depositor_pubkey),
-                payout: bet.stake,
+                payout: mpc.secure_add(bet.stake, penalty_share),
             });
         }
     } else {
-        // Proportional payout to winners
-        for bet in &decrypted_bets {
+        // Proportional payout to winners, by stake or time-weighted stake.
+        // Summed in u128 so many large weights cannot saturate.
+        let winners_weight: u128 = decrypted_bets
+            .iter()
+            .zip(&weights)
+            .filter(|(bet, _)| bet.choice == winning_choice)
+            .map(|(_, weight)| *weight as u128)
+            .sum();
+        for (bet, weight) in decrypted_bets.iter().zip(&weights) {
             let is_winner = bet.choice == winning_choice;
             let payout_amount = if is_winner {
-                // payout = (bet.stake / winners_pool) * distributable
-                mpc.secure_mul_div(distributable, bet.stake, winners_pool)
+                // payout = (weight / winners_weight) * distributable
+                mpc.secure_share(distributable, *weight, winners_weight)
             } else {
                 0
             };
-            
+
             payouts.push(Payout {
                 recipient: encode_pubkey(&bet.depositor_pubkey),
                 payout: payout_amount,
@@ -205,40 +318,154 @@ pub fn resolve_market(input: MXEInput) -> Result<MXEOutput, String> {
         }
     }
 
-    // Step 6: Build result
+    // Step 6: Commit payouts to a Merkle root for receipt markets
+    let payout_root = bet_indexes
+        .iter()
+        .zip(&payouts)
+        .map(|(index, payout)| index.map(|index| payout_leaf(index, payout.payout)))
+        .collect::<Option<Vec<_>>>()
+        .map(|leaves| hex::encode(merkle_root(leaves)));
+
+    // Step 7: Build result
     let result = MarketResult {
         market_id: hex::encode(&input.market_id),
         winning_choice,
         total_pool,
+        subsidy_refund,
         fee_amount,
+        protocol_fee,
+        creator_fee,
+        referral_fees,
         payouts,
+        payout_root,
+        void: input.void,
         timestamp: mpc.now_unix(),
     };
 
-    // Step 7: Sign the result
-    let result_json = serde_json::to_vec(&result)
-        .map_err(|e| format!("Serialization error: {}", e))?;
+    // Step 8: Sign the result
+    let result_json =
+        serde_json::to_vec(&result).map_err(|e| format!("Serialization error: {}", e))?;
     let signature = mpc.sign_result(&result_json);
 
-    Ok(MXEOutput {
-        result,
-        signature,
-    })
+    Ok(MXEOutput { result, signature })
+}
+
+/// Resolve a market whose bets commit to blobs in an off-chain store.
+/// Every blob is fetched and checked against its on-chain hash before decryption.
+pub fn resolve_market_with_store(
+    mut input: MXEInput,
+    store: &dyn BlobStore,
+) -> Result<MXEOutput, String> {
+    // Superseded blobs are never fetched, so stores may prune them
+    drop_superseded_bets(&mut input.encrypted_bets);
+    load_committed_blobs(&mut input.encrypted_bets, store)?;
+    resolve_market(input)
 }
 
 // ========== HELPER FUNCTIONS ==========
 
+/// Keep only the latest revision of each amended bet, preserving input order.
+/// Bets without a `bet_id` are always kept.
+pub fn drop_superseded_bets(bets: &mut Vec<EncryptedBet>) {
+    let mut latest: HashMap<Vec<u8>, u8> = HashMap::new();
+    for bet in bets.iter() {
+        if let Some(id) = &bet.bet_id {
+            let revision = latest.entry(id.clone()).or_insert(bet.revision);
+            *revision = (*revision).max(bet.revision);
+        }
+    }
+
+    let mut seen: HashSet<Vec<u8>> = HashSet::new();
+    bets.retain(|bet| match &bet.bet_id {
+        Some(id) => latest[id] == bet.revision && seen.insert(id.clone()),
+        None => true,
+    });
+}
+
+/// Sum each referrer's cut of the stakes it referred
+fn referral_fees(
+    mpc: &MPCContext,
+    bets: &[DecryptedBet],
+    referrers: &[Option<Vec<u8>>],
+    referrer_fee_bps: u16,
+) -> Vec<Payout> {
+    let mut fees: Vec<Payout> = Vec::new();
+    for (bet, referrer) in bets.iter().zip(referrers) {
+        let Some(referrer) = referrer else { continue };
+        let fee = mpc.secure_mul_div(bet.stake, referrer_fee_bps as u64, 10000);
+        let recipient = encode_pubkey(referrer);
+        match fees.iter_mut().find(|f| f.recipient == recipient) {
+            Some(existing) => existing.payout = mpc.secure_add(existing.payout, fee),
+            None => fees.push(Payout {
+                recipient,
+                payout: fee,
+            }),
+        }
+    }
+    fees
+}
+
+/// Stake scaled by the weight of a bet placed at `timestamp`: full until
+/// `full_weight_secs` after opening, then falling linearly to `min_weight_bps`
+/// at the deadline. Rounds in the bettor's favour, so a nonzero stake with a
+/// nonzero minimum always keeps some weight.
+fn decayed_stake(decay: &TimeDecay, stake: u64, timestamp: i64) -> u64 {
+    let start = decay.open_time as i128 + decay.full_weight_secs as i128;
+    let end = decay.deadline as i128;
+    let timestamp = timestamp as i128;
+    let max_drop_bps = 10_000 - decay.min_weight_bps.min(10_000) as u128;
+    let drop_bps = if timestamp <= start {
+        0
+    } else if timestamp >= end {
+        max_drop_bps
+    } else {
+        max_drop_bps * (timestamp - start) as u128 / (end - start) as u128
+    };
+    stake - (stake as u128 * drop_bps / 10_000) as u64
+}
+
+/// `sha256(0x00 || bet_index || amount)`, matching the program's `payout_leaf`
+fn payout_leaf(bet_index: u64, amount: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(bet_index.to_le_bytes());
+    hasher.update(amount.to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// Root over sorted-pair inner nodes, `sha256(0x01 || min || max)`; an odd
+/// node is carried up unhashed, as in the program's allowlist trees
+fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => {
+                    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+                    let mut hasher = Sha256::new();
+                    hasher.update([1u8]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().into()
+                }
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level.first().copied().unwrap_or([0; 32])
+}
+
 fn parse_oracle_report(data: &[u8]) -> Result<OracleReport, String> {
     // TODO: Decrypt and parse oracle data
     // In production: use MPC decryption
-    serde_json::from_slice(data)
-        .map_err(|e| format!("Oracle parse error: {}", e))
+    serde_json::from_slice(data).map_err(|e| format!("Oracle parse error: {}", e))
 }
 
 fn encode_pubkey(bytes: &[u8]) -> String {
     // Convert to base58 for Solana compatibility
     // In production, use bs58 crate
-    hex::encode(bytes)  // Placeholder: using hex
+    hex::encode(bytes) // Placeholder: using hex
 }
 
 // ========== EXPORTS ==========
@@ -247,24 +474,24 @@ fn encode_pubkey(bytes: &[u8]) -> String {
 pub extern "C" fn execute_mxe(input_ptr: *const u8, input_len: usize) -> *mut u8 {
     // Entry point for Arcium runtime
     // Deserialize input, run resolve_market, serialize output
-    
+
     let input_slice = unsafe { std::slice::from_raw_parts(input_ptr, input_len) };
-    
+
     let input: MXEInput = match serde_json::from_slice(input_slice) {
         Ok(i) => i,
         Err(_) => return std::ptr::null_mut(),
     };
-    
+
     let output = match resolve_market(input) {
         Ok(o) => o,
         Err(_) => return std::ptr::null_mut(),
     };
-    
+
     let output_json = match serde_json::to_vec(&output) {
         Ok(j) => j,
         Err(_) => return std::ptr::null_mut(),
     };
-    
+
     // Allocate and return output
     let boxed = output_json.into_boxed_slice();
     Box::into_raw(boxed) as *mut u8
@@ -285,19 +512,393 @@ mod tests {
                     depositor_pubkey: vec![0; 32],
                     encrypted_blob: vec![1, 100, 0, 0, 0, 0, 0, 0, 0], // choice=1, stake=100
                     amount: 100,
+                    blob_hash: None,
+                    bet_id: None,
+                    revision: 0,
+                    referrer: None,
+                    bet_index: None,
+                    timestamp: None,
                 },
                 EncryptedBet {
                     depositor_pubkey: vec![1; 32],
                     encrypted_blob: vec![0, 50, 0, 0, 0, 0, 0, 0, 0], // choice=0, stake=50
                     amount: 50,
+                    blob_hash: None,
+                    bet_id: None,
+                    revision: 0,
+                    referrer: None,
+                    bet_index: None,
+                    timestamp: None,
                 },
             ],
             encrypted_oracle: None,
-            fee_bps: 50, // 0.5%
+            protocol_fee_bps: 50, // 0.5%
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: None,
+            void: false,
         };
 
         let result = resolve_market(input).unwrap();
         assert_eq!(result.result.total_pool, 150);
         assert_eq!(result.result.winning_choice, 1); // YES wins (100 > 50)
     }
+
+    #[test]
+    fn test_amended_bets_replace_earlier_revisions() {
+        let bet = |id: u8, revision: u8, blob: Vec<u8>| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: Some(vec![id; 32]),
+            revision,
+            referrer: None,
+            bet_index: None,
+            timestamp: None,
+        };
+        let input = MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: vec![
+                bet(1, 0, vec![1, 200, 0, 0, 0, 0, 0, 0, 0]), // superseded: YES 200
+                bet(2, 0, vec![0, 50, 0, 0, 0, 0, 0, 0, 0]),
+                bet(1, 1, vec![0, 30, 0, 0, 0, 0, 0, 0, 0]), // latest: NO 30
+            ],
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: None,
+            void: false,
+        };
+
+        let result = resolve_market(input).unwrap();
+        assert_eq!(result.result.total_pool, 80);
+        assert_eq!(result.result.winning_choice, 0);
+        assert_eq!(result.result.payouts.len(), 2);
+    }
+
+    #[test]
+    fn test_fee_split_comes_out_of_the_winners_share() {
+        let bet = |id: u8, blob: Vec<u8>, referrer: Option<u8>| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: referrer.map(|r| vec![r; 32]),
+            bet_index: None,
+            timestamp: None,
+        };
+        let input = MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: vec![
+                bet(1, vec![1, 0xe8, 0x03, 0, 0, 0, 0, 0, 0], Some(9)), // YES 1000
+                bet(2, vec![1, 0xe8, 0x03, 0, 0, 0, 0, 0, 0], Some(9)), // YES 1000
+                bet(3, vec![0, 0xe8, 0x03, 0, 0, 0, 0, 0, 0], None),    // NO 1000
+            ],
+            encrypted_oracle: None,
+            protocol_fee_bps: 50,
+            creator_fee_bps: 200,
+            referrer_fee_bps: 100,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: None,
+            void: false,
+        };
+
+        let result = resolve_market(input).unwrap().result;
+        assert_eq!((result.protocol_fee, result.creator_fee), (15, 60));
+        assert_eq!(result.referral_fees.len(), 1);
+        assert_eq!(result.referral_fees[0].recipient, hex::encode([9; 32]));
+        assert_eq!(result.referral_fees[0].payout, 20);
+        assert_eq!(result.fee_amount, 95);
+        // The two YES bets split the remaining 2905, rounding down
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid, vec![1_452, 1_452, 0]);
+    }
+
+    #[test]
+    fn test_receipt_bets_commit_payouts_to_a_root() {
+        let bet = |id: u8, blob: Vec<u8>| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: None,
+            bet_index: Some(id as u64),
+            timestamp: None,
+        };
+        let input = MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: vec![
+                bet(0, vec![1, 100, 0, 0, 0, 0, 0, 0, 0]), // YES 100
+                bet(1, vec![0, 50, 0, 0, 0, 0, 0, 0, 0]),  // NO 50
+            ],
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: None,
+            void: false,
+        };
+
+        let result = resolve_market(input).unwrap().result;
+        let root = merkle_root(vec![payout_leaf(0, 150), payout_leaf(1, 0)]);
+        assert_eq!(result.payout_root, Some(hex::encode(root)));
+        assert_eq!(
+            root,
+            merkle_root(vec![payout_leaf(1, 0), payout_leaf(0, 150)])
+        );
+    }
+
+    #[test]
+    fn test_time_decay_favours_early_winners() {
+        let bet = |id: u8, blob: Vec<u8>, timestamp: i64| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: None,
+            bet_index: None,
+            timestamp: Some(timestamp),
+        };
+        let input = |bets, time_decay| MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: bets,
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay,
+            void: false,
+        };
+        let decay = TimeDecay {
+            open_time: 0,
+            deadline: 1000,
+            full_weight_secs: 200,
+            min_weight_bps: 5000,
+        };
+        let bets = || {
+            vec![
+                bet(1, vec![1, 100, 0, 0, 0, 0, 0, 0, 0], 100), // YES 100, full weight
+                bet(2, vec![1, 100, 0, 0, 0, 0, 0, 0, 0], 1000), // YES 100, at the deadline
+                bet(3, vec![0, 150, 0, 0, 0, 0, 0, 0, 0], 600), // NO 150
+            ]
+        };
+
+        // Weights 100 and 50 split the 350; the odd unit stays in escrow
+        let result = resolve_market(input(bets(), Some(decay.clone())))
+            .unwrap()
+            .result;
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid, vec![233, 116, 0]);
+        let result = resolve_market(input(bets(), None)).unwrap().result;
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid, vec![175, 175, 0]);
+
+        // Halfway through the decay window the weight is 75%
+        assert_eq!(decayed_stake(&decay, 100, 600), 75);
+        // Weights round up, so dust bets are never zeroed
+        assert_eq!(decayed_stake(&decay, 1, 1000), 1);
+        assert_eq!(decayed_stake(&decay, 3, 999), 2);
+
+        let mut late = bet(1, vec![1, 100, 0, 0, 0, 0, 0, 0, 0], 0);
+        late.timestamp = None;
+        assert!(resolve_market(input(vec![late], Some(decay))).is_err());
+    }
+
+    #[test]
+    fn test_time_decay_weights_do_not_overflow() {
+        let decay = TimeDecay {
+            open_time: i64::MIN,
+            deadline: i64::MAX,
+            full_weight_secs: 0,
+            min_weight_bps: 1,
+        };
+        assert_eq!(
+            decayed_stake(&decay, u64::MAX, i64::MAX),
+            u64::MAX / 10_000 + 1
+        );
+        // Midway the drop is 4999 bps, computed without overflowing i64 or u128
+        let discount = (u64::MAX as u128 * 4_999 / 10_000) as u64;
+        assert_eq!(decayed_stake(&decay, u64::MAX, 0), u64::MAX - discount);
+
+        // Winning weights sum past u64::MAX; each share still fits
+        let big = (u64::MAX / 2).to_le_bytes();
+        let blob = |choice: u8| [&[choice][..], &big].concat();
+        let bet = |id: u8, choice: u8, timestamp: i64| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob(choice),
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: None,
+            bet_index: None,
+            timestamp: Some(timestamp),
+        };
+        let input = MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: vec![
+                bet(1, 1, -100),
+                bet(2, 1, -100),
+                bet(3, 1, 100),
+                bet(4, 0, 0),
+            ],
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 0,
+            time_decay: Some(TimeDecay {
+                open_time: -100,
+                deadline: 100,
+                full_weight_secs: 0,
+                min_weight_bps: 10_000,
+            }),
+            void: false,
+        };
+        let result = resolve_market(input).unwrap().result;
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid[..3], [result.total_pool / 3; 3]);
+        assert!(paid.iter().sum::<u64>() <= result.total_pool);
+    }
+
+    #[test]
+    fn test_subsidy_is_split_across_outcomes() {
+        let bet = |id: u8, blob: Vec<u8>| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: None,
+            bet_index: None,
+            timestamp: None,
+        };
+        let input = |bets| MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: bets,
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 1000,
+            penalties: 0,
+            time_decay: None,
+            void: false,
+        };
+
+        // Both sides bet: YES takes the stakes and the whole subsidy
+        let result = resolve_market(input(vec![
+            bet(1, vec![1, 200, 0, 0, 0, 0, 0, 0, 0]), // YES 200
+            bet(2, vec![0, 100, 0, 0, 0, 0, 0, 0, 0]), // NO 100
+        ]))
+        .unwrap()
+        .result;
+        assert_eq!((result.total_pool, result.subsidy_refund), (1300, 0));
+        assert_eq!(result.payouts[0].payout, 1300);
+
+        // Nobody bet NO, so its half goes back to the sponsor
+        let result = resolve_market(input(vec![bet(1, vec![1, 200, 0, 0, 0, 0, 0, 0, 0])]))
+            .unwrap()
+            .result;
+        assert_eq!((result.total_pool, result.subsidy_refund), (700, 500));
+        assert_eq!(result.payouts[0].payout, 700);
+    }
+
+    #[test]
+    fn test_void_market_refunds_every_stake() {
+        let bet = |id: u8, blob: Vec<u8>| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: Some(vec![9; 32]),
+            bet_index: None,
+            timestamp: None,
+        };
+        let input = MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: vec![
+                bet(1, vec![1, 200, 0, 0, 0, 0, 0, 0, 0]), // YES 200
+                bet(2, vec![0, 100, 0, 0, 0, 0, 0, 0, 0]), // NO 100
+            ],
+            encrypted_oracle: None,
+            protocol_fee_bps: 50,
+            creator_fee_bps: 200,
+            referrer_fee_bps: 100,
+            subsidy: 1000,
+            penalties: 0,
+            time_decay: None,
+            void: true,
+        };
+
+        // No fees, and the subsidy goes back whole
+        let result = resolve_market(input).unwrap().result;
+        assert!(result.void);
+        assert_eq!((result.fee_amount, result.subsidy_refund), (0, 1000));
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid, vec![200, 100]);
+    }
+
+    #[test]
+    fn test_withdrawal_penalties_are_paid_out() {
+        let bet = |id: u8, blob: Vec<u8>| EncryptedBet {
+            depositor_pubkey: vec![id; 32],
+            encrypted_blob: blob,
+            amount: 0,
+            blob_hash: None,
+            bet_id: None,
+            revision: 0,
+            referrer: None,
+            bet_index: None,
+            timestamp: None,
+        };
+        let input = |void| MXEInput {
+            market_id: vec![1, 2, 3, 4],
+            encrypted_bets: vec![
+                bet(1, vec![1, 150, 0, 0, 0, 0, 0, 0, 0]), // YES 150
+                bet(2, vec![1, 50, 0, 0, 0, 0, 0, 0, 0]),  // YES 50
+                bet(3, vec![0, 100, 0, 0, 0, 0, 0, 0, 0]), // NO 100
+            ],
+            encrypted_oracle: None,
+            protocol_fee_bps: 0,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            subsidy: 0,
+            penalties: 40,
+            time_decay: None,
+            void,
+        };
+
+        // Winners share the penalties with the losing stakes
+        let result = resolve_market(input(false)).unwrap().result;
+        assert_eq!(result.total_pool, 340);
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid, vec![255, 85, 0]);
+
+        // A refund spreads them over the refunded stakes
+        let result = resolve_market(input(true)).unwrap().result;
+        let paid: Vec<u64> = result.payouts.iter().map(|p| p.payout).collect();
+        assert_eq!(paid, vec![170, 56, 113]);
+    }
 }
@@ -1,5 +1,13 @@
-use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::{prelude::*, solana_program::program_option::COption, system_program};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::spl_token_2022::instruction::AuthorityType,
+    token_interface::{
+        self, Approve, Burn, CloseAccount, Mint, MintTo, Revoke, SetAuthority, TokenAccount,
+        TokenInterface, TransferChecked,
+    },
+};
+use serde::{Deserialize, Serialize};
 
 declare_id!("9EnJdXf861nSXfaJ4YFQgXzXSxjHM72523oF1nic4hnR");
 
@@ -8,74 +16,684 @@ pub mod market_factory {
     use super::*;
 
     /// Create a new prediction market
+    #[allow(clippy::too_many_arguments)]
     pub fn create_market(
         ctx: Context<CreateMarket>,
         question: String,
         deadline: i64,
         mxe_program_id: Pubkey,
+        resolver: Pubkey,
+        bet_storage: BetStorage,
+        blob_store_uri: String,
+        withdrawal_penalty_bps: u16,
+        stake_limits: StakeLimits,
+        open_time: Option<i64>,
+        resolve_after: Option<i64>,
+        collateral: Collateral,
+        allowlist_root: Option<[u8; 32]>,
+        creator_fee_bps: u16,
+        referrer_fee_bps: u16,
+        receipt_tokens: bool,
+        lmsr_liquidity: Option<u64>,
+        subsidy: u64,
+        time_decay: Option<TimeDecay>,
+        condition: Option<ParentCondition>,
+        metadata: MarketMetadata,
     ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
         require!(question.len() <= 280, ErrorCode::QuestionTooLong);
-        require!(deadline > Clock::get()?.unix_timestamp, ErrorCode::InvalidDeadline);
+        require!(deadline > now, ErrorCode::InvalidDeadline);
+        // open_time < deadline <= resolve_after
+        let open_time = open_time.unwrap_or(now);
+        require!(open_time < deadline, ErrorCode::InvalidTimeline);
+        if let Some(resolve_after) = resolve_after {
+            require!(resolve_after >= deadline, ErrorCode::InvalidTimeline);
+        }
+        match bet_storage {
+            BetStorage::OnChain => require!(blob_store_uri.is_empty(), ErrorCode::InvalidBlobStore),
+            BetStorage::OffChain | BetStorage::Ledger => require!(
+                !blob_store_uri.is_empty() && blob_store_uri.len() <= MAX_BLOB_STORE_URI_LEN,
+                ErrorCode::InvalidBlobStore
+            ),
+        }
+        require!(
+            withdrawal_penalty_bps <= MAX_BPS,
+            ErrorCode::InvalidWithdrawalPenalty
+        );
+        stake_limits.validate()?;
+        metadata.validate()?;
+        if let Some(decay) = &time_decay {
+            decay.validate(open_time, deadline)?;
+        }
+        if let Some(condition) = &condition {
+            require!(condition.outcome < 2, ErrorCode::InvalidOutcome);
+            require_keys_neq!(
+                condition.market,
+                ctx.accounts.market.key(),
+                ErrorCode::InvalidCondition
+            );
+        }
+        require!(
+            ctx.accounts.parent_market.is_some() == condition.is_some(),
+            ErrorCode::ParentMarketMismatch
+        );
+        require!(
+            creator_fee_bps <= MAX_CREATOR_FEE_BPS && referrer_fee_bps <= MAX_REFERRER_FEE_BPS,
+            ErrorCode::InvalidFeeBps
+        );
+        // Receipts are minted per bet log by the market's token program
+        require!(
+            !receipt_tokens
+                || (collateral == Collateral::Token && bet_storage != BetStorage::Ledger),
+            ErrorCode::ReceiptsUnsupported
+        );
+        // LMSR trades are public and priced on chain; none of the bet-log
+        // features apply
+        if let Some(liquidity) = lmsr_liquidity {
+            require!(liquidity > 0, ErrorCode::InvalidLiquidity);
+            require!(
+                bet_storage == BetStorage::OnChain
+                    && !receipt_tokens
+                    && creator_fee_bps == 0
+                    && referrer_fee_bps == 0
+                    && subsidy == 0
+                    && ctx.accounts.sponsor.is_none()
+                    && time_decay.is_none()
+                    && condition.is_none(),
+                ErrorCode::WrongMarketKind
+            );
+        }
+        if let (Some(condition), Some(parent)) = (&condition, ctx.accounts.parent_market.as_mut()) {
+            require_keys_eq!(
+                parent.key(),
+                condition.market,
+                ErrorCode::ParentMarketMismatch
+            );
+            // The parent has to be a live market, and stays open until this
+            // one is final. Holding it open takes its creator's consent, or a
+            // stranger could keep any market from ever closing
+            require!(!parent.is_finalized(), ErrorCode::InvalidCondition);
+            let consent = ctx.accounts.parent_creator.as_ref().map(|signer| signer.key());
+            require!(consent == Some(parent.creator), ErrorCode::Unauthorized);
+            parent.open_child_count = parent.open_child_count.checked_add(1).unwrap();
+        }
+
+        // Token markets escrow in an SPL token account, native SOL markets in a
+        // lamport vault; exactly one of the two is created
+        let market_key = ctx.accounts.market.key();
+        let (escrow_vault, token_mint) = match collateral {
+            Collateral::Token => {
+                require!(
+                    ctx.accounts.sol_vault.is_none(),
+                    ErrorCode::CollateralAccountsMismatch
+                );
+                let escrow_vault = required(ctx.accounts.escrow_vault.as_ref())?;
+                let token_mint = required(ctx.accounts.token_mint.as_ref())?;
+                (escrow_vault.key(), token_mint.key())
+            }
+            Collateral::NativeSol => {
+                require!(
+                    ctx.accounts.escrow_vault.is_none(),
+                    ErrorCode::CollateralAccountsMismatch
+                );
+                let sol_vault = required(ctx.accounts.sol_vault.as_mut())?;
+                sol_vault.market = market_key;
+                sol_vault.bump = ctx.bumps.sol_vault;
+                sol_vault.version = ACCOUNT_VERSION;
+                (sol_vault.key(), Pubkey::default())
+            }
+        };
+
+        // The creator funds an LMSR market's worst-case loss up front. A subsidy
+        // comes from the sponsor when one signs, else from the creator.
+        let funding = lmsr_liquidity.map_or(subsidy, lmsr_funding);
+        let funder = ctx
+            .accounts
+            .sponsor
+            .as_ref()
+            .unwrap_or(&ctx.accounts.creator)
+            .to_account_info();
+        let mut received = 0;
+        if funding > 0 {
+            let mut escrow = Escrow::new(
+                collateral,
+                ctx.accounts.escrow_vault.as_mut(),
+                ctx.accounts.token_mint.as_ref(),
+                ctx.accounts.creator_token_account.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                ctx.accounts.sol_vault.as_ref(),
+            )?;
+            received = transfer_to_escrow(
+                &mut escrow,
+                &funder,
+                &[],
+                &ctx.accounts.system_program,
+                funding,
+            )?;
+            require!(
+                lmsr_liquidity.is_none() || received == funding,
+                ErrorCode::TransferFeeUnsupported
+            );
+        }
 
         let market = &mut ctx.accounts.market;
         market.creator = ctx.accounts.creator.key();
         market.question = question;
         market.deadline = deadline;
         market.mxe_program_id = mxe_program_id;
-        market.escrow_vault = ctx.accounts.escrow_vault.key();
-        market.total_pool = 0;
+        market.resolver = resolver;
+        market.escrow_vault = escrow_vault;
+        market.token_mint = token_mint;
+        market.collateral = collateral;
+        market.total_pool = received;
         market.state = MarketState::Open;
         market.result_hash = [0u8; 32];
         market.bump = ctx.bumps.market;
         market.bet_count = 0;
+        market.closed_bet_count = 0;
+        market.bet_storage = bet_storage;
+        market.blob_store_uri = blob_store_uri;
+        market.ledger_page_count = 0;
+        market.closed_ledger_page_count = 0;
+        market.withdrawal_penalty_bps = withdrawal_penalty_bps;
+        market.stake_limits = stake_limits;
+        market.open_time = open_time;
+        market.resolve_after = resolve_after;
+        market.position_count = 0;
+        market.closed_position_count = 0;
+        market.referral_count = 0;
+        market.closed_referral_count = 0;
+        market.open_child_count = 0;
+        market.penalty_pool = 0;
+        market.open_parlay_count = 0;
+        market.allowlist_root = allowlist_root;
+        market.creator_fee_bps = creator_fee_bps;
+        market.referrer_fee_bps = referrer_fee_bps;
+        market.receipt_tokens = receipt_tokens;
+        market.payout_root = [0u8; 32];
+        market.lmsr = lmsr_liquidity.map(|liquidity| LmsrState {
+            liquidity,
+            outstanding: [0; 2],
+        });
+        market.winning_outcome = None;
+        market.subsidy = if lmsr_liquidity.is_some() { 0 } else { received };
+        market.subsidy_sponsor = funder.key();
+        market.subsidy_refund = 0;
+        market.time_decay = time_decay;
+        market.condition = condition;
+        market.template = None;
+        market.metadata = metadata;
+        market.version = ACCOUNT_VERSION;
+
+        emit!(MarketCreated {
+            market: market.key(),
+            creator: market.creator,
+            token_mint,
+            collateral,
+            question: market.question.clone(),
+            deadline: market.deadline,
+            mxe_program_id: market.mxe_program_id,
+            resolver: market.resolver,
+            bet_storage: market.bet_storage.clone(),
+            open_time: market.open_time,
+            resolve_after: market.resolve_after,
+            allowlist_root,
+            receipt_tokens,
+            lmsr_liquidity,
+            subsidy: market.subsidy,
+            subsidy_sponsor: market.subsidy_sponsor,
+            time_decay,
+            condition,
+            metadata: market.metadata.clone(),
+        });
 
         msg!("Market created: {}", market.key());
         Ok(())
     }
 
+    /// Create a template for a market that recurs every `cadence` seconds. Its
+    /// first instance closes at `first_deadline`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_market_template(
+        ctx: Context<CreateMarketTemplate>,
+        template_id: u32,
+        question_pattern: String,
+        duration: i64,
+        cadence: i64,
+        outcome_count: u8,
+        creator_fee_bps: u16,
+        oracle: OracleConfig,
+        collateral: Collateral,
+        first_deadline: i64,
+        metadata: MarketMetadata,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(question_pattern.len() <= 280, ErrorCode::QuestionTooLong);
+        require!(first_deadline > now, ErrorCode::InvalidDeadline);
+        // Instances never overlap: each opens no earlier than the last one closed
+        require!(
+            duration > 0 && cadence >= duration && oracle.resolve_delay.unwrap_or(0) >= 0,
+            ErrorCode::InvalidTimeline
+        );
+        require!(outcome_count == 2, ErrorCode::InvalidOutcomeCount);
+        metadata.validate()?;
+        require!(
+            creator_fee_bps <= MAX_CREATOR_FEE_BPS,
+            ErrorCode::InvalidFeeBps
+        );
+        let token_mint = match collateral {
+            Collateral::Token => required(ctx.accounts.token_mint.as_ref())?.key(),
+            Collateral::NativeSol => {
+                require!(
+                    ctx.accounts.token_mint.is_none(),
+                    ErrorCode::CollateralAccountsMismatch
+                );
+                Pubkey::default()
+            }
+        };
+
+        let template = &mut ctx.accounts.template;
+        template.creator = ctx.accounts.creator.key();
+        template.template_id = template_id;
+        template.question_pattern = question_pattern;
+        template.duration = duration;
+        template.cadence = cadence;
+        template.outcome_count = outcome_count;
+        template.creator_fee_bps = creator_fee_bps;
+        template.oracle = oracle;
+        template.metadata = metadata;
+        template.collateral = collateral;
+        template.token_mint = token_mint;
+        template.next_deadline = first_deadline;
+        template.spawned = 0;
+        template.bump = ctx.bumps.template;
+        template.version = ACCOUNT_VERSION;
+
+        emit!(MarketTemplateCreated {
+            template: template.key(),
+            creator: template.creator,
+            question_pattern: template.question_pattern.clone(),
+            duration,
+            cadence,
+            first_deadline,
+        });
+
+        msg!("Market template created: {}", template.key());
+        Ok(())
+    }
+
+    /// Open the next instance of a template. Anyone may call this once the
+    /// previous instance has stopped taking bets.
+    pub fn spawn_market(ctx: Context<SpawnMarket>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let template = &mut ctx.accounts.template;
+        // The previous instance closed one cadence before the next deadline
+        require!(
+            template.spawned == 0 || now >= template.next_deadline - template.cadence,
+            ErrorCode::PreviousInstanceOpen
+        );
+        // Skip the slots that passed while nobody spawned
+        let mut deadline = template.next_deadline;
+        if deadline <= now {
+            deadline += ((now - deadline) / template.cadence + 1) * template.cadence;
+        }
+        let index = template.spawned;
+        let question = template.question_pattern.replace("{n}", &index.to_string());
+        require!(question.len() <= 280, ErrorCode::QuestionTooLong);
+
+        let market_key = ctx.accounts.market.key();
+        let escrow_vault = match template.collateral {
+            Collateral::Token => {
+                require!(
+                    ctx.accounts.sol_vault.is_none(),
+                    ErrorCode::CollateralAccountsMismatch
+                );
+                required(ctx.accounts.token_mint.as_ref())?;
+                required(ctx.accounts.escrow_vault.as_ref())?.key()
+            }
+            Collateral::NativeSol => {
+                require!(
+                    ctx.accounts.escrow_vault.is_none(),
+                    ErrorCode::CollateralAccountsMismatch
+                );
+                let sol_vault = required(ctx.accounts.sol_vault.as_mut())?;
+                sol_vault.market = market_key;
+                sol_vault.bump = ctx.bumps.sol_vault;
+                sol_vault.version = ACCOUNT_VERSION;
+                sol_vault.key()
+            }
+        };
+
+        // Instances belong to the template's creator, who gets the rent back
+        // when closing them
+        let market = &mut ctx.accounts.market;
+        market.creator = template.creator;
+        market.question = question;
+        market.deadline = deadline;
+        market.mxe_program_id = template.oracle.mxe_program_id;
+        market.resolver = template.oracle.resolver;
+        market.escrow_vault = escrow_vault;
+        market.token_mint = template.token_mint;
+        market.collateral = template.collateral;
+        market.total_pool = 0;
+        market.state = MarketState::Open;
+        market.result_hash = [0u8; 32];
+        market.bump = ctx.bumps.market;
+        market.bet_count = 0;
+        market.closed_bet_count = 0;
+        market.bet_storage = BetStorage::OnChain;
+        market.blob_store_uri = String::new();
+        market.ledger_page_count = 0;
+        market.closed_ledger_page_count = 0;
+        market.withdrawal_penalty_bps = 0;
+        market.stake_limits = StakeLimits::default();
+        market.open_time = deadline - template.duration;
+        market.resolve_after = template.oracle.resolve_delay.map(|delay| deadline + delay);
+        market.position_count = 0;
+        market.closed_position_count = 0;
+        market.referral_count = 0;
+        market.closed_referral_count = 0;
+        market.open_child_count = 0;
+        market.penalty_pool = 0;
+        market.open_parlay_count = 0;
+        market.allowlist_root = None;
+        market.creator_fee_bps = template.creator_fee_bps;
+        market.referrer_fee_bps = 0;
+        market.receipt_tokens = false;
+        market.payout_root = [0u8; 32];
+        market.lmsr = None;
+        market.winning_outcome = None;
+        market.subsidy = 0;
+        market.subsidy_sponsor = template.creator;
+        market.subsidy_refund = 0;
+        market.time_decay = None;
+        market.condition = None;
+        market.template = Some(TemplateInstance {
+            template: template.key(),
+            index,
+        });
+        market.metadata = template.metadata.clone();
+        market.version = ACCOUNT_VERSION;
+
+        template.next_deadline = deadline + template.cadence;
+        template.spawned += 1;
+
+        emit!(MarketCreated {
+            market: market.key(),
+            creator: market.creator,
+            token_mint: market.token_mint,
+            collateral: market.collateral,
+            question: market.question.clone(),
+            deadline,
+            mxe_program_id: market.mxe_program_id,
+            resolver: market.resolver,
+            bet_storage: BetStorage::OnChain,
+            open_time: market.open_time,
+            resolve_after: market.resolve_after,
+            allowlist_root: None,
+            receipt_tokens: false,
+            lmsr_liquidity: None,
+            subsidy: 0,
+            subsidy_sponsor: market.subsidy_sponsor,
+            time_decay: None,
+            condition: None,
+            metadata: market.metadata.clone(),
+        });
+        emit!(MarketSpawned {
+            template: template.key(),
+            market: market.key(),
+            index,
+        });
+
+        msg!("Market spawned: {} (instance {})", market.key(), index);
+        Ok(())
+    }
+
     /// Deposit an encrypted bet into the market
     pub fn deposit_bet(
         ctx: Context<DepositBet>,
         encrypted_blob: Vec<u8>,
         choice: u8,
         amount: u64,
+        allowlist_proof: Vec<[u8; 32]>,
     ) -> Result<()> {
         require!(
-            ctx.accounts.market.state == MarketState::Open,
-            ErrorCode::MarketNotOpen
+            ctx.accounts.market.bet_storage == BetStorage::OnChain,
+            ErrorCode::WrongBetStorage
+        );
+        require!(encrypted_blob.len() <= MAX_BLOB_LEN, ErrorCode::BlobTooLarge);
+
+        record_bet(
+            ctx,
+            encrypted_blob,
+            [0u8; 32],
+            choice,
+            amount,
+            &allowlist_proof,
+        )
+    }
+
+    /// Deposit a bet whose encrypted blob lives in the market's off-chain blob store.
+    /// Only the SHA-256 of the ciphertext is kept on chain.
+    pub fn deposit_bet_commitment(
+        ctx: Context<DepositBet>,
+        blob_hash: [u8; 32],
+        choice: u8,
+        amount: u64,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.market.bet_storage == BetStorage::OffChain,
+            ErrorCode::WrongBetStorage
         );
+
+        record_bet(
+            ctx,
+            Vec::new(),
+            blob_hash,
+            choice,
+            amount,
+            &allowlist_proof,
+        )
+    }
+
+    /// Withdraw a bet before the deadline. The stake minus the market's withdrawal
+    /// penalty is refunded; the penalty joins `penalty_pool`. The bet log is closed.
+    pub fn withdraw_bet(ctx: Context<WithdrawBet>) -> Result<()> {
+        require_betting_open(&ctx.accounts.market)?;
         require!(
-            Clock::get()?.unix_timestamp < ctx.accounts.market.deadline,
-            ErrorCode::DeadlinePassed
+            !ctx.accounts.market.receipt_tokens,
+            ErrorCode::ReceiptRequired
+        );
+
+        let amount = ctx.accounts.bet_log.amount;
+        let penalty = withdrawal_penalty(amount, ctx.accounts.market.withdrawal_penalty_bps);
+        let refund = amount - penalty;
+        let escrow = Escrow::new(
+            ctx.accounts.market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.depositor_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        transfer_from_escrow(
+            &escrow,
+            &ctx.accounts.depositor.to_account_info(),
+            &ctx.accounts.market,
+            refund,
+        )?;
+
+        let position = &mut ctx.accounts.position;
+        position.total_staked = position.total_staked.checked_sub(amount).unwrap();
+        apply_referral_change(
+            &ctx.accounts.bet_log,
+            ctx.accounts.referral.as_mut(),
+            amount,
+            0,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.total_pool = market.total_pool.checked_sub(refund).unwrap();
+        market.penalty_pool = market.penalty_pool.checked_add(penalty).unwrap();
+        market.closed_bet_count = market.closed_bet_count.checked_add(1).unwrap();
+
+        emit!(BetWithdrawn {
+            market: market.key(),
+            bet_log: ctx.accounts.bet_log.key(),
+            depositor: ctx.accounts.depositor.key(),
+            refund,
+            penalty,
+            total_pool: market.total_pool,
+        });
+
+        msg!("Bet withdrawn: {} refunded, {} penalty", refund, penalty);
+        Ok(())
+    }
+
+    /// Replace the encrypted blob of an on-chain bet and move its stake to `amount`
+    pub fn amend_bet(
+        ctx: Context<AmendBet>,
+        encrypted_blob: Vec<u8>,
+        choice: u8,
+        amount: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.market.bet_storage == BetStorage::OnChain,
+            ErrorCode::WrongBetStorage
+        );
+        require!(encrypted_blob.len() <= MAX_BLOB_LEN, ErrorCode::BlobTooLarge);
+
+        amend_recorded_bet(ctx, encrypted_blob, [0u8; 32], choice, amount)
+    }
+
+    /// Point an off-chain bet at a new blob commitment and move its stake to `amount`
+    pub fn amend_bet_commitment(
+        ctx: Context<AmendBet>,
+        blob_hash: [u8; 32],
+        choice: u8,
+        amount: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.market.bet_storage == BetStorage::OffChain,
+            ErrorCode::WrongBetStorage
+        );
+
+        amend_recorded_bet(ctx, Vec::new(), blob_hash, choice, amount)
+    }
+
+    /// Open the next page of a ledger market's bet ledger. Must be called
+    /// before the deposit that would land on a new page.
+    pub fn open_ledger_page(ctx: Context<OpenLedgerPage>, page_index: u32) -> Result<()> {
+        require!(
+            ctx.accounts.market.bet_storage == BetStorage::Ledger,
+            ErrorCode::WrongBetStorage
+        );
+        require!(
+            page_index == ctx.accounts.market.ledger_page_count,
+            ErrorCode::InvalidLedgerPage
+        );
+
+        let mut page = ctx.accounts.ledger_page.load_init()?;
+        page.market = ctx.accounts.market.key();
+        page.payer = ctx.accounts.payer.key();
+        page.page_index = page_index;
+        page.entry_count = 0;
+        page.version = ACCOUNT_VERSION;
+
+        let market = &mut ctx.accounts.market;
+        market.ledger_page_count = market.ledger_page_count.checked_add(1).unwrap();
+
+        msg!("Ledger page {} opened", page_index);
+        Ok(())
+    }
+
+    /// Append a bet to the market's zero-copy ledger instead of creating a `BetLog`
+    pub fn deposit_bet_ledger(
+        ctx: Context<DepositBetLedger>,
+        blob_hash: [u8; 32],
+        amount: u64,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.market.bet_storage == BetStorage::Ledger,
+            ErrorCode::WrongBetStorage
         );
-        require!(encrypted_blob.len() <= 512, ErrorCode::BlobTooLarge);
+        require_betting_open(&ctx.accounts.market)?;
+        require_allowlisted(
+            &ctx.accounts.market,
+            &ctx.accounts.user.key(),
+            &allowlist_proof,
+        )?;
         require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            ctx.accounts.referral.is_some() == ctx.accounts.referrer.is_some(),
+            ErrorCode::ReferralAccountMismatch
+        );
+        open_position_if_new(
+            &mut ctx.accounts.market,
+            &mut ctx.accounts.position,
+            ctx.accounts.user.key(),
+            ctx.bumps.position,
+        );
+        let mut escrow = Escrow::new(
+            ctx.accounts.market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.user_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        let amount = escrow_stake(
+            &mut escrow,
+            ctx.accounts.session.as_mut(),
+            &ctx.accounts.market.key(),
+            &ctx.accounts.user.key(),
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+            amount,
+        )?;
+        apply_stake_change(&ctx.accounts.market, &mut ctx.accounts.position, 0, amount)?;
+        let referrer = ctx.accounts.referrer.as_ref().map(|r| r.key());
+        if let (Some(referral), Some(referrer)) = (ctx.accounts.referral.as_mut(), referrer) {
+            open_referral_if_new(
+                &mut ctx.accounts.market,
+                referral,
+                referrer,
+                ctx.accounts.payer.key(),
+                ctx.bumps.referral,
+            );
+            referral.referred_stake = referral.referred_stake.checked_add(amount).unwrap();
+        }
 
-        // Transfer funds to escrow
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_token_account.to_account_info(),
-            to: ctx.accounts.escrow_vault.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
+        let timestamp = Clock::get()?.unix_timestamp;
+        let mut page = ctx.accounts.ledger_page.load_mut()?;
+        let slot = page.entry_count as usize;
+        page.entries[slot] = LedgerEntry {
+            depositor: ctx.accounts.user.key(),
+            amount,
+            blob_hash,
+            timestamp,
+            referrer: referrer.unwrap_or_default(),
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
-
-        // Store bet log
-        let bet_log = &mut ctx.accounts.bet_log;
-        bet_log.market = ctx.accounts.market.key();
-        bet_log.depositor = ctx.accounts.user.key();
-        bet_log.amount = amount;
-        bet_log.encrypted_blob = encrypted_blob;
-        bet_log.timestamp = Clock::get()?.unix_timestamp;
-        bet_log.choice_hint = choice; // Not trusted, only for UX
-        bet_log.bump = ctx.bumps.bet_log;
+        page.entry_count = page.entry_count.checked_add(1).unwrap();
 
-        // Update market state
         let market = &mut ctx.accounts.market;
+        let bet_index = market.bet_count;
         market.total_pool = market.total_pool.checked_add(amount).unwrap();
         market.bet_count = market.bet_count.checked_add(1).unwrap();
 
+        emit!(BetDeposited {
+            market: market.key(),
+            bet_log: ctx.accounts.ledger_page.key(),
+            depositor: ctx.accounts.user.key(),
+            bet_index,
+            amount,
+            total_pool: market.total_pool,
+            timestamp,
+            blob_hash,
+        });
+
         msg!("Bet placed: {} tokens", amount);
         Ok(())
     }
@@ -86,10 +704,14 @@ pub mod market_factory {
             ctx.accounts.market.state == MarketState::Open,
             ErrorCode::InvalidMarketState
         );
+        let now = Clock::get()?.unix_timestamp;
         require!(
-            Clock::get()?.unix_timestamp >= ctx.accounts.market.deadline,
+            now >= ctx.accounts.market.deadline,
             ErrorCode::DeadlineNotReached
         );
+        if let Some(resolve_after) = ctx.accounts.market.resolve_after {
+            require!(now >= resolve_after, ErrorCode::ResolveTimeNotReached);
+        }
 
         let market = &mut ctx.accounts.market;
         market.state = MarketState::Enqueued;
@@ -101,14 +723,110 @@ pub mod market_factory {
         job.callback_account = ctx.accounts.market.key();
         job.timestamp = Clock::get()?.unix_timestamp;
         job.bump = ctx.bumps.resolution_job;
+        job.attempts = 0;
+        job.last_error = 0;
+        job.updated_at = job.timestamp;
+        job.version = ACCOUNT_VERSION;
+
+        emit!(ResolutionEnqueued {
+            market: job.market,
+            resolution_job: job.key(),
+            bet_count: ctx.accounts.market.bet_count,
+            total_pool: ctx.accounts.market.total_pool,
+            timestamp: job.timestamp,
+        });
 
         msg!("Market enqueued for resolution");
         Ok(())
     }
 
-    /// Callback from Arcium MXE with settlement result
-    pub fn callback_settle(
-        ctx: Context<CallbackSettle>,
+    /// Mark a resolution job as running (also used to retry a failed job).
+    /// Settlement completes it.
+    pub fn mark_job_running(ctx: Context<UpdateResolutionJob>) -> Result<()> {
+        require!(
+            ctx.accounts.market.state == MarketState::Enqueued,
+            ErrorCode::InvalidMarketState
+        );
+        let job = &mut ctx.accounts.resolution_job;
+        require!(
+            job.status == JobStatus::Pending || job.status == JobStatus::Failed,
+            ErrorCode::InvalidJobStatus
+        );
+        require!(
+            job.attempts < MAX_JOB_ATTEMPTS,
+            ErrorCode::MaxJobAttemptsExceeded
+        );
+
+        job.status = JobStatus::Running;
+        job.attempts = job.attempts.checked_add(1).unwrap();
+        job.updated_at = Clock::get()?.unix_timestamp;
+
+        msg!("Resolution job running (attempt {})", job.attempts);
+        Ok(())
+    }
+
+    /// Mark a running resolution job as failed with an MXE error code
+    pub fn mark_job_failed(ctx: Context<UpdateResolutionJob>, error_code: u32) -> Result<()> {
+        require!(
+            ctx.accounts.market.state == MarketState::Enqueued,
+            ErrorCode::InvalidMarketState
+        );
+        let job = &mut ctx.accounts.resolution_job;
+        require!(
+            job.status == JobStatus::Running,
+            ErrorCode::InvalidJobStatus
+        );
+
+        job.status = JobStatus::Failed;
+        job.last_error = error_code;
+        job.updated_at = Clock::get()?.unix_timestamp;
+
+        msg!("Resolution job failed with code {}", error_code);
+        Ok(())
+    }
+
+    /// Cancel a market whose resolution job failed its last allowed attempt,
+    /// as nothing can settle it any more. Stakes come back through
+    /// `refund_position` (`redeem_receipt` on receipt markets) with their share
+    /// of the withdrawal penalties, and the subsidy through `reclaim_subsidy`.
+    /// Anyone may call this.
+    pub fn void_stuck_market(ctx: Context<VoidStuckMarket>) -> Result<()> {
+        require!(
+            ctx.accounts.market.state == MarketState::Enqueued,
+            ErrorCode::InvalidMarketState
+        );
+        let job = &ctx.accounts.resolution_job;
+        require!(
+            job.status == JobStatus::Failed && job.attempts >= MAX_JOB_ATTEMPTS,
+            ErrorCode::ResolutionNotStuck
+        );
+        let (attempts, last_error) = (job.attempts, job.last_error);
+
+        release_parent(&ctx.accounts.market, ctx.accounts.parent_market.as_mut())?;
+
+        let market = &mut ctx.accounts.market;
+        market.state = MarketState::Cancelled;
+        market.subsidy_refund = market.subsidy;
+
+        emit!(StuckMarketVoided {
+            market: market.key(),
+            attempts,
+            last_error,
+        });
+
+        msg!("Stuck market voided after {} attempts", attempts);
+        Ok(())
+    }
+
+    /// Callback from Arcium MXE with settlement result, signed by the market's
+    /// resolver; completes the resolution job. The winning outcome is the
+    /// result's, or none when it is void, and so are the fees. Each nonzero
+    /// referral fee takes two `remaining_accounts`, the referrer's `Referral`
+    /// and its fee account, followed by one account per nonzero payout.
+    /// The result's `subsidy_refund`, the subsidy it left undistributed, is
+    /// owed back to the sponsor.
+    pub fn callback_settle<'info>(
+        ctx: Context<'_, '_, '_, 'info, CallbackSettle<'info>>,
         mxe_result: Vec<u8>,
         result_signature: Vec<u8>,
     ) -> Result<()> {
@@ -116,53 +834,222 @@ pub mod market_factory {
             ctx.accounts.market.state == MarketState::Enqueued,
             ErrorCode::InvalidMarketState
         );
+        // A failed job has to be restarted before its result is accepted
+        let job = &ctx.accounts.resolution_job;
+        require!(
+            job.status == JobStatus::Pending || job.status == JobStatus::Running,
+            ErrorCode::InvalidJobStatus
+        );
 
-        // TODO: Verify MXE signature against mxe_program_id
-        // This should use Arcium's verification SDK
+        // SECURITY: `verify_mxe_signature` is still a stub that accepts
+        // anything. Until it checks the result against `mxe_program_id` with
+        // Arcium's verification SDK, the `has_one = resolver` signer check is
+        // the only authentication of the result, and of the job updates that
+        // lead up to it: whoever holds the resolver key decides the payouts.
         verify_mxe_signature(
             &ctx.accounts.market.mxe_program_id,
             &mxe_result,
             &result_signature,
         )?;
+        let result = parse_mxe_result(&mxe_result, &ctx.accounts.market.key())?;
+        let winning_outcome = result.winning_outcome()?;
+        let fees = result.fees()?;
+        let subsidy_refund = result.subsidy_refund;
+        require!(
+            subsidy_refund <= ctx.accounts.market.subsidy,
+            ErrorCode::InvalidSubsidyRefund
+        );
+
+        // A conditional market waits for its parent to finalize, and is void
+        // unless the parent settled to the outcome it was conditioned on
+        let condition_met = match ctx.accounts.market.condition {
+            None => true,
+            Some(condition) => {
+                let parent = ctx
+                    .accounts
+                    .parent_market
+                    .as_mut()
+                    .ok_or_else(|| error!(ErrorCode::ParentMarketMismatch))?;
+                require_keys_eq!(
+                    parent.key(),
+                    condition.market,
+                    ErrorCode::ParentMarketMismatch
+                );
+                require!(parent.is_finalized(), ErrorCode::ParentNotFinalized);
+                parent.open_child_count = parent.open_child_count.checked_sub(1).unwrap();
+                parent.state == MarketState::Settled
+                    && parent.winning_outcome == Some(condition.outcome)
+            }
+        };
+        match winning_outcome {
+            Some(_) => require!(condition_met, ErrorCode::WinningOutcomeMismatch),
+            // Void: every stake and the whole subsidy go back
+            None => {
+                require!(!condition_met, ErrorCode::WinningOutcomeMismatch);
+                require!(
+                    subsidy_refund == ctx.accounts.market.subsidy,
+                    ErrorCode::InvalidSubsidyRefund
+                );
+            }
+        }
 
-        // Parse result and execute payouts
-        let payouts: Vec<Payout> = parse_mxe_result(&mxe_result)?;
+        // Execute payouts. LMSR markets pay through the winning outcome and
+        // receipt markets through the payout root; holders redeem later.
+        let payouts: Vec<Payout> = if ctx.accounts.market.lmsr.is_some() {
+            Vec::new()
+        } else if ctx.accounts.market.receipt_tokens {
+            ctx.accounts.market.payout_root = result.payout_root()?;
+            Vec::new()
+        } else {
+            result.payouts()?
+        };
+        // Remaining accounts: a `Referral` and a fee account per referral fee,
+        // then one per payout
+        let referral_account_count = 2 * fees.referrals.len();
+        let payout_accounts = ctx
+            .remaining_accounts
+            .get(referral_account_count..)
+            .unwrap_or_default();
+        require!(
+            payout_accounts.len() == payouts.len(),
+            ErrorCode::PayoutRecipientMismatch
+        );
+        let escrow = Escrow::new(
+            ctx.accounts.market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            None,
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
 
         // Execute transfers
-        for payout in payouts {
-            if payout.amount > 0 {
-                // Transfer from escrow to winner
-                let seeds = &[
-                    b"market",
-                    ctx.accounts.market.creator.as_ref(),
-                    &[ctx.accounts.market.bump],
-                ];
-                let signer = &[&seeds[..]];
-
-                let cpi_accounts = Transfer {
-                    from: ctx.accounts.escrow_vault.to_account_info(),
-                    to: ctx.accounts.winner_token_account.to_account_info(),
-                    authority: ctx.accounts.market.to_account_info(),
-                };
-                let cpi_program = ctx.accounts.token_program.to_account_info();
-                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-                token::transfer(cpi_ctx, payout.amount)?;
+        let payout_count = payouts.len() as u32;
+        let mut total_paid_out: u64 = 0;
+        for (payout, destination) in payouts.iter().zip(payout_accounts) {
+            pay_to_owner(
+                &escrow,
+                destination,
+                &payout.recipient,
+                &ctx.accounts.market,
+                payout.amount,
+                ErrorCode::PayoutRecipientMismatch,
+            )?;
+            total_paid_out = total_paid_out.checked_add(payout.amount).unwrap();
+
+            emit!(PayoutSent {
+                market: ctx.accounts.market.key(),
+                recipient: payout.recipient,
+                token_account: destination.key(),
+                amount: payout.amount,
+            });
+        }
+
+        // Fee cuts, each capped by the market's rates. LMSR collateral backs the
+        // outstanding shares, a void market refunds in full, and a refunded
+        // subsidy was never in play, so no fee comes out of any of them.
+        let market = &ctx.accounts.market;
+        let fee_pool = if market.lmsr.is_some() || winning_outcome.is_none() {
+            0
+        } else {
+            market.total_pool - subsidy_refund
+        };
+        require!(
+            fees.protocol <= fee_cap(fee_pool, PROTOCOL_FEE_BPS)
+                && fees.creator <= fee_cap(fee_pool, market.creator_fee_bps),
+            ErrorCode::FeeExceedsLimit
+        );
+        require!(
+            ctx.remaining_accounts.len() == referral_account_count + payouts.len(),
+            ErrorCode::FeeRecipientMismatch
+        );
+        let fee_recipients = [
+            (fees.protocol, PROTOCOL_TREASURY, ctx.accounts.protocol_fee_account.as_ref()),
+            (fees.creator, market.creator, ctx.accounts.creator_fee_account.as_ref()),
+        ];
+        for (amount, owner, account) in fee_recipients {
+            if amount > 0 {
+                let destination = required_fee_account(account.map(|a| a.as_ref()))?;
+                pay_fee(&escrow, destination, &owner, market, amount)?;
             }
         }
+        // Each referrer's cut is capped by the live stake it referred
+        let mut referral_total: u64 = 0;
+        for (referral, accounts) in fees.referrals.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            require_keys_eq!(
+                *accounts[0].owner,
+                crate::ID,
+                ErrorCode::FeeRecipientMismatch
+            );
+            let record = Referral::try_deserialize(&mut &accounts[0].try_borrow_data()?[..])?;
+            require!(
+                record.market == market.key() && record.referrer == referral.recipient,
+                ErrorCode::FeeRecipientMismatch
+            );
+            let cap = if fee_pool == 0 {
+                0
+            } else {
+                fee_cap(record.referred_stake, market.referrer_fee_bps)
+            };
+            require!(referral.amount <= cap, ErrorCode::FeeExceedsLimit);
+            pay_fee(
+                &escrow,
+                &accounts[1],
+                &referral.recipient,
+                market,
+                referral.amount,
+            )?;
+            referral_total = referral_total.checked_add(referral.amount).unwrap();
+        }
+
+        emit!(FeesPaid {
+            market: market.key(),
+            protocol_fee: fees.protocol,
+            creator_fee: fees.creator,
+            referral_fees: referral_total,
+        });
 
         // Update market state
         let market = &mut ctx.accounts.market;
         market.state = MarketState::Settled;
         market.result_hash = hash_result(&mxe_result);
+        market.subsidy_refund = subsidy_refund;
+        market.winning_outcome = winning_outcome;
+
+        let job = &mut ctx.accounts.resolution_job;
+        if job.status == JobStatus::Pending {
+            job.attempts = job.attempts.checked_add(1).unwrap();
+        }
+        job.status = JobStatus::Completed;
+        job.last_error = 0;
+        job.updated_at = Clock::get()?.unix_timestamp;
+
+        emit!(MarketSettled {
+            market: market.key(),
+            winning_outcome,
+            result_hash: market.result_hash,
+            total_pool: market.total_pool,
+            total_paid_out,
+            payout_count,
+        });
 
         msg!("Market settled successfully");
         Ok(())
     }
 
-    /// Cancel market (only if no bets placed)
+    /// Cancel market (only if no live bets: none placed, or all withdrawn)
     pub fn cancel_market(ctx: Context<CancelMarket>) -> Result<()> {
         require!(
-            ctx.accounts.market.bet_count == 0,
+            !ctx.accounts.market.is_finalized(),
+            ErrorCode::InvalidMarketState
+        );
+        let traded = ctx
+            .accounts
+            .market
+            .lmsr
+            .is_some_and(|lmsr| lmsr.outstanding != [0; 2]);
+        require!(
+            ctx.accounts.market.live_bet_count() == 0 && !traded,
             ErrorCode::CannotCancelWithBets
         );
         require!(
@@ -170,191 +1057,2856 @@ pub mod market_factory {
             ErrorCode::Unauthorized
         );
 
+        release_parent(&ctx.accounts.market, ctx.accounts.parent_market.as_mut())?;
+
+        // Nobody bet, so the whole subsidy goes back. No stake is left to
+        // share the withdrawal penalties either: they are the creator's, and
+        // `close_escrow` pays them out.
         let market = &mut ctx.accounts.market;
         market.state = MarketState::Cancelled;
+        market.subsidy_refund = market.subsidy;
+        market.total_pool = market.total_pool.checked_sub(market.penalty_pool).unwrap();
+        market.penalty_pool = 0;
+
+        emit!(MarketCancelled {
+            market: market.key(),
+            creator: market.creator,
+        });
 
         msg!("Market cancelled");
         Ok(())
     }
-}
 
-// ========== ACCOUNTS ==========
+    /// Refund a wallet's stake on a market voided by `void_stuck_market`,
+    /// along with its share of the withdrawal penalties. LMSR positions give
+    /// up their shares for what they cost.
+    pub fn refund_position(ctx: Context<RefundPosition>) -> Result<()> {
+        require!(
+            ctx.accounts.market.state == MarketState::Cancelled,
+            ErrorCode::InvalidMarketState
+        );
+        // Receipt stakes are refunded to whoever holds the receipt
+        require!(
+            !ctx.accounts.market.receipt_tokens,
+            ErrorCode::ReceiptRequired
+        );
+        let stake = ctx.accounts.position.total_staked;
+        require!(stake > 0, ErrorCode::InvalidAmount);
 
-#[derive(Accounts)]
-#[instruction(question: String)]
-pub struct CreateMarket<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = 8 + Market::INIT_SPACE,
-        seeds = [b"market", creator.key().as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
+        let refund = take_void_refund(&mut ctx.accounts.market, stake);
+        let position = &mut ctx.accounts.position;
+        if let Some(lmsr) = ctx.accounts.market.lmsr.as_mut() {
+            for (outstanding, held) in lmsr.outstanding.iter_mut().zip(position.shares) {
+                *outstanding = outstanding.checked_sub(held).unwrap();
+            }
+        }
+        position.total_staked = 0;
+        position.shares = [0; 2];
 
-    #[account(
-        init,
-        payer = creator,
-        token::mint = token_mint,
-        token::authority = market,
-        seeds = [b"escrow", market.key().as_ref()],
-        bump
-    )]
-    pub escrow_vault: Account<'info, TokenAccount>,
+        let escrow = Escrow::new(
+            ctx.accounts.market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.owner_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        transfer_from_escrow(
+            &escrow,
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.market,
+            refund,
+        )?;
 
-    pub token_mint: Account<'info, token::Mint>,
+        emit!(PositionRefunded {
+            market: ctx.accounts.market.key(),
+            owner: ctx.accounts.owner.key(),
+            amount: refund,
+        });
 
-    #[account(mut)]
-    pub creator: Signer<'info>,
+        msg!("Position refunded: {}", refund);
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
-}
+    /// Replace or clear the market's allowlist; only before the first bet
+    pub fn set_allowlist_root(
+        ctx: Context<SetAllowlistRoot>,
+        allowlist_root: Option<[u8; 32]>,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.state == MarketState::Open,
+            ErrorCode::InvalidMarketState
+        );
+        require!(market.bet_count == 0, ErrorCode::AllowlistLocked);
+        market.allowlist_root = allowlist_root;
 
-#[derive(Accounts)]
-pub struct DepositBet<'info> {
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+        emit!(AllowlistRootUpdated {
+            market: market.key(),
+            allowlist_root,
+        });
 
-    #[account(
-        init,
-        payer = user,
-        space = 8 + BetLog::INIT_SPACE,
-        seeds = [b"bet", market.key().as_ref(), user.key().as_ref(), &market.bet_count.to_le_bytes()],
-        bump
-    )]
-    pub bet_log: Account<'info, BetLog>,
+        msg!("Allowlist updated");
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [b"escrow", market.key().as_ref()],
-        bump
-    )]
-    pub escrow_vault: Account<'info, TokenAccount>,
+    /// Let `session_key` place bets for the signing wallet until `expires_at`, up
+    /// to `spend_cap` in total and only on `allowed_markets`. The session PDA is
+    /// approved as delegate of `owner_token_account` for the cap. A token account
+    /// has a single delegate, so the approval of an earlier session or a relayer
+    /// must be spent or revoked first.
+    pub fn create_session(
+        ctx: Context<CreateSession>,
+        session_key: Pubkey,
+        expires_at: i64,
+        spend_cap: u64,
+        allowed_markets: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            expires_at > Clock::get()?.unix_timestamp,
+            ErrorCode::InvalidSessionExpiry
+        );
+        require!(spend_cap > 0, ErrorCode::InvalidAmount);
+        require!(
+            !allowed_markets.is_empty() && allowed_markets.len() <= MAX_SESSION_MARKETS,
+            ErrorCode::InvalidSessionMarkets
+        );
+        let tokens = &ctx.accounts.owner_token_account;
+        require!(
+            tokens.delegate.is_none() || tokens.delegated_amount == 0,
+            ErrorCode::DelegateInUse
+        );
 
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+        let session = &mut ctx.accounts.session;
+        session.owner = ctx.accounts.owner.key();
+        session.session_key = session_key;
+        session.expires_at = expires_at;
+        session.spend_cap = spend_cap;
+        session.spent = 0;
+        session.allowed_markets = allowed_markets;
+        session.bump = ctx.bumps.session;
+        session.version = ACCOUNT_VERSION;
+        session.token_account = ctx.accounts.owner_token_account.key();
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+        let cpi_accounts = Approve {
+            to: ctx.accounts.owner_token_account.to_account_info(),
+            delegate: session.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::approve(cpi_ctx, spend_cap)?;
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
+        emit!(SessionCreated {
+            owner: session.owner,
+            session_key,
+            expires_at,
+            spend_cap,
+            allowed_markets: session.allowed_markets.clone(),
+        });
+
+        msg!("Session created: {}", session_key);
+        Ok(())
+    }
+
+    /// End a session early, returning its rent to the owner. Its token approval
+    /// is revoked unless the owner has since approved another delegate.
+    pub fn revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+        let session = ctx.accounts.session.key();
+        if ctx.accounts.token_account.delegate == COption::Some(session) {
+            let cpi_accounts = Revoke {
+                source: ctx.accounts.token_account.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            };
+            let cpi_ctx =
+                CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+            token_interface::revoke(cpi_ctx)?;
+        }
+
+        emit!(SessionRevoked {
+            owner: ctx.accounts.owner.key(),
+            session_key: ctx.accounts.session.session_key,
+        });
+
+        msg!("Session revoked");
+        Ok(())
+    }
+
+    /// Buy `shares` of `outcome` (0 = NO, 1 = YES) from an LMSR market at the
+    /// price the cost function quotes now. Fails if that costs more than `max_cost`.
+    pub fn buy_shares(
+        ctx: Context<BuyShares>,
+        outcome: u8,
+        shares: u64,
+        max_cost: u64,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require_betting_open(&ctx.accounts.market)?;
+        require_allowlisted(
+            &ctx.accounts.market,
+            &ctx.accounts.user.key(),
+            &allowlist_proof,
+        )?;
+        let lmsr = ctx.accounts.market.lmsr.ok_or(ErrorCode::WrongMarketKind)?;
+        require!(outcome < 2, ErrorCode::InvalidOutcome);
+        require!(shares > 0, ErrorCode::InvalidAmount);
+
+        let cost = lmsr_buy_cost(lmsr.liquidity, lmsr.outstanding, outcome, shares)
+            .filter(|cost| *cost > 0)
+            .ok_or(ErrorCode::InvalidAmount)?;
+        require!(cost <= max_cost, ErrorCode::SlippageExceeded);
+
+        open_position_if_new(
+            &mut ctx.accounts.market,
+            &mut ctx.accounts.position,
+            ctx.accounts.user.key(),
+            ctx.bumps.position,
+        );
+        apply_stake_change(&ctx.accounts.market, &mut ctx.accounts.position, 0, cost)?;
+
+        let mut escrow = Escrow::new(
+            ctx.accounts.market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.user_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        let received = transfer_to_escrow(
+            &mut escrow,
+            &ctx.accounts.user.to_account_info(),
+            &[],
+            &ctx.accounts.system_program,
+            cost,
+        )?;
+        require!(received == cost, ErrorCode::TransferFeeUnsupported);
+
+        let position = &mut ctx.accounts.position;
+        position.shares[outcome as usize] = position.shares[outcome as usize]
+            .checked_add(shares)
+            .unwrap();
+
+        let market = &mut ctx.accounts.market;
+        let lmsr = market.lmsr.as_mut().unwrap();
+        lmsr.outstanding[outcome as usize] = lmsr.outstanding[outcome as usize]
+            .checked_add(shares)
+            .ok_or(ErrorCode::Overflow)?;
+        let outstanding = lmsr.outstanding;
+        market.total_pool = market.total_pool.checked_add(cost).unwrap();
+
+        emit!(SharesBought {
+            market: market.key(),
+            buyer: ctx.accounts.user.key(),
+            outcome,
+            shares,
+            cost,
+            outstanding,
+        });
+
+        msg!("Bought {} shares of {} for {}", shares, outcome, cost);
+        Ok(())
+    }
+
+    /// Pay out an LMSR position at one unit of collateral per winning share
+    pub fn redeem_shares(ctx: Context<RedeemShares>) -> Result<()> {
+        require!(
+            ctx.accounts.market.state == MarketState::Settled,
+            ErrorCode::InvalidMarketState
+        );
+        let winner = ctx
+            .accounts
+            .market
+            .winning_outcome
+            .ok_or(ErrorCode::WrongMarketKind)? as usize;
+        let payout = ctx.accounts.position.shares[winner];
+        require!(payout > 0, ErrorCode::InvalidAmount);
+
+        let escrow = Escrow::new(
+            ctx.accounts.market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.owner_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        transfer_from_escrow(
+            &escrow,
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.market,
+            payout,
+        )?;
+
+        ctx.accounts.position.shares = [0; 2];
+        let market = &mut ctx.accounts.market;
+        let lmsr = market.lmsr.as_mut().unwrap();
+        lmsr.outstanding[winner] -= payout;
+        market.total_pool = market.total_pool.checked_sub(payout).unwrap();
+
+        emit!(SharesRedeemed {
+            market: market.key(),
+            owner: ctx.accounts.owner.key(),
+            shares: payout,
+        });
+
+        msg!("Redeemed {} winning shares", payout);
+        Ok(())
+    }
+
+    /// Return what is left of an LMSR market's collateral to the creator once
+    /// every winning share has been redeemed
+    pub fn withdraw_liquidity(ctx: Context<WithdrawLiquidity>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.is_finalized(), ErrorCode::MarketNotFinalized);
+        let lmsr = market.lmsr.ok_or(ErrorCode::WrongMarketKind)?;
+        if let Some(winner) = market.winning_outcome {
+            require!(
+                lmsr.outstanding[winner as usize] == 0,
+                ErrorCode::SharesOutstanding
+            );
+        }
+        // A voided market refunds every share before the creator is paid
+        if market.state == MarketState::Cancelled {
+            require!(lmsr.outstanding == [0; 2], ErrorCode::SharesOutstanding);
+        }
+
+        let amount = market.total_pool;
+        let escrow = Escrow::new(
+            market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.creator_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        if amount > 0 {
+            transfer_from_escrow(
+                &escrow,
+                &ctx.accounts.creator.to_account_info(),
+                &ctx.accounts.market,
+                amount,
+            )?;
+        }
+        ctx.accounts.market.total_pool = 0;
+
+        msg!("Liquidity withdrawn: {}", amount);
+        Ok(())
+    }
+
+    /// Open a parlay pool over 2 to `MAX_PARLAY_LEGS` open token markets of one
+    /// mint, passed as remaining accounts in the order of `legs`
+    pub fn create_parlay_pool(ctx: Context<CreateParlayPool>, legs: Vec<Pubkey>) -> Result<()> {
+        require!(
+            (2..=MAX_PARLAY_LEGS).contains(&legs.len())
+                && legs.windows(2).all(|pair| pair[0] < pair[1]),
+            ErrorCode::InvalidParlayLegs
+        );
+        let mut markets = load_parlay_legs(&legs, ctx.remaining_accounts)?;
+        let token_mint = ctx.accounts.token_mint.key();
+        for market in &markets {
+            require!(
+                market.state == MarketState::Open
+                    && market.collateral == Collateral::Token
+                    && market.token_mint == token_mint,
+                ErrorCode::InvalidParlayLegs
+            );
+        }
+        // Tickets close with the first leg
+        let deadline = markets.iter().map(|market| market.deadline).min().unwrap();
+        require!(
+            Clock::get()?.unix_timestamp < deadline,
+            ErrorCode::DeadlinePassed
+        );
+        count_open_parlay(&mut markets, ctx.remaining_accounts, true)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.legs = legs;
+        pool.token_mint = token_mint;
+        pool.escrow_vault = ctx.accounts.escrow_vault.key();
+        pool.deadline = deadline;
+        pool.stakes = [0; 16];
+        pool.total_staked = 0;
+        pool.settled = false;
+        pool.winning_outcomes = None;
+        pool.bump = ctx.bumps.pool;
+        pool.version = ACCOUNT_VERSION;
+
+        emit!(ParlayPoolCreated {
+            pool: pool.key(),
+            legs: pool.legs.clone(),
+            token_mint,
+            deadline,
+        });
+
+        msg!("Parlay pool created: {}", pool.key());
+        Ok(())
+    }
+
+    /// Stake on one outcome per leg; bit `i` of `outcomes` is leg `i`'s outcome
+    pub fn place_parlay(ctx: Context<PlaceParlay>, outcomes: u8, amount: u64) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(
+            Clock::get()?.unix_timestamp < pool.deadline,
+            ErrorCode::DeadlinePassed
+        );
+        require!(
+            (outcomes as usize) < 1 << pool.legs.len(),
+            ErrorCode::InvalidOutcome
+        );
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let mut escrow = Escrow::Token {
+            escrow_vault: &mut ctx.accounts.escrow_vault,
+            token_mint: &ctx.accounts.token_mint,
+            wallet_tokens: Some(&ctx.accounts.owner_token_account),
+            token_program: &ctx.accounts.token_program,
+        };
+        let stake = transfer_to_escrow(
+            &mut escrow,
+            &ctx.accounts.owner.to_account_info(),
+            &[],
+            &ctx.accounts.system_program,
+            amount,
+        )?;
+
+        let pool = &mut ctx.accounts.pool;
+        let outcome_stake = &mut pool.stakes[outcomes as usize];
+        *outcome_stake = outcome_stake.checked_add(stake).unwrap();
+        pool.total_staked = pool.total_staked.checked_add(stake).unwrap();
+
+        let parlay = &mut ctx.accounts.parlay;
+        parlay.pool = pool.key();
+        parlay.owner = ctx.accounts.owner.key();
+        parlay.outcomes = outcomes;
+        parlay.stake = stake;
+        parlay.bump = ctx.bumps.parlay;
+        parlay.version = ACCOUNT_VERSION;
+
+        emit!(ParlayPlaced {
+            pool: pool.key(),
+            parlay: parlay.key(),
+            owner: parlay.owner,
+            outcomes,
+            stake,
+        });
+
+        msg!("Parlay placed: {}", stake);
+        Ok(())
+    }
+
+    /// Settle a parlay pool once every leg is settled or cancelled; the leg
+    /// markets are passed as remaining accounts, in order, and may close after
+    pub fn settle_parlay_pool(ctx: Context<SettleParlayPool>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(!pool.settled, ErrorCode::InvalidMarketState);
+        let mut markets = load_parlay_legs(&pool.legs, ctx.remaining_accounts)?;
+        require!(
+            markets.iter().all(Market::is_finalized),
+            ErrorCode::ParlayLegsNotFinalized
+        );
+        count_open_parlay(&mut markets, ctx.remaining_accounts, false)?;
+
+        // A cancelled or void leg voids the parlay, and so does nobody having
+        // called every leg; either way each ticket gets its stake back
+        let winning_outcomes = markets
+            .iter()
+            .enumerate()
+            .try_fold(0u8, |outcomes, (i, market)| {
+                let outcome = market
+                    .winning_outcome
+                    .filter(|_| market.state == MarketState::Settled)?;
+                Some(outcomes | outcome << i)
+            })
+            .filter(|outcomes| pool.stakes[*outcomes as usize] > 0);
+
+        let pool = &mut ctx.accounts.pool;
+        pool.settled = true;
+        pool.winning_outcomes = winning_outcomes;
+
+        emit!(ParlaySettled {
+            pool: pool.key(),
+            winning_outcomes,
+            winning_stake: winning_outcomes.map_or(0, |outcomes| pool.stakes[outcomes as usize]),
+            total_staked: pool.total_staked,
+        });
+
+        msg!("Parlay pool settled");
+        Ok(())
+    }
+
+    /// Pay a ticket its share of a settled pool, or its stake back if the pool
+    /// is void, and close it
+    pub fn claim_parlay(ctx: Context<ClaimParlay>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(pool.settled, ErrorCode::ParlayNotSettled);
+        let parlay = &ctx.accounts.parlay;
+        let amount = match pool.winning_outcomes {
+            None => parlay.stake,
+            Some(outcomes) if outcomes == parlay.outcomes => {
+                let winning_stake = pool.stakes[outcomes as usize] as u128;
+                (pool.total_staked as u128 * parlay.stake as u128 / winning_stake) as u64
+            }
+            Some(_) => 0,
+        };
+        if amount > 0 {
+            pay_from_parlay_pool(
+                pool,
+                &ctx.accounts.escrow_vault,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.owner_token_account,
+                &ctx.accounts.token_program,
+                amount,
+            )?;
+        }
+
+        emit!(ParlayClaimed {
+            pool: pool.key(),
+            parlay: parlay.key(),
+            owner: parlay.owner,
+            amount,
+        });
+
+        msg!("Parlay claimed: {}", amount);
+        Ok(())
+    }
+
+    /// Return the undistributed subsidy to its sponsor once the market is final
+    pub fn reclaim_subsidy(ctx: Context<ReclaimSubsidy>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.is_finalized(), ErrorCode::MarketNotFinalized);
+        let amount = market.subsidy_refund;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let escrow = Escrow::new(
+            market.collateral,
+            ctx.accounts.escrow_vault.as_mut(),
+            ctx.accounts.token_mint.as_ref(),
+            ctx.accounts.sponsor_token_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            ctx.accounts.sol_vault.as_ref(),
+        )?;
+        transfer_from_escrow(
+            &escrow,
+            &ctx.accounts.sponsor.to_account_info(),
+            &ctx.accounts.market,
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.subsidy_refund = 0;
+        market.total_pool = market.total_pool.checked_sub(amount).unwrap();
+
+        emit!(SubsidyReclaimed {
+            market: market.key(),
+            sponsor: market.subsidy_sponsor,
+            amount,
+        });
+
+        msg!("Subsidy reclaimed: {}", amount);
+        Ok(())
+    }
+
+    /// Redeem a bet's receipt; whoever holds it gets the funds. While betting is
+    /// open this withdraws the bet (minus the withdrawal penalty); once settled
+    /// it pays `amount`, the bet's entry in the payout tree, proven by `proof`;
+    /// once voided it refunds the stake.
+    /// The receipt is burned and the bet log closed to its depositor.
+    pub fn redeem_receipt(
+        ctx: Context<RedeemReceipt>,
+        bet_index: u64,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(ctx.accounts.market.receipt_tokens, ErrorCode::ReceiptsUnsupported);
+        require!(
+            ctx.accounts.receipt_account.amount == 1,
+            ErrorCode::ReceiptNotHeld
+        );
+
+        let stake = ctx.accounts.bet_log.amount;
+        let paid = match ctx.accounts.market.state {
+            MarketState::Open => {
+                require_betting_open(&ctx.accounts.market)?;
+                let penalty = withdrawal_penalty(stake, ctx.accounts.market.withdrawal_penalty_bps);
+                let refund = stake - penalty;
+                let position = &mut ctx.accounts.position;
+                position.total_staked = position.total_staked.checked_sub(stake).unwrap();
+                apply_referral_change(
+                    &ctx.accounts.bet_log,
+                    ctx.accounts.referral.as_mut(),
+                    stake,
+                    0,
+                )?;
+                let market = &mut ctx.accounts.market;
+                market.total_pool = market.total_pool.checked_sub(refund).unwrap();
+                market.penalty_pool = market.penalty_pool.checked_add(penalty).unwrap();
+                refund
+            }
+            MarketState::Settled => {
+                let leaf = payout_leaf(bet_index, amount);
+                require!(
+                    fold_proof(leaf, &proof) == ctx.accounts.market.payout_root,
+                    ErrorCode::InvalidPayoutProof
+                );
+                amount
+            }
+            // Voided by `void_stuck_market`: the stake comes back with its
+            // share of the withdrawal penalties
+            MarketState::Cancelled => {
+                let position = &mut ctx.accounts.position;
+                position.total_staked = position.total_staked.checked_sub(stake).unwrap();
+                take_void_refund(&mut ctx.accounts.market, stake)
+            }
+            _ => return err!(ErrorCode::InvalidMarketState),
+        };
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            from: ctx.accounts.receipt_account.to_account_info(),
+            authority: ctx.accounts.holder.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::burn(cpi_ctx, 1)?;
+
+        if paid > 0 {
+            let escrow = Escrow::new(
+                ctx.accounts.market.collateral,
+                Some(&mut ctx.accounts.escrow_vault),
+                Some(&ctx.accounts.token_mint),
+                Some(&ctx.accounts.holder_token_account),
+                Some(&ctx.accounts.token_program),
+                None,
+            )?;
+            transfer_from_escrow(
+                &escrow,
+                &ctx.accounts.holder.to_account_info(),
+                &ctx.accounts.market,
+                paid,
+            )?;
+        }
+
+        let market = &mut ctx.accounts.market;
+        market.closed_bet_count = market.closed_bet_count.checked_add(1).unwrap();
+
+        emit!(ReceiptRedeemed {
+            market: market.key(),
+            bet_log: ctx.accounts.bet_log.key(),
+            bet_index,
+            holder: ctx.accounts.holder.key(),
+            amount: paid,
+        });
+
+        msg!("Receipt redeemed: {}", paid);
+        Ok(())
+    }
+
+    /// Close a bet log once its market is final, returning rent to the depositor
+    pub fn close_bet_log(ctx: Context<CloseBetLog>) -> Result<()> {
+        require!(
+            ctx.accounts.market.is_finalized(),
+            ErrorCode::MarketNotFinalized
+        );
+        // Receipt bet logs close when their receipt is redeemed
+        require!(
+            !ctx.accounts.market.receipt_tokens,
+            ErrorCode::ReceiptRequired
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.closed_bet_count = market.closed_bet_count.checked_add(1).unwrap();
+
+        msg!("Bet log closed");
+        Ok(())
+    }
+
+    /// Close a ledger page once its market is final, returning rent to whoever opened it
+    pub fn close_ledger_page(ctx: Context<CloseLedgerPage>) -> Result<()> {
+        require!(
+            ctx.accounts.market.is_finalized(),
+            ErrorCode::MarketNotFinalized
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.closed_ledger_page_count = market.closed_ledger_page_count.checked_add(1).unwrap();
+
+        msg!("Ledger page closed");
+        Ok(())
+    }
+
+    /// Close a wallet's position once its market is final, returning rent to the owner
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        require!(
+            ctx.accounts.market.is_finalized(),
+            ErrorCode::MarketNotFinalized
+        );
+        let unredeemed = ctx
+            .accounts
+            .market
+            .winning_outcome
+            .map_or(0, |winner| ctx.accounts.position.shares[winner as usize]);
+        require!(unredeemed == 0, ErrorCode::SharesOutstanding);
+        require!(
+            ctx.accounts.market.state != MarketState::Cancelled
+                || ctx.accounts.position.total_staked == 0,
+            ErrorCode::StakeNotRefunded
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.closed_position_count = market.closed_position_count.checked_add(1).unwrap();
+
+        msg!("Position closed");
+        Ok(())
+    }
+
+    /// Close a referral once its market is final, returning rent to whoever paid it
+    pub fn close_referral(ctx: Context<CloseReferral>) -> Result<()> {
+        require!(
+            ctx.accounts.market.is_finalized(),
+            ErrorCode::MarketNotFinalized
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.closed_referral_count = market.closed_referral_count.checked_add(1).unwrap();
+
+        msg!("Referral closed");
+        Ok(())
+    }
+
+    /// Close the resolution job of a final market, returning rent to the creator
+    pub fn close_resolution_job(ctx: Context<CloseResolutionJob>) -> Result<()> {
+        require!(
+            ctx.accounts.market.is_finalized(),
+            ErrorCode::MarketNotFinalized
+        );
+
+        msg!("Resolution job closed");
+        Ok(())
+    }
+
+    /// Close the escrow vault of a final market once every claim on it is paid,
+    /// sweeping what is left and the rent to the creator.
+    /// Token-2022 fee mints need the escrow's withheld fees harvested first.
+    pub fn close_escrow(ctx: Context<CloseEscrow>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.is_finalized(), ErrorCode::MarketNotFinalized);
+        // Once the sponsor, receipt holders and LMSR liquidity are paid, what
+        // is left is the creator's: rounding dust, and the withdrawal
+        // penalties `cancel_market` released. A cancelled market's pool, stakes
+        // and penalties still in it, belongs to bettors until refunded.
+        let unclaimed = market.subsidy_refund > 0
+            || (market.receipt_tokens && market.closed_bet_count < market.bet_count)
+            || (market.lmsr.is_some() && market.total_pool > 0)
+            || (market.state == MarketState::Cancelled && market.total_pool > 0);
+        require!(!unclaimed, ErrorCode::EscrowNotEmpty);
+
+        if market.collateral == Collateral::NativeSol {
+            // Closing hands the creator any lamports above rent as well
+            let sol_vault = required(ctx.accounts.sol_vault.as_ref())?;
+            sol_vault.close(ctx.accounts.creator.to_account_info())?;
+
+            msg!("SOL vault closed");
+            return Ok(());
+        }
+
+        let dust = required(ctx.accounts.escrow_vault.as_ref())?.amount;
+        if dust > 0 {
+            let escrow = Escrow::new(
+                Collateral::Token,
+                ctx.accounts.escrow_vault.as_mut(),
+                ctx.accounts.token_mint.as_ref(),
+                ctx.accounts.creator_token_account.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+                None,
+            )?;
+            transfer_from_escrow(
+                &escrow,
+                &ctx.accounts.creator.to_account_info(),
+                &ctx.accounts.market,
+                dust,
+            )?;
+            msg!("Escrow dust swept: {}", dust);
+        }
+
+        let escrow_vault = required(ctx.accounts.escrow_vault.as_ref())?;
+        let token_program = required(ctx.accounts.token_program.as_ref())?;
+        let seed = ctx.accounts.market.seed();
+        let seeds = &[b"market", seed.as_ref(), &[ctx.accounts.market.bump]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = CloseAccount {
+            account: escrow_vault.to_account_info(),
+            destination: ctx.accounts.creator.to_account_info(),
+            authority: ctx.accounts.market.to_account_info(),
+        };
+        let cpi_program = token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token_interface::close_account(cpi_ctx)?;
+
+        msg!("Escrow closed");
+        Ok(())
+    }
+
+    /// Close a final market after all of its other accounts are closed
+    pub fn close_market(ctx: Context<CloseMarket>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.is_finalized(), ErrorCode::MarketNotFinalized);
+        // Ledger markets have no bet logs, only ledger pages
+        require!(
+            market.bet_storage == BetStorage::Ledger || market.closed_bet_count == market.bet_count,
+            ErrorCode::AccountsStillOpen
+        );
+        require!(
+            market.closed_ledger_page_count == market.ledger_page_count
+                && market.closed_position_count == market.position_count
+                && market.closed_referral_count == market.referral_count
+                && market.open_child_count == 0
+                && market.open_parlay_count == 0,
+            ErrorCode::AccountsStillOpen
+        );
+        require!(
+            ctx.accounts.escrow_vault.data_is_empty()
+                && ctx.accounts.resolution_job.data_is_empty(),
+            ErrorCode::AccountsStillOpen
+        );
+
+        emit!(MarketClosed {
+            market: market.key(),
+            creator: market.creator,
+        });
+
+        msg!("Market closed");
+        Ok(())
+    }
+
+    /// Bring a market created before accounts were versioned to the current
+    /// layout, along with its bet logs and resolution job passed as remaining
+    /// accounts. Each bet log is followed by its depositor's position, which
+    /// is opened or credited with the stake of a version 0 bet. Accounts that
+    /// are already current are left alone, so the same accounts may be passed
+    /// again. Anyone may call this; `payer` covers the extra rent.
+    pub fn migrate_market<'info>(
+        ctx: Context<'_, '_, '_, 'info, MigrateMarket<'info>>,
+    ) -> Result<()> {
+        let payer = &ctx.accounts.payer;
+        let system_program = &ctx.accounts.system_program;
+        let market_info = ctx.accounts.market.to_account_info();
+        require_keys_eq!(*market_info.owner, crate::ID, ErrorCode::NotMigratable);
+        let mut migrated = 0u32;
+        if let Some(v0) = read_v0::<MarketV0>(&market_info)? {
+            // The mint was not recorded; the escrow holds it
+            let escrow = required(ctx.accounts.escrow_vault.as_ref())?;
+            require_keys_eq!(
+                escrow.key(),
+                v0.escrow_vault,
+                ErrorCode::CollateralAccountsMismatch
+            );
+            let current = v0.migrate(escrow.mint);
+            let space = 8 + Market::INIT_SPACE;
+            write_migrated(&market_info, &current, space, payer, system_program)?;
+            migrated += 1;
+        }
+
+        let market_key = market_info.key();
+        let mut market = Market::try_deserialize(&mut &market_info.try_borrow_data()?[..])?;
+        let mut accounts = ctx.remaining_accounts.iter();
+        while let Some(account) = accounts.next() {
+            if migrate_account(
+                account,
+                &mut accounts,
+                market_key,
+                &mut market,
+                payer,
+                system_program,
+            )? {
+                migrated += 1;
+            }
+        }
+        // Positions opened for version 0 bets are counted on the market
+        market.try_serialize(&mut &mut market_info.try_borrow_mut_data()?[..])?;
+
+        emit!(MarketMigrated {
+            market: market_key,
+            migrated,
+            version: ACCOUNT_VERSION,
+        });
+
+        msg!("Migrated {} accounts of market {}", migrated, market_key);
+        Ok(())
+    }
 }
 
+// ========== ACCOUNTS ==========
+
 #[derive(Accounts)]
-pub struct EnqueueResolution<'info> {
-    #[account(mut)]
+#[instruction(question: String)]
+pub struct CreateMarket<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + Market::INIT_SPACE,
+        seeds = [b"market", creator.key().as_ref()],
+        bump
+    )]
     pub market: Account<'info, Market>,
 
+    /// Token markets only
     #[account(
         init,
-        payer = payer,
-        space = 8 + ResolutionJob::INIT_SPACE,
-        seeds = [b"rqueue", market.key().as_ref()],
+        payer = creator,
+        token::mint = token_mint,
+        token::authority = market,
+        seeds = [b"escrow", market.key().as_ref()],
         bump
     )]
-    pub resolution_job: Account<'info, ResolutionJob>,
-
-    #[account(mut)]
-    pub payer: Signer<'info>,
-
-    pub system_program: Program<'info, System>,
-}
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
 
-#[derive(Accounts)]
-pub struct CallbackSettle<'info> {
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
 
+    /// Native SOL markets only
     #[account(
-        mut,
-        seeds = [b"escrow", market.key().as_ref()],
+        init,
+        payer = creator,
+        space = 8 + SolVault::INIT_SPACE,
+        seeds = [b"sol_vault", market.key().as_ref()],
         bump
     )]
-    pub escrow_vault: Account<'info, TokenAccount>,
+    pub sol_vault: Option<Account<'info, SolVault>>,
 
-    /// CHECK: Winner account verified in handler
+    /// Token markets: funds an LMSR market maker or the subsidy; owned by the
+    /// sponsor when one signs
     #[account(mut)]
-    pub winner_token_account: Account<'info, TokenAccount>,
+    pub creator_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
-    pub token_program: Program<'info, Token>,
-}
+    #[account(mut)]
+    pub creator: Signer<'info>,
 
-#[derive(Accounts)]
-pub struct CancelMarket<'info> {
-    #[account(mut, has_one = creator)]
+    /// Pays the subsidy instead of the creator and gets back what is left of it
+    #[account(mut)]
+    pub sponsor: Option<Signer<'info>>,
+
+    /// Conditional markets: the parent named in `condition`, still unsettled
+    #[account(mut)]
+    pub parent_market: Option<Account<'info, Market>>,
+
+    /// Conditional markets: the parent's creator, consenting to keep it open
+    /// until this one is final
+    pub parent_creator: Option<Signer<'info>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(template_id: u32)]
+pub struct CreateMarketTemplate<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + MarketTemplate::INIT_SPACE,
+        seeds = [b"template", creator.key().as_ref(), &template_id.to_le_bytes()],
+        bump
+    )]
+    pub template: Account<'info, MarketTemplate>,
+
+    /// Token templates only
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SpawnMarket<'info> {
+    #[account(mut)]
+    pub template: Account<'info, MarketTemplate>,
+
+    /// Instance number `template.spawned`
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Market::INIT_SPACE,
+        seeds = [
+            b"market",
+            template_market_seed(&template.key(), template.spawned).as_ref()
+        ],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// Token templates only
+    #[account(
+        init,
+        payer = payer,
+        token::mint = token_mint,
+        token::authority = market,
+        seeds = [b"escrow", market.key().as_ref()],
+        bump
+    )]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = template.token_mint @ ErrorCode::CollateralAccountsMismatch)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    /// Native SOL templates only
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + SolVault::INIT_SPACE,
+        seeds = [b"sol_vault", market.key().as_ref()],
+        bump
+    )]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    /// Anyone; pays the rent of the new instance
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct DepositBet<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + BetLog::space(&market.bet_storage, referrer.is_some()),
+        seeds = [b"bet", market.key().as_ref(), user.key().as_ref(), &market.bet_count.to_le_bytes()],
+        bump
+    )]
+    pub bet_log: Account<'info, BetLog>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"position", market.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `user_token_account`, `token_program`
+    #[account(
+        mut,
+        seeds = [b"escrow", market.key().as_ref()],
+        bump
+    )]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    /// CHECK: Bettor the bet is recorded for; `authority` proves its consent
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// Signs the stake transfer: `user` itself, or on token markets a
+    /// delegate approved on `user_token_account` or a session key of `user`
+    pub authority: Signer<'info>,
+
+    /// Set when `authority` is a session key; the session moves the stake
+    #[account(
+        mut,
+        seeds = [b"session", user.key().as_ref(), authority.key().as_ref()],
+        bump = session.bump
+    )]
+    pub session: Option<Account<'info, SessionAuthority>>,
+
+    /// Pays the bet log and position rent; may be a relayer
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Wallet credited with the referral cut of this bet, if any
+    #[account(constraint = referrer.key() != user.key() @ ErrorCode::SelfReferral)]
+    pub referrer: Option<UncheckedAccount<'info>>,
+
+    /// Set with `referrer`: its `Referral` on this market, opened on its first referred bet
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + Referral::INIT_SPACE,
+        seeds = [b"referral", market.key().as_ref(), referrer.as_ref().map(|r| r.key()).unwrap_or_default().as_ref()],
+        bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    /// Receipt markets: `receipt_mint`, `receipt_account`, `associated_token_program`
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"receipt", bet_log.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = market,
+        mint::token_program = token_program
+    )]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    /// The user's associated token account for the receipt
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuyShares<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"position", market.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `user_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemShares<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"position", market.key().as_ref(), owner.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `owner_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`; the payout goes to `owner`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLiquidity<'info> {
+    #[account(mut, has_one = creator)]
+    pub market: Account<'info, Market>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `creator_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub creator_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`; the collateral goes to `creator`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimSubsidy<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `sponsor_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub sponsor_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`; the refund goes to `sponsor`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut, address = market.subsidy_sponsor @ ErrorCode::Unauthorized)]
+    pub sponsor: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+#[instruction(legs: Vec<Pubkey>)]
+pub struct CreateParlayPool<'info> {
+    /// One pool per set of legs
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + ParlayPool::INIT_SPACE,
+        seeds = [b"parlay_pool", parlay_legs_hash(&legs).as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, ParlayPool>,
+
+    #[account(
+        init,
+        payer = payer,
+        token::mint = token_mint,
+        token::authority = pool,
+        token::token_program = token_program,
+        seeds = [b"parlay_escrow", pool.key().as_ref()],
+        bump
+    )]
+    pub escrow_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceParlay<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, ParlayPool>,
+
+    /// One ticket per wallet and pool
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Parlay::INIT_SPACE,
+        seeds = [b"parlay", pool.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub parlay: Account<'info, Parlay>,
+
+    #[account(mut, address = pool.escrow_vault)]
+    pub escrow_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool.token_mint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleParlayPool<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, ParlayPool>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimParlay<'info> {
+    pub pool: Account<'info, ParlayPool>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = pool,
+        has_one = owner,
+        seeds = [b"parlay", pool.key().as_ref(), owner.key().as_ref()],
+        bump = parlay.bump
+    )]
+    pub parlay: Account<'info, Parlay>,
+
+    #[account(mut, address = pool.escrow_vault)]
+    pub escrow_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool.token_mint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(bet_index: u64)]
+pub struct RedeemReceipt<'info> {
+    #[account(mut)]
     pub market: Account<'info, Market>,
 
-    pub creator: Signer<'info>,
+    /// Seeded by `bet_index`, which keys the bet's payout leaf
+    #[account(
+        mut,
+        close = depositor,
+        seeds = [b"bet", market.key().as_ref(), depositor.key().as_ref(), &bet_index.to_le_bytes()],
+        bump = bet_log.bump,
+        has_one = market,
+        has_one = depositor
+    )]
+    pub bet_log: Account<'info, BetLog>,
+
+    #[account(
+        mut,
+        seeds = [b"position", market.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Referred bets: the referrer's `Referral`, while betting is open
+    #[account(
+        mut,
+        seeds = [b"referral", market.key().as_ref(), bet_log.referrer.unwrap_or_default().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    #[account(
+        mut,
+        seeds = [b"receipt", bet_log.key().as_ref()],
+        bump
+    )]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, token::mint = receipt_mint, token::authority = holder)]
+    pub receipt_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub holder: Signer<'info>,
+
+    /// CHECK: Rent destination, matched against `bet_log.depositor`
+    #[account(mut)]
+    pub depositor: UncheckedAccount<'info>,
+
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// Receives the refund or payout; any account of the market mint
+    #[account(mut, token::mint = token_mint)]
+    pub holder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawBet<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = depositor,
+        has_one = market,
+        has_one = depositor
+    )]
+    pub bet_log: Account<'info, BetLog>,
+
+    #[account(
+        mut,
+        seeds = [b"position", market.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Referred bets: the referrer's `Referral`
+    #[account(
+        mut,
+        seeds = [b"referral", market.key().as_ref(), bet_log.referrer.unwrap_or_default().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `depositor_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub depositor_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`; the refund goes to `depositor`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct AmendBet<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(mut, has_one = market, has_one = depositor)]
+    pub bet_log: Account<'info, BetLog>,
+
+    #[account(
+        mut,
+        seeds = [b"position", market.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Referred bets: the referrer's `Referral`
+    #[account(
+        mut,
+        seeds = [b"referral", market.key().as_ref(), bet_log.referrer.unwrap_or_default().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `depositor_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub depositor_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`; top-ups come from and refunds go to `depositor`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(page_index: u32)]
+pub struct OpenLedgerPage<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<BetLedgerPage>(),
+        seeds = [b"ledger", market.key().as_ref(), &page_index.to_le_bytes()],
+        bump
+    )]
+    pub ledger_page: AccountLoader<'info, BetLedgerPage>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositBetLedger<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"ledger", market.key().as_ref(), &market.current_ledger_page().to_le_bytes()],
+        bump
+    )]
+    pub ledger_page: AccountLoader<'info, BetLedgerPage>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"position", market.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `user_token_account`, `token_program`
+    #[account(
+        mut,
+        seeds = [b"escrow", market.key().as_ref()],
+        bump
+    )]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    /// CHECK: Bettor the bet is recorded for; `authority` proves its consent
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// Signs the stake transfer, as for `deposit_bet`
+    pub authority: Signer<'info>,
+
+    /// Set when `authority` is a session key; the session moves the stake
+    #[account(
+        mut,
+        seeds = [b"session", user.key().as_ref(), authority.key().as_ref()],
+        bump = session.bump
+    )]
+    pub session: Option<Account<'info, SessionAuthority>>,
+
+    /// Pays the position and referral rent; may be a relayer
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Wallet credited with the referral cut of this bet, if any
+    #[account(constraint = referrer.key() != user.key() @ ErrorCode::SelfReferral)]
+    pub referrer: Option<UncheckedAccount<'info>>,
+
+    /// Set with `referrer`: its `Referral` on this market, opened on its first referred bet
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + Referral::INIT_SPACE,
+        seeds = [b"referral", market.key().as_ref(), referrer.as_ref().map(|r| r.key()).unwrap_or_default().as_ref()],
+        bump
+    )]
+    pub referral: Option<Account<'info, Referral>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnqueueResolution<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + ResolutionJob::INIT_SPACE,
+        seeds = [b"rqueue", market.key().as_ref()],
+        bump
+    )]
+    pub resolution_job: Account<'info, ResolutionJob>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateResolutionJob<'info> {
+    /// Only the resolver: a creator able to fail the job could run it out of
+    /// attempts and strand every stake
+    #[account(constraint = authority.key() == market.resolver @ ErrorCode::Unauthorized)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [b"rqueue", market.key().as_ref()],
+        bump = resolution_job.bump
+    )]
+    pub resolution_job: Account<'info, ResolutionJob>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct VoidStuckMarket<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        has_one = market,
+        seeds = [b"rqueue", market.key().as_ref()],
+        bump = resolution_job.bump
+    )]
+    pub resolution_job: Account<'info, ResolutionJob>,
+
+    /// Conditional markets: the parent, released to close once this one is final
+    #[account(mut)]
+    pub parent_market: Option<Account<'info, Market>>,
+}
+
+#[derive(Accounts)]
+pub struct CallbackSettle<'info> {
+    #[account(mut, has_one = resolver @ ErrorCode::Unauthorized)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [b"rqueue", market.key().as_ref()],
+        bump = resolution_job.bump
+    )]
+    pub resolution_job: Account<'info, ResolutionJob>,
+
+    pub resolver: Signer<'info>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `token_program`. Payouts go
+    /// to remaining accounts, each its recipient's token account (or wallet,
+    /// for native SOL), after one per referral fee.
+    #[account(
+        mut,
+        seeds = [b"escrow", market.key().as_ref()],
+        bump
+    )]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    /// Native SOL markets: `sol_vault`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    /// CHECK: `PROTOCOL_TREASURY`'s token account, or the wallet itself for
+    /// native SOL; verified in handler. Only needed for a nonzero protocol fee.
+    #[account(mut)]
+    pub protocol_fee_account: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Creator's token account or wallet, as above
+    #[account(mut)]
+    pub creator_fee_account: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
+    /// Conditional markets: the parent, whose settled outcome decides whether
+    /// this market pays out or is void
+    #[account(mut)]
+    pub parent_market: Option<Account<'info, Market>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelMarket<'info> {
+    #[account(mut, has_one = creator)]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Conditional markets: the parent, released to close once this one is final
+    #[account(mut)]
+    pub parent_market: Option<Account<'info, Market>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundPosition<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"position", market.key().as_ref(), owner.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// Token markets: `escrow_vault`, `token_mint`, `owner_token_account`, `token_program`
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`; the refund goes to `owner`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowlistRoot<'info> {
+    #[account(mut, has_one = creator)]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(session_key: Pubkey)]
+pub struct CreateSession<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + SessionAuthority::INIT_SPACE,
+        seeds = [b"session", owner.key().as_ref(), session_key.as_ref()],
+        bump
+    )]
+    pub session: Account<'info, SessionAuthority>,
+
+    #[account(mut, token::authority = owner)]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    #[account(mut, close = owner, has_one = owner, has_one = token_account)]
+    pub session: Account<'info, SessionAuthority>,
+
+    /// The token account the session was approved on
+    #[account(mut)]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseBetLog<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = depositor,
+        has_one = market,
+        has_one = depositor
+    )]
+    pub bet_log: Account<'info, BetLog>,
+
+    /// CHECK: Rent destination, matched against `bet_log.depositor`
+    #[account(mut)]
+    pub depositor: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLedgerPage<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = payer,
+        has_one = market,
+        has_one = payer
+    )]
+    pub ledger_page: AccountLoader<'info, BetLedgerPage>,
+
+    /// CHECK: Rent destination, matched against `ledger_page.payer`
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = market,
+        has_one = owner
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    /// CHECK: Rent destination, matched against `position.owner`
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseReferral<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = payer,
+        has_one = market,
+        has_one = payer
+    )]
+    pub referral: Account<'info, Referral>,
+
+    /// CHECK: Rent destination, matched against `referral.payer`
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseResolutionJob<'info> {
+    #[account(has_one = creator)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = creator,
+        has_one = market,
+        seeds = [b"rqueue", market.key().as_ref()],
+        bump = resolution_job.bump
+    )]
+    pub resolution_job: Account<'info, ResolutionJob>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEscrow<'info> {
+    #[account(has_one = creator)]
+    pub market: Account<'info, Market>,
+
+    /// Token markets: `escrow_vault`, `token_program`, plus `token_mint` and
+    /// `creator_token_account` to sweep any dust left in the escrow
+    #[account(mut, address = market.escrow_vault)]
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = market.token_mint)]
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub creator_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Native SOL markets: `sol_vault`
+    #[account(mut, address = market.escrow_vault)]
+    pub sol_vault: Option<Account<'info, SolVault>>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMarket<'info> {
+    #[account(mut, close = creator, has_one = creator, has_one = escrow_vault)]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Must already be closed
+    pub escrow_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Must already be closed (or never created)
+    #[account(seeds = [b"rqueue", market.key().as_ref()], bump)]
+    pub resolution_job: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarket<'info> {
+    /// CHECK: A version 0 market does not deserialize; checked in the handler
+    #[account(mut)]
+    pub market: UncheckedAccount<'info>,
+
+    /// Version 0 markets: their token escrow, read for the mint
+    pub escrow_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// ========== STATE ==========
+
+/// Maximum number of times a resolution job may be started before it is stuck
+pub const MAX_JOB_ATTEMPTS: u8 = 3;
+
+/// Maximum size of an inline encrypted bet blob
+pub const MAX_BLOB_LEN: usize = 512;
+
+/// Maximum length of an off-chain blob store base URI
+pub const MAX_BLOB_STORE_URI_LEN: usize = 128;
+
+/// Basis-point denominator for penalties and fees
+pub const MAX_BPS: u16 = 10_000;
+
+/// Protocol cut of every settled pool
+pub const PROTOCOL_FEE_BPS: u16 = 50;
+
+/// Upper bound on a market's creator cut
+pub const MAX_CREATOR_FEE_BPS: u16 = 500;
+
+/// Upper bound on a market's referrer cut
+pub const MAX_REFERRER_FEE_BPS: u16 = 100;
+
+/// Owner of the token accounts (or the wallet, for native SOL) that receive
+/// the protocol cut
+pub const PROTOCOL_TREASURY: Pubkey = anchor_lang::solana_program::pubkey!("6m1YoeYrh35gqxQpJgm7Yto214ue1vSYnbBeRVyYQkvM");
+
+/// Markets a single session key may bet on
+pub const MAX_SESSION_MARKETS: usize = 8;
+
+/// Entries per bet ledger page (keeps a page under the 10 KiB CPI allocation limit)
+pub const LEDGER_PAGE_CAPACITY: usize = 80;
+
+/// Legs per parlay; a pool tracks the stake on each of the 2^legs outcome sets
+pub const MAX_PARLAY_LEGS: usize = 4;
+
+/// Tags per market, and bytes per tag
+pub const MAX_TAGS: usize = 5;
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Upper bound on a market's off-chain metadata URI
+pub const MAX_METADATA_URI_LEN: usize = 200;
+
+/// Layout version stamped on every account at creation. Markets, bet logs
+/// and resolution jobs created before versioning count as version 0 until
+/// `migrate_market` brings them up.
+pub const ACCOUNT_VERSION: u8 = 1;
+
+/// Spare bytes after `version`, for fields added later without a realloc
+pub const ACCOUNT_RESERVED_LEN: usize = 63;
+
+/// Fields appended to `Market` after `version`, out of its reserved bytes:
+/// `referral_count`, `closed_referral_count`, `open_child_count`,
+/// `penalty_pool`, `open_parlay_count`
+pub const MARKET_APPENDED_LEN: usize =
+    4 * std::mem::size_of::<u32>() + std::mem::size_of::<u64>();
+
+/// Fields appended to `SessionAuthority` after `version`: `token_account`
+pub const SESSION_APPENDED_LEN: usize = std::mem::size_of::<Pubkey>();
+
+/// Spare bytes on bet logs, positions, referrals and parlay tickets; kept small since
+/// every bettor pays rent for them
+pub const BETTOR_ACCOUNT_RESERVED_LEN: usize = 4;
+
+#[account]
+#[derive(InitSpace)]
+pub struct Market {
+    pub creator: Pubkey,
+    #[max_len(280)]
+    pub question: String,
+    pub deadline: i64,
+    pub mxe_program_id: Pubkey,
+    /// Key of the MXE cluster's settlement crank: the only signer allowed to
+    /// settle and to report resolution job progress
+    pub resolver: Pubkey,
+    /// Token escrow, or the `SolVault` of a native SOL market
+    pub escrow_vault: Pubkey,
+    /// SPL Token or Token-2022 mint of the escrow; default for native SOL markets
+    pub token_mint: Pubkey,
+    pub total_pool: u64,
+    pub state: MarketState,
+    pub result_hash: [u8; 32],
+    pub bump: u8,
+    pub bet_count: u64,
+    pub closed_bet_count: u64,
+    pub bet_storage: BetStorage,
+    /// Base URI of the off-chain blob store; blobs live at `<uri>/<hex(blob_hash)>`
+    #[max_len(128)]
+    pub blob_store_uri: String,
+    pub ledger_page_count: u32,
+    pub closed_ledger_page_count: u32,
+    /// Share of the stake kept in the pool when a bet is withdrawn or reduced
+    pub withdrawal_penalty_bps: u16,
+    pub stake_limits: StakeLimits,
+    pub position_count: u32,
+    pub closed_position_count: u32,
+    /// Betting opens at `open_time` and closes at `deadline`
+    pub open_time: i64,
+    /// Earliest resolution time, for events that happen after betting closes;
+    /// `None` means resolution may start at the deadline
+    pub resolve_after: Option<i64>,
+    pub collateral: Collateral,
+    /// Merkle root of the wallets allowed to bet; `None` for a public market
+    pub allowlist_root: Option<[u8; 32]>,
+    /// Creator's cut of the pool at settlement, up to `MAX_CREATOR_FEE_BPS`
+    pub creator_fee_bps: u16,
+    /// Referrer's cut of each referred stake, up to `MAX_REFERRER_FEE_BPS`
+    pub referrer_fee_bps: u16,
+    /// Each bet mints a receipt token; refunds and payouts go to its holder
+    pub receipt_tokens: bool,
+    /// Receipt markets: Merkle root of `payout_leaf(bet_index, amount)` for
+    /// every bet, set at settlement
+    pub payout_root: [u8; 32],
+    /// Set for LMSR markets, which sell shares instead of taking bets
+    pub lmsr: Option<LmsrState>,
+    /// Outcome that won, set at settlement; `None` once settled means the
+    /// market was void and refunded every stake
+    pub winning_outcome: Option<u8>,
+    /// Escrowed at creation and split evenly across outcomes; not a bet
+    pub subsidy: u64,
+    /// Funded the subsidy; the creator unless a sponsor signed
+    pub subsidy_sponsor: Pubkey,
+    /// Subsidy owed back to the sponsor, set at settlement or cancellation
+    pub subsidy_refund: u64,
+    /// Weights winners by how early they bet; `None` weights by stake alone
+    pub time_decay: Option<TimeDecay>,
+    /// Conditional markets: pays out only if the parent settles to this outcome,
+    /// and is void otherwise
+    pub condition: Option<ParentCondition>,
+    /// Set for instances spawned from a `MarketTemplate`
+    pub template: Option<TemplateInstance>,
+    /// Fixed at creation, so the rules cannot change once bets are placed
+    pub metadata: MarketMetadata,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub referral_count: u32,
+    pub closed_referral_count: u32,
+    /// Conditional markets on this one that are not yet final; this market
+    /// cannot close before them
+    pub open_child_count: u32,
+    /// Withdrawal penalties kept in escrow. They are pool money for the bets
+    /// that stay: the MXE pays them out with the pool. A market cancelled
+    /// without bets releases them to the creator.
+    pub penalty_pool: u64,
+    /// Parlay pools with this market as a leg that are not yet settled; this
+    /// market cannot close before them
+    pub open_parlay_count: u32,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN - MARKET_APPENDED_LEN],
+}
+
+// Appended fields have to fit in `reserved`, keeping markets at their version 1
+// size: no realloc, and never the size `read_v0` takes for version 0
+const _: () = assert!(Market::INIT_SPACE == 1414);
+
+impl Market {
+    /// Seed after `"market"` in the market's address: its creator, or
+    /// `template_market_seed` for a template instance
+    pub fn seed(&self) -> [u8; 32] {
+        match &self.template {
+            Some(instance) => template_market_seed(&instance.template, instance.index),
+            None => self.creator.to_bytes(),
+        }
+    }
+
+    /// Settled or cancelled: no more funds move and accounts may be closed
+    pub fn is_finalized(&self) -> bool {
+        self.state == MarketState::Settled || self.state == MarketState::Cancelled
+    }
+
+    /// Bets that still hold a stake; withdrawn and closed bet logs are excluded
+    pub fn live_bet_count(&self) -> u64 {
+        self.bet_count - self.closed_bet_count
+    }
+
+    /// Settled without a winner because its parent condition failed
+    pub fn is_void(&self) -> bool {
+        self.state == MarketState::Settled && self.winning_outcome.is_none()
+    }
+
+    /// Ledger page the next bet is appended to
+    pub fn current_ledger_page(&self) -> u32 {
+        (self.bet_count / LEDGER_PAGE_CAPACITY as u64) as u32
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct BetLog {
+    pub market: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    /// Empty for off-chain storage
+    #[max_len(512)]
+    pub encrypted_blob: Vec<u8>,
+    pub timestamp: i64,
+    pub choice_hint: u8,
+    pub bump: u8,
+    /// SHA-256 of the off-chain ciphertext; zero for on-chain storage
+    pub blob_hash: [u8; 32],
+    /// Bumped on every amendment; the MXE keeps only the latest revision
+    pub revision: u8,
+    /// Earns the market's referrer cut of this bet's stake
+    pub referrer: Option<Pubkey>,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; BETTOR_ACCOUNT_RESERVED_LEN],
+}
+
+impl BetLog {
+    /// Allocated size without the discriminator. Off-chain bets skip the blob
+    /// buffer and unreferred bets the referrer key; neither changes after deposit.
+    pub fn space(storage: &BetStorage, referred: bool) -> usize {
+        let blob = match storage {
+            BetStorage::OnChain => 0,
+            BetStorage::OffChain | BetStorage::Ledger => MAX_BLOB_LEN,
+        };
+        let referrer = if referred { 0 } else { 32 };
+        BetLog::INIT_SPACE - blob - referrer
+    }
+}
+
+/// Optional stake bounds set at market creation; `None` means unbounded
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, InitSpace)]
+pub struct StakeLimits {
+    pub min_stake: Option<u64>,
+    pub max_stake: Option<u64>,
+    /// Cap on one wallet's combined live stake, tracked in its `UserPosition`
+    pub max_wallet_exposure: Option<u64>,
+}
+
+impl StakeLimits {
+    fn validate(&self) -> Result<()> {
+        if let (Some(min), Some(max)) = (self.min_stake, self.max_stake) {
+            require!(min <= max, ErrorCode::InvalidStakeLimits);
+        }
+        if let (Some(min), Some(cap)) = (self.min_stake, self.max_wallet_exposure) {
+            require!(min <= cap, ErrorCode::InvalidStakeLimits);
+        }
+        Ok(())
+    }
+
+    /// Enforce the per-bet bounds and return the wallet's new total stake
+    fn check(&self, total_staked: u64, previous: u64, amount: u64) -> Result<u64> {
+        if let Some(min) = self.min_stake {
+            require!(amount >= min, ErrorCode::StakeBelowMinimum);
+        }
+        if let Some(max) = self.max_stake {
+            require!(amount <= max, ErrorCode::StakeAboveMaximum);
+        }
+
+        let total_staked = (total_staked - previous).checked_add(amount).unwrap();
+        if let Some(cap) = self.max_wallet_exposure {
+            require!(total_staked <= cap, ErrorCode::WalletExposureExceeded);
+        }
+        Ok(total_staked)
+    }
+}
+
+/// A wallet's combined live stake on one market
+#[account]
+#[derive(InitSpace)]
+pub struct UserPosition {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub total_staked: u64,
+    pub bump: u8,
+    /// LMSR markets: shares held per outcome (NO, YES)
+    pub shares: [u64; 2],
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; BETTOR_ACCOUNT_RESERVED_LEN],
+}
+
+/// A referrer's combined live referred stake on one market, which caps its
+/// fee at settlement
+#[account]
+#[derive(InitSpace)]
+pub struct Referral {
+    pub market: Pubkey,
+    pub referrer: Pubkey,
+    /// Paid the rent on the first referred bet; refunded on close
+    pub payer: Pubkey,
+    pub referred_stake: u64,
+    pub bump: u8,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; BETTOR_ACCOUNT_RESERVED_LEN],
+}
+
+/// Append-only page of fixed-size bet records for `BetStorage::Ledger` markets.
+/// Page `n` holds bets `n * LEDGER_PAGE_CAPACITY ..`, so resolving a market reads
+/// `ceil(bet_count / LEDGER_PAGE_CAPACITY)` accounts instead of `bet_count`.
+#[account(zero_copy)]
+pub struct BetLedgerPage {
+    pub market: Pubkey,
+    pub payer: Pubkey,
+    pub page_index: u32,
+    pub entry_count: u32,
+    pub entries: [LedgerEntry; LEDGER_PAGE_CAPACITY],
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN],
+}
+
+#[zero_copy]
+pub struct LedgerEntry {
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub blob_hash: [u8; 32],
+    pub timestamp: i64,
+    /// Earns the market's referrer cut of this bet's stake; zero if unreferred
+    pub referrer: Pubkey,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ResolutionJob {
+    pub market: Pubkey,
+    pub status: JobStatus,
+    pub callback_account: Pubkey,
+    pub timestamp: i64,
+    pub bump: u8,
+    pub attempts: u8,
+    pub last_error: u32,
+    pub updated_at: i64,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum MarketState {
+    Open,
+    Enqueued,
+    Settling,
+    Settled,
+    Cancelled,
+}
+
+/// An ephemeral key a wallet lets bet on its behalf. Bets placed with it are
+/// recorded for `owner`; the session itself is the token delegate that moves
+/// the stake, so it only works on token markets.
+#[account]
+#[derive(InitSpace)]
+pub struct SessionAuthority {
+    pub owner: Pubkey,
+    pub session_key: Pubkey,
+    pub expires_at: i64,
+    /// Total stake the session may ever move
+    pub spend_cap: u64,
+    pub spent: u64,
+    #[max_len(8)]
+    pub allowed_markets: Vec<Pubkey>,
+    pub bump: u8,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    /// Token account whose delegate the session is
+    pub token_account: Pubkey,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN - SESSION_APPENDED_LEN],
+}
+
+// Sessions keep their version 1 size too
+const _: () = assert!(SessionAuthority::INIT_SPACE == 413);
+
+/// Lamport escrow of a native SOL market; every lamport above rent is pool
+#[account]
+#[derive(InitSpace)]
+pub struct SolVault {
+    pub market: Pubkey,
+    pub bump: u8,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN],
+}
+
+/// A market that recurs on a fixed cadence. `spawn_market` opens instance
+/// `spawned` once the previous instance has stopped taking bets.
+#[account]
+#[derive(InitSpace)]
+pub struct MarketTemplate {
+    pub creator: Pubkey,
+    pub template_id: u32,
+    /// Question of every instance; `{n}` is replaced by the instance number
+    #[max_len(280)]
+    pub question_pattern: String,
+    /// Betting window of each instance, ending at its deadline
+    pub duration: i64,
+    /// Time between consecutive instance deadlines; at least `duration`
+    pub cadence: i64,
+    /// Markets are binary, so always 2
+    pub outcome_count: u8,
+    pub creator_fee_bps: u16,
+    pub oracle: OracleConfig,
+    /// Copied to every instance
+    pub metadata: MarketMetadata,
+    pub collateral: Collateral,
+    /// Default for native SOL templates
+    pub token_mint: Pubkey,
+    /// Deadline of the next instance; moved past the current time if it is
+    /// spawned late
+    pub next_deadline: i64,
+    /// Instances spawned so far, and the number of the next one
+    pub spawned: u32,
+    pub bump: u8,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN],
+}
+
+/// Pari-mutuel pool of parlay tickets over a fixed set of markets, paid from
+/// its own escrow rather than the legs' pools
+#[account]
+#[derive(InitSpace)]
+pub struct ParlayPool {
+    /// Leg markets in ascending key order; bit `i` of an outcome set is the
+    /// outcome picked on leg `i`
+    #[max_len(4)]
+    pub legs: Vec<Pubkey>,
+    pub token_mint: Pubkey,
+    pub escrow_vault: Pubkey,
+    /// Earliest leg deadline; tickets are placed before it
+    pub deadline: i64,
+    /// Stake on each outcome set
+    pub stakes: [u64; 16],
+    pub total_staked: u64,
+    pub settled: bool,
+    /// Outcome set the legs settled to; `None` once settled means void, and
+    /// every ticket gets its stake back
+    pub winning_outcomes: Option<u8>,
+    pub bump: u8,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN],
+}
+
+/// A wallet's ticket in a parlay pool
+#[account]
+#[derive(InitSpace)]
+pub struct Parlay {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    /// Outcome set picked; bit `i` is the outcome on leg `i`
+    pub outcomes: u8,
+    pub stake: u64,
+    pub bump: u8,
+    /// `ACCOUNT_VERSION` when created
+    pub version: u8,
+    pub reserved: [u8; BETTOR_ACCOUNT_RESERVED_LEN],
+}
+
+/// What a market's stakes are denominated in
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum Collateral {
+    /// SPL Token or Token-2022 `token_mint`, held in the escrow token account
+    Token,
+    /// Lamports, held in the market's `SolVault`; no wrapping needed
+    NativeSol,
+}
+
+/// Where encrypted bet blobs are kept
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum BetStorage {
+    /// Full ciphertext in `BetLog.encrypted_blob`
+    OnChain,
+    /// Ciphertext in an external store, only its hash in `BetLog.blob_hash`
+    OffChain,
+    /// Ciphertext in an external store, hash appended to paged `BetLedgerPage`s
+    Ledger,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Payout {
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+/// The MXE's `MarketResult`, the JSON `callback_settle` receives as `mxe_result`.
+/// Keys and the payout root are hex encoded.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MxeResult {
+    pub market_id: String,
+    pub winning_choice: u8,
+    /// Stakes plus the subsidy in play
+    pub total_pool: u64,
+    #[serde(default)]
+    pub subsidy_refund: u64,
+    pub fee_amount: u64,
+    pub protocol_fee: u64,
+    pub creator_fee: u64,
+    pub referral_fees: Vec<MxePayout>,
+    /// One per bet, in the order of the MXE input
+    pub payouts: Vec<MxePayout>,
+    /// Receipt markets only
+    #[serde(default)]
+    pub payout_root: Option<String>,
+    #[serde(default)]
+    pub void: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MxePayout {
+    pub recipient: String,
+    pub payout: u64,
+}
+
+impl MxeResult {
+    /// `None` for a void result, which refunds every stake
+    fn winning_outcome(&self) -> Result<Option<u8>> {
+        if self.void {
+            return Ok(None);
+        }
+        require!(self.winning_choice < 2, ErrorCode::InvalidOutcome);
+        Ok(Some(self.winning_choice))
+    }
+
+    /// The nonzero payouts, in result order
+    fn payouts(&self) -> Result<Vec<Payout>> {
+        self.payouts
+            .iter()
+            .filter(|payout| payout.payout > 0)
+            .map(|payout| {
+                Ok(Payout {
+                    recipient: Pubkey::new_from_array(decode_hex(&payout.recipient)?),
+                    amount: payout.payout,
+                })
+            })
+            .collect()
+    }
+
+    /// The fee split, with the nonzero referral cuts in result order. The cuts
+    /// must add up to `fee_amount` and name each referrer once.
+    fn fees(&self) -> Result<FeeSplit> {
+        let mut referrals: Vec<Payout> = Vec::new();
+        for fee in self.referral_fees.iter().filter(|fee| fee.payout > 0) {
+            let recipient = Pubkey::new_from_array(decode_hex(&fee.recipient)?);
+            require!(
+                referrals
+                    .iter()
+                    .all(|referral| referral.recipient != recipient),
+                ErrorCode::InvalidMxeResult
+            );
+            referrals.push(Payout {
+                recipient,
+                amount: fee.payout,
+            });
+        }
+        let total = referrals
+            .iter()
+            .try_fold(self.protocol_fee, |total, referral| {
+                total.checked_add(referral.amount)
+            })
+            .and_then(|total| total.checked_add(self.creator_fee));
+        require!(total == Some(self.fee_amount), ErrorCode::InvalidMxeResult);
+        Ok(FeeSplit {
+            protocol: self.protocol_fee,
+            creator: self.creator_fee,
+            referrals,
+        })
+    }
+
+    /// Receipt markets settle to the root over their payout leaves
+    fn payout_root(&self) -> Result<[u8; 32]> {
+        let root = self
+            .payout_root
+            .as_deref()
+            .ok_or_else(|| error!(ErrorCode::InvalidMxeResult))?;
+        decode_hex(root)
+    }
+}
+
+/// Fee cuts computed by the MXE, paid out of escrow by `callback_settle`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeeSplit {
+    pub protocol: u64,
+    pub creator: u64,
+    /// Per referrer wallet, summed over its referred bets
+    pub referrals: Vec<Payout>,
+}
+
+/// Early-bettor weighting: the MXE splits winnings by stake times a weight
+/// that starts at full and falls linearly, from `full_weight_secs` after
+/// `open_time`, to `min_weight_bps` at the deadline
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct TimeDecay {
+    /// Bets this soon after `open_time` keep their full stake as weight
+    pub full_weight_secs: u32,
+    /// Weight of a bet placed at the deadline, in bps of its stake
+    pub min_weight_bps: u16,
+}
+
+impl TimeDecay {
+    fn validate(&self, open_time: i64, deadline: i64) -> Result<()> {
+        require!(
+            self.min_weight_bps > 0 && self.min_weight_bps <= MAX_BPS,
+            ErrorCode::InvalidTimeDecay
+        );
+        require!(
+            open_time.saturating_add(self.full_weight_secs as i64) < deadline,
+            ErrorCode::InvalidTimeDecay
+        );
+        Ok(())
+    }
+}
+
+/// The parent market a conditional market depends on, and the outcome the
+/// parent must settle to for bets on this one to count
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct ParentCondition {
+    pub market: Pubkey,
+    pub outcome: u8,
+}
+
+/// How a template's instances are resolved
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct OracleConfig {
+    pub mxe_program_id: Pubkey,
+    pub resolver: Pubkey,
+    /// Seconds after the deadline before resolution may start; `None`
+    /// resolves at the deadline
+    pub resolve_delay: Option<i64>,
+}
+
+/// The template a market was spawned from, and its instance number
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct TemplateInstance {
+    pub template: Pubkey,
+    pub index: u32,
+}
+
+/// Descriptive data beyond the question. The description and the full
+/// resolution rules live in the document at `metadata_uri`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct MarketMetadata {
+    pub category: Category,
+    /// Up to `MAX_TAGS` non-empty tags of at most `MAX_TAG_LEN` bytes
+    #[max_len(5, 32)]
+    pub tags: Vec<String>,
+    /// Empty when there is no off-chain document
+    #[max_len(200)]
+    pub metadata_uri: String,
+    /// SHA-256 of the resolution rules document
+    pub rules_hash: [u8; 32],
+}
+
+impl MarketMetadata {
+    fn validate(&self) -> Result<()> {
+        require!(
+            self.tags.len() <= MAX_TAGS
+                && self
+                    .tags
+                    .iter()
+                    .all(|tag| !tag.is_empty() && tag.len() <= MAX_TAG_LEN)
+                && self.metadata_uri.len() <= MAX_METADATA_URI_LEN,
+            ErrorCode::InvalidMetadata
+        );
+        Ok(())
+    }
+}
+
+/// Topic a market is listed under
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub enum Category {
+    #[default]
+    Other,
+    Crypto,
+    Politics,
+    Sports,
+    Economics,
+    Science,
+    Entertainment,
+}
+
+/// Market maker state of an LMSR market
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct LmsrState {
+    /// Liquidity parameter `b`; the creator funds `lmsr_funding(b)` up front
+    pub liquidity: u64,
+    /// Shares sold per outcome (NO, YES)
+    pub outstanding: [u64; 2],
+}
+
+// ========== EVENTS ==========
+
+#[event]
+pub struct MarketCreated {
+    pub market: Pubkey,
+    pub creator: Pubkey,
+    pub token_mint: Pubkey,
+    pub collateral: Collateral,
+    pub question: String,
+    pub deadline: i64,
+    pub mxe_program_id: Pubkey,
+    pub resolver: Pubkey,
+    pub bet_storage: BetStorage,
+    pub open_time: i64,
+    pub resolve_after: Option<i64>,
+    pub allowlist_root: Option<[u8; 32]>,
+    pub receipt_tokens: bool,
+    pub lmsr_liquidity: Option<u64>,
+    pub subsidy: u64,
+    pub subsidy_sponsor: Pubkey,
+    pub time_decay: Option<TimeDecay>,
+    pub condition: Option<ParentCondition>,
+    pub metadata: MarketMetadata,
+}
+
+#[event]
+pub struct MarketTemplateCreated {
+    pub template: Pubkey,
+    pub creator: Pubkey,
+    pub question_pattern: String,
+    pub duration: i64,
+    pub cadence: i64,
+    pub first_deadline: i64,
+}
+
+/// Emitted with `MarketCreated` for each template instance
+#[event]
+pub struct MarketSpawned {
+    pub template: Pubkey,
+    pub market: Pubkey,
+    pub index: u32,
+}
+
+/// Emitted per bet. Carries the bet index only; the choice stays encrypted.
+#[event]
+pub struct BetDeposited {
+    pub market: Pubkey,
+    pub bet_log: Pubkey,
+    pub depositor: Pubkey,
+    pub bet_index: u64,
+    pub amount: u64,
+    pub total_pool: u64,
+    pub timestamp: i64,
+    /// Zero for on-chain storage
+    pub blob_hash: [u8; 32],
+}
+
+#[event]
+pub struct BetWithdrawn {
+    pub market: Pubkey,
+    pub bet_log: Pubkey,
+    pub depositor: Pubkey,
+    pub refund: u64,
+    pub penalty: u64,
+    pub total_pool: u64,
+}
+
+/// Emitted when a bet's blob and stake are replaced. Like `BetDeposited`, never the choice.
+#[event]
+pub struct BetAmended {
+    pub market: Pubkey,
+    pub bet_log: Pubkey,
+    pub depositor: Pubkey,
+    pub revision: u8,
+    pub amount: u64,
+    pub total_pool: u64,
+    /// Zero for on-chain storage
+    pub blob_hash: [u8; 32],
+}
+
+#[event]
+pub struct ResolutionEnqueued {
+    pub market: Pubkey,
+    pub resolution_job: Pubkey,
+    pub bet_count: u64,
+    pub total_pool: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MarketSettled {
+    pub market: Pubkey,
+    /// `None` for a void conditional market
+    pub winning_outcome: Option<u8>,
+    pub result_hash: [u8; 32],
+    pub total_pool: u64,
+    pub total_paid_out: u64,
+    pub payout_count: u32,
+}
+
+#[event]
+pub struct PayoutSent {
+    pub market: Pubkey,
+    pub recipient: Pubkey,
+    pub token_account: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct FeesPaid {
+    pub market: Pubkey,
+    pub protocol_fee: u64,
+    pub creator_fee: u64,
+    pub referral_fees: u64,
+}
+
+#[event]
+pub struct MarketCancelled {
+    pub market: Pubkey,
+    pub creator: Pubkey,
+}
+
+#[event]
+pub struct AllowlistRootUpdated {
+    pub market: Pubkey,
+    pub allowlist_root: Option<[u8; 32]>,
+}
+
+/// LMSR trades are public: outcome, size and price
+#[event]
+pub struct SharesBought {
+    pub market: Pubkey,
+    pub buyer: Pubkey,
+    pub outcome: u8,
+    pub shares: u64,
+    pub cost: u64,
+    /// Shares sold per outcome after the trade
+    pub outstanding: [u64; 2],
+}
+
+#[event]
+pub struct StuckMarketVoided {
+    pub market: Pubkey,
+    pub attempts: u8,
+    pub last_error: u32,
+}
+
+#[event]
+pub struct PositionRefunded {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct SharesRedeemed {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub shares: u64,
+}
+
+#[event]
+pub struct SubsidyReclaimed {
+    pub market: Pubkey,
+    pub sponsor: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ParlayPoolCreated {
+    pub pool: Pubkey,
+    pub legs: Vec<Pubkey>,
+    pub token_mint: Pubkey,
+    pub deadline: i64,
+}
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+#[event]
+pub struct ParlayPlaced {
+    pub pool: Pubkey,
+    pub parlay: Pubkey,
+    pub owner: Pubkey,
+    pub outcomes: u8,
+    pub stake: u64,
 }
 
-// ========== STATE ==========
+/// `winning_outcomes` is `None` for a void pool, which refunds every ticket
+#[event]
+pub struct ParlaySettled {
+    pub pool: Pubkey,
+    pub winning_outcomes: Option<u8>,
+    pub winning_stake: u64,
+    pub total_staked: u64,
+}
 
-#[account]
-#[derive(InitSpace)]
-pub struct Market {
-    pub creator: Pubkey,
-    #[max_len(280)]
-    pub question: String,
-    pub deadline: i64,
-    pub mxe_program_id: Pubkey,
-    pub escrow_vault: Pubkey,
-    pub total_pool: u64,
-    pub state: MarketState,
-    pub result_hash: [u8; 32],
-    pub bump: u8,
-    pub bet_count: u64,
+#[event]
+pub struct ParlayClaimed {
+    pub pool: Pubkey,
+    pub parlay: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct BetLog {
+#[event]
+pub struct ReceiptRedeemed {
     pub market: Pubkey,
-    pub depositor: Pubkey,
+    pub bet_log: Pubkey,
+    pub bet_index: u64,
+    pub holder: Pubkey,
     pub amount: u64,
-    #[max_len(512)]
-    pub encrypted_blob: Vec<u8>,
-    pub timestamp: i64,
-    pub choice_hint: u8,
-    pub bump: u8,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct ResolutionJob {
-    pub market: Pubkey,
-    pub status: JobStatus,
-    pub callback_account: Pubkey,
-    pub timestamp: i64,
-    pub bump: u8,
+#[event]
+pub struct SessionCreated {
+    pub owner: Pubkey,
+    pub session_key: Pubkey,
+    pub expires_at: i64,
+    pub spend_cap: u64,
+    pub allowed_markets: Vec<Pubkey>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
-pub enum MarketState {
-    Open,
-    Enqueued,
-    Settling,
-    Settled,
-    Cancelled,
+#[event]
+pub struct SessionRevoked {
+    pub owner: Pubkey,
+    pub session_key: Pubkey,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
-pub enum JobStatus {
-    Pending,
-    Running,
-    Completed,
-    Failed,
+#[event]
+pub struct MarketClosed {
+    pub market: Pubkey,
+    pub creator: Pubkey,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct Payout {
-    pub recipient: Pubkey,
-    pub amount: u64,
+#[event]
+pub struct MarketMigrated {
+    pub market: Pubkey,
+    /// Accounts brought up to `version`; already current ones are not counted
+    pub migrated: u32,
+    pub version: u8,
 }
 
 // ========== ERRORS ==========
@@ -383,10 +3935,779 @@ pub enum ErrorCode {
     Unauthorized,
     #[msg("MXE signature verification failed")]
     InvalidMXESignature,
+    #[msg("Invalid resolution job status for this operation")]
+    InvalidJobStatus,
+    #[msg("Resolution job has reached its maximum number of attempts")]
+    MaxJobAttemptsExceeded,
+    #[msg("Market must be settled or cancelled")]
+    MarketNotFinalized,
+    #[msg("Escrow still owes a subsidy refund, receipt redemptions, LMSR liquidity or withdrawal penalties")]
+    EscrowNotEmpty,
+    #[msg("Bet logs, resolution job or escrow are still open")]
+    AccountsStillOpen,
+    #[msg("Blob store URI is invalid for this storage mode")]
+    InvalidBlobStore,
+    #[msg("Bet storage mode does not match the market")]
+    WrongBetStorage,
+    #[msg("Ledger pages must be opened in order")]
+    InvalidLedgerPage,
+    #[msg("Withdrawal penalty exceeds 10000 basis points")]
+    InvalidWithdrawalPenalty,
+    #[msg("Bet has reached the maximum number of amendments")]
+    TooManyAmendments,
+    #[msg("Minimum stake exceeds the maximum stake or wallet cap")]
+    InvalidStakeLimits,
+    #[msg("Stake is below the market minimum")]
+    StakeBelowMinimum,
+    #[msg("Stake is above the market maximum")]
+    StakeAboveMaximum,
+    #[msg("Wallet exposure cap exceeded")]
+    WalletExposureExceeded,
+    #[msg("Timeline must satisfy open_time < deadline <= resolve_after")]
+    InvalidTimeline,
+    #[msg("Betting has not opened yet")]
+    BettingNotOpen,
+    #[msg("Resolution time not yet reached")]
+    ResolveTimeNotReached,
+    #[msg("Accounts do not match the market's collateral")]
+    CollateralAccountsMismatch,
+    #[msg("Wallet is not on the market allowlist")]
+    NotAllowlisted,
+    #[msg("Allowlist cannot change once betting has started")]
+    AllowlistLocked,
+    #[msg("Creator or referrer fee exceeds the protocol limit")]
+    InvalidFeeBps,
+    #[msg("Fee exceeds the market's rate")]
+    FeeExceedsLimit,
+    #[msg("Fee account does not belong to the fee recipient")]
+    FeeRecipientMismatch,
+    #[msg("A wallet cannot refer its own bet")]
+    SelfReferral,
+    #[msg("Signer is neither the bettor nor a delegate of its token account")]
+    InvalidTransferAuthority,
+    #[msg("Session expiry must be in the future")]
+    InvalidSessionExpiry,
+    #[msg("A session needs between 1 and 8 allowed markets")]
+    InvalidSessionMarkets,
+    #[msg("Session has expired")]
+    SessionExpired,
+    #[msg("Session is not allowed on this market")]
+    SessionMarketNotAllowed,
+    #[msg("Bet exceeds the session's spending cap")]
+    SessionCapExceeded,
+    #[msg("Receipts need token collateral and a bet log per bet")]
+    ReceiptsUnsupported,
+    #[msg("Receipt accounts do not match the market's receipt mode")]
+    ReceiptAccountsMismatch,
+    #[msg("Receipt bets only move through redeem_receipt")]
+    ReceiptRequired,
+    #[msg("Signer does not hold the bet's receipt")]
+    ReceiptNotHeld,
+    #[msg("Payout proof does not match the market's payout root")]
+    InvalidPayoutProof,
+    #[msg("MXE result is malformed")]
+    InvalidMxeResult,
+    #[msg("Not available for this market's pricing mode")]
+    WrongMarketKind,
+    #[msg("LMSR liquidity must be greater than zero")]
+    InvalidLiquidity,
+    #[msg("Outcome must be 0 (NO) or 1 (YES)")]
+    InvalidOutcome,
+    #[msg("Trade costs more than the slippage limit")]
+    SlippageExceeded,
+    #[msg("LMSR markets need collateral without a transfer fee")]
+    TransferFeeUnsupported,
+    #[msg("Winning shares have not all been redeemed")]
+    SharesOutstanding,
+    #[msg("Subsidy refund exceeds the market's subsidy")]
+    InvalidSubsidyRefund,
+    #[msg("Time decay needs a weight of 1..=10000 bps and must start before the deadline")]
+    InvalidTimeDecay,
+    #[msg("A market cannot be conditioned on itself")]
+    InvalidCondition,
+    #[msg("Parent market account does not match the market's condition")]
+    ParentMarketMismatch,
+    #[msg("Parent market must be settled or cancelled first")]
+    ParentNotFinalized,
+    #[msg("Settle with a winning outcome exactly when the parent condition holds")]
+    WinningOutcomeMismatch,
+    #[msg("A parlay needs 2 to 4 open token markets of one mint, in ascending key order")]
+    InvalidParlayLegs,
+    #[msg("Every parlay leg must be settled or cancelled first")]
+    ParlayLegsNotFinalized,
+    #[msg("Parlay pool is not settled yet")]
+    ParlayNotSettled,
+    #[msg("Markets have exactly 2 outcomes")]
+    InvalidOutcomeCount,
+    #[msg("The template's previous instance is still taking bets")]
+    PreviousInstanceOpen,
+    #[msg("Metadata allows up to 5 tags of 1..=32 bytes and a URI of at most 200 bytes")]
+    InvalidMetadata,
+    #[msg("Not a version 0 or current account of this market")]
+    NotMigratable,
+    #[msg("Payout accounts do not match the MXE result's recipients")]
+    PayoutRecipientMismatch,
+    #[msg("Referred bets need their referrer's referral account; others take none")]
+    ReferralAccountMismatch,
+    #[msg("Token account already has a delegate; spend or revoke its approval first")]
+    DelegateInUse,
+    #[msg("Arithmetic overflow")]
+    Overflow,
+    #[msg("Resolution job has not failed its last allowed attempt")]
+    ResolutionNotStuck,
+    #[msg("Position still holds a stake to refund")]
+    StakeNotRefunded,
 }
 
 // ========== HELPER FUNCTIONS ==========
 
+fn require_betting_open(market: &Market) -> Result<()> {
+    require!(market.state == MarketState::Open, ErrorCode::MarketNotOpen);
+    let now = Clock::get()?.unix_timestamp;
+    require!(now >= market.open_time, ErrorCode::BettingNotOpen);
+    require!(now < market.deadline, ErrorCode::DeadlinePassed);
+    Ok(())
+}
+
+/// Private markets only accept wallets with a Merkle proof against `allowlist_root`
+fn require_allowlisted(market: &Market, wallet: &Pubkey, proof: &[[u8; 32]]) -> Result<()> {
+    if let Some(root) = market.allowlist_root {
+        require!(
+            fold_proof(allowlist_leaf(wallet), proof) == root,
+            ErrorCode::NotAllowlisted
+        );
+    }
+    Ok(())
+}
+
+/// Fold a Merkle proof from `leaf` up to its root
+fn fold_proof(leaf: [u8; 32], proof: &[[u8; 32]]) -> [u8; 32] {
+    proof
+        .iter()
+        .fold(leaf, |node, sibling| allowlist_parent(&node, sibling))
+}
+
+/// Payout tree leaf of a receipt market: `sha256(0x00 || bet_index || amount)`,
+/// little-endian. Inner nodes hash like the allowlist's.
+pub fn payout_leaf(bet_index: u64, amount: u64) -> [u8; 32] {
+    use anchor_lang::solana_program::hash::hashv;
+    hashv(&[&[0u8], &bet_index.to_le_bytes(), &amount.to_le_bytes()]).to_bytes()
+}
+
+/// Address seed of instance `index` of `template`: `sha256(template || index)`,
+/// little-endian
+pub fn template_market_seed(template: &Pubkey, index: u32) -> [u8; 32] {
+    use anchor_lang::solana_program::hash::hashv;
+    hashv(&[template.as_ref(), &index.to_le_bytes()]).to_bytes()
+}
+
+/// Allowlist leaf for `wallet`: `sha256(0x00 || wallet)`
+pub fn allowlist_leaf(wallet: &Pubkey) -> [u8; 32] {
+    use anchor_lang::solana_program::hash::hashv;
+    hashv(&[&[0u8], wallet.as_ref()]).to_bytes()
+}
+
+/// Allowlist inner node: `sha256(0x01 || min(a, b) || max(a, b))`. Sorting the
+/// pair means proofs are plain sibling lists with no left/right flags.
+pub fn allowlist_parent(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    use anchor_lang::solana_program::hash::hashv;
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    hashv(&[&[1u8], left, right]).to_bytes()
+}
+
+/// Unwrap an optional account that the market's collateral requires
+fn required<T>(account: Option<T>) -> Result<T> {
+    account.ok_or_else(|| error!(ErrorCode::CollateralAccountsMismatch))
+}
+
+/// Where a market's stake moves in and out, resolved from its collateral
+enum Escrow<'a, 'info> {
+    /// The SPL escrow and the wallet-side token account of the transfer, if
+    /// there is a single one
+    Token {
+        escrow_vault: &'a mut InterfaceAccount<'info, TokenAccount>,
+        token_mint: &'a InterfaceAccount<'info, Mint>,
+        wallet_tokens: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+        token_program: &'a Interface<'info, TokenInterface>,
+    },
+    /// The lamport vault of a native SOL market
+    Sol(&'a Account<'info, SolVault>),
+}
+
+impl<'a, 'info> Escrow<'a, 'info> {
+    fn new(
+        collateral: Collateral,
+        escrow_vault: Option<&'a mut InterfaceAccount<'info, TokenAccount>>,
+        token_mint: Option<&'a InterfaceAccount<'info, Mint>>,
+        wallet_tokens: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+        token_program: Option<&'a Interface<'info, TokenInterface>>,
+        sol_vault: Option<&'a Account<'info, SolVault>>,
+    ) -> Result<Self> {
+        Ok(match collateral {
+            Collateral::Token => Escrow::Token {
+                escrow_vault: required(escrow_vault)?,
+                token_mint: required(token_mint)?,
+                wallet_tokens,
+                token_program: required(token_program)?,
+            },
+            Collateral::NativeSol => Escrow::Sol(required(sol_vault)?),
+        })
+    }
+}
+
+/// A stake moves on the bettor's own signature, or for token markets on the
+/// signature of a delegate approved on the bettor's token account. The token
+/// program enforces the delegated amount.
+fn require_stake_authority(escrow: &Escrow, user: &Pubkey, authority: &Pubkey) -> Result<()> {
+    if authority == user {
+        return Ok(());
+    }
+    let delegated = match escrow {
+        Escrow::Token { wallet_tokens, .. } => wallet_tokens.is_some_and(|tokens| {
+            tokens.owner == *user && tokens.delegate == COption::Some(*authority)
+        }),
+        Escrow::Sol(_) => false,
+    };
+    require!(delegated, ErrorCode::InvalidTransferAuthority);
+    Ok(())
+}
+
+/// Move a new stake into escrow: through `session` when a session key signs,
+/// otherwise on the signature of the bettor or its token delegate
+fn escrow_stake<'info>(
+    escrow: &mut Escrow<'_, 'info>,
+    session: Option<&mut Account<'info, SessionAuthority>>,
+    market: &Pubkey,
+    user: &Pubkey,
+    authority: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    amount: u64,
+) -> Result<u64> {
+    match session {
+        Some(session) => {
+            spend_session(session, escrow, market, amount)?;
+            let seeds = &[
+                b"session",
+                session.owner.as_ref(),
+                session.session_key.as_ref(),
+                &[session.bump],
+            ];
+            transfer_to_escrow(
+                escrow,
+                &session.to_account_info(),
+                &[&seeds[..]],
+                system_program,
+                amount,
+            )
+        }
+        None => {
+            require_stake_authority(escrow, user, authority.key)?;
+            transfer_to_escrow(escrow, authority, &[], system_program, amount)
+        }
+    }
+}
+
+/// Check a session-key bet against the session's expiry, markets and cap, and
+/// count it against the cap
+fn spend_session(
+    session: &mut SessionAuthority,
+    escrow: &Escrow,
+    market: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    require!(
+        matches!(escrow, Escrow::Token { .. }),
+        ErrorCode::InvalidTransferAuthority
+    );
+    require!(
+        Clock::get()?.unix_timestamp < session.expires_at,
+        ErrorCode::SessionExpired
+    );
+    require!(
+        session.allowed_markets.contains(market),
+        ErrorCode::SessionMarketNotAllowed
+    );
+    session.spent = session
+        .spent
+        .checked_add(amount)
+        .filter(|spent| *spent <= session.spend_cap)
+        .ok_or(ErrorCode::SessionCapExceeded)?;
+    Ok(())
+}
+
+/// Move `amount` into escrow and return what actually arrived. Token-2022 mints
+/// with a transfer fee deliver less than `amount`; stakes record the net amount so
+/// `total_pool` always matches the escrow balance. `signer_seeds` is empty
+/// unless `authority` is a PDA of this program.
+fn transfer_to_escrow<'info>(
+    escrow: &mut Escrow<'_, 'info>,
+    authority: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    system_program: &Program<'info, System>,
+    amount: u64,
+) -> Result<u64> {
+    match escrow {
+        Escrow::Token {
+            escrow_vault,
+            token_mint,
+            wallet_tokens,
+            token_program,
+        } => {
+            let before = escrow_vault.amount;
+            let cpi_accounts = TransferChecked {
+                from: required(*wallet_tokens)?.to_account_info(),
+                mint: token_mint.to_account_info(),
+                to: escrow_vault.to_account_info(),
+                authority: authority.clone(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                cpi_accounts,
+                signer_seeds,
+            );
+            token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)?;
+
+            escrow_vault.reload()?;
+            let received = escrow_vault.amount.checked_sub(before).unwrap();
+            require!(received > 0, ErrorCode::InvalidAmount);
+            Ok(received)
+        }
+        Escrow::Sol(sol_vault) => {
+            let cpi_accounts = system_program::Transfer {
+                from: authority.clone(),
+                to: sol_vault.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, amount)?;
+            Ok(amount)
+        }
+    }
+}
+
+/// Pay `amount` out of escrow: to `wallet`'s token account for token markets,
+/// straight to the `wallet` lamports for native SOL markets.
+fn transfer_from_escrow<'info>(
+    escrow: &Escrow<'_, 'info>,
+    wallet: &AccountInfo<'info>,
+    market: &Account<'info, Market>,
+    amount: u64,
+) -> Result<()> {
+    match escrow {
+        Escrow::Token { wallet_tokens, .. } => {
+            let wallet_tokens = required(*wallet_tokens)?.to_account_info();
+            pay_from_escrow(escrow, &wallet_tokens, market, amount)
+        }
+        Escrow::Sol(_) => pay_from_escrow(escrow, wallet, market, amount),
+    }
+}
+
+/// Pay `amount` out of escrow to `destination`: a token account of the market
+/// mint, or a wallet for native SOL markets
+fn pay_from_escrow<'info>(
+    escrow: &Escrow<'_, 'info>,
+    destination: &AccountInfo<'info>,
+    market: &Account<'info, Market>,
+    amount: u64,
+) -> Result<()> {
+    match escrow {
+        Escrow::Token {
+            escrow_vault,
+            token_mint,
+            token_program,
+            ..
+        } => {
+            let seed = market.seed();
+            let seeds = &[b"market", seed.as_ref(), &[market.bump]];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = TransferChecked {
+                from: escrow_vault.to_account_info(),
+                mint: token_mint.to_account_info(),
+                to: destination.clone(),
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+            token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)
+        }
+        // The vault is owned by this program, so lamports move without a CPI
+        Escrow::Sol(sol_vault) => {
+            sol_vault.sub_lamports(amount)?;
+            destination.add_lamports(amount)?;
+            Ok(())
+        }
+    }
+}
+
+/// `init_if_needed` leaves a fresh position zeroed; claim it for `owner`
+fn open_position_if_new(
+    market: &mut Account<Market>,
+    position: &mut Account<UserPosition>,
+    owner: Pubkey,
+    bump: u8,
+) {
+    if position.owner == Pubkey::default() {
+        position.market = market.key();
+        position.owner = owner;
+        position.total_staked = 0;
+        position.bump = bump;
+        position.shares = [0; 2];
+        position.version = ACCOUNT_VERSION;
+        market.position_count = market.position_count.checked_add(1).unwrap();
+    }
+}
+
+/// A conditional market that goes final without settling lets its parent
+/// close: `parent` has to be the market it was conditioned on
+fn release_parent(market: &Market, parent: Option<&mut Account<Market>>) -> Result<()> {
+    if let Some(condition) = market.condition {
+        let parent = parent.ok_or_else(|| error!(ErrorCode::ParentMarketMismatch))?;
+        require_keys_eq!(
+            parent.key(),
+            condition.market,
+            ErrorCode::ParentMarketMismatch
+        );
+        parent.open_child_count = parent.open_child_count.checked_sub(1).unwrap();
+    }
+    Ok(())
+}
+
+/// Take what a voided market owes for `stake` out of its pool: the stake and
+/// its share of the withdrawal penalties, the last stake taking the remainder
+fn take_void_refund(market: &mut Market, stake: u64) -> u64 {
+    let staked = market.total_pool - market.penalty_pool - market.subsidy_refund;
+    let penalties = if stake == staked {
+        market.penalty_pool
+    } else {
+        (market.penalty_pool as u128 * stake as u128 / staked as u128) as u64
+    };
+    market.penalty_pool -= penalties;
+    market.total_pool = market.total_pool.checked_sub(stake + penalties).unwrap();
+    stake + penalties
+}
+
+/// `init_if_needed` leaves a fresh referral zeroed; claim it for `referrer`
+fn open_referral_if_new(
+    market: &mut Account<Market>,
+    referral: &mut Account<Referral>,
+    referrer: Pubkey,
+    payer: Pubkey,
+    bump: u8,
+) {
+    if referral.referrer == Pubkey::default() {
+        referral.market = market.key();
+        referral.referrer = referrer;
+        referral.payer = payer;
+        referral.referred_stake = 0;
+        referral.bump = bump;
+        referral.version = ACCOUNT_VERSION;
+        market.referral_count = market.referral_count.checked_add(1).unwrap();
+    }
+}
+
+/// Move a bet's referred stake from `previous` to `amount`; a referred bet
+/// must come with its referrer's `Referral`, and only a referred bet may
+fn apply_referral_change(
+    bet_log: &BetLog,
+    referral: Option<&mut Account<Referral>>,
+    previous: u64,
+    amount: u64,
+) -> Result<()> {
+    match (bet_log.referrer, referral) {
+        (None, None) => Ok(()),
+        (Some(_), Some(referral)) => {
+            referral.referred_stake = referral
+                .referred_stake
+                .checked_sub(previous)
+                .and_then(|stake| stake.checked_add(amount))
+                .unwrap();
+            Ok(())
+        }
+        _ => err!(ErrorCode::ReferralAccountMismatch),
+    }
+}
+
+/// Move one bet's stake from `previous` to `amount` within the market's stake limits
+fn apply_stake_change(
+    market: &Market,
+    position: &mut UserPosition,
+    previous: u64,
+    amount: u64,
+) -> Result<()> {
+    position.total_staked = market
+        .stake_limits
+        .check(position.total_staked, previous, amount)?;
+    Ok(())
+}
+
+/// Largest fee a `fee_bps` rate allows on `pool`
+fn fee_cap(pool: u64, fee_bps: u16) -> u64 {
+    (pool as u128 * fee_bps as u128 / MAX_BPS as u128) as u64
+}
+
+fn required_fee_account<'a, 'info>(
+    account: Option<&'a AccountInfo<'info>>,
+) -> Result<&'a AccountInfo<'info>> {
+    account.ok_or_else(|| error!(ErrorCode::FeeRecipientMismatch))
+}
+
+/// Pay a fee cut to `owner`, as `pay_to_owner` does
+fn pay_fee<'info>(
+    escrow: &Escrow<'_, 'info>,
+    destination: &AccountInfo<'info>,
+    owner: &Pubkey,
+    market: &Account<'info, Market>,
+    amount: u64,
+) -> Result<()> {
+    pay_to_owner(
+        escrow,
+        destination,
+        owner,
+        market,
+        amount,
+        ErrorCode::FeeRecipientMismatch,
+    )
+}
+
+/// Pay `amount` to `destination`, which must be `owner`'s token account of the
+/// market mint, or `owner` itself for native SOL markets; `mismatch` otherwise
+fn pay_to_owner<'info>(
+    escrow: &Escrow<'_, 'info>,
+    destination: &AccountInfo<'info>,
+    owner: &Pubkey,
+    market: &Account<'info, Market>,
+    amount: u64,
+    mismatch: ErrorCode,
+) -> Result<()> {
+    match escrow {
+        Escrow::Token { .. } => {
+            // The transfer CPI rejects accounts the token program doesn't own
+            let tokens = TokenAccount::try_deserialize(&mut &destination.try_borrow_data()?[..])?;
+            if tokens.owner != *owner || tokens.mint != market.token_mint {
+                return Err(error!(mismatch));
+            }
+        }
+        Escrow::Sol(_) => require_keys_eq!(destination.key(), *owner, mismatch),
+    }
+    pay_from_escrow(escrow, destination, market, amount)
+}
+
+fn withdrawal_penalty(amount: u64, penalty_bps: u16) -> u64 {
+    (amount as u128 * penalty_bps as u128 / MAX_BPS as u128) as u64
+}
+
+/// Shared by the per-bet deposit modes: escrow the stake and write the bet log
+fn record_bet(
+    ctx: Context<DepositBet>,
+    encrypted_blob: Vec<u8>,
+    blob_hash: [u8; 32],
+    choice: u8,
+    amount: u64,
+    allowlist_proof: &[[u8; 32]],
+) -> Result<()> {
+    require_betting_open(&ctx.accounts.market)?;
+    require_allowlisted(
+        &ctx.accounts.market,
+        &ctx.accounts.user.key(),
+        allowlist_proof,
+    )?;
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    require!(
+        ctx.accounts.market.lmsr.is_none(),
+        ErrorCode::WrongMarketKind
+    );
+    let receipt_tokens = ctx.accounts.market.receipt_tokens;
+    require!(
+        ctx.accounts.receipt_mint.is_some() == receipt_tokens
+            && ctx.accounts.receipt_account.is_some() == receipt_tokens,
+        ErrorCode::ReceiptAccountsMismatch
+    );
+    require!(
+        ctx.accounts.referral.is_some() == ctx.accounts.referrer.is_some(),
+        ErrorCode::ReferralAccountMismatch
+    );
+    open_position_if_new(
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.position,
+        ctx.accounts.user.key(),
+        ctx.bumps.position,
+    );
+    // Stakes are recorded net of any Token-2022 transfer fee
+    let mut escrow = Escrow::new(
+        ctx.accounts.market.collateral,
+        ctx.accounts.escrow_vault.as_mut(),
+        ctx.accounts.token_mint.as_ref(),
+        ctx.accounts.user_token_account.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+        ctx.accounts.sol_vault.as_ref(),
+    )?;
+    let amount = escrow_stake(
+        &mut escrow,
+        ctx.accounts.session.as_mut(),
+        &ctx.accounts.market.key(),
+        &ctx.accounts.user.key(),
+        &ctx.accounts.authority.to_account_info(),
+        &ctx.accounts.system_program,
+        amount,
+    )?;
+    apply_stake_change(&ctx.accounts.market, &mut ctx.accounts.position, 0, amount)?;
+    if let (Some(referral), Some(referrer)) = (
+        ctx.accounts.referral.as_mut(),
+        ctx.accounts.referrer.as_ref(),
+    ) {
+        open_referral_if_new(
+            &mut ctx.accounts.market,
+            referral,
+            referrer.key(),
+            ctx.accounts.payer.key(),
+            ctx.bumps.referral,
+        );
+        referral.referred_stake = referral.referred_stake.checked_add(amount).unwrap();
+    }
+
+    // Store bet log
+    let bet_log = &mut ctx.accounts.bet_log;
+    bet_log.market = ctx.accounts.market.key();
+    bet_log.depositor = ctx.accounts.user.key();
+    bet_log.amount = amount;
+    bet_log.encrypted_blob = encrypted_blob;
+    bet_log.timestamp = Clock::get()?.unix_timestamp;
+    bet_log.choice_hint = choice; // Not trusted, only for UX
+    bet_log.bump = ctx.bumps.bet_log;
+    bet_log.blob_hash = blob_hash;
+    bet_log.revision = 0;
+    bet_log.referrer = ctx.accounts.referrer.as_ref().map(|r| r.key());
+    bet_log.version = ACCOUNT_VERSION;
+
+    if let (Some(receipt_mint), Some(receipt_account)) = (
+        ctx.accounts.receipt_mint.as_ref(),
+        ctx.accounts.receipt_account.as_ref(),
+    ) {
+        mint_receipt(
+            &ctx.accounts.market,
+            receipt_mint,
+            receipt_account,
+            required(ctx.accounts.token_program.as_ref())?,
+        )?;
+    }
+
+    // Update market state
+    let market = &mut ctx.accounts.market;
+    let bet_index = market.bet_count;
+    market.total_pool = market.total_pool.checked_add(amount).unwrap();
+    market.bet_count = market.bet_count.checked_add(1).unwrap();
+
+    emit!(BetDeposited {
+        market: market.key(),
+        bet_log: bet_log.key(),
+        depositor: bet_log.depositor,
+        bet_index,
+        amount,
+        total_pool: market.total_pool,
+        timestamp: bet_log.timestamp,
+        blob_hash,
+    });
+
+    msg!("Bet placed: {} tokens", amount);
+    Ok(())
+}
+
+/// Shared by both amendment modes. Raising the stake escrows the difference;
+/// lowering it refunds the difference minus the withdrawal penalty.
+fn amend_recorded_bet(
+    ctx: Context<AmendBet>,
+    encrypted_blob: Vec<u8>,
+    blob_hash: [u8; 32],
+    choice: u8,
+    amount: u64,
+) -> Result<()> {
+    require_betting_open(&ctx.accounts.market)?;
+    require!(
+        !ctx.accounts.market.receipt_tokens,
+        ErrorCode::ReceiptRequired
+    );
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    require!(
+        ctx.accounts.bet_log.revision < u8::MAX,
+        ErrorCode::TooManyAmendments
+    );
+
+    let previous = ctx.accounts.bet_log.amount;
+    let mut escrow = Escrow::new(
+        ctx.accounts.market.collateral,
+        ctx.accounts.escrow_vault.as_mut(),
+        ctx.accounts.token_mint.as_ref(),
+        ctx.accounts.depositor_token_account.as_ref(),
+        ctx.accounts.token_program.as_ref(),
+        ctx.accounts.sol_vault.as_ref(),
+    )?;
+    let mut amount = amount;
+    if amount > previous {
+        let received = transfer_to_escrow(
+            &mut escrow,
+            &ctx.accounts.depositor.to_account_info(),
+            &[],
+            &ctx.accounts.system_program,
+            amount - previous,
+        )?;
+        amount = previous.checked_add(received).unwrap();
+        let market = &mut ctx.accounts.market;
+        market.total_pool = market.total_pool.checked_add(received).unwrap();
+    } else if amount < previous {
+        let reduction = previous - amount;
+        let penalty = withdrawal_penalty(reduction, ctx.accounts.market.withdrawal_penalty_bps);
+        let refund = reduction - penalty;
+        transfer_from_escrow(
+            &escrow,
+            &ctx.accounts.depositor.to_account_info(),
+            &ctx.accounts.market,
+            refund,
+        )?;
+        let market = &mut ctx.accounts.market;
+        market.total_pool = market.total_pool.checked_sub(refund).unwrap();
+        market.penalty_pool = market.penalty_pool.checked_add(penalty).unwrap();
+    }
+    apply_stake_change(
+        &ctx.accounts.market,
+        &mut ctx.accounts.position,
+        previous,
+        amount,
+    )?;
+    apply_referral_change(
+        &ctx.accounts.bet_log,
+        ctx.accounts.referral.as_mut(),
+        previous,
+        amount,
+    )?;
+
+    let bet_log = &mut ctx.accounts.bet_log;
+    bet_log.amount = amount;
+    bet_log.encrypted_blob = encrypted_blob;
+    bet_log.blob_hash = blob_hash;
+    bet_log.choice_hint = choice;
+    bet_log.revision += 1;
+    // Under time decay an amended bet weighs from its latest change, so an
+    // early placeholder cannot be re-aimed at the last second
+    if ctx.accounts.market.time_decay.is_some() {
+        bet_log.timestamp = Clock::get()?.unix_timestamp;
+    }
+
+    emit!(BetAmended {
+        market: ctx.accounts.market.key(),
+        bet_log: bet_log.key(),
+        depositor: bet_log.depositor,
+        revision: bet_log.revision,
+        amount,
+        total_pool: ctx.accounts.market.total_pool,
+        blob_hash,
+    });
+
+    msg!("Bet amended: {} tokens", amount);
+    Ok(())
+}
+
+/// SECURITY: accepts every result. `callback_settle` relies on its resolver
+/// signer check alone until this is implemented.
 fn verify_mxe_signature(
     _mxe_program_id: &Pubkey,
     _result: &[u8],
@@ -398,13 +4719,464 @@ fn verify_mxe_signature(
     Ok(())
 }
 
-fn parse_mxe_result(result: &[u8]) -> Result<Vec<Payout>> {
-    // TODO: Parse the MXE result format
-    // Expected format: JSON with array of {recipient: Pubkey, amount: u64}
-    Ok(vec![])
+/// Parse the MXE's JSON result and check it was computed for `market`
+fn parse_mxe_result(result: &[u8], market: &Pubkey) -> Result<MxeResult> {
+    let result: MxeResult =
+        serde_json::from_slice(result).map_err(|_| error!(ErrorCode::InvalidMxeResult))?;
+    require_keys_eq!(
+        Pubkey::new_from_array(decode_hex(&result.market_id)?),
+        *market,
+        ErrorCode::InvalidMxeResult
+    );
+    Ok(result)
+}
+
+/// A 32-byte key or hash from the MXE's hex encoding
+fn decode_hex(value: &str) -> Result<[u8; 32]> {
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(value, &mut bytes).map_err(|_| error!(ErrorCode::InvalidMxeResult))?;
+    Ok(bytes)
+}
+
+/// Mint the single receipt token for a bet, then drop the mint authority so
+/// the supply stays at one
+fn mint_receipt<'info>(
+    market: &Account<'info, Market>,
+    receipt_mint: &InterfaceAccount<'info, Mint>,
+    receipt_account: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    let seed = market.seed();
+    let seeds = &[b"market", seed.as_ref(), &[market.bump]];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = MintTo {
+        mint: receipt_mint.to_account_info(),
+        to: receipt_account.to_account_info(),
+        authority: market.to_account_info(),
+    };
+    let cpi_ctx =
+        CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token_interface::mint_to(cpi_ctx, 1)?;
+
+    let cpi_accounts = SetAuthority {
+        current_authority: market.to_account_info(),
+        account_or_mint: receipt_mint.to_account_info(),
+    };
+    let cpi_ctx =
+        CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token_interface::set_authority(cpi_ctx, AuthorityType::MintTokens, None)
 }
 
 fn hash_result(result: &[u8]) -> [u8; 32] {
     use anchor_lang::solana_program::hash::hash;
     hash(result).to_bytes()
 }
+
+// ========== MIGRATION ==========
+
+// Layouts of the program's accounts before versioning, i.e. version 0. Each
+// was allocated at its full `INIT_SPACE`, a size no current account of the
+// same kind has, so the size tells the two apart.
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+struct MarketV0 {
+    creator: Pubkey,
+    #[max_len(280)]
+    question: String,
+    deadline: i64,
+    mxe_program_id: Pubkey,
+    escrow_vault: Pubkey,
+    total_pool: u64,
+    state: MarketState,
+    result_hash: [u8; 32],
+    bump: u8,
+    bet_count: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+struct BetLogV0 {
+    market: Pubkey,
+    depositor: Pubkey,
+    amount: u64,
+    #[max_len(512)]
+    encrypted_blob: Vec<u8>,
+    timestamp: i64,
+    choice_hint: u8,
+    bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+struct ResolutionJobV0 {
+    market: Pubkey,
+    status: JobStatus,
+    callback_account: Pubkey,
+    timestamp: i64,
+    bump: u8,
+}
+
+impl MarketV0 {
+    /// Version 0 markets were public on-chain token markets with no fees or
+    /// schedule; a settled one records no winning outcome. Their creator
+    /// becomes the resolver, as nobody else was ever named.
+    fn migrate(self, token_mint: Pubkey) -> Market {
+        Market {
+            creator: self.creator,
+            question: self.question,
+            deadline: self.deadline,
+            mxe_program_id: self.mxe_program_id,
+            resolver: self.creator,
+            escrow_vault: self.escrow_vault,
+            token_mint,
+            total_pool: self.total_pool,
+            state: self.state,
+            result_hash: self.result_hash,
+            bump: self.bump,
+            bet_count: self.bet_count,
+            closed_bet_count: 0,
+            bet_storage: BetStorage::OnChain,
+            blob_store_uri: String::new(),
+            ledger_page_count: 0,
+            closed_ledger_page_count: 0,
+            withdrawal_penalty_bps: 0,
+            stake_limits: StakeLimits::default(),
+            position_count: 0,
+            closed_position_count: 0,
+            open_time: 0,
+            resolve_after: None,
+            collateral: Collateral::Token,
+            allowlist_root: None,
+            creator_fee_bps: 0,
+            referrer_fee_bps: 0,
+            receipt_tokens: false,
+            payout_root: [0; 32],
+            lmsr: None,
+            winning_outcome: None,
+            subsidy: 0,
+            subsidy_sponsor: self.creator,
+            subsidy_refund: 0,
+            time_decay: None,
+            condition: None,
+            template: None,
+            metadata: MarketMetadata::default(),
+            version: ACCOUNT_VERSION,
+            referral_count: 0,
+            closed_referral_count: 0,
+            open_child_count: 0,
+            penalty_pool: 0,
+            open_parlay_count: 0,
+            reserved: [0; ACCOUNT_RESERVED_LEN - MARKET_APPENDED_LEN],
+        }
+    }
+}
+
+/// The version 0 contents of `account`, or `None` if it is not version 0
+fn read_v0<T: AnchorDeserialize + anchor_lang::Space>(account: &AccountInfo) -> Result<Option<T>> {
+    if account.data_len() != 8 + T::INIT_SPACE {
+        return Ok(None);
+    }
+    let data = account.try_borrow_data()?;
+    Ok(Some(T::deserialize(&mut &data[8..])?))
+}
+
+/// Migrate one version 0 bet log or resolution job of `market`; `false` if it
+/// is already current. A bet log's position is the next of `accounts`.
+fn migrate_account<'a, 'info: 'a>(
+    account: &AccountInfo<'info>,
+    accounts: &mut impl Iterator<Item = &'a AccountInfo<'info>>,
+    market_key: Pubkey,
+    market: &mut Market,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<bool> {
+    use anchor_lang::Discriminator;
+    require_keys_eq!(*account.owner, crate::ID, ErrorCode::NotMigratable);
+    let discriminator: [u8; 8] = account
+        .try_borrow_data()?
+        .get(..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| error!(ErrorCode::NotMigratable))?;
+
+    match discriminator {
+        d if d == BetLog::DISCRIMINATOR => {
+            // The position slot is taken whatever the bet log's version, so
+            // the same account list lines up again on a re-run
+            let position = accounts
+                .next()
+                .ok_or_else(|| error!(ErrorCode::NotMigratable))?;
+            let v0 = match read_v0::<BetLogV0>(account)? {
+                Some(v0) => v0,
+                None => return Ok(false),
+            };
+            require_keys_eq!(v0.market, market_key, ErrorCode::NotMigratable);
+            credit_position(
+                position,
+                market_key,
+                market,
+                &v0.depositor,
+                v0.amount,
+                payer,
+                system_program,
+            )?;
+            let bet_log = BetLog {
+                market: v0.market,
+                depositor: v0.depositor,
+                amount: v0.amount,
+                encrypted_blob: v0.encrypted_blob,
+                timestamp: v0.timestamp,
+                choice_hint: v0.choice_hint,
+                bump: v0.bump,
+                blob_hash: [0; 32],
+                revision: 0,
+                referrer: None,
+                version: ACCOUNT_VERSION,
+                reserved: [0; BETTOR_ACCOUNT_RESERVED_LEN],
+            };
+            let space = 8 + BetLog::space(&BetStorage::OnChain, false);
+            write_migrated(account, &bet_log, space, payer, system_program)?;
+        }
+        d if d == ResolutionJob::DISCRIMINATOR => {
+            let v0 = match read_v0::<ResolutionJobV0>(account)? {
+                Some(v0) => v0,
+                None => return Ok(false),
+            };
+            require_keys_eq!(v0.market, market_key, ErrorCode::NotMigratable);
+            let job = ResolutionJob {
+                market: v0.market,
+                status: v0.status,
+                callback_account: v0.callback_account,
+                timestamp: v0.timestamp,
+                bump: v0.bump,
+                attempts: 0,
+                last_error: 0,
+                updated_at: v0.timestamp,
+                version: ACCOUNT_VERSION,
+                reserved: [0; ACCOUNT_RESERVED_LEN],
+            };
+            let space = 8 + ResolutionJob::INIT_SPACE;
+            write_migrated(account, &job, space, payer, system_program)?;
+        }
+        _ => return err!(ErrorCode::NotMigratable),
+    }
+    Ok(true)
+}
+
+/// Version 0 bets predate positions: open `owner`'s position for the bet's
+/// stake, or add the stake to the one an earlier bet opened
+fn credit_position<'info>(
+    position: &AccountInfo<'info>,
+    market_key: Pubkey,
+    market: &mut Market,
+    owner: &Pubkey,
+    amount: u64,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let (address, bump) = Pubkey::find_program_address(
+        &[b"position", market_key.as_ref(), owner.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(position.key(), address, ErrorCode::NotMigratable);
+    if *position.owner == crate::ID {
+        let mut data = position.try_borrow_mut_data()?;
+        let mut held = UserPosition::try_deserialize(&mut &data[..])?;
+        held.total_staked = held.total_staked.checked_add(amount).unwrap();
+        return held.try_serialize(&mut &mut data[..]);
+    }
+
+    let space = 8 + UserPosition::INIT_SPACE;
+    let cpi_accounts = system_program::CreateAccount {
+        from: payer.to_account_info(),
+        to: position.clone(),
+    };
+    let seeds: &[&[u8]] = &[b"position", market_key.as_ref(), owner.as_ref(), &[bump]];
+    let signer = &[seeds];
+    let cpi_ctx =
+        CpiContext::new_with_signer(system_program.to_account_info(), cpi_accounts, signer);
+    system_program::create_account(
+        cpi_ctx,
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+    )?;
+    let held = UserPosition {
+        market: market_key,
+        owner: *owner,
+        total_staked: amount,
+        bump,
+        shares: [0; 2],
+        version: ACCOUNT_VERSION,
+        reserved: [0; BETTOR_ACCOUNT_RESERVED_LEN],
+    };
+    held.try_serialize(&mut &mut position.try_borrow_mut_data()?[..])?;
+    market.position_count = market.position_count.checked_add(1).unwrap();
+    Ok(())
+}
+
+/// Resize `account` to `space` bytes and write `value` over it. `payer` tops
+/// up the rent if the account grew.
+fn write_migrated<'info, T: AccountSerialize>(
+    account: &AccountInfo<'info>,
+    value: &T,
+    space: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let rent = Rent::get()?;
+    let top_up = rent
+        .minimum_balance(space)
+        .saturating_sub(rent.minimum_balance(account.data_len()));
+    if top_up > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: payer.to_account_info(),
+            to: account.clone(),
+        };
+        let cpi_ctx = CpiContext::new(system_program.to_account_info(), cpi_accounts);
+        system_program::transfer(cpi_ctx, top_up)?;
+    }
+    account.realloc(space, true)?;
+
+    let mut data = account.try_borrow_mut_data()?;
+    data.fill(0);
+    value.try_serialize(&mut &mut data[..])?;
+    Ok(())
+}
+
+// ========== PARLAYS ==========
+
+/// Seed of the parlay pool over `legs`: `sha256(legs[0] || legs[1] || ...)`
+pub fn parlay_legs_hash(legs: &[Pubkey]) -> [u8; 32] {
+    use anchor_lang::solana_program::hash::hashv;
+    let legs: Vec<&[u8]> = legs.iter().map(|leg| leg.as_ref()).collect();
+    hashv(&legs).to_bytes()
+}
+
+/// Load a parlay's leg markets from `accounts`, which must be exactly `legs`
+fn load_parlay_legs(legs: &[Pubkey], accounts: &[AccountInfo]) -> Result<Vec<Market>> {
+    require!(accounts.len() == legs.len(), ErrorCode::InvalidParlayLegs);
+    legs.iter()
+        .zip(accounts)
+        .map(|(leg, account)| {
+            require_keys_eq!(account.key(), *leg, ErrorCode::InvalidParlayLegs);
+            require_keys_eq!(*account.owner, crate::ID, ErrorCode::InvalidParlayLegs);
+            Market::try_deserialize(&mut &account.data.borrow()[..])
+        })
+        .collect()
+}
+
+/// Count a parlay pool as open on each of its leg markets, or no longer, and
+/// write them back; `close_market` waits for every pool on a leg to settle
+fn count_open_parlay(markets: &mut [Market], accounts: &[AccountInfo], opened: bool) -> Result<()> {
+    for (market, account) in markets.iter_mut().zip(accounts) {
+        require!(account.is_writable, ErrorCode::InvalidParlayLegs);
+        market.open_parlay_count = if opened {
+            market.open_parlay_count.checked_add(1).unwrap()
+        } else {
+            market.open_parlay_count.checked_sub(1).unwrap()
+        };
+        market.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
+    }
+    Ok(())
+}
+
+/// Pay `amount` out of a parlay pool's escrow, signed by the pool
+fn pay_from_parlay_pool<'info>(
+    pool: &Account<'info, ParlayPool>,
+    escrow_vault: &InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let legs_hash = parlay_legs_hash(&pool.legs);
+    let seeds = &[b"parlay_pool".as_ref(), legs_hash.as_ref(), &[pool.bump]];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = TransferChecked {
+        from: escrow_vault.to_account_info(),
+        mint: token_mint.to_account_info(),
+        to: destination.to_account_info(),
+        authority: pool.to_account_info(),
+    };
+    let cpi_ctx =
+        CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)
+}
+
+// ========== LMSR ==========
+
+/// Fixed-point scale of the LMSR math: 1.0 is `LMSR_ONE`
+const LMSR_ONE: u128 = 1_000_000_000_000;
+
+/// ln(2) at `LMSR_ONE` scale
+const LMSR_LN_2: u128 = 693_147_180_560;
+
+/// Past this, e^-x is below the fixed-point resolution
+const LMSR_MAX_EXPONENT: u128 = 42 * LMSR_ONE;
+
+/// e^-x for fixed-point x >= 0, as 2^-k * e^-r with x = k * ln(2) + r
+fn exp_neg(x: u128) -> u128 {
+    if x >= LMSR_MAX_EXPONENT {
+        return 0;
+    }
+    let k = x / LMSR_LN_2;
+    let r = x - k * LMSR_LN_2;
+    // Taylor series of e^r; r < ln(2), so the terms vanish well before 16
+    let mut term = LMSR_ONE;
+    let mut e_r = LMSR_ONE;
+    for i in 1..16 {
+        term = term * r / (LMSR_ONE * i);
+        e_r += term;
+    }
+    (LMSR_ONE * LMSR_ONE / e_r) >> k
+}
+
+/// ln(1 + y) for fixed-point 0 <= y <= 1, as 2 * atanh(y / (2 + y))
+fn ln_1p(y: u128) -> u128 {
+    let z = y * LMSR_ONE / (2 * LMSR_ONE + y);
+    let z_squared = z * z / LMSR_ONE;
+    let mut term = z;
+    let mut atanh = 0;
+    for n in 0..16 {
+        atanh += term / (2 * n + 1);
+        term = term * z_squared / LMSR_ONE;
+    }
+    2 * atanh
+}
+
+/// Cost function C(q) = b * ln(e^(q0 / b) + e^(q1 / b)) at `LMSR_ONE` scale,
+/// evaluated as max(q) + b * ln(1 + e^(-|q0 - q1| / b)) so no exponent is positive
+fn lmsr_cost(liquidity: u64, outstanding: [u64; 2]) -> u128 {
+    let [no, yes] = outstanding;
+    let b = liquidity as u128;
+    let spread = no.abs_diff(yes) as u128 * LMSR_ONE / b;
+    no.max(yes) as u128 * LMSR_ONE + b * ln_1p(exp_neg(spread))
+}
+
+/// Collateral needed to buy `shares` of `outcome`: C(q + shares) - C(q),
+/// rounded up. `None` for an invalid outcome or an overflowing trade.
+pub fn lmsr_buy_cost(
+    liquidity: u64,
+    outstanding: [u64; 2],
+    outcome: u8,
+    shares: u64,
+) -> Option<u64> {
+    let mut after = outstanding;
+    let held = after.get_mut(outcome as usize)?;
+    *held = held.checked_add(shares)?;
+    let delta = lmsr_cost(liquidity, after).saturating_sub(lmsr_cost(liquidity, outstanding));
+    u64::try_from(lmsr_round_up(delta)?).ok()
+}
+
+/// Creator funding of an LMSR market, C(0) = b * ln(2) rounded up. With every
+/// trade rounded up too, escrow always covers max(q), the most any outcome pays.
+pub fn lmsr_funding(liquidity: u64) -> u64 {
+    // C(0) is at most u64::MAX * ln(2) at `LMSR_ONE` scale, far from overflowing
+    lmsr_round_up(lmsr_cost(liquidity, [0; 2])).unwrap() as u64
+}
+
+/// Whole units of an `LMSR_ONE`-scaled amount, rounded up; `None` on overflow.
+/// Spelled out rather than `div_ceil`, which the SBF toolchain's rustc lacks.
+fn lmsr_round_up(scaled: u128) -> Option<u128> {
+    Some(scaled.checked_add(LMSR_ONE - 1)? / LMSR_ONE)
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use common::*;
+use market_factory::{allowlist_leaf, allowlist_parent, ErrorCode, Market, MarketCreated};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Anchor's `ConstraintHasOne` framework error
+const CONSTRAINT_HAS_ONE: u32 = 2001;
+
+/// Three-wallet tree: `root = parent(parent(a, b), c)`
+struct Allowlist {
+    root: [u8; 32],
+    proofs: [Vec<[u8; 32]>; 3],
+}
+
+fn allowlist(wallets: [&Pubkey; 3]) -> Allowlist {
+    let [a, b, c] = wallets.map(allowlist_leaf);
+    let ab = allowlist_parent(&a, &b);
+    Allowlist {
+        root: allowlist_parent(&ab, &c),
+        proofs: [vec![b, c], vec![a, c], vec![ab]],
+    }
+}
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    mint: Pubkey,
+    market: Pubkey,
+}
+
+async fn private_market(allowlist_root: Option<[u8; 32]>) -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let mut args = create_market_args("Members only?", now(&mut ctx).await + 3_600);
+    args.allowlist_root = allowlist_root;
+    let logs = send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    assert_eq!(
+        decode_events::<MarketCreated>(&logs)[0].allowlist_root,
+        allowlist_root
+    );
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        creator,
+        mint,
+        market,
+    }
+}
+
+impl Fixture {
+    async fn bettor(&mut self) -> (Keypair, Pubkey) {
+        let bettor = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &bettor.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, 100).await;
+        (bettor, tokens)
+    }
+
+    async fn bet(&mut self, bettor: &Keypair, tokens: &Pubkey, proof: Vec<[u8; 32]>) -> TxOutcome {
+        let market: Market = fetch(&mut self.ctx, self.market).await;
+        send(
+            &mut self.ctx,
+            &[deposit_bet_with_proof_ix(
+                &self.market,
+                &self.mint,
+                &bettor.pubkey(),
+                tokens,
+                market.bet_count,
+                vec![1; 9],
+                1,
+                10,
+                proof,
+            )],
+            &[bettor],
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn only_listed_wallets_can_bet() {
+    let mut f = private_market(None).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let (bob, _) = f.bettor().await;
+    let (carol, carol_tokens) = f.bettor().await;
+    let (mallory, mallory_tokens) = f.bettor().await;
+    let list = allowlist([&alice.pubkey(), &bob.pubkey(), &carol.pubkey()]);
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[set_allowlist_root_ix(&f.market, &creator, Some(list.root))],
+        &[&f.creator],
+    )
+    .await;
+
+    let outcome = f.bet(&alice, &alice_tokens, list.proofs[0].clone()).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let outcome = f.bet(&carol, &carol_tokens, list.proofs[2].clone()).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+
+    // A proof only works for the wallet it was built for
+    for proof in [Vec::new(), list.proofs[0].clone()] {
+        let outcome = f.bet(&mallory, &mallory_tokens, proof).await;
+        assert_error(&outcome, ErrorCode::NotAllowlisted);
+    }
+    let outcome = f.bet(&alice, &alice_tokens, list.proofs[1].clone()).await;
+    assert_error(&outcome, ErrorCode::NotAllowlisted);
+    assert_eq!(fetch::<Market>(&mut f.ctx, f.market).await.bet_count, 2);
+}
+
+#[tokio::test]
+async fn root_rotates_until_the_first_bet() {
+    let mut f = private_market(Some([7; 32])).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let creator = f.creator.pubkey();
+
+    let outcome = send(
+        &mut f.ctx,
+        &[set_allowlist_root_ix(&f.market, &alice.pubkey(), None)],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(anchor_error_code(&outcome.result), Some(CONSTRAINT_HAS_ONE));
+
+    // Clearing the root opens the market to everyone
+    send_ok(
+        &mut f.ctx,
+        &[set_allowlist_root_ix(&f.market, &creator, None)],
+        &[&f.creator],
+    )
+    .await;
+    let outcome = f.bet(&alice, &alice_tokens, Vec::new()).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+
+    let outcome = send(
+        &mut f.ctx,
+        &[set_allowlist_root_ix(&f.market, &creator, Some([7; 32]))],
+        &[&f.creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::AllowlistLocked);
+    assert_eq!(
+        fetch::<Market>(&mut f.ctx, f.market).await.allowlist_root,
+        None
+    );
+}
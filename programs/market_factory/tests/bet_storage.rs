@@ -0,0 +1,164 @@
+mod common;
+
+use anchor_lang::{solana_program::hash::hash, Space};
+use common::*;
+use market_factory::{
+    BetDeposited, BetLedgerPage, BetLog, BetStorage, ErrorCode, UserPosition, LEDGER_PAGE_CAPACITY,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+async fn market_with_storage(
+    ctx: &mut ProgramTestContext,
+    bet_storage: BetStorage,
+    blob_store_uri: &str,
+) -> (Keypair, Pubkey, Pubkey) {
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(ctx).await;
+    let mint = create_mint(ctx, &payer).await;
+    let mut args = create_market_args("Off chain?", now(ctx).await + 3_600);
+    args.bet_storage = bet_storage;
+    args.blob_store_uri = blob_store_uri.to_string();
+    send_ok(
+        ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    (creator, mint, market)
+}
+
+#[tokio::test]
+async fn off_chain_bets_store_only_the_commitment() {
+    let mut ctx = start().await;
+    let (_, mint, market) =
+        market_with_storage(&mut ctx, BetStorage::OffChain, "file:///tmp/blobs").await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+
+    let blob_hash = hash(&[1u8; 300]).to_bytes();
+    let logs = send_ok(
+        &mut ctx,
+        &[deposit_bet_commitment_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            blob_hash,
+            40,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_eq!(decode_events::<BetDeposited>(&logs)[0].blob_hash, blob_hash);
+    assert_eq!(token_balance(&mut ctx, escrow_pda(&market)).await, 40);
+
+    let address = bet_log_pda(&market, &bettor.pubkey(), 0);
+    let bet: BetLog = fetch(&mut ctx, address).await;
+    assert_eq!(bet.blob_hash, blob_hash);
+    assert!(bet.encrypted_blob.is_empty());
+    assert_eq!(bet.amount, 40);
+
+    // Compare what the bettor pays in rent, not data length: every account
+    // carries 128 bytes of overhead, and a wallet's first bet also opens its
+    // position
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let bet_rent = |storage| rent.minimum_balance(8 + BetLog::space(&storage, false));
+    let position_rent = rent.minimum_balance(8 + UserPosition::INIT_SPACE);
+    let position = position_pda(&market, &bettor.pubkey());
+    assert_eq!(
+        lamports(&mut ctx, address).await,
+        bet_rent(BetStorage::OffChain)
+    );
+    assert_eq!(lamports(&mut ctx, position).await, position_rent);
+
+    let inline = bet_rent(BetStorage::OnChain);
+    let off_chain = bet_rent(BetStorage::OffChain);
+    // A first bet, position included, costs less than half as much
+    assert!(
+        2 * (off_chain + position_rent) < inline + position_rent,
+        "{off_chain} + {position_rent} vs {inline} + {position_rent}"
+    );
+    // A later bet saves more than 60%
+    assert!(5 * off_chain < 2 * inline, "{off_chain} vs {inline}");
+    // Only a shared ledger page gets a later bet's share under a fifth
+    let ledger_entry = rent.minimum_balance(8 + std::mem::size_of::<BetLedgerPage>())
+        / LEDGER_PAGE_CAPACITY as u64;
+    assert!(5 * ledger_entry < inline, "{ledger_entry} vs {inline}");
+}
+
+#[tokio::test]
+async fn deposits_must_match_the_storage_mode() {
+    let mut ctx = start().await;
+    let (_, mint, off_chain) =
+        market_with_storage(&mut ctx, BetStorage::OffChain, "ipfs://bets").await;
+    let (_, on_chain_mint, on_chain) = market_with_storage(&mut ctx, BetStorage::OnChain, "").await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+
+    let outcome = send(
+        &mut ctx,
+        &[deposit_bet_ix(
+            &off_chain,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            vec![1; 9],
+            1,
+            10,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongBetStorage);
+
+    let outcome = send(
+        &mut ctx,
+        &[deposit_bet_commitment_ix(
+            &on_chain,
+            &on_chain_mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            [1; 32],
+            10,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongBetStorage);
+}
+
+#[tokio::test]
+async fn create_market_validates_blob_store_uri() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    for (bet_storage, uri) in [
+        (BetStorage::OffChain, String::new()),
+        (BetStorage::OffChain, "x".repeat(129)),
+        (BetStorage::OnChain, "ipfs://unused".to_string()),
+    ] {
+        let mut args = create_market_args("Bad store?", deadline);
+        args.bet_storage = bet_storage;
+        args.blob_store_uri = uri;
+        let outcome = send(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::InvalidBlobStore);
+    }
+}
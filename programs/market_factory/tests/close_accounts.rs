@@ -0,0 +1,213 @@
+mod common;
+
+use common::*;
+use market_factory::{ErrorCode, Market, MarketClosed};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+async fn open_market(ctx: &mut ProgramTestContext) -> (Keypair, Pubkey, Pubkey, i64) {
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(ctx).await;
+    let mint = create_mint(ctx, &payer).await;
+    let deadline = now(ctx).await + 3_600;
+    send_ok(
+        ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Close me?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    (creator, mint, market, deadline)
+}
+
+#[tokio::test]
+async fn bet_logs_and_job_return_rent_after_settlement() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let (creator, mint, market, deadline) = open_market(&mut ctx).await;
+
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+    for bet_index in 0..2 {
+        send_ok(
+            &mut ctx,
+            &[deposit_bet_ix(
+                &market,
+                &mint,
+                &bettor.pubkey(),
+                &tokens,
+                bet_index,
+                vec![1; 9],
+                1,
+                10,
+            )],
+            &[&bettor],
+        )
+        .await;
+    }
+
+    let bet_log = bet_log_pda(&market, &bettor.pubkey(), 0);
+    let outcome = send(
+        &mut ctx,
+        &[close_bet_log_ix(&market, &bet_log, &bettor.pubkey())],
+        &[],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::MarketNotFinalized);
+
+    warp_to_timestamp(&mut ctx, deadline).await;
+    send_ok(&mut ctx, &[enqueue_resolution_ix(&market, &payer)], &[]).await;
+    send_ok(
+        &mut ctx,
+        &[callback_settle_ix(
+            &market,
+            &mint,
+            &mxe_result(&market),
+            &[],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+
+    // Permissionless: anyone may crank the close, rent always goes to the depositor
+    for bet_index in 0..2 {
+        let bet_log = bet_log_pda(&market, &bettor.pubkey(), bet_index);
+        let rent = lamports(&mut ctx, bet_log).await;
+        let before = lamports(&mut ctx, bettor.pubkey()).await;
+        send_ok(
+            &mut ctx,
+            &[close_bet_log_ix(&market, &bet_log, &bettor.pubkey())],
+            &[],
+        )
+        .await;
+        assert!(!account_exists(&mut ctx, bet_log).await);
+        assert_eq!(lamports(&mut ctx, bettor.pubkey()).await, before + rent);
+    }
+    let state: Market = fetch(&mut ctx, market).await;
+    assert_eq!(state.closed_bet_count, 2);
+
+    let job = resolution_job_pda(&market);
+    let rent = lamports(&mut ctx, job).await;
+    let before = lamports(&mut ctx, creator.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[close_resolution_job_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, job).await);
+    // The context payer covers fees, so the creator receives the full rent
+    assert_eq!(lamports(&mut ctx, creator.pubkey()).await, before + rent);
+
+    // Nothing was paid out, so the escrow still holds both stakes; they are
+    // swept to the creator, who must name a token account for them
+    let outcome = send(
+        &mut ctx,
+        &[close_escrow_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::CollateralAccountsMismatch);
+
+    let escrow = escrow_pda(&market);
+    let creator_tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    let rent = lamports(&mut ctx, escrow).await;
+    let before = lamports(&mut ctx, creator.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[close_escrow_sweep_ix(
+            &market,
+            &mint,
+            &creator.pubkey(),
+            &creator_tokens,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, escrow).await);
+    assert_eq!(token_balance(&mut ctx, creator_tokens).await, 20);
+    assert_eq!(lamports(&mut ctx, creator.pubkey()).await, before + rent);
+}
+
+#[tokio::test]
+async fn cancelled_market_closes_escrow_then_market() {
+    let mut ctx = start().await;
+    let (creator, mint, market, deadline) = open_market(&mut ctx).await;
+    send_ok(
+        &mut ctx,
+        &[cancel_market_ix(
+            &market,
+            &creator.pubkey(),
+            &creator.pubkey(),
+        )],
+        &[&creator],
+    )
+    .await;
+
+    let outcome = send(
+        &mut ctx,
+        &[close_market_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::AccountsStillOpen);
+
+    let escrow = escrow_pda(&market);
+    let reclaimed = lamports(&mut ctx, escrow).await + lamports(&mut ctx, market).await;
+    let before = lamports(&mut ctx, creator.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[close_escrow_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    let logs = send_ok(
+        &mut ctx,
+        &[close_market_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert_eq!(decode_events::<MarketClosed>(&logs).len(), 1);
+    assert!(!account_exists(&mut ctx, escrow).await);
+    assert!(!account_exists(&mut ctx, market).await);
+    assert_eq!(
+        lamports(&mut ctx, creator.pubkey()).await,
+        before + reclaimed
+    );
+
+    // The creator's market PDA is free again
+    send_ok(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Second market?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn open_market_accounts_cannot_be_closed() {
+    let mut ctx = start().await;
+    let (creator, _, market, _) = open_market(&mut ctx).await;
+
+    let outcome = send(
+        &mut ctx,
+        &[close_escrow_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::MarketNotFinalized);
+}
@@ -0,0 +1,2217 @@
+//! Shared `solana-program-test` harness for the market_factory integration tests.
+
+#![allow(dead_code)]
+
+use std::sync::Once;
+
+use anchor_lang::{
+    prelude::Pubkey, solana_program::instruction::Instruction, AccountDeserialize, Event,
+    InstructionData, ToAccountMetas, ZeroCopy,
+};
+use anchor_spl::{
+    associated_token::get_associated_token_address,
+    token::spl_token,
+    token_2022::spl_token_2022::{
+        self,
+        extension::{transfer_fee, ExtensionType, StateWithExtensions},
+    },
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use market_factory::{
+    BetStorage, Collateral, FeeSplit, MarketMetadata, MxePayout, MxeResult, OracleConfig,
+    StakeLimits,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::AccountMeta,
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    signature::{keypair_from_seed, Keypair, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+
+/// Fixed seed for the keypair that stands in for the MXE cluster authority
+const MXE_AUTHORITY_SEED: [u8; 32] = [7u8; 32];
+
+/// Stands in for the MXE cluster: every test market's `mxe_program_id` and
+/// `resolver`, so it signs settlements
+pub fn mxe_authority() -> Keypair {
+    keypair_from_seed(&MXE_AUTHORITY_SEED).unwrap()
+}
+
+// ========== PROGRAM HARNESS ==========
+
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    // Anchor's entrypoint wants `&'info [AccountInfo<'info>]`; the accounts only
+    // need to outlive this call, so leaking them in tests is harmless.
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    market_factory::entry(program_id, accounts, data)
+}
+
+/// Forwards every syscall to program-test, except `sol_log_data`, which the native
+/// builtin stubs only print to stdout. Routing it through `sol_log` puts `emit!`
+/// payloads into the transaction logs so tests can decode them.
+struct EventLogStubs {
+    inner: Box<dyn SyscallStubs>,
+}
+
+impl SyscallStubs for EventLogStubs {
+    fn sol_log(&self, message: &str) {
+        self.inner.sol_log(message)
+    }
+    fn sol_log_compute_units(&self) {
+        self.inner.sol_log_compute_units()
+    }
+    fn sol_remaining_compute_units(&self) -> u64 {
+        self.inner.sol_remaining_compute_units()
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.inner
+            .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_last_restart_slot(var_addr)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.inner.sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.inner.sol_set_return_data(data)
+    }
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let encoded: Vec<String> = fields.iter().map(|f| BASE64.encode(f)).collect();
+        self.inner
+            .sol_log(&format!("Program data: {}", encoded.join(" ")))
+    }
+    fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+        self.inner.sol_get_processed_sibling_instruction(index)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        self.inner.sol_get_stack_height()
+    }
+}
+
+pub async fn start() -> ProgramTestContext {
+    let mut program_test = ProgramTest::new(
+        "market_factory",
+        market_factory::ID,
+        processor!(process_instruction),
+    );
+    program_test.prefer_bpf(false);
+    let ctx = program_test.start_with_context().await;
+
+    // program-test installs its stubs on first start; wrap them exactly once.
+    // Every test passes through here before sending a transaction, so no
+    // instruction can observe the placeholder.
+    static WRAP_STUBS: Once = Once::new();
+    WRAP_STUBS.call_once(|| {
+        let inner = program_stubs::set_syscall_stubs(Box::new(NoopStubs));
+        program_stubs::set_syscall_stubs(Box::new(EventLogStubs { inner }));
+    });
+
+    ctx
+}
+
+struct NoopStubs;
+impl SyscallStubs for NoopStubs {}
+
+// ========== TRANSACTIONS ==========
+
+pub struct TxOutcome {
+    pub result: Result<(), TransactionError>,
+    pub logs: Vec<String>,
+}
+
+pub async fn send(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> TxOutcome {
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers: Vec<&Keypair> = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    let outcome = ctx
+        .banks_client
+        .process_transaction_with_metadata(tx)
+        .await
+        .unwrap();
+    TxOutcome {
+        result: outcome.result,
+        logs: outcome.metadata.map(|m| m.log_messages).unwrap_or_default(),
+    }
+}
+
+pub async fn send_ok(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Vec<String> {
+    let outcome = send(ctx, instructions, signers).await;
+    if let Err(err) = outcome.result {
+        panic!("transaction failed: {err:?}\n{}", outcome.logs.join("\n"));
+    }
+    outcome.logs
+}
+
+/// Decode every `emit!`-ed event of type `E` from a transaction's log messages
+pub fn decode_events<E: Event>(logs: &[String]) -> Vec<E> {
+    logs.iter()
+        .filter_map(|line| line.split_once("Program data: ").map(|(_, data)| data))
+        .filter_map(|data| BASE64.decode(data).ok())
+        .filter(|bytes| bytes.len() >= 8 && bytes[..8] == E::DISCRIMINATOR)
+        .map(|bytes| E::deserialize(&mut &bytes[8..]).unwrap())
+        .collect()
+}
+
+// ========== CLOCK ==========
+
+pub async fn now(ctx: &mut ProgramTestContext) -> i64 {
+    ctx.banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp
+}
+
+/// Move the cluster clock to `unix_timestamp`
+pub async fn warp_to_timestamp(ctx: &mut ProgramTestContext, unix_timestamp: i64) {
+    let mut clock = ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    ctx.set_sysvar(&clock);
+}
+
+// ========== ACCOUNTS ==========
+
+pub async fn fund(ctx: &mut ProgramTestContext, to: &Pubkey, lamports: u64) {
+    let ix = system_instruction::transfer(&ctx.payer.pubkey(), to, lamports);
+    send_ok(ctx, &[ix], &[]).await;
+}
+
+pub async fn new_funded_keypair(ctx: &mut ProgramTestContext) -> Keypair {
+    let keypair = Keypair::new();
+    fund(ctx, &keypair.pubkey(), 10_000_000_000).await;
+    keypair
+}
+
+pub async fn fetch<T: AccountDeserialize>(ctx: &mut ProgramTestContext, address: Pubkey) -> T {
+    let account = ctx
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .expect("account not found");
+    T::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+/// Read a zero-copy account, checking its discriminator
+pub async fn fetch_zero_copy<T: ZeroCopy>(ctx: &mut ProgramTestContext, address: Pubkey) -> T {
+    let account = ctx
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .expect("account not found");
+    assert_eq!(account.data[..8], T::DISCRIMINATOR);
+    bytemuck::pod_read_unaligned(&account.data[8..8 + std::mem::size_of::<T>()])
+}
+
+pub async fn lamports(ctx: &mut ProgramTestContext, address: Pubkey) -> u64 {
+    ctx.banks_client.get_balance(address).await.unwrap()
+}
+
+pub async fn account_exists(ctx: &mut ProgramTestContext, address: Pubkey) -> bool {
+    ctx.banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+// ========== SPL TOKEN ==========
+
+pub async fn create_mint(ctx: &mut ProgramTestContext, authority: &Pubkey) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let ixs = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_mint(&spl_token::ID, &mint.pubkey(), authority, None, 6)
+            .unwrap(),
+    ];
+    send_ok(ctx, &ixs, &[&mint]).await;
+    mint.pubkey()
+}
+
+pub async fn create_token_account(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let ixs = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_account(&spl_token::ID, &account.pubkey(), mint, owner)
+            .unwrap(),
+    ];
+    send_ok(ctx, &ixs, &[&account]).await;
+    account.pubkey()
+}
+
+/// Token-2022 mint charging `fee_bps` (capped at `max_fee`) on every transfer
+pub async fn create_fee_mint(
+    ctx: &mut ProgramTestContext,
+    authority: &Pubkey,
+    fee_bps: u16,
+    max_fee: u64,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+        ExtensionType::TransferFeeConfig,
+    ])
+    .unwrap();
+    let ixs = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &spl_token_2022::ID,
+        ),
+        transfer_fee::instruction::initialize_transfer_fee_config(
+            &spl_token_2022::ID,
+            &mint.pubkey(),
+            Some(authority),
+            Some(authority),
+            fee_bps,
+            max_fee,
+        )
+        .unwrap(),
+        spl_token_2022::instruction::initialize_mint(
+            &spl_token_2022::ID,
+            &mint.pubkey(),
+            authority,
+            None,
+            6,
+        )
+        .unwrap(),
+    ];
+    send_ok(ctx, &ixs, &[&mint]).await;
+    mint.pubkey()
+}
+
+/// Token-2022 account for a mint created by [`create_fee_mint`]
+pub async fn create_fee_token_account(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&[
+        ExtensionType::TransferFeeAmount,
+    ])
+    .unwrap();
+    let ixs = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &spl_token_2022::ID,
+        ),
+        spl_token_2022::instruction::initialize_account(
+            &spl_token_2022::ID,
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    send_ok(ctx, &ixs, &[&account]).await;
+    account.pubkey()
+}
+
+/// Point an instruction built by the helpers below at Token-2022
+pub fn with_token_2022(mut ix: Instruction) -> Instruction {
+    for meta in ix.accounts.iter_mut() {
+        if meta.pubkey == spl_token::ID {
+            meta.pubkey = spl_token_2022::ID;
+        }
+    }
+    ix
+}
+
+/// Mint authority is always the context payer. Works for both token programs.
+pub async fn mint_to(ctx: &mut ProgramTestContext, mint: &Pubkey, account: &Pubkey, amount: u64) {
+    let token_program = ctx
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .expect("mint not found")
+        .owner;
+    let ix = spl_token_2022::instruction::mint_to(
+        &token_program,
+        mint,
+        account,
+        &ctx.payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    send_ok(ctx, &[ix], &[]).await;
+}
+
+/// Approve `delegate` to move up to `amount` out of `owner`'s token account
+pub async fn approve(
+    ctx: &mut ProgramTestContext,
+    account: &Pubkey,
+    delegate: &Pubkey,
+    owner: &Keypair,
+    amount: u64,
+) {
+    let ix = spl_token::instruction::approve(
+        &spl_token::ID,
+        account,
+        delegate,
+        &owner.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    send_ok(ctx, &[ix], &[owner]).await;
+}
+
+pub async fn token_balance(ctx: &mut ProgramTestContext, account: Pubkey) -> u64 {
+    let account = ctx
+        .banks_client
+        .get_account(account)
+        .await
+        .unwrap()
+        .expect("token account not found");
+    StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+        .unwrap()
+        .base
+        .amount
+}
+
+/// The delegate approved on a token account with an unspent allowance, if any
+pub async fn token_delegate(ctx: &mut ProgramTestContext, account: Pubkey) -> Option<Pubkey> {
+    let account = ctx
+        .banks_client
+        .get_account(account)
+        .await
+        .unwrap()
+        .expect("token account not found");
+    let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+        .unwrap()
+        .base;
+    Option::from(state.delegate).filter(|_| state.delegated_amount > 0)
+}
+
+// ========== PDAS ==========
+
+pub fn market_pda(creator: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"market", creator.as_ref()], &market_factory::ID).0
+}
+
+pub fn escrow_pda(market: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"escrow", market.as_ref()], &market_factory::ID).0
+}
+
+pub fn sol_vault_pda(market: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"sol_vault", market.as_ref()], &market_factory::ID).0
+}
+
+pub fn bet_log_pda(market: &Pubkey, depositor: &Pubkey, bet_index: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"bet",
+            market.as_ref(),
+            depositor.as_ref(),
+            &bet_index.to_le_bytes(),
+        ],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn ledger_page_pda(market: &Pubkey, page_index: u32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"ledger", market.as_ref(), &page_index.to_le_bytes()],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn receipt_mint_pda(bet_log: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"receipt", bet_log.as_ref()], &market_factory::ID).0
+}
+
+/// `owner`'s associated token account for a bet's receipt
+pub fn receipt_account(bet_log: &Pubkey, owner: &Pubkey) -> Pubkey {
+    get_associated_token_address(owner, &receipt_mint_pda(bet_log))
+}
+
+pub fn session_pda(owner: &Pubkey, session_key: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"session", owner.as_ref(), session_key.as_ref()],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn position_pda(market: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"position", market.as_ref(), owner.as_ref()],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn referral_pda(market: &Pubkey, referrer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"referral", market.as_ref(), referrer.as_ref()],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn parlay_pool_pda(legs: &[Pubkey]) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"parlay_pool", &market_factory::parlay_legs_hash(legs)],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn parlay_escrow_pda(pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"parlay_escrow", pool.as_ref()], &market_factory::ID).0
+}
+
+pub fn parlay_pda(pool: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"parlay", pool.as_ref(), owner.as_ref()],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn template_pda(creator: &Pubkey, template_id: u32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"template", creator.as_ref(), &template_id.to_le_bytes()],
+        &market_factory::ID,
+    )
+    .0
+}
+
+/// Instance `index` of `template`
+pub fn template_market_pda(template: &Pubkey, index: u32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"market",
+            &market_factory::template_market_seed(template, index),
+        ],
+        &market_factory::ID,
+    )
+    .0
+}
+
+pub fn resolution_job_pda(market: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"rqueue", market.as_ref()], &market_factory::ID).0
+}
+
+// ========== INSTRUCTIONS ==========
+
+/// Default `create_market` arguments: on-chain bet storage
+pub fn create_market_args(
+    question: &str,
+    deadline: i64,
+) -> market_factory::instruction::CreateMarket {
+    market_factory::instruction::CreateMarket {
+        question: question.to_string(),
+        deadline,
+        mxe_program_id: mxe_authority().pubkey(),
+        resolver: mxe_authority().pubkey(),
+        bet_storage: BetStorage::OnChain,
+        blob_store_uri: String::new(),
+        withdrawal_penalty_bps: 0,
+        stake_limits: StakeLimits::default(),
+        open_time: None,
+        resolve_after: None,
+        collateral: Collateral::Token,
+        allowlist_root: None,
+        creator_fee_bps: 0,
+        referrer_fee_bps: 0,
+        receipt_tokens: false,
+        lmsr_liquidity: None,
+        subsidy: 0,
+        time_decay: None,
+        condition: None,
+        metadata: MarketMetadata::default(),
+    }
+}
+
+pub fn create_market_ix_with(
+    creator: &Pubkey,
+    token_mint: &Pubkey,
+    args: market_factory::instruction::CreateMarket,
+) -> Instruction {
+    let parent = args.condition.map(|condition| condition.market);
+    create_market_with_parent_ix(creator, token_mint, args, parent, None)
+}
+
+/// `create_market_ix_with` for a market conditioned on a parent created by
+/// `parent_creator`, who signs to consent
+pub fn create_conditional_market_ix(
+    creator: &Pubkey,
+    token_mint: &Pubkey,
+    args: market_factory::instruction::CreateMarket,
+    parent_creator: &Pubkey,
+) -> Instruction {
+    let parent = args.condition.map(|condition| condition.market);
+    create_market_with_parent_ix(creator, token_mint, args, parent, Some(parent_creator))
+}
+
+/// `create_market_ix_with` passing `parent` instead of the parent named in
+/// `args.condition`, and `parent_creator` as the consenting signer
+pub fn create_market_with_parent_ix(
+    creator: &Pubkey,
+    token_mint: &Pubkey,
+    args: market_factory::instruction::CreateMarket,
+    parent: Option<Pubkey>,
+    parent_creator: Option<&Pubkey>,
+) -> Instruction {
+    let market = market_pda(creator);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CreateMarket {
+            market,
+            escrow_vault: Some(escrow_pda(&market)),
+            token_mint: Some(*token_mint),
+            sol_vault: None,
+            creator_token_account: None,
+            creator: *creator,
+            sponsor: None,
+            parent_market: parent,
+            parent_creator: parent_creator.copied(),
+            system_program: system_program::ID,
+            token_program: Some(spl_token::ID),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: args.data(),
+    }
+}
+
+/// Native SOL market; `args.collateral` is overridden
+pub fn create_sol_market_ix(
+    creator: &Pubkey,
+    mut args: market_factory::instruction::CreateMarket,
+) -> Instruction {
+    let market = market_pda(creator);
+    args.collateral = Collateral::NativeSol;
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CreateMarket {
+            market,
+            escrow_vault: None,
+            token_mint: None,
+            sol_vault: Some(sol_vault_pda(&market)),
+            creator_token_account: None,
+            creator: *creator,
+            sponsor: None,
+            parent_market: args.condition.map(|condition| condition.market),
+            parent_creator: None,
+            system_program: system_program::ID,
+            token_program: None,
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: args.data(),
+    }
+}
+
+/// LMSR market with liquidity `b`, funded from `creator_tokens`
+pub fn create_lmsr_market_ix(
+    creator: &Pubkey,
+    token_mint: &Pubkey,
+    creator_tokens: &Pubkey,
+    question: &str,
+    deadline: i64,
+    liquidity: u64,
+) -> Instruction {
+    let market = market_pda(creator);
+    let mut args = create_market_args(question, deadline);
+    args.lmsr_liquidity = Some(liquidity);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CreateMarket {
+            market,
+            escrow_vault: Some(escrow_pda(&market)),
+            token_mint: Some(*token_mint),
+            sol_vault: None,
+            creator_token_account: Some(*creator_tokens),
+            creator: *creator,
+            sponsor: None,
+            parent_market: args.condition.map(|condition| condition.market),
+            parent_creator: None,
+            system_program: system_program::ID,
+            token_program: Some(spl_token::ID),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: args.data(),
+    }
+}
+
+/// Token market whose `args.subsidy` comes out of `funder_tokens`, owned by
+/// `sponsor` or, without one, the creator
+pub fn create_subsidized_market_ix(
+    creator: &Pubkey,
+    token_mint: &Pubkey,
+    sponsor: Option<&Pubkey>,
+    funder_tokens: &Pubkey,
+    args: market_factory::instruction::CreateMarket,
+) -> Instruction {
+    let market = market_pda(creator);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CreateMarket {
+            market,
+            escrow_vault: Some(escrow_pda(&market)),
+            token_mint: Some(*token_mint),
+            sol_vault: None,
+            creator_token_account: Some(*funder_tokens),
+            creator: *creator,
+            sponsor: sponsor.copied(),
+            parent_market: args.condition.map(|condition| condition.market),
+            parent_creator: None,
+            system_program: system_program::ID,
+            token_program: Some(spl_token::ID),
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: args.data(),
+    }
+}
+
+pub fn create_market_ix(
+    creator: &Pubkey,
+    token_mint: &Pubkey,
+    question: &str,
+    deadline: i64,
+) -> Instruction {
+    create_market_ix_with(creator, token_mint, create_market_args(question, deadline))
+}
+
+/// Default `create_market_template` arguments: weekly binary token template
+pub fn create_market_template_args(
+    question_pattern: &str,
+    first_deadline: i64,
+) -> market_factory::instruction::CreateMarketTemplate {
+    market_factory::instruction::CreateMarketTemplate {
+        template_id: 0,
+        question_pattern: question_pattern.to_string(),
+        duration: 86_400,
+        cadence: 7 * 86_400,
+        outcome_count: 2,
+        creator_fee_bps: 0,
+        oracle: OracleConfig {
+            mxe_program_id: mxe_authority().pubkey(),
+            resolver: mxe_authority().pubkey(),
+            resolve_delay: None,
+        },
+        collateral: Collateral::Token,
+        first_deadline,
+        metadata: MarketMetadata::default(),
+    }
+}
+
+/// `token_mint` is `None` for native SOL templates
+pub fn create_market_template_ix(
+    creator: &Pubkey,
+    token_mint: Option<&Pubkey>,
+    args: market_factory::instruction::CreateMarketTemplate,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CreateMarketTemplate {
+            template: template_pda(creator, args.template_id),
+            token_mint: token_mint.copied(),
+            creator: *creator,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: args.data(),
+    }
+}
+
+/// Spawn instance `index`; `token_mint` is `None` for native SOL templates
+pub fn spawn_market_ix(
+    template: &Pubkey,
+    index: u32,
+    token_mint: Option<&Pubkey>,
+    payer: &Pubkey,
+) -> Instruction {
+    let market = template_market_pda(template, index);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::SpawnMarket {
+            template: *template,
+            market,
+            escrow_vault: token_mint.map(|_| escrow_pda(&market)),
+            token_mint: token_mint.copied(),
+            sol_vault: token_mint.is_none().then(|| sol_vault_pda(&market)),
+            payer: *payer,
+            system_program: system_program::ID,
+            token_program: token_mint.map(|_| spl_token::ID),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::SpawnMarket {}.data(),
+    }
+}
+
+/// The market mint and the wallet's token account, or `None` for native SOL markets
+type TokenSide<'a> = Option<(&'a Pubkey, &'a Pubkey)>;
+
+/// The collateral-dependent accounts of a stake-moving instruction
+struct CollateralAccounts {
+    escrow_vault: Option<Pubkey>,
+    token_mint: Option<Pubkey>,
+    wallet_tokens: Option<Pubkey>,
+    sol_vault: Option<Pubkey>,
+    token_program: Option<Pubkey>,
+}
+
+fn collateral_accounts(market: &Pubkey, tokens: TokenSide) -> CollateralAccounts {
+    match tokens {
+        Some((mint, wallet_tokens)) => CollateralAccounts {
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*mint),
+            wallet_tokens: Some(*wallet_tokens),
+            sol_vault: None,
+            token_program: Some(spl_token::ID),
+        },
+        None => CollateralAccounts {
+            escrow_vault: None,
+            token_mint: None,
+            wallet_tokens: None,
+            sol_vault: Some(sol_vault_pda(market)),
+            token_program: None,
+        },
+    }
+}
+
+fn deposit_bet_accounts(
+    market: &Pubkey,
+    tokens: TokenSide,
+    user: &Pubkey,
+    bet_index: u64,
+) -> market_factory::accounts::DepositBet {
+    let CollateralAccounts {
+        escrow_vault,
+        token_mint,
+        wallet_tokens: user_token_account,
+        sol_vault,
+        token_program,
+    } = collateral_accounts(market, tokens);
+    market_factory::accounts::DepositBet {
+        market: *market,
+        bet_log: bet_log_pda(market, user, bet_index),
+        position: position_pda(market, user),
+        escrow_vault,
+        token_mint,
+        user_token_account,
+        sol_vault,
+        user: *user,
+        authority: *user,
+        session: None,
+        payer: *user,
+        referrer: None,
+        referral: None,
+        receipt_mint: None,
+        receipt_account: None,
+        token_program,
+        associated_token_program: None,
+        system_program: system_program::ID,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    encrypted_blob: Vec<u8>,
+    choice: u8,
+    amount: u64,
+) -> Instruction {
+    deposit_bet_with_proof_ix(
+        market,
+        token_mint,
+        user,
+        user_token_account,
+        bet_index,
+        encrypted_blob,
+        choice,
+        amount,
+        Vec::new(),
+    )
+}
+
+/// On-chain deposit that credits `referrer` with the market's referral cut
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_referred_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    amount: u64,
+    referrer: &Pubkey,
+) -> Instruction {
+    let mut accounts = deposit_bet_accounts(
+        market,
+        Some((token_mint, user_token_account)),
+        user,
+        bet_index,
+    );
+    accounts.referrer = Some(*referrer);
+    accounts.referral = Some(referral_pda(market, referrer));
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::DepositBet {
+            encrypted_blob: vec![1; 9],
+            choice: 1,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+/// Bet recorded for `user` but submitted by a relayer: `payer` covers the
+/// rent and `authority` (the user or an approved delegate) moves the stake.
+/// `user_token_account` is `None` for native SOL markets.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_relayed_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: Option<&Pubkey>,
+    bet_index: u64,
+    amount: u64,
+    authority: &Pubkey,
+    payer: &Pubkey,
+) -> Instruction {
+    let tokens = user_token_account.map(|account| (token_mint, account));
+    let mut accounts = deposit_bet_accounts(market, tokens, user, bet_index);
+    accounts.authority = *authority;
+    accounts.payer = *payer;
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::DepositBet {
+            encrypted_blob: vec![1; 9],
+            choice: 1,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+/// Bet for `owner` signed and paid for by `session_key` through its session
+pub fn deposit_bet_session_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    bet_index: u64,
+    amount: u64,
+    session_key: &Pubkey,
+) -> Instruction {
+    let mut accounts = deposit_bet_accounts(
+        market,
+        Some((token_mint, owner_token_account)),
+        owner,
+        bet_index,
+    );
+    accounts.authority = *session_key;
+    accounts.session = Some(session_pda(owner, session_key));
+    accounts.payer = *session_key;
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::DepositBet {
+            encrypted_blob: vec![1; 9],
+            choice: 1,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+/// Bet on a receipt market; the receipt lands in `user`'s associated account
+pub fn deposit_bet_receipt_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = deposit_bet_accounts(
+        market,
+        Some((token_mint, user_token_account)),
+        user,
+        bet_index,
+    );
+    accounts.receipt_mint = Some(receipt_mint_pda(&accounts.bet_log));
+    accounts.receipt_account = Some(receipt_account(&accounts.bet_log, user));
+    accounts.associated_token_program = Some(anchor_spl::associated_token::ID);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::DepositBet {
+            encrypted_blob: vec![1; 9],
+            choice: 1,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+/// For allowlisted markets; `allowlist_proof` runs from the leaf's sibling up
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_with_proof_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    encrypted_blob: Vec<u8>,
+    choice: u8,
+    amount: u64,
+    allowlist_proof: Vec<[u8; 32]>,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: deposit_bet_accounts(
+            market,
+            Some((token_mint, user_token_account)),
+            user,
+            bet_index,
+        )
+        .to_account_metas(None),
+        data: market_factory::instruction::DepositBet {
+            encrypted_blob,
+            choice,
+            amount,
+            allowlist_proof,
+        }
+        .data(),
+    }
+}
+
+pub fn deposit_bet_sol_ix(
+    market: &Pubkey,
+    user: &Pubkey,
+    bet_index: u64,
+    encrypted_blob: Vec<u8>,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: deposit_bet_accounts(market, None, user, bet_index).to_account_metas(None),
+        data: market_factory::instruction::DepositBet {
+            encrypted_blob,
+            choice: 0,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+pub fn deposit_bet_commitment_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    bet_index: u64,
+    blob_hash: [u8; 32],
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: deposit_bet_accounts(
+            market,
+            Some((token_mint, user_token_account)),
+            user,
+            bet_index,
+        )
+        .to_account_metas(None),
+        data: market_factory::instruction::DepositBetCommitment {
+            blob_hash,
+            choice: 0,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+fn withdraw_bet_accounts(
+    market: &Pubkey,
+    tokens: TokenSide,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+) -> market_factory::accounts::WithdrawBet {
+    let CollateralAccounts {
+        escrow_vault,
+        token_mint,
+        wallet_tokens: depositor_token_account,
+        sol_vault,
+        token_program,
+    } = collateral_accounts(market, tokens);
+    market_factory::accounts::WithdrawBet {
+        market: *market,
+        bet_log: *bet_log,
+        position: position_pda(market, depositor),
+        referral: None,
+        escrow_vault,
+        token_mint,
+        depositor_token_account,
+        sol_vault,
+        depositor: *depositor,
+        token_program,
+    }
+}
+
+pub fn withdraw_bet_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: withdraw_bet_accounts(
+            market,
+            Some((token_mint, depositor_token_account)),
+            bet_log,
+            depositor,
+        )
+        .to_account_metas(None),
+        data: market_factory::instruction::WithdrawBet {}.data(),
+    }
+}
+
+/// Withdrawal of a bet referred by `referrer`, which passes its referral
+pub fn withdraw_bet_referred_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    referrer: &Pubkey,
+) -> Instruction {
+    let mut accounts = withdraw_bet_accounts(
+        market,
+        Some((token_mint, depositor_token_account)),
+        bet_log,
+        depositor,
+    );
+    accounts.referral = Some(referral_pda(market, referrer));
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::WithdrawBet {}.data(),
+    }
+}
+
+pub fn withdraw_bet_sol_ix(market: &Pubkey, bet_log: &Pubkey, depositor: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: withdraw_bet_accounts(market, None, bet_log, depositor).to_account_metas(None),
+        data: market_factory::instruction::WithdrawBet {}.data(),
+    }
+}
+
+fn amend_bet_accounts(
+    market: &Pubkey,
+    tokens: TokenSide,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+) -> market_factory::accounts::AmendBet {
+    let CollateralAccounts {
+        escrow_vault,
+        token_mint,
+        wallet_tokens: depositor_token_account,
+        sol_vault,
+        token_program,
+    } = collateral_accounts(market, tokens);
+    market_factory::accounts::AmendBet {
+        market: *market,
+        bet_log: *bet_log,
+        position: position_pda(market, depositor),
+        referral: None,
+        escrow_vault,
+        token_mint,
+        depositor_token_account,
+        sol_vault,
+        depositor: *depositor,
+        token_program,
+        system_program: system_program::ID,
+    }
+}
+
+pub fn amend_bet_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    encrypted_blob: Vec<u8>,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: amend_bet_accounts(
+            market,
+            Some((token_mint, depositor_token_account)),
+            bet_log,
+            depositor,
+        )
+        .to_account_metas(None),
+        data: market_factory::instruction::AmendBet {
+            encrypted_blob,
+            choice: 0,
+            amount,
+        }
+        .data(),
+    }
+}
+
+/// Amendment of a bet referred by `referrer`, which passes its referral
+#[allow(clippy::too_many_arguments)]
+pub fn amend_bet_referred_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    encrypted_blob: Vec<u8>,
+    amount: u64,
+    referrer: &Pubkey,
+) -> Instruction {
+    let mut accounts = amend_bet_accounts(
+        market,
+        Some((token_mint, depositor_token_account)),
+        bet_log,
+        depositor,
+    );
+    accounts.referral = Some(referral_pda(market, referrer));
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::AmendBet {
+            encrypted_blob,
+            choice: 0,
+            amount,
+        }
+        .data(),
+    }
+}
+
+pub fn amend_bet_sol_ix(
+    market: &Pubkey,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    encrypted_blob: Vec<u8>,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: amend_bet_accounts(market, None, bet_log, depositor).to_account_metas(None),
+        data: market_factory::instruction::AmendBet {
+            encrypted_blob,
+            choice: 0,
+            amount,
+        }
+        .data(),
+    }
+}
+
+pub fn amend_bet_commitment_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    bet_log: &Pubkey,
+    depositor: &Pubkey,
+    depositor_token_account: &Pubkey,
+    blob_hash: [u8; 32],
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: amend_bet_accounts(
+            market,
+            Some((token_mint, depositor_token_account)),
+            bet_log,
+            depositor,
+        )
+        .to_account_metas(None),
+        data: market_factory::instruction::AmendBetCommitment {
+            blob_hash,
+            choice: 0,
+            amount,
+        }
+        .data(),
+    }
+}
+
+pub fn open_ledger_page_ix(market: &Pubkey, payer: &Pubkey, page_index: u32) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::OpenLedgerPage {
+            market: *market,
+            ledger_page: ledger_page_pda(market, page_index),
+            payer: *payer,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::OpenLedgerPage { page_index }.data(),
+    }
+}
+
+fn deposit_bet_ledger_accounts(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    page_index: u32,
+) -> market_factory::accounts::DepositBetLedger {
+    market_factory::accounts::DepositBetLedger {
+        market: *market,
+        ledger_page: ledger_page_pda(market, page_index),
+        position: position_pda(market, user),
+        escrow_vault: Some(escrow_pda(market)),
+        token_mint: Some(*token_mint),
+        user_token_account: Some(*user_token_account),
+        sol_vault: None,
+        user: *user,
+        authority: *user,
+        session: None,
+        payer: *user,
+        referrer: None,
+        referral: None,
+        token_program: Some(spl_token::ID),
+        system_program: system_program::ID,
+    }
+}
+
+fn deposit_bet_ledger_with(
+    accounts: market_factory::accounts::DepositBetLedger,
+    blob_hash: [u8; 32],
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: accounts.to_account_metas(None),
+        data: market_factory::instruction::DepositBetLedger {
+            blob_hash,
+            amount,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+/// `page_index` must be the market's current page (`bet_count / LEDGER_PAGE_CAPACITY`)
+pub fn deposit_bet_ledger_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    page_index: u32,
+    blob_hash: [u8; 32],
+    amount: u64,
+) -> Instruction {
+    let accounts =
+        deposit_bet_ledger_accounts(market, token_mint, user, user_token_account, page_index);
+    deposit_bet_ledger_with(accounts, blob_hash, amount)
+}
+
+/// Ledger bet recorded for `user` as with `deposit_bet_relayed_ix`: `payer`
+/// covers the position and referral rent and `authority` moves the stake
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_bet_ledger_relayed_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    page_index: u32,
+    amount: u64,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    referrer: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts =
+        deposit_bet_ledger_accounts(market, token_mint, user, user_token_account, page_index);
+    accounts.authority = *authority;
+    accounts.payer = *payer;
+    accounts.referrer = referrer.copied();
+    accounts.referral = referrer.map(|referrer| referral_pda(market, referrer));
+    deposit_bet_ledger_with(accounts, [7; 32], amount)
+}
+
+/// Ledger bet for `owner` signed and paid for by `session_key` through its session
+pub fn deposit_bet_ledger_session_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    page_index: u32,
+    amount: u64,
+    session_key: &Pubkey,
+) -> Instruction {
+    let mut accounts =
+        deposit_bet_ledger_accounts(market, token_mint, owner, owner_token_account, page_index);
+    accounts.authority = *session_key;
+    accounts.session = Some(session_pda(owner, session_key));
+    accounts.payer = *session_key;
+    deposit_bet_ledger_with(accounts, [7; 32], amount)
+}
+
+pub fn close_ledger_page_ix(market: &Pubkey, page_index: u32, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseLedgerPage {
+            market: *market,
+            ledger_page: ledger_page_pda(market, page_index),
+            payer: *payer,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseLedgerPage {}.data(),
+    }
+}
+
+pub fn enqueue_resolution_ix(market: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::EnqueueResolution {
+            market: *market,
+            resolution_job: resolution_job_pda(market),
+            payer: *payer,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::EnqueueResolution {}.data(),
+    }
+}
+
+/// MXE result for `market` settling YES with nothing to pay out
+pub fn mxe_result(market: &Pubkey) -> MxeResult {
+    MxeResult {
+        market_id: hex::encode(market),
+        winning_choice: 1,
+        ..MxeResult::default()
+    }
+}
+
+pub fn mxe_payout(recipient: &Pubkey, payout: u64) -> MxePayout {
+    MxePayout {
+        recipient: hex::encode(recipient),
+        payout,
+    }
+}
+
+/// The JSON bytes the MXE signs and `callback_settle` hashes
+pub fn encode_result(result: &MxeResult) -> Vec<u8> {
+    serde_json::to_vec(result).unwrap()
+}
+
+/// Token-market settlement paying each nonzero payout of `result` to the
+/// matching entry of `payout_accounts`
+pub fn callback_settle_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    result: &MxeResult,
+    payout_accounts: &[Pubkey],
+) -> Instruction {
+    let mut accounts = market_factory::accounts::CallbackSettle {
+        market: *market,
+        resolution_job: resolution_job_pda(market),
+        resolver: mxe_authority().pubkey(),
+        escrow_vault: Some(escrow_pda(market)),
+        token_mint: Some(*token_mint),
+        sol_vault: None,
+        protocol_fee_account: None,
+        creator_fee_account: None,
+        token_program: Some(spl_token::ID),
+        parent_market: None,
+    }
+    .to_account_metas(None);
+    accounts.extend(
+        payout_accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    Instruction {
+        program_id: market_factory::ID,
+        accounts,
+        data: market_factory::instruction::CallbackSettle {
+            mxe_result: encode_result(result),
+            result_signature: vec![0x42; 64],
+        }
+        .data(),
+    }
+}
+
+/// Native SOL settlement; payouts go straight to the recipient wallets
+pub fn callback_settle_sol_ix(
+    market: &Pubkey,
+    result: &MxeResult,
+    payout_wallets: &[Pubkey],
+) -> Instruction {
+    let mut accounts = market_factory::accounts::CallbackSettle {
+        market: *market,
+        resolution_job: resolution_job_pda(market),
+        resolver: mxe_authority().pubkey(),
+        escrow_vault: None,
+        token_mint: None,
+        sol_vault: Some(sol_vault_pda(market)),
+        protocol_fee_account: None,
+        creator_fee_account: None,
+        token_program: None,
+        parent_market: None,
+    }
+    .to_account_metas(None);
+    accounts.extend(
+        payout_wallets
+            .iter()
+            .map(|wallet| AccountMeta::new(*wallet, false)),
+    );
+    Instruction {
+        program_id: market_factory::ID,
+        accounts,
+        data: market_factory::instruction::CallbackSettle {
+            mxe_result: encode_result(result),
+            result_signature: vec![0x42; 64],
+        }
+        .data(),
+    }
+}
+
+/// Token-market settlement naming the winning outcome, with nothing paid out
+/// directly; conditional markets pass their `parent`, and `None` voids them
+pub fn callback_settle_outcome_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    winning_outcome: Option<u8>,
+    parent: Option<&Pubkey>,
+    subsidy_refund: u64,
+) -> Instruction {
+    let result = MxeResult {
+        winning_choice: winning_outcome.unwrap_or(0),
+        subsidy_refund,
+        void: winning_outcome.is_none(),
+        ..mxe_result(market)
+    };
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CallbackSettle {
+            market: *market,
+            resolution_job: resolution_job_pda(market),
+            resolver: mxe_authority().pubkey(),
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*token_mint),
+            sol_vault: None,
+            protocol_fee_account: None,
+            creator_fee_account: None,
+            token_program: Some(spl_token::ID),
+            parent_market: parent.copied(),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CallbackSettle {
+            mxe_result: encode_result(&result),
+            result_signature: vec![0x42; 64],
+        }
+        .data(),
+    }
+}
+
+/// MXE result for `market` settling YES and charging `fees`
+pub fn fee_result(market: &Pubkey, fees: &FeeSplit) -> MxeResult {
+    let referral_fees: Vec<MxePayout> = fees
+        .referrals
+        .iter()
+        .map(|referral| mxe_payout(&referral.recipient, referral.amount))
+        .collect();
+    MxeResult {
+        fee_amount: fees.protocol
+            + fees.creator
+            + fees
+                .referrals
+                .iter()
+                .map(|referral| referral.amount)
+                .sum::<u64>(),
+        protocol_fee: fees.protocol,
+        creator_fee: fees.creator,
+        referral_fees,
+        ..mxe_result(market)
+    }
+}
+
+/// Token-market settlement that also pays `fees`. Each referral passes the
+/// recipient's referral PDA and the matching entry of `referrer_accounts`.
+pub fn callback_settle_with_fees_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    fees: FeeSplit,
+    protocol_fee_account: Option<Pubkey>,
+    creator_fee_account: Option<Pubkey>,
+    referrer_accounts: &[Pubkey],
+) -> Instruction {
+    let mut accounts = market_factory::accounts::CallbackSettle {
+        market: *market,
+        resolution_job: resolution_job_pda(market),
+        resolver: mxe_authority().pubkey(),
+        escrow_vault: Some(escrow_pda(market)),
+        token_mint: Some(*token_mint),
+        sol_vault: None,
+        protocol_fee_account,
+        creator_fee_account,
+        token_program: Some(spl_token::ID),
+        parent_market: None,
+    }
+    .to_account_metas(None);
+    for (referral, account) in fees.referrals.iter().zip(referrer_accounts) {
+        accounts.push(AccountMeta::new_readonly(
+            referral_pda(market, &referral.recipient),
+            false,
+        ));
+        accounts.push(AccountMeta::new(*account, false));
+    }
+    Instruction {
+        program_id: market_factory::ID,
+        accounts,
+        data: market_factory::instruction::CallbackSettle {
+            mxe_result: encode_result(&fee_result(market, &fees)),
+            result_signature: vec![0x42; 64],
+        }
+        .data(),
+    }
+}
+
+pub fn cancel_market_ix(market: &Pubkey, creator: &Pubkey, authority: &Pubkey) -> Instruction {
+    cancel_conditional_market_ix(market, creator, authority, None)
+}
+
+/// Conditional markets pass their `parent`
+pub fn cancel_conditional_market_ix(
+    market: &Pubkey,
+    creator: &Pubkey,
+    authority: &Pubkey,
+    parent: Option<&Pubkey>,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CancelMarket {
+            market: *market,
+            creator: *creator,
+            authority: *authority,
+            parent_market: parent.copied(),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CancelMarket {}.data(),
+    }
+}
+
+pub fn set_allowlist_root_ix(
+    market: &Pubkey,
+    creator: &Pubkey,
+    allowlist_root: Option<[u8; 32]>,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::SetAllowlistRoot {
+            market: *market,
+            creator: *creator,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::SetAllowlistRoot { allowlist_root }.data(),
+    }
+}
+
+pub fn create_session_ix(
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    session_key: &Pubkey,
+    expires_at: i64,
+    spend_cap: u64,
+    allowed_markets: Vec<Pubkey>,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CreateSession {
+            session: session_pda(owner, session_key),
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CreateSession {
+            session_key: *session_key,
+            expires_at,
+            spend_cap,
+            allowed_markets,
+        }
+        .data(),
+    }
+}
+
+pub fn revoke_session_ix(
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    session_key: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::RevokeSession {
+            session: session_pda(owner, session_key),
+            token_account: *owner_token_account,
+            owner: *owner,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::RevokeSession {}.data(),
+    }
+}
+
+pub fn buy_shares_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    outcome: u8,
+    shares: u64,
+    max_cost: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::BuyShares {
+            market: *market,
+            position: position_pda(market, user),
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*token_mint),
+            user_token_account: Some(*user_token_account),
+            sol_vault: None,
+            user: *user,
+            token_program: Some(spl_token::ID),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::BuyShares {
+            outcome,
+            shares,
+            max_cost,
+            allowlist_proof: Vec::new(),
+        }
+        .data(),
+    }
+}
+
+pub fn redeem_shares_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::RedeemShares {
+            market: *market,
+            position: position_pda(market, owner),
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*token_mint),
+            owner_token_account: Some(*owner_token_account),
+            sol_vault: None,
+            owner: *owner,
+            token_program: Some(spl_token::ID),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::RedeemShares {}.data(),
+    }
+}
+
+pub fn withdraw_liquidity_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    creator: &Pubkey,
+    creator_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::WithdrawLiquidity {
+            market: *market,
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*token_mint),
+            creator_token_account: Some(*creator_token_account),
+            sol_vault: None,
+            creator: *creator,
+            token_program: Some(spl_token::ID),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::WithdrawLiquidity {}.data(),
+    }
+}
+
+pub fn reclaim_subsidy_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    sponsor: &Pubkey,
+    sponsor_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::ReclaimSubsidy {
+            market: *market,
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*token_mint),
+            sponsor_token_account: Some(*sponsor_token_account),
+            sol_vault: None,
+            sponsor: *sponsor,
+            token_program: Some(spl_token::ID),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::ReclaimSubsidy {}.data(),
+    }
+}
+
+/// Leg markets ride along as read-only remaining accounts, in `legs` order
+fn with_parlay_legs(mut accounts: Vec<AccountMeta>, legs: &[Pubkey]) -> Vec<AccountMeta> {
+    accounts.extend(legs.iter().map(|leg| AccountMeta::new(*leg, false)));
+    accounts
+}
+
+pub fn create_parlay_pool_ix(legs: &[Pubkey], token_mint: &Pubkey, payer: &Pubkey) -> Instruction {
+    let pool = parlay_pool_pda(legs);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: with_parlay_legs(
+            market_factory::accounts::CreateParlayPool {
+                pool,
+                escrow_vault: parlay_escrow_pda(&pool),
+                token_mint: *token_mint,
+                payer: *payer,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            legs,
+        ),
+        data: market_factory::instruction::CreateParlayPool {
+            legs: legs.to_vec(),
+        }
+        .data(),
+    }
+}
+
+pub fn place_parlay_ix(
+    pool: &Pubkey,
+    token_mint: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    outcomes: u8,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::PlaceParlay {
+            pool: *pool,
+            parlay: parlay_pda(pool, owner),
+            escrow_vault: parlay_escrow_pda(pool),
+            token_mint: *token_mint,
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::PlaceParlay { outcomes, amount }.data(),
+    }
+}
+
+pub fn settle_parlay_pool_ix(pool: &Pubkey, legs: &[Pubkey]) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: with_parlay_legs(
+            market_factory::accounts::SettleParlayPool { pool: *pool }.to_account_metas(None),
+            legs,
+        ),
+        data: market_factory::instruction::SettleParlayPool {}.data(),
+    }
+}
+
+pub fn claim_parlay_ix(
+    pool: &Pubkey,
+    token_mint: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::ClaimParlay {
+            pool: *pool,
+            parlay: parlay_pda(pool, owner),
+            escrow_vault: parlay_escrow_pda(pool),
+            token_mint: *token_mint,
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::ClaimParlay {}.data(),
+    }
+}
+
+/// `holder` redeems from its associated receipt account into `holder_tokens`
+#[allow(clippy::too_many_arguments)]
+pub fn redeem_receipt_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    depositor: &Pubkey,
+    bet_index: u64,
+    holder: &Pubkey,
+    holder_tokens: &Pubkey,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    let bet_log = &bet_log_pda(market, depositor, bet_index);
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::RedeemReceipt {
+            market: *market,
+            bet_log: *bet_log,
+            position: position_pda(market, depositor),
+            referral: None,
+            receipt_mint: receipt_mint_pda(bet_log),
+            receipt_account: receipt_account(bet_log, holder),
+            holder: *holder,
+            depositor: *depositor,
+            escrow_vault: escrow_pda(market),
+            token_mint: *token_mint,
+            holder_token_account: *holder_tokens,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::RedeemReceipt {
+            bet_index,
+            amount,
+            proof,
+        }
+        .data(),
+    }
+}
+
+pub fn close_bet_log_ix(market: &Pubkey, bet_log: &Pubkey, depositor: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseBetLog {
+            market: *market,
+            bet_log: *bet_log,
+            depositor: *depositor,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseBetLog {}.data(),
+    }
+}
+
+pub fn close_position_ix(market: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::ClosePosition {
+            market: *market,
+            position: position_pda(market, owner),
+            owner: *owner,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::ClosePosition {}.data(),
+    }
+}
+
+pub fn close_referral_ix(market: &Pubkey, referrer: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseReferral {
+            market: *market,
+            referral: referral_pda(market, referrer),
+            payer: *payer,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseReferral {}.data(),
+    }
+}
+
+pub fn close_resolution_job_ix(market: &Pubkey, creator: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseResolutionJob {
+            market: *market,
+            resolution_job: resolution_job_pda(market),
+            creator: *creator,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseResolutionJob {}.data(),
+    }
+}
+
+pub fn close_escrow_ix(market: &Pubkey, creator: &Pubkey) -> Instruction {
+    close_escrow_with(market, None, creator)
+}
+
+/// Close a token escrow, sweeping any dust to `creator_token_account`
+pub fn close_escrow_sweep_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    creator: &Pubkey,
+    creator_token_account: &Pubkey,
+) -> Instruction {
+    close_escrow_with(market, Some((token_mint, creator_token_account)), creator)
+}
+
+fn close_escrow_with(
+    market: &Pubkey,
+    sweep: Option<(&Pubkey, &Pubkey)>,
+    creator: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseEscrow {
+            market: *market,
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: sweep.map(|(mint, _)| *mint),
+            creator_token_account: sweep.map(|(_, tokens)| *tokens),
+            sol_vault: None,
+            creator: *creator,
+            token_program: Some(spl_token::ID),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseEscrow {}.data(),
+    }
+}
+
+pub fn close_escrow_sol_ix(market: &Pubkey, creator: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseEscrow {
+            market: *market,
+            escrow_vault: None,
+            token_mint: None,
+            creator_token_account: None,
+            sol_vault: Some(sol_vault_pda(market)),
+            creator: *creator,
+            token_program: None,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseEscrow {}.data(),
+    }
+}
+
+fn close_market_with_vault(market: &Pubkey, escrow_vault: Pubkey, creator: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::CloseMarket {
+            market: *market,
+            escrow_vault,
+            resolution_job: resolution_job_pda(market),
+            creator: *creator,
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::CloseMarket {}.data(),
+    }
+}
+
+pub fn close_market_ix(market: &Pubkey, creator: &Pubkey) -> Instruction {
+    close_market_with_vault(market, escrow_pda(market), creator)
+}
+
+pub fn close_sol_market_ix(market: &Pubkey, creator: &Pubkey) -> Instruction {
+    close_market_with_vault(market, sol_vault_pda(market), creator)
+}
+
+/// Migrate `market` and the related `accounts`, passed as remaining accounts.
+/// `escrow_vault` is only needed for a version 0 market.
+pub fn migrate_market_ix(
+    market: &Pubkey,
+    escrow_vault: Option<Pubkey>,
+    accounts: &[Pubkey],
+    payer: &Pubkey,
+) -> Instruction {
+    let mut metas = market_factory::accounts::MigrateMarket {
+        market: *market,
+        escrow_vault,
+        payer: *payer,
+        system_program: system_program::ID,
+    }
+    .to_account_metas(None);
+    metas.extend(
+        accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: metas,
+        data: market_factory::instruction::MigrateMarket {}.data(),
+    }
+}
+
+pub fn update_resolution_job_ix<D: InstructionData>(
+    market: &Pubkey,
+    authority: &Pubkey,
+    data: D,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::UpdateResolutionJob {
+            market: *market,
+            resolution_job: resolution_job_pda(market),
+            authority: *authority,
+        }
+        .to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+pub fn mark_job_running_ix(market: &Pubkey, authority: &Pubkey) -> Instruction {
+    update_resolution_job_ix(
+        market,
+        authority,
+        market_factory::instruction::MarkJobRunning {},
+    )
+}
+
+pub fn mark_job_failed_ix(market: &Pubkey, authority: &Pubkey, error_code: u32) -> Instruction {
+    update_resolution_job_ix(
+        market,
+        authority,
+        market_factory::instruction::MarkJobFailed { error_code },
+    )
+}
+
+/// Conditional markets pass their `parent`
+pub fn void_stuck_market_ix(market: &Pubkey, parent: Option<&Pubkey>) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::VoidStuckMarket {
+            market: *market,
+            resolution_job: resolution_job_pda(market),
+            parent_market: parent.copied(),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::VoidStuckMarket {}.data(),
+    }
+}
+
+pub fn refund_position_ix(
+    market: &Pubkey,
+    token_mint: &Pubkey,
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: market_factory::ID,
+        accounts: market_factory::accounts::RefundPosition {
+            market: *market,
+            position: position_pda(market, owner),
+            escrow_vault: Some(escrow_pda(market)),
+            token_mint: Some(*token_mint),
+            owner_token_account: Some(*owner_token_account),
+            sol_vault: None,
+            owner: *owner,
+            token_program: Some(spl_token::ID),
+        }
+        .to_account_metas(None),
+        data: market_factory::instruction::RefundPosition {}.data(),
+    }
+}
+
+// ========== ERRORS ==========
+
+/// Map a failed transaction to the Anchor error code it carries, if any
+pub fn anchor_error_code(result: &Result<(), TransactionError>) -> Option<u32> {
+    use solana_sdk::instruction::InstructionError;
+    match result {
+        Err(TransactionError::InstructionError(_, InstructionError::Custom(code))) => Some(*code),
+        _ => None,
+    }
+}
+
+pub fn assert_error(outcome: &TxOutcome, expected: market_factory::ErrorCode) {
+    let expected: u32 = expected.into();
+    assert_eq!(
+        anchor_error_code(&outcome.result),
+        Some(expected),
+        "unexpected result {:?}\n{}",
+        outcome.result,
+        outcome.logs.join("\n")
+    );
+}
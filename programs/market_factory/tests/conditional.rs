@@ -0,0 +1,359 @@
+mod common;
+
+use common::*;
+use market_factory::{ErrorCode, Market, MarketCreated, MarketSettled, ParentCondition};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const NO: u8 = 0;
+const YES: u8 = 1;
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    mint: Pubkey,
+    deadline: i64,
+}
+
+async fn fixture() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    Fixture {
+        ctx,
+        mint,
+        deadline,
+    }
+}
+
+impl Fixture {
+    /// Plain market
+    async fn market(&mut self) -> (Keypair, Pubkey) {
+        let creator = new_funded_keypair(&mut self.ctx).await;
+        let args = create_market_args("If?", self.deadline);
+        send_ok(
+            &mut self.ctx,
+            &[create_market_ix_with(&creator.pubkey(), &self.mint, args)],
+            &[&creator],
+        )
+        .await;
+        let market = market_pda(&creator.pubkey());
+        (creator, market)
+    }
+
+    /// Market conditioned on `parent` resolving YES, with its creator's consent
+    async fn child(&mut self, parent_creator: &Keypair, parent: Pubkey) -> (Keypair, Pubkey) {
+        let creator = new_funded_keypair(&mut self.ctx).await;
+        let mut args = create_market_args("Then?", self.deadline);
+        args.condition = Some(ParentCondition {
+            market: parent,
+            outcome: YES,
+        });
+        let ix = create_conditional_market_ix(
+            &creator.pubkey(),
+            &self.mint,
+            args,
+            &parent_creator.pubkey(),
+        );
+        send_ok(&mut self.ctx, &[ix], &[&creator, parent_creator]).await;
+        let market = market_pda(&creator.pubkey());
+        (creator, market)
+    }
+
+    async fn settle(
+        &mut self,
+        market: &Pubkey,
+        parent: Option<&Pubkey>,
+        winning_outcome: Option<u8>,
+    ) -> TxOutcome {
+        let ix = callback_settle_outcome_ix(market, &self.mint, winning_outcome, parent, 0);
+        send(&mut self.ctx, &[ix], &[&mxe_authority()]).await
+    }
+}
+
+#[tokio::test]
+async fn condition_is_validated_at_creation() {
+    let mut f = fixture().await;
+    let (parent_creator, parent) = f.market().await;
+
+    let (settled_creator, settled) = f.market().await;
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(
+            &settled,
+            &settled_creator.pubkey(),
+            &settled_creator.pubkey(),
+        )],
+        &[&settled_creator],
+    )
+    .await;
+
+    let creator = new_funded_keypair(&mut f.ctx).await;
+    let own = market_pda(&creator.pubkey());
+    let cases = [
+        (parent, Some(parent), 2, ErrorCode::InvalidOutcome),
+        (own, None, YES, ErrorCode::InvalidCondition),
+        // A parent that is already final cannot decide anything
+        (settled, Some(settled), YES, ErrorCode::InvalidCondition),
+    ];
+    for (market, parent_account, outcome, expected) in cases {
+        let mut args = create_market_args("Then?", f.deadline);
+        args.condition = Some(ParentCondition { market, outcome });
+        let ix =
+            create_market_with_parent_ix(&creator.pubkey(), &f.mint, args, parent_account, None);
+        let outcome = send(&mut f.ctx, &[ix], &[&creator]).await;
+        assert_error(&outcome, expected);
+    }
+
+    // The parent account must be the one the condition names, and exist
+    let condition = Some(ParentCondition {
+        market: parent,
+        outcome: YES,
+    });
+    for parent_account in [None, Some(settled)] {
+        let mut args = create_market_args("Then?", f.deadline);
+        args.condition = condition;
+        let ix =
+            create_market_with_parent_ix(&creator.pubkey(), &f.mint, args, parent_account, None);
+        let outcome = send(&mut f.ctx, &[ix], &[&creator]).await;
+        assert_error(&outcome, ErrorCode::ParentMarketMismatch);
+    }
+    let mut args = create_market_args("Then?", f.deadline);
+    args.condition = Some(ParentCondition {
+        market: Pubkey::new_unique(),
+        outcome: YES,
+    });
+    let outcome = send(
+        &mut f.ctx,
+        &[create_market_ix_with(&creator.pubkey(), &f.mint, args)],
+        &[&creator],
+    )
+    .await;
+    assert!(outcome.result.is_err());
+
+    // LMSR markets cannot be voided after trading
+    let creator_tokens = create_token_account(&mut f.ctx, &f.mint, &creator.pubkey()).await;
+    mint_to(&mut f.ctx, &f.mint, &creator_tokens, 1_000).await;
+    let mut args = create_market_args("Then?", f.deadline);
+    args.lmsr_liquidity = Some(1_000);
+    args.condition = Some(ParentCondition {
+        market: parent,
+        outcome: YES,
+    });
+    let outcome = send(
+        &mut f.ctx,
+        &[create_subsidized_market_ix(
+            &creator.pubkey(),
+            &f.mint,
+            None,
+            &creator_tokens,
+            args,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongMarketKind);
+
+    let condition = ParentCondition {
+        market: parent,
+        outcome: YES,
+    };
+    let mut args = create_market_args("Then?", f.deadline);
+    args.condition = Some(condition);
+    let logs = send_ok(
+        &mut f.ctx,
+        &[create_conditional_market_ix(
+            &creator.pubkey(),
+            &f.mint,
+            args,
+            &parent_creator.pubkey(),
+        )],
+        &[&creator, &parent_creator],
+    )
+    .await;
+    assert_eq!(
+        decode_events::<MarketCreated>(&logs)[0].condition,
+        Some(condition)
+    );
+    let market: Market = fetch(&mut f.ctx, market_pda(&creator.pubkey())).await;
+    assert_eq!(market.condition, Some(condition));
+    assert_eq!(
+        fetch::<Market>(&mut f.ctx, parent).await.open_child_count,
+        1
+    );
+
+    // Cancelling the child releases the parent
+    let child = market_pda(&creator.pubkey());
+    let cancel =
+        |parent| cancel_conditional_market_ix(&child, &creator.pubkey(), &creator.pubkey(), parent);
+    let outcome = send(&mut f.ctx, &[cancel(None)], &[&creator]).await;
+    assert_error(&outcome, ErrorCode::ParentMarketMismatch);
+    send_ok(&mut f.ctx, &[cancel(Some(&parent))], &[&creator]).await;
+    assert_eq!(
+        fetch::<Market>(&mut f.ctx, parent).await.open_child_count,
+        0
+    );
+}
+
+#[tokio::test]
+async fn child_settles_after_its_parent() {
+    let mut f = fixture().await;
+    let (parent_creator, parent) = f.market().await;
+    let (_, other) = f.market().await;
+    let (_, child) = f.child(&parent_creator, parent).await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[
+            enqueue_resolution_ix(&parent, &payer),
+            enqueue_resolution_ix(&child, &payer),
+        ],
+        &[],
+    )
+    .await;
+
+    let cases = [
+        (None, ErrorCode::ParentMarketMismatch),
+        (Some(&other), ErrorCode::ParentMarketMismatch),
+        (Some(&parent), ErrorCode::ParentNotFinalized),
+    ];
+    for (parent_account, expected) in cases {
+        let outcome = f.settle(&child, parent_account, Some(YES)).await;
+        assert_error(&outcome, expected);
+    }
+
+    let outcome = f.settle(&parent, None, None).await;
+    assert_error(&outcome, ErrorCode::WinningOutcomeMismatch);
+    let outcome = f.settle(&parent, None, Some(YES)).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let settled = &decode_events::<MarketSettled>(&outcome.logs)[0];
+    assert_eq!(settled.winning_outcome, Some(YES));
+
+    // The parent outlives its child, so nothing else can take its address
+    let creator = parent_creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[
+            close_resolution_job_ix(&parent, &creator),
+            close_escrow_ix(&parent, &creator),
+        ],
+        &[&parent_creator],
+    )
+    .await;
+    let outcome = send(
+        &mut f.ctx,
+        &[close_market_ix(&parent, &creator)],
+        &[&parent_creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::AccountsStillOpen);
+
+    // The parent went the child's way, so the child cannot be voided
+    let outcome = f.settle(&child, Some(&parent), None).await;
+    assert_error(&outcome, ErrorCode::WinningOutcomeMismatch);
+    let outcome = f.settle(&child, Some(&parent), Some(NO)).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let market: Market = fetch(&mut f.ctx, child).await;
+    assert_eq!(market.winning_outcome, Some(NO));
+    assert!(!market.is_void());
+    send_ok(
+        &mut f.ctx,
+        &[close_market_ix(&parent, &creator)],
+        &[&parent_creator],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn child_is_void_unless_its_outcome_comes_in() {
+    let mut f = fixture().await;
+    let (settled_creator, settled_parent) = f.market().await;
+    let (parent_creator, cancelled_parent) = f.market().await;
+    let (child_creator, child) = f.child(&settled_creator, settled_parent).await;
+    let (_, orphan) = f.child(&parent_creator, cancelled_parent).await;
+    // Conditioned on a market that will itself be void
+    let (_, grandchild) = f.child(&child_creator, child).await;
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(
+            &cancelled_parent,
+            &parent_creator.pubkey(),
+            &parent_creator.pubkey(),
+        )],
+        &[&parent_creator],
+    )
+    .await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[
+            enqueue_resolution_ix(&settled_parent, &payer),
+            enqueue_resolution_ix(&child, &payer),
+            enqueue_resolution_ix(&orphan, &payer),
+            enqueue_resolution_ix(&grandchild, &payer),
+        ],
+        &[],
+    )
+    .await;
+    let outcome = f.settle(&settled_parent, None, Some(NO)).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+
+    for (market, parent) in [
+        (child, settled_parent),
+        (orphan, cancelled_parent),
+        (grandchild, child),
+    ] {
+        let outcome = f.settle(&market, Some(&parent), Some(YES)).await;
+        assert_error(&outcome, ErrorCode::WinningOutcomeMismatch);
+        let outcome = f.settle(&market, Some(&parent), None).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+        let settled = &decode_events::<MarketSettled>(&outcome.logs)[0];
+        assert_eq!(settled.winning_outcome, None);
+        assert!(fetch::<Market>(&mut f.ctx, market).await.is_void());
+    }
+}
+
+#[tokio::test]
+async fn only_the_parent_creator_can_hold_it_open() {
+    let mut f = fixture().await;
+    let (parent_creator, parent) = f.market().await;
+    let stranger = new_funded_keypair(&mut f.ctx).await;
+    let args = || {
+        let mut args = create_market_args("Then?", f.deadline);
+        args.condition = Some(ParentCondition {
+            market: parent,
+            outcome: YES,
+        });
+        args
+    };
+
+    let without_consent = create_market_ix_with(&stranger.pubkey(), &f.mint, args());
+    let outcome = send(&mut f.ctx, &[without_consent], &[&stranger]).await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+    let own_consent =
+        create_conditional_market_ix(&stranger.pubkey(), &f.mint, args(), &stranger.pubkey());
+    let outcome = send(&mut f.ctx, &[own_consent], &[&stranger]).await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+    assert_eq!(
+        fetch::<Market>(&mut f.ctx, parent).await.open_child_count,
+        0
+    );
+
+    // Without children the parent closes as usual
+    let creator = parent_creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[
+            cancel_market_ix(&parent, &creator, &creator),
+            close_escrow_ix(&parent, &creator),
+            close_market_ix(&parent, &creator),
+        ],
+        &[&parent_creator],
+    )
+    .await;
+}
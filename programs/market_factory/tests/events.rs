@@ -0,0 +1,147 @@
+mod common;
+
+use anchor_lang::solana_program::hash::hash;
+use common::*;
+use market_factory::{
+    BetDeposited, MarketCancelled, MarketCreated, MarketSettled, MxeResult, PayoutSent,
+    ResolutionEnqueued,
+};
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn lifecycle_events_are_decodable_from_logs() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+
+    let mint = create_mint(&mut ctx, &payer).await;
+    let bettor_tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &bettor_tokens, 1_000).await;
+
+    let deadline = now(&mut ctx).await + 3_600;
+    let market = market_pda(&creator.pubkey());
+    let logs = send_ok(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Will it rain?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+    let created = decode_events::<MarketCreated>(&logs);
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].market, market);
+    assert_eq!(created[0].creator, creator.pubkey());
+    assert_eq!(created[0].token_mint, mint);
+    assert_eq!(created[0].question, "Will it rain?");
+    assert_eq!(created[0].deadline, deadline);
+
+    for (bet_index, amount) in [(0u64, 100u64), (1, 250)] {
+        let logs = send_ok(
+            &mut ctx,
+            &[deposit_bet_ix(
+                &market,
+                &mint,
+                &bettor.pubkey(),
+                &bettor_tokens,
+                bet_index,
+                vec![1, 2, 3],
+                1,
+                amount,
+            )],
+            &[&bettor],
+        )
+        .await;
+        let deposited = decode_events::<BetDeposited>(&logs);
+        assert_eq!(deposited.len(), 1);
+        assert_eq!(deposited[0].bet_index, bet_index);
+        assert_eq!(deposited[0].amount, amount);
+        assert_eq!(
+            deposited[0].bet_log,
+            bet_log_pda(&market, &bettor.pubkey(), bet_index)
+        );
+    }
+
+    warp_to_timestamp(&mut ctx, deadline).await;
+    let logs = send_ok(&mut ctx, &[enqueue_resolution_ix(&market, &payer)], &[]).await;
+    let enqueued = decode_events::<ResolutionEnqueued>(&logs);
+    assert_eq!(enqueued.len(), 1);
+    assert_eq!(enqueued[0].resolution_job, resolution_job_pda(&market));
+    assert_eq!(enqueued[0].bet_count, 2);
+    assert_eq!(enqueued[0].total_pool, 350);
+
+    let result = MxeResult {
+        payouts: vec![
+            mxe_payout(&bettor.pubkey(), 100),
+            mxe_payout(&bettor.pubkey(), 250),
+        ],
+        ..mxe_result(&market)
+    };
+    let logs = send_ok(
+        &mut ctx,
+        &[callback_settle_ix(
+            &market,
+            &mint,
+            &result,
+            &[bettor_tokens, bettor_tokens],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    let paid = decode_events::<PayoutSent>(&logs);
+    assert_eq!(paid.len(), 2);
+    assert_eq!(paid[1].recipient, bettor.pubkey());
+    assert_eq!(paid[1].token_account, bettor_tokens);
+    assert_eq!(paid[1].amount, 250);
+    let settled = decode_events::<MarketSettled>(&logs);
+    assert_eq!(settled.len(), 1);
+    assert_eq!(
+        settled[0].result_hash,
+        hash(&encode_result(&result)).to_bytes()
+    );
+    assert_eq!(settled[0].total_pool, 350);
+    assert_eq!(settled[0].total_paid_out, 350);
+    assert_eq!(settled[0].payout_count, 2);
+}
+
+#[tokio::test]
+async fn cancel_emits_market_cancelled() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+
+    let deadline = now(&mut ctx).await + 3_600;
+    let market = market_pda(&creator.pubkey());
+    send_ok(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Cancelled?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+
+    let logs = send_ok(
+        &mut ctx,
+        &[cancel_market_ix(
+            &market,
+            &creator.pubkey(),
+            &creator.pubkey(),
+        )],
+        &[&creator],
+    )
+    .await;
+    let cancelled = decode_events::<MarketCancelled>(&logs);
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].market, market);
+    assert_eq!(cancelled[0].creator, creator.pubkey());
+}
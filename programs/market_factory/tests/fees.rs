@@ -0,0 +1,388 @@
+mod common;
+
+use anchor_lang::InstructionData;
+use common::*;
+use market_factory::{
+    BetLog, ErrorCode, FeeSplit, FeesPaid, Market, MarketState, Payout, Referral, PROTOCOL_TREASURY,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    mint: Pubkey,
+    market: Pubkey,
+    deadline: i64,
+}
+
+/// 2% creator cut, 1% referrer cut
+async fn market_with_fees() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let mut args = create_market_args("Who gets paid?", deadline);
+    args.creator_fee_bps = 200;
+    args.referrer_fee_bps = 100;
+    send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        creator,
+        mint,
+        market,
+        deadline,
+    }
+}
+
+impl Fixture {
+    async fn bettor(&mut self) -> (Keypair, Pubkey) {
+        let bettor = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &bettor.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, 1_000).await;
+        (bettor, tokens)
+    }
+
+    /// Alice bets 1000 referred by Bob, Carol 1000 on her own; then the
+    /// market is enqueued. Returns Bob's wallet.
+    async fn referred_pool(&mut self) -> Pubkey {
+        let (bob, _, _) = self.referred_bets().await;
+        self.enqueue().await;
+        bob
+    }
+
+    /// The bets of `referred_pool`, still open. Returns Bob's wallet and
+    /// Alice with her token account.
+    async fn referred_bets(&mut self) -> (Pubkey, Keypair, Pubkey) {
+        let (alice, alice_tokens) = self.bettor().await;
+        let (carol, carol_tokens) = self.bettor().await;
+        let bob = Pubkey::new_unique();
+        send_ok(
+            &mut self.ctx,
+            &[deposit_bet_referred_ix(
+                &self.market,
+                &self.mint,
+                &alice.pubkey(),
+                &alice_tokens,
+                0,
+                1_000,
+                &bob,
+            )],
+            &[&alice],
+        )
+        .await;
+        send_ok(
+            &mut self.ctx,
+            &[deposit_bet_ix(
+                &self.market,
+                &self.mint,
+                &carol.pubkey(),
+                &carol_tokens,
+                1,
+                vec![1; 9],
+                1,
+                1_000,
+            )],
+            &[&carol],
+        )
+        .await;
+        let log: BetLog = fetch(&mut self.ctx, bet_log_pda(&self.market, &alice.pubkey(), 0)).await;
+        assert_eq!(log.referrer, Some(bob));
+        (bob, alice, alice_tokens)
+    }
+
+    async fn enqueue(&mut self) {
+        warp_to_timestamp(&mut self.ctx, self.deadline).await;
+        let payer = self.ctx.payer.pubkey();
+        send_ok(
+            &mut self.ctx,
+            &[enqueue_resolution_ix(&self.market, &payer)],
+            &[],
+        )
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn settlement_pays_each_fee_cut() {
+    let mut f = market_with_fees().await;
+    let bob = f.referred_pool().await;
+    let treasury_tokens = create_token_account(&mut f.ctx, &f.mint, &PROTOCOL_TREASURY).await;
+    let creator_tokens = create_token_account(&mut f.ctx, &f.mint, &f.creator.pubkey()).await;
+    let bob_tokens = create_token_account(&mut f.ctx, &f.mint, &bob).await;
+
+    // 0.5% protocol and 2% creator of the 2000 pool, 1% of Alice's 1000 to Bob
+    let fees = FeeSplit {
+        protocol: 10,
+        creator: 40,
+        referrals: vec![Payout {
+            recipient: bob,
+            amount: 10,
+        }],
+    };
+    let logs = send_ok(
+        &mut f.ctx,
+        &[callback_settle_with_fees_ix(
+            &f.market,
+            &f.mint,
+            fees,
+            Some(treasury_tokens),
+            Some(creator_tokens),
+            &[bob_tokens],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    let event = &decode_events::<FeesPaid>(&logs)[0];
+    assert_eq!(
+        (event.protocol_fee, event.creator_fee, event.referral_fees),
+        (10, 40, 10)
+    );
+    assert_eq!(token_balance(&mut f.ctx, treasury_tokens).await, 10);
+    assert_eq!(token_balance(&mut f.ctx, creator_tokens).await, 40);
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 10);
+    assert_eq!(
+        token_balance(&mut f.ctx, escrow_pda(&f.market)).await,
+        1_940
+    );
+    assert!(fetch::<Market>(&mut f.ctx, f.market).await.state == MarketState::Settled);
+
+    let referral: Referral = fetch(&mut f.ctx, referral_pda(&f.market, &bob)).await;
+    assert_eq!((referral.referrer, referral.referred_stake), (bob, 1_000));
+}
+
+#[tokio::test]
+async fn fees_are_capped_and_routed_to_their_owners() {
+    let mut f = market_with_fees().await;
+    let bob = f.referred_pool().await;
+    let creator_tokens = create_token_account(&mut f.ctx, &f.mint, &f.creator.pubkey()).await;
+    let bob_tokens = create_token_account(&mut f.ctx, &f.mint, &bob).await;
+    // Dave referred nobody
+    let dave = Pubkey::new_unique();
+    let dave_tokens = create_token_account(&mut f.ctx, &f.mint, &dave).await;
+    let referral = |recipient, amount| Payout { recipient, amount };
+
+    let cases = [
+        // Above the creator's 2% of the pool, and Bob's 1% of the 1000 he
+        // referred though under 1% of the pool
+        (41, vec![], vec![], ErrorCode::FeeExceedsLimit),
+        (
+            0,
+            vec![referral(bob, 11)],
+            vec![bob_tokens],
+            ErrorCode::FeeExceedsLimit,
+        ),
+        // Creator fee sent to Bob's account
+        (40, vec![], vec![], ErrorCode::FeeRecipientMismatch),
+        // Referral without its account
+        (
+            0,
+            vec![referral(bob, 10)],
+            vec![],
+            ErrorCode::FeeRecipientMismatch,
+        ),
+        // Referral to a wallet no bet names as its referrer
+        (
+            0,
+            vec![referral(dave, 10)],
+            vec![dave_tokens],
+            ErrorCode::FeeRecipientMismatch,
+        ),
+        // Bob listed twice to get his cap twice
+        (
+            0,
+            vec![referral(bob, 10), referral(bob, 10)],
+            vec![bob_tokens, bob_tokens],
+            ErrorCode::InvalidMxeResult,
+        ),
+    ];
+    for (i, (creator, referrals, referrer_accounts, expected)) in cases.into_iter().enumerate() {
+        let creator_account = if i == 2 { bob_tokens } else { creator_tokens };
+        let outcome = send(
+            &mut f.ctx,
+            &[callback_settle_with_fees_ix(
+                &f.market,
+                &f.mint,
+                FeeSplit {
+                    protocol: 0,
+                    creator,
+                    referrals,
+                },
+                None,
+                Some(creator_account),
+                &referrer_accounts,
+            )],
+            &[&mxe_authority()],
+        )
+        .await;
+        assert_error(&outcome, expected);
+    }
+
+    // The cuts must add up to the result's fee total
+    let mut ix = callback_settle_with_fees_ix(
+        &f.market,
+        &f.mint,
+        FeeSplit::default(),
+        None,
+        Some(creator_tokens),
+        &[],
+    );
+    let mut result = fee_result(
+        &f.market,
+        &FeeSplit {
+            creator: 40,
+            ..FeeSplit::default()
+        },
+    );
+    result.fee_amount = 10;
+    ix.data = market_factory::instruction::CallbackSettle {
+        mxe_result: encode_result(&result),
+        result_signature: vec![0x42; 64],
+    }
+    .data();
+    let outcome = send(&mut f.ctx, &[ix], &[&mxe_authority()]).await;
+    assert_error(&outcome, ErrorCode::InvalidMxeResult);
+    assert!(fetch::<Market>(&mut f.ctx, f.market).await.state == MarketState::Enqueued);
+}
+
+#[tokio::test]
+async fn referral_follows_the_referred_stake() {
+    let mut f = market_with_fees().await;
+    let (bob, alice, alice_tokens) = f.referred_bets().await;
+    let bet_log = bet_log_pda(&f.market, &alice.pubkey(), 0);
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(market.referral_count, 1);
+
+    // A referred bet cannot change without its referral
+    let outcome = send(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet_log,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::ReferralAccountMismatch);
+    send_ok(
+        &mut f.ctx,
+        &[amend_bet_referred_ix(
+            &f.market,
+            &f.mint,
+            &bet_log,
+            &alice.pubkey(),
+            &alice_tokens,
+            vec![2; 9],
+            400,
+            &bob,
+        )],
+        &[&alice],
+    )
+    .await;
+    let referral: Referral = fetch(&mut f.ctx, referral_pda(&f.market, &bob)).await;
+    assert_eq!(referral.referred_stake, 400);
+    f.enqueue().await;
+
+    // Bob's cap is now 1% of 400
+    let bob_tokens = create_token_account(&mut f.ctx, &f.mint, &bob).await;
+    let settle = |amount| {
+        callback_settle_with_fees_ix(
+            &f.market,
+            &f.mint,
+            FeeSplit {
+                referrals: vec![Payout {
+                    recipient: bob,
+                    amount,
+                }],
+                ..FeeSplit::default()
+            },
+            None,
+            None,
+            &[bob_tokens],
+        )
+    };
+    let outcome = send(&mut f.ctx, &[settle(5)], &[&mxe_authority()]).await;
+    assert_error(&outcome, ErrorCode::FeeExceedsLimit);
+    send_ok(&mut f.ctx, &[settle(4)], &[&mxe_authority()]).await;
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 4);
+
+    // Rent goes back to Alice, who opened the referral
+    let rent = lamports(&mut f.ctx, referral_pda(&f.market, &bob)).await;
+    let before = lamports(&mut f.ctx, alice.pubkey()).await;
+    send_ok(
+        &mut f.ctx,
+        &[close_referral_ix(&f.market, &bob, &alice.pubkey())],
+        &[],
+    )
+    .await;
+    assert!(!account_exists(&mut f.ctx, referral_pda(&f.market, &bob)).await);
+    assert_eq!(lamports(&mut f.ctx, alice.pubkey()).await, before + rent);
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(market.closed_referral_count, 1);
+}
+
+#[tokio::test]
+async fn fee_rates_are_bounded_at_creation() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    for (creator_fee_bps, referrer_fee_bps) in [(501, 0), (0, 101)] {
+        let mut args = create_market_args("Greedy?", deadline);
+        args.creator_fee_bps = creator_fee_bps;
+        args.referrer_fee_bps = referrer_fee_bps;
+        let outcome = send(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::InvalidFeeBps);
+    }
+
+    // A bettor cannot name themselves as referrer
+    send_ok(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Fair?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    let tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+    let outcome = send(
+        &mut ctx,
+        &[deposit_bet_referred_ix(
+            &market,
+            &mint,
+            &creator.pubkey(),
+            &tokens,
+            0,
+            100,
+            &creator.pubkey(),
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::SelfReferral);
+}
@@ -0,0 +1,316 @@
+mod common;
+
+use common::*;
+use market_factory::{
+    BetDeposited, BetLedgerPage, BetStorage, ErrorCode, Market, Referral, SessionAuthority,
+    LEDGER_PAGE_CAPACITY,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Anchor's `ConstraintSeeds` and `AccountOwnedByWrongProgram` framework errors
+const CONSTRAINT_SEEDS: u32 = 2006;
+const ACCOUNT_OWNED_BY_WRONG_PROGRAM: u32 = 3007;
+
+async fn ledger_market(ctx: &mut ProgramTestContext) -> (Keypair, Pubkey, Pubkey) {
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(ctx).await;
+    let mint = create_mint(ctx, &payer).await;
+    let mut args = create_market_args("Ledger?", now(ctx).await + 3_600);
+    args.bet_storage = BetStorage::Ledger;
+    args.blob_store_uri = "ipfs://bets".to_string();
+    send_ok(
+        ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    (creator, mint, market)
+}
+
+fn blob_hash(bet_index: usize) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&(bet_index as u64).to_le_bytes());
+    hash
+}
+
+#[tokio::test]
+async fn ledger_appends_fixed_entries_across_pages() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let (_, mint, market) = ledger_market(&mut ctx).await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 10_000).await;
+
+    send_ok(&mut ctx, &[open_ledger_page_ix(&market, &payer, 0)], &[]).await;
+    for batch in 0..LEDGER_PAGE_CAPACITY / 10 {
+        let instructions: Vec<_> = (batch * 10..batch * 10 + 10)
+            .map(|i| {
+                deposit_bet_ledger_ix(
+                    &market,
+                    &mint,
+                    &bettor.pubkey(),
+                    &tokens,
+                    0,
+                    blob_hash(i),
+                    i as u64 + 1,
+                )
+            })
+            .collect();
+        send_ok(&mut ctx, &instructions, &[&bettor]).await;
+    }
+
+    let page: BetLedgerPage = fetch_zero_copy(&mut ctx, ledger_page_pda(&market, 0)).await;
+    assert_eq!(page.market, market);
+    assert_eq!(page.payer, payer);
+    assert_eq!(page.entry_count as usize, LEDGER_PAGE_CAPACITY);
+    let entry = page.entries[42];
+    assert_eq!(entry.depositor, bettor.pubkey());
+    assert_eq!(entry.amount, 43);
+    assert_eq!(entry.blob_hash, blob_hash(42));
+    assert!(entry.timestamp > 0);
+    assert_eq!(entry.referrer, Pubkey::default());
+
+    // A full page stops matching the market's current page seed
+    let deposit = |page_index| {
+        deposit_bet_ledger_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            page_index,
+            blob_hash(LEDGER_PAGE_CAPACITY),
+            5,
+        )
+    };
+    let outcome = send(&mut ctx, &[deposit(0)], &[&bettor]).await;
+    assert_eq!(anchor_error_code(&outcome.result), Some(CONSTRAINT_SEEDS));
+    let outcome = send(&mut ctx, &[deposit(1)], &[&bettor]).await;
+    assert_eq!(
+        anchor_error_code(&outcome.result),
+        Some(ACCOUNT_OWNED_BY_WRONG_PROGRAM)
+    );
+
+    let outcome = send(&mut ctx, &[open_ledger_page_ix(&market, &payer, 2)], &[]).await;
+    assert_error(&outcome, ErrorCode::InvalidLedgerPage);
+
+    let logs = send_ok(
+        &mut ctx,
+        &[open_ledger_page_ix(&market, &payer, 1), deposit(1)],
+        &[&bettor],
+    )
+    .await;
+    let event = &decode_events::<BetDeposited>(&logs)[0];
+    assert_eq!(event.bet_log, ledger_page_pda(&market, 1));
+    assert_eq!(event.bet_index, LEDGER_PAGE_CAPACITY as u64);
+
+    let state: Market = fetch(&mut ctx, market).await;
+    let expected_pool = (1..=LEDGER_PAGE_CAPACITY as u64).sum::<u64>() + 5;
+    assert_eq!(state.bet_count, LEDGER_PAGE_CAPACITY as u64 + 1);
+    assert_eq!(state.ledger_page_count, 2);
+    assert_eq!(state.total_pool, expected_pool);
+    assert_eq!(
+        token_balance(&mut ctx, escrow_pda(&market)).await,
+        expected_pool
+    );
+    let page: BetLedgerPage = fetch_zero_copy(&mut ctx, ledger_page_pda(&market, 1)).await;
+    assert_eq!(page.page_index, 1);
+    assert_eq!(page.entry_count, 1);
+}
+
+#[tokio::test]
+async fn ledger_bets_take_relayers_sessions_and_referrers() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let (_, mint, market) = ledger_market(&mut ctx).await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 1_000).await;
+    let relayer = new_funded_keypair(&mut ctx).await;
+    let referrer = Pubkey::new_unique();
+    send_ok(&mut ctx, &[open_ledger_page_ix(&market, &payer, 0)], &[]).await;
+
+    // The relayer pays the rent and moves the stake as the bettor's delegate
+    let relayed = |authority: &Keypair| {
+        deposit_bet_ledger_relayed_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            60,
+            &authority.pubkey(),
+            &relayer.pubkey(),
+            Some(&referrer),
+        )
+    };
+    let outcome = send(&mut ctx, &[relayed(&relayer)], &[&relayer]).await;
+    assert_error(&outcome, ErrorCode::InvalidTransferAuthority);
+    approve(&mut ctx, &tokens, &relayer.pubkey(), &bettor, 60).await;
+    let bettor_lamports = lamports(&mut ctx, bettor.pubkey()).await;
+    send_ok(&mut ctx, &[relayed(&relayer)], &[&relayer]).await;
+    assert_eq!(lamports(&mut ctx, bettor.pubkey()).await, bettor_lamports);
+    assert_eq!(token_balance(&mut ctx, tokens).await, 940);
+    let referral: Referral = fetch(&mut ctx, referral_pda(&market, &referrer)).await;
+    assert_eq!(
+        (referral.referred_stake, referral.payer),
+        (60, relayer.pubkey())
+    );
+
+    // A session key bets within its cap
+    let session_key = new_funded_keypair(&mut ctx).await;
+    let expires_at = now(&mut ctx).await + 600;
+    send_ok(
+        &mut ctx,
+        &[create_session_ix(
+            &bettor.pubkey(),
+            &tokens,
+            &session_key.pubkey(),
+            expires_at,
+            50,
+            vec![market],
+        )],
+        &[&bettor],
+    )
+    .await;
+    let session_bet = |amount| {
+        deposit_bet_ledger_session_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            amount,
+            &session_key.pubkey(),
+        )
+    };
+    let outcome = send(&mut ctx, &[session_bet(51)], &[&session_key]).await;
+    assert_error(&outcome, ErrorCode::SessionCapExceeded);
+    send_ok(&mut ctx, &[session_bet(40)], &[&session_key]).await;
+    let session: SessionAuthority = fetch(
+        &mut ctx,
+        session_pda(&bettor.pubkey(), &session_key.pubkey()),
+    )
+    .await;
+    assert_eq!(session.spent, 40);
+
+    let page: BetLedgerPage = fetch_zero_copy(&mut ctx, ledger_page_pda(&market, 0)).await;
+    let entries = &page.entries[..page.entry_count as usize];
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|e| e.depositor == bettor.pubkey()));
+    assert_eq!((entries[0].amount, entries[0].referrer), (60, referrer));
+    assert_eq!(
+        (entries[1].amount, entries[1].referrer),
+        (40, Pubkey::default())
+    );
+    assert_eq!(token_balance(&mut ctx, tokens).await, 900);
+}
+
+#[tokio::test]
+async fn ledger_pages_close_before_the_market() {
+    let mut ctx = start().await;
+    let (creator, _, market) = ledger_market(&mut ctx).await;
+    let sponsor = new_funded_keypair(&mut ctx).await;
+    send_ok(
+        &mut ctx,
+        &[open_ledger_page_ix(&market, &sponsor.pubkey(), 0)],
+        &[&sponsor],
+    )
+    .await;
+
+    let page = ledger_page_pda(&market, 0);
+    let outcome = send(
+        &mut ctx,
+        &[close_ledger_page_ix(&market, 0, &sponsor.pubkey())],
+        &[],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::MarketNotFinalized);
+
+    send_ok(
+        &mut ctx,
+        &[
+            cancel_market_ix(&market, &creator.pubkey(), &creator.pubkey()),
+            close_escrow_ix(&market, &creator.pubkey()),
+        ],
+        &[&creator],
+    )
+    .await;
+    let outcome = send(
+        &mut ctx,
+        &[close_market_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::AccountsStillOpen);
+
+    // Anyone may close the page; rent goes back to whoever opened it
+    let rent = lamports(&mut ctx, page).await;
+    let before = lamports(&mut ctx, sponsor.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[close_ledger_page_ix(&market, 0, &sponsor.pubkey())],
+        &[],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, page).await);
+    assert_eq!(lamports(&mut ctx, sponsor.pubkey()).await, before + rent);
+
+    send_ok(
+        &mut ctx,
+        &[close_market_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, market).await);
+}
+
+#[tokio::test]
+async fn ledger_markets_reject_bet_log_deposits() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let (_, mint, market) = ledger_market(&mut ctx).await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+
+    let outcome = send(
+        &mut ctx,
+        &[deposit_bet_commitment_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            [1; 32],
+            10,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongBetStorage);
+
+    let on_chain = {
+        let creator = new_funded_keypair(&mut ctx).await;
+        let deadline = now(&mut ctx).await + 3_600;
+        send_ok(
+            &mut ctx,
+            &[create_market_ix(
+                &creator.pubkey(),
+                &mint,
+                "Inline?",
+                deadline,
+            )],
+            &[&creator],
+        )
+        .await;
+        market_pda(&creator.pubkey())
+    };
+    let outcome = send(&mut ctx, &[open_ledger_page_ix(&on_chain, &payer, 0)], &[]).await;
+    assert_error(&outcome, ErrorCode::WrongBetStorage);
+}
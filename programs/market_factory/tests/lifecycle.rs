@@ -0,0 +1,718 @@
+mod common;
+
+use anchor_lang::{solana_program::hash::hash, InstructionData};
+use common::*;
+use market_factory::{
+    BetLog, ErrorCode, JobStatus, Market, MarketState, MxeResult, ResolutionJob, StuckMarketVoided,
+    MAX_JOB_ATTEMPTS,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    instruction::AccountMeta,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    mint: Pubkey,
+    market: Pubkey,
+    deadline: i64,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        let mut ctx = start().await;
+        let payer = ctx.payer.pubkey();
+        let creator = new_funded_keypair(&mut ctx).await;
+        let mint = create_mint(&mut ctx, &payer).await;
+        let deadline = now(&mut ctx).await + 3_600;
+        send_ok(
+            &mut ctx,
+            &[create_market_ix(
+                &creator.pubkey(),
+                &mint,
+                "Will the lifecycle pass?",
+                deadline,
+            )],
+            &[&creator],
+        )
+        .await;
+        let market = market_pda(&creator.pubkey());
+        Self {
+            ctx,
+            creator,
+            mint,
+            market,
+            deadline,
+        }
+    }
+
+    async fn bettor(&mut self, balance: u64) -> (Keypair, Pubkey) {
+        let bettor = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &bettor.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, balance).await;
+        (bettor, tokens)
+    }
+
+    async fn deposit(
+        &mut self,
+        bettor: &Keypair,
+        tokens: &Pubkey,
+        blob: Vec<u8>,
+        amount: u64,
+    ) -> TxOutcome {
+        let bet_index = self.market().await.bet_count;
+        let ix = deposit_bet_ix(
+            &self.market,
+            &self.mint,
+            &bettor.pubkey(),
+            tokens,
+            bet_index,
+            blob,
+            1,
+            amount,
+        );
+        send(&mut self.ctx, &[ix], &[bettor]).await
+    }
+
+    async fn enqueue(&mut self) -> TxOutcome {
+        let payer = self.ctx.payer.pubkey();
+        send(
+            &mut self.ctx,
+            &[enqueue_resolution_ix(&self.market, &payer)],
+            &[],
+        )
+        .await
+    }
+
+    async fn market(&mut self) -> Market {
+        fetch(&mut self.ctx, self.market).await
+    }
+
+    async fn escrow_balance(&mut self) -> u64 {
+        token_balance(&mut self.ctx, escrow_pda(&self.market)).await
+    }
+}
+
+// ========== HAPPY PATH ==========
+
+#[tokio::test]
+async fn full_lifecycle_create_deposit_enqueue_settle() {
+    let mut f = Fixture::new().await;
+
+    let market = f.market().await;
+    assert!(market.state == MarketState::Open);
+    assert_eq!(market.creator, f.creator.pubkey());
+    assert_eq!(market.escrow_vault, escrow_pda(&f.market));
+    assert_eq!(market.deadline, f.deadline);
+    assert_eq!(market.mxe_program_id, mxe_authority().pubkey());
+    assert_eq!(f.escrow_balance().await, 0);
+
+    let (alice, alice_tokens) = f.bettor(1_000).await;
+    let (bob, bob_tokens) = f.bettor(1_000).await;
+    assert!(f
+        .deposit(&alice, &alice_tokens, vec![1; 9], 400)
+        .await
+        .result
+        .is_ok());
+    assert!(f
+        .deposit(&bob, &bob_tokens, vec![0; 9], 250)
+        .await
+        .result
+        .is_ok());
+
+    assert_eq!(f.escrow_balance().await, 650);
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 600);
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 750);
+    let market = f.market().await;
+    assert_eq!(market.total_pool, 650);
+    assert_eq!(market.bet_count, 2);
+
+    let bet: BetLog = fetch(&mut f.ctx, bet_log_pda(&f.market, &bob.pubkey(), 1)).await;
+    assert_eq!(bet.market, f.market);
+    assert_eq!(bet.depositor, bob.pubkey());
+    assert_eq!(bet.amount, 250);
+    assert_eq!(bet.encrypted_blob, vec![0; 9]);
+
+    warp_to_timestamp(&mut f.ctx, f.deadline + 1).await;
+    assert!(f.enqueue().await.result.is_ok());
+    assert!(f.market().await.state == MarketState::Enqueued);
+    let job: ResolutionJob = fetch(&mut f.ctx, resolution_job_pda(&f.market)).await;
+    assert_eq!(job.market, f.market);
+    assert!(job.status == JobStatus::Pending);
+    assert_eq!(job.attempts, 0);
+
+    // Alice called it and takes the pool; Bob's zero payout needs no account
+    let result = MxeResult {
+        total_pool: 650,
+        payouts: vec![
+            mxe_payout(&alice.pubkey(), 650),
+            mxe_payout(&bob.pubkey(), 0),
+        ],
+        ..mxe_result(&f.market)
+    };
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_ix(
+            &f.market,
+            &f.mint,
+            &result,
+            &[alice_tokens],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    let market = f.market().await;
+    assert!(market.state == MarketState::Settled);
+    assert_eq!(market.result_hash, hash(&encode_result(&result)).to_bytes());
+    assert_eq!(f.escrow_balance().await, 0);
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 1_250);
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 750);
+}
+
+#[tokio::test]
+async fn cancel_market_without_bets() {
+    let mut f = Fixture::new().await;
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(&f.market, &creator, &creator)],
+        &[&f.creator],
+    )
+    .await;
+    assert!(f.market().await.state == MarketState::Cancelled);
+    assert_eq!(f.escrow_balance().await, 0);
+}
+
+#[tokio::test]
+async fn resolution_job_retries_until_the_attempt_limit() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    let mxe = mxe_authority();
+    for attempt in 1..=MAX_JOB_ATTEMPTS {
+        send_ok(
+            &mut f.ctx,
+            &[mark_job_running_ix(&f.market, &mxe.pubkey())],
+            &[&mxe],
+        )
+        .await;
+        let job: ResolutionJob = fetch(&mut f.ctx, resolution_job_pda(&f.market)).await;
+        assert!(job.status == JobStatus::Running);
+        assert_eq!(job.attempts, attempt);
+
+        send_ok(
+            &mut f.ctx,
+            &[mark_job_failed_ix(
+                &f.market,
+                &mxe.pubkey(),
+                40 + attempt as u32,
+            )],
+            &[&mxe],
+        )
+        .await;
+        let job: ResolutionJob = fetch(&mut f.ctx, resolution_job_pda(&f.market)).await;
+        assert!(job.status == JobStatus::Failed);
+        assert_eq!(job.last_error, 40 + attempt as u32);
+    }
+
+    let outcome = send(
+        &mut f.ctx,
+        &[mark_job_running_ix(&f.market, &mxe.pubkey())],
+        &[&mxe],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::MaxJobAttemptsExceeded);
+}
+
+#[tokio::test]
+async fn stuck_market_is_voided_and_refunded() {
+    let mut f = Fixture::new().await;
+    let (alice, alice_tokens) = f.bettor(1_000).await;
+    let (bob, bob_tokens) = f.bettor(1_000).await;
+    assert!(f
+        .deposit(&alice, &alice_tokens, vec![1; 9], 400)
+        .await
+        .result
+        .is_ok());
+    assert!(f
+        .deposit(&bob, &bob_tokens, vec![0; 9], 250)
+        .await
+        .result
+        .is_ok());
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    // Not before the job has failed its last attempt
+    let mxe = mxe_authority();
+    let void = void_stuck_market_ix(&f.market, None);
+    for attempt in 1..=MAX_JOB_ATTEMPTS {
+        let outcome = send(&mut f.ctx, std::slice::from_ref(&void), &[]).await;
+        assert_error(&outcome, ErrorCode::ResolutionNotStuck);
+        send_ok(
+            &mut f.ctx,
+            &[
+                mark_job_running_ix(&f.market, &mxe.pubkey()),
+                mark_job_failed_ix(&f.market, &mxe.pubkey(), attempt as u32),
+            ],
+            &[&mxe],
+        )
+        .await;
+    }
+
+    // Anyone may void it then, and nothing settles it afterwards
+    let logs = send_ok(&mut f.ctx, &[void], &[]).await;
+    let event = &decode_events::<StuckMarketVoided>(&logs)[0];
+    assert_eq!((event.attempts, event.last_error), (MAX_JOB_ATTEMPTS, 3));
+    assert!(f.market().await.state == MarketState::Cancelled);
+    let settle = callback_settle_ix(&f.market, &f.mint, &mxe_result(&f.market), &[]);
+    let outcome = send(&mut f.ctx, &[settle], &[&mxe]).await;
+    assert_error(&outcome, ErrorCode::InvalidMarketState);
+
+    // Positions close only once refunded, and the escrow once all of them are
+    let outcome = send(
+        &mut f.ctx,
+        &[close_position_ix(&f.market, &alice.pubkey())],
+        &[],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::StakeNotRefunded);
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[refund_position_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    let outcome = send(
+        &mut f.ctx,
+        &[close_escrow_ix(&f.market, &creator)],
+        &[&f.creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::EscrowNotEmpty);
+    send_ok(
+        &mut f.ctx,
+        &[refund_position_ix(
+            &f.market,
+            &f.mint,
+            &bob.pubkey(),
+            &bob_tokens,
+        )],
+        &[&bob],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 1_000);
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 1_000);
+    assert_eq!(f.escrow_balance().await, 0);
+
+    // A position is refunded once
+    let outcome = send(
+        &mut f.ctx,
+        &[refund_position_ix(
+            &f.market,
+            &f.mint,
+            &bob.pubkey(),
+            &bob_tokens,
+        )],
+        &[&bob],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidAmount);
+    send_ok(
+        &mut f.ctx,
+        &[
+            close_position_ix(&f.market, &alice.pubkey()),
+            close_position_ix(&f.market, &bob.pubkey()),
+            close_escrow_ix(&f.market, &creator),
+        ],
+        &[&f.creator],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn settlement_completes_resolution_job() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    // The MXE cluster signs with the key recorded as `resolver`
+    let mxe = mxe_authority();
+    send_ok(
+        &mut f.ctx,
+        &[
+            mark_job_running_ix(&f.market, &mxe.pubkey()),
+            callback_settle_ix(&f.market, &f.mint, &mxe_result(&f.market), &[]),
+        ],
+        &[&mxe],
+    )
+    .await;
+    let job: ResolutionJob = fetch(&mut f.ctx, resolution_job_pda(&f.market)).await;
+    assert!(job.status == JobStatus::Completed);
+    assert_eq!(job.attempts, 1);
+    assert_eq!(job.last_error, 0);
+
+    // Nothing is left to report once the market has settled
+    let outcome = send(
+        &mut f.ctx,
+        &[mark_job_failed_ix(&f.market, &mxe.pubkey(), 1)],
+        &[&mxe],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidMarketState);
+}
+
+// ========== ERROR CODES ==========
+
+#[tokio::test]
+async fn create_market_rejects_long_question() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    let question = "?".repeat(281);
+    let outcome = send(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            &question,
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::QuestionTooLong);
+}
+
+#[tokio::test]
+async fn create_market_rejects_past_deadline() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await;
+
+    let outcome = send(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Too late?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidDeadline);
+}
+
+#[tokio::test]
+async fn deposit_rejects_cancelled_market() {
+    let mut f = Fixture::new().await;
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(&f.market, &creator, &creator)],
+        &[&f.creator],
+    )
+    .await;
+
+    let (bettor, tokens) = f.bettor(100).await;
+    let outcome = f.deposit(&bettor, &tokens, vec![1; 9], 10).await;
+    assert_error(&outcome, ErrorCode::MarketNotOpen);
+}
+
+#[tokio::test]
+async fn deposit_rejects_after_deadline() {
+    let mut f = Fixture::new().await;
+    let (bettor, tokens) = f.bettor(100).await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+
+    let outcome = f.deposit(&bettor, &tokens, vec![1; 9], 10).await;
+    assert_error(&outcome, ErrorCode::DeadlinePassed);
+}
+
+#[tokio::test]
+async fn deposit_rejects_oversized_blob() {
+    let mut f = Fixture::new().await;
+    let (bettor, tokens) = f.bettor(100).await;
+
+    let outcome = f.deposit(&bettor, &tokens, vec![1; 513], 10).await;
+    assert_error(&outcome, ErrorCode::BlobTooLarge);
+}
+
+#[tokio::test]
+async fn deposit_rejects_zero_amount() {
+    let mut f = Fixture::new().await;
+    let (bettor, tokens) = f.bettor(100).await;
+
+    let outcome = f.deposit(&bettor, &tokens, vec![1; 9], 0).await;
+    assert_error(&outcome, ErrorCode::InvalidAmount);
+    assert_eq!(f.escrow_balance().await, 0);
+}
+
+#[tokio::test]
+async fn settle_rejects_market_that_is_not_enqueued() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+    let settle = || callback_settle_ix(&f.market, &f.mint, &mxe_result(&f.market), &[]);
+    send_ok(&mut f.ctx, &[settle()], &[&mxe_authority()]).await;
+
+    // A settled market cannot be settled again
+    let outcome = send(&mut f.ctx, &[settle()], &[&mxe_authority()]).await;
+    assert_error(&outcome, ErrorCode::InvalidMarketState);
+}
+
+#[tokio::test]
+async fn enqueue_rejects_before_deadline() {
+    let mut f = Fixture::new().await;
+    let outcome = f.enqueue().await;
+    assert_error(&outcome, ErrorCode::DeadlineNotReached);
+}
+
+#[tokio::test]
+async fn cancel_rejects_market_with_bets() {
+    let mut f = Fixture::new().await;
+    let (bettor, tokens) = f.bettor(100).await;
+    assert!(f
+        .deposit(&bettor, &tokens, vec![1; 9], 10)
+        .await
+        .result
+        .is_ok());
+
+    let creator = f.creator.pubkey();
+    let outcome = send(
+        &mut f.ctx,
+        &[cancel_market_ix(&f.market, &creator, &creator)],
+        &[&f.creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::CannotCancelWithBets);
+    assert!(f.market().await.state == MarketState::Open);
+}
+
+#[tokio::test]
+async fn cancel_rejects_foreign_authority() {
+    let mut f = Fixture::new().await;
+    let intruder = new_funded_keypair(&mut f.ctx).await;
+
+    let outcome = send(
+        &mut f.ctx,
+        &[cancel_market_ix(
+            &f.market,
+            &f.creator.pubkey(),
+            &intruder.pubkey(),
+        )],
+        &[&f.creator, &intruder],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+}
+
+#[tokio::test]
+async fn job_updates_reject_foreign_authority() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+    let intruder = new_funded_keypair(&mut f.ctx).await;
+
+    let outcome = send(
+        &mut f.ctx,
+        &[mark_job_running_ix(&f.market, &intruder.pubkey())],
+        &[&intruder],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+}
+
+#[tokio::test]
+async fn job_updates_reject_invalid_transition() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    let mxe = mxe_authority();
+    let outcome = send(
+        &mut f.ctx,
+        &[mark_job_failed_ix(&f.market, &mxe.pubkey(), 1)],
+        &[&mxe],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidJobStatus);
+}
+
+#[tokio::test]
+async fn creator_cannot_run_the_job_out_of_attempts() {
+    let mut f = Fixture::new().await;
+    let (alice, alice_tokens) = f.bettor(1_000).await;
+    assert!(f
+        .deposit(&alice, &alice_tokens, vec![1; 9], 400)
+        .await
+        .result
+        .is_ok());
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    // A hostile creator tries to burn through the retries, leaving the job
+    // stuck and the stake locked; only the resolver reports job progress
+    let creator = f.creator.pubkey();
+    let mxe = mxe_authority();
+    send_ok(
+        &mut f.ctx,
+        &[mark_job_running_ix(&f.market, &mxe.pubkey())],
+        &[&mxe],
+    )
+    .await;
+    for _ in 0..MAX_JOB_ATTEMPTS {
+        let outcome = send(
+            &mut f.ctx,
+            &[
+                mark_job_failed_ix(&f.market, &creator, 1),
+                mark_job_running_ix(&f.market, &creator),
+            ],
+            &[&f.creator],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::Unauthorized);
+    }
+    let job: ResolutionJob = fetch(&mut f.ctx, resolution_job_pda(&f.market)).await;
+    assert!(job.status == JobStatus::Running);
+    assert_eq!(job.attempts, 1);
+
+    // The resolver still settles, and Alice is paid
+    let result = MxeResult {
+        total_pool: 400,
+        payouts: vec![mxe_payout(&alice.pubkey(), 400)],
+        ..mxe_result(&f.market)
+    };
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_ix(
+            &f.market,
+            &f.mint,
+            &result,
+            &[alice_tokens],
+        )],
+        &[&mxe],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 1_000);
+}
+
+#[tokio::test]
+async fn settle_rejects_foreign_resolver() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    // Neither a stranger nor the creator may stand in for the resolver
+    let intruder = new_funded_keypair(&mut f.ctx).await;
+    let creator = f.creator.insecure_clone();
+    for signer in [&intruder, &creator] {
+        let mut ix = callback_settle_ix(&f.market, &f.mint, &mxe_result(&f.market), &[]);
+        ix.accounts[2] = AccountMeta::new_readonly(signer.pubkey(), true);
+        let outcome = send(&mut f.ctx, &[ix], &[signer]).await;
+        assert_error(&outcome, ErrorCode::Unauthorized);
+    }
+    assert!(f.market().await.state == MarketState::Enqueued);
+}
+
+#[tokio::test]
+async fn settle_rejects_failed_job_until_restarted() {
+    let mut f = Fixture::new().await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    let mxe = mxe_authority();
+    send_ok(
+        &mut f.ctx,
+        &[
+            mark_job_running_ix(&f.market, &mxe.pubkey()),
+            mark_job_failed_ix(&f.market, &mxe.pubkey(), 7),
+        ],
+        &[&mxe],
+    )
+    .await;
+    let settle = || callback_settle_ix(&f.market, &f.mint, &mxe_result(&f.market), &[]);
+    let outcome = send(&mut f.ctx, &[settle()], &[&mxe]).await;
+    assert_error(&outcome, ErrorCode::InvalidJobStatus);
+
+    send_ok(
+        &mut f.ctx,
+        &[mark_job_running_ix(&f.market, &mxe.pubkey()), settle()],
+        &[&mxe],
+    )
+    .await;
+    let job: ResolutionJob = fetch(&mut f.ctx, resolution_job_pda(&f.market)).await;
+    assert!(job.status == JobStatus::Completed);
+    assert_eq!((job.attempts, job.last_error), (2, 0));
+}
+
+#[tokio::test]
+async fn settle_rejects_results_it_cannot_parse_or_pay() {
+    let mut f = Fixture::new().await;
+    let (alice, alice_tokens) = f.bettor(1_000).await;
+    let (_, bob_tokens) = f.bettor(0).await;
+    assert!(f
+        .deposit(&alice, &alice_tokens, vec![1; 9], 400)
+        .await
+        .result
+        .is_ok());
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    assert!(f.enqueue().await.result.is_ok());
+
+    // Not the MXE's JSON, or computed for another market
+    let mut raw = callback_settle_ix(&f.market, &f.mint, &mxe_result(&f.market), &[]);
+    raw.data = market_factory::instruction::CallbackSettle {
+        mxe_result: vec![1],
+        result_signature: vec![0x42; 64],
+    }
+    .data();
+    let foreign = mxe_result(&Pubkey::new_unique());
+    let foreign = callback_settle_ix(&f.market, &f.mint, &foreign, &[]);
+    for ix in [raw, foreign] {
+        let outcome = send(&mut f.ctx, &[ix], &[&mxe_authority()]).await;
+        assert_error(&outcome, ErrorCode::InvalidMxeResult);
+    }
+
+    // Every nonzero payout needs its recipient's own token account
+    let result = MxeResult {
+        payouts: vec![mxe_payout(&alice.pubkey(), 400)],
+        ..mxe_result(&f.market)
+    };
+    for accounts in [vec![], vec![bob_tokens], vec![alice_tokens, bob_tokens]] {
+        let ix = callback_settle_ix(&f.market, &f.mint, &result, &accounts);
+        let outcome = send(&mut f.ctx, &[ix], &[&mxe_authority()]).await;
+        assert_error(&outcome, ErrorCode::PayoutRecipientMismatch);
+    }
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 0);
+
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_ix(
+            &f.market,
+            &f.mint,
+            &result,
+            &[alice_tokens],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 1_000);
+}
+
+// `ErrorCode::InvalidMXESignature` is not reachable yet: `verify_mxe_signature`
+// accepts every result until Arcium's verification SDK is wired in.
@@ -0,0 +1,377 @@
+mod common;
+
+use anchor_lang::InstructionData;
+use common::*;
+use market_factory::{
+    lmsr_buy_cost, lmsr_funding, ErrorCode, LmsrState, Market, MarketState, SharesBought,
+    UserPosition,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    instruction::AccountMeta,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const NO: u8 = 0;
+const YES: u8 = 1;
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    creator_tokens: Pubkey,
+    mint: Pubkey,
+    market: Pubkey,
+    deadline: i64,
+}
+
+/// LMSR market with `b = 1000`, which the creator funds with 694
+async fn lmsr_market() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let creator_tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    mint_to(&mut ctx, &mint, &creator_tokens, 1_000).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    send_ok(
+        &mut ctx,
+        &[create_lmsr_market_ix(
+            &creator.pubkey(),
+            &mint,
+            &creator_tokens,
+            "Priced?",
+            deadline,
+            1_000,
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        creator,
+        creator_tokens,
+        mint,
+        market,
+        deadline,
+    }
+}
+
+impl Fixture {
+    async fn trader(&mut self) -> (Keypair, Pubkey) {
+        let trader = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &trader.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, 1_000).await;
+        (trader, tokens)
+    }
+
+    async fn buy(
+        &mut self,
+        trader: &Keypair,
+        tokens: &Pubkey,
+        outcome: u8,
+        shares: u64,
+        max_cost: u64,
+    ) -> TxOutcome {
+        send(
+            &mut self.ctx,
+            &[buy_shares_ix(
+                &self.market,
+                &self.mint,
+                &trader.pubkey(),
+                tokens,
+                outcome,
+                shares,
+                max_cost,
+            )],
+            &[trader],
+        )
+        .await
+    }
+
+    /// Close betting and settle with `outcome` winning
+    async fn settle(&mut self, outcome: u8) {
+        warp_to_timestamp(&mut self.ctx, self.deadline).await;
+        let payer = self.ctx.payer.pubkey();
+        send_ok(
+            &mut self.ctx,
+            &[
+                enqueue_resolution_ix(&self.market, &payer),
+                callback_settle_outcome_ix(&self.market, &self.mint, Some(outcome), None, 0),
+            ],
+            &[&mxe_authority()],
+        )
+        .await;
+    }
+}
+
+#[test]
+fn cost_function_quotes_and_stays_solvent() {
+    // b * ln(2) = 693.15; 100 YES from an even book costs 51.25
+    assert_eq!(lmsr_funding(1_000), 694);
+    assert_eq!(lmsr_buy_cost(1_000, [0, 0], YES, 100), Some(52));
+    assert_eq!(lmsr_buy_cost(1_000, [0, 0], NO, 100), Some(52));
+    assert_eq!(lmsr_buy_cost(1_000, [0, 0], 2, 100), None);
+    // A share never costs more than one unit
+    assert_eq!(lmsr_buy_cost(1_000, [0, 50_000], YES, 1_000), Some(1_000));
+
+    // Every trade rounds up, so escrow covers the largest payout
+    let liquidity = 1_000_000_000_000;
+    let mut escrow = lmsr_funding(liquidity) as u128;
+    let mut outstanding = [0u64; 2];
+    for i in 0..200u64 {
+        let outcome = (i % 3 == 0) as u8;
+        let shares = 1 + i * 7_919_000_011 % 4_000_000_000_000;
+        escrow += lmsr_buy_cost(liquidity, outstanding, outcome, shares).unwrap() as u128;
+        outstanding[outcome as usize] += shares;
+        assert!(escrow >= *outstanding.iter().max().unwrap() as u128);
+    }
+}
+
+#[tokio::test]
+async fn trades_are_priced_by_the_cost_function() {
+    let mut f = lmsr_market().await;
+    assert_eq!(token_balance(&mut f.ctx, f.creator_tokens).await, 306);
+    let (alice, alice_tokens) = f.trader().await;
+    let (bob, bob_tokens) = f.trader().await;
+
+    let outcome = f.buy(&alice, &alice_tokens, YES, 100, 51).await;
+    assert_error(&outcome, ErrorCode::SlippageExceeded);
+    let outcome = f.buy(&alice, &alice_tokens, YES, 100, 52).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let bought = &decode_events::<SharesBought>(&outcome.logs)[0];
+    assert_eq!(
+        (bought.outcome, bought.cost, bought.outstanding),
+        (YES, 52, [0, 100])
+    );
+
+    // YES gets dearer as it is bought and NO cheaper
+    let outcome = f.buy(&bob, &bob_tokens, YES, 100, 1_000).await;
+    assert_eq!(decode_events::<SharesBought>(&outcome.logs)[0].cost, 54);
+    let outcome = f.buy(&bob, &bob_tokens, NO, 100, 1_000).await;
+    assert_eq!(decode_events::<SharesBought>(&outcome.logs)[0].cost, 47);
+
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(
+        market.lmsr,
+        Some(LmsrState {
+            liquidity: 1_000,
+            outstanding: [100, 200],
+        })
+    );
+    assert_eq!(market.total_pool, 694 + 52 + 54 + 47);
+    assert_eq!(
+        token_balance(&mut f.ctx, escrow_pda(&f.market)).await,
+        market.total_pool
+    );
+    let position: UserPosition = fetch(&mut f.ctx, position_pda(&f.market, &bob.pubkey())).await;
+    assert_eq!((position.shares, position.total_staked), ([100, 100], 101));
+}
+
+#[tokio::test]
+async fn winning_shares_pay_one_unit_each() {
+    let mut f = lmsr_market().await;
+    let (alice, alice_tokens) = f.trader().await;
+    let (carol, carol_tokens) = f.trader().await;
+    f.buy(&alice, &alice_tokens, YES, 200, 1_000).await;
+    f.buy(&carol, &carol_tokens, NO, 100, 1_000).await;
+    let alice_before = token_balance(&mut f.ctx, alice_tokens).await;
+    f.settle(YES).await;
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert!(market.state == MarketState::Settled);
+    assert_eq!(market.winning_outcome, Some(YES));
+
+    // The maker's collateral stays until every winning share is paid
+    let creator = f.creator.pubkey();
+    let withdraw = || withdraw_liquidity_ix(&f.market, &f.mint, &creator, &f.creator_tokens);
+    let outcome = send(&mut f.ctx, &[withdraw()], &[&f.creator]).await;
+    assert_error(&outcome, ErrorCode::SharesOutstanding);
+    let outcome = send(
+        &mut f.ctx,
+        &[close_position_ix(&f.market, &alice.pubkey())],
+        &[],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::SharesOutstanding);
+
+    send_ok(
+        &mut f.ctx,
+        &[redeem_shares_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(
+        token_balance(&mut f.ctx, alice_tokens).await,
+        alice_before + 200
+    );
+    let outcome = send(
+        &mut f.ctx,
+        &[redeem_shares_ix(
+            &f.market,
+            &f.mint,
+            &carol.pubkey(),
+            &carol_tokens,
+        )],
+        &[&carol],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidAmount);
+
+    // What is left goes back to the creator, emptying the escrow
+    let left = fetch::<Market>(&mut f.ctx, f.market).await.total_pool;
+    send_ok(&mut f.ctx, &[withdraw()], &[&f.creator]).await;
+    assert_eq!(
+        token_balance(&mut f.ctx, f.creator_tokens).await,
+        306 + left
+    );
+    for owner in [alice.pubkey(), carol.pubkey()] {
+        send_ok(&mut f.ctx, &[close_position_ix(&f.market, &owner)], &[]).await;
+    }
+    send_ok(
+        &mut f.ctx,
+        &[
+            close_resolution_job_ix(&f.market, &creator),
+            close_escrow_ix(&f.market, &creator),
+            close_market_ix(&f.market, &creator),
+        ],
+        &[&f.creator],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn only_the_resolver_names_the_winning_outcome() {
+    let mut f = lmsr_market().await;
+    let (carol, carol_tokens) = f.trader().await;
+    f.buy(&carol, &carol_tokens, NO, 100, 1_000).await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    send_ok(&mut f.ctx, &[enqueue_resolution_ix(&f.market, &payer)], &[]).await;
+
+    // A NO holder cannot settle the market to NO in the resolver's place
+    let mut ix = callback_settle_outcome_ix(&f.market, &f.mint, Some(NO), None, 0);
+    ix.accounts[2] = AccountMeta::new_readonly(carol.pubkey(), true);
+    let outcome = send(&mut f.ctx, &[ix], &[&carol]).await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+
+    // The outcome is the MXE result's, which must name one of the two
+    let outcome = send(
+        &mut f.ctx,
+        &[callback_settle_outcome_ix(
+            &f.market,
+            &f.mint,
+            Some(2),
+            None,
+            0,
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidOutcome);
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_outcome_ix(
+            &f.market,
+            &f.mint,
+            Some(YES),
+            None,
+            0,
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(market.winning_outcome, Some(YES));
+}
+
+#[tokio::test]
+async fn lmsr_is_a_separate_mode() {
+    let mut f = lmsr_market().await;
+    let (alice, alice_tokens) = f.trader().await;
+
+    // No encrypted bets in an LMSR market, and no trades in a parimutuel one
+    let outcome = send(
+        &mut f.ctx,
+        &[deposit_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+            0,
+            vec![1; 9],
+            1,
+            100,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongMarketKind);
+    let other = new_funded_keypair(&mut f.ctx).await;
+    send_ok(
+        &mut f.ctx,
+        &[create_market_ix(
+            &other.pubkey(),
+            &f.mint,
+            "Pooled?",
+            f.deadline,
+        )],
+        &[&other],
+    )
+    .await;
+    let outcome = send(
+        &mut f.ctx,
+        &[buy_shares_ix(
+            &market_pda(&other.pubkey()),
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+            YES,
+            100,
+            1_000,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongMarketKind);
+
+    // Traded markets cannot be cancelled
+    f.buy(&alice, &alice_tokens, NO, 10, 1_000).await;
+    let creator = f.creator.pubkey();
+    let outcome = send(
+        &mut f.ctx,
+        &[cancel_market_ix(&f.market, &creator, &creator)],
+        &[&f.creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::CannotCancelWithBets);
+
+    let cases = [
+        (0, false, ErrorCode::InvalidLiquidity),
+        (1_000, true, ErrorCode::WrongMarketKind),
+    ];
+    for (liquidity, receipt_tokens, expected) in cases {
+        let creator = new_funded_keypair(&mut f.ctx).await;
+        let mut ix = create_lmsr_market_ix(
+            &creator.pubkey(),
+            &f.mint,
+            &f.creator_tokens,
+            "Invalid?",
+            f.deadline,
+            liquidity,
+        );
+        if receipt_tokens {
+            let mut args = create_market_args("Invalid?", f.deadline);
+            args.lmsr_liquidity = Some(liquidity);
+            args.receipt_tokens = true;
+            ix.data = args.data();
+        }
+        let outcome = send(&mut f.ctx, &[ix], &[&creator]).await;
+        assert_error(&outcome, expected);
+    }
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::*;
+use market_factory::{Category, ErrorCode, Market, MarketCreated, MarketMetadata};
+use solana_program_test::tokio;
+use solana_sdk::{hash::hash, signature::Signer};
+
+const RULES: &[u8] = b"Resolves YES if the BTC/USD daily close on Coinbase is above 100000.";
+
+fn metadata() -> MarketMetadata {
+    MarketMetadata {
+        category: Category::Crypto,
+        tags: vec!["btc".to_string(), "weekly".to_string()],
+        metadata_uri: "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+            .to_string(),
+        rules_hash: hash(RULES).to_bytes(),
+    }
+}
+
+#[tokio::test]
+async fn metadata_is_validated() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let creator = new_funded_keypair(&mut ctx).await;
+
+    let invalid = [
+        MarketMetadata {
+            tags: vec!["tag".to_string(); 6],
+            ..metadata()
+        },
+        MarketMetadata {
+            tags: vec![String::new()],
+            ..metadata()
+        },
+        MarketMetadata {
+            tags: vec!["t".repeat(33)],
+            ..metadata()
+        },
+        MarketMetadata {
+            metadata_uri: "u".repeat(201),
+            ..metadata()
+        },
+    ];
+    for metadata in invalid {
+        let mut args = create_market_args("BTC above 100k?", deadline);
+        args.metadata = metadata;
+        let outcome = send(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::InvalidMetadata);
+    }
+
+    let mut args = create_market_template_args("BTC above 100k in week {n}?", deadline);
+    args.metadata.tags = vec!["t".repeat(33)];
+    let outcome = send(
+        &mut ctx,
+        &[create_market_template_ix(
+            &creator.pubkey(),
+            Some(&mint),
+            args,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidMetadata);
+}
+
+#[tokio::test]
+async fn metadata_is_committed_at_creation() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let creator = new_funded_keypair(&mut ctx).await;
+
+    let mut args = create_market_args("BTC above 100k?", deadline);
+    args.metadata = metadata();
+    let logs = send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    assert_eq!(
+        decode_events::<MarketCreated>(&logs)[0].metadata,
+        metadata()
+    );
+    let market: Market = fetch(&mut ctx, market_pda(&creator.pubkey())).await;
+    assert_eq!(market.metadata, metadata());
+    assert_eq!(market.metadata.rules_hash, hash(RULES).to_bytes());
+
+    // Template instances carry the template's metadata
+    let mut args = create_market_template_args("BTC above 100k in week {n}?", deadline);
+    args.metadata = metadata();
+    send_ok(
+        &mut ctx,
+        &[create_market_template_ix(
+            &creator.pubkey(),
+            Some(&mint),
+            args,
+        )],
+        &[&creator],
+    )
+    .await;
+    let template = template_pda(&creator.pubkey(), 0);
+    send_ok(
+        &mut ctx,
+        &[spawn_market_ix(&template, 0, Some(&mint), &payer)],
+        &[],
+    )
+    .await;
+    let instance: Market = fetch(&mut ctx, template_market_pda(&template, 0)).await;
+    assert_eq!(instance.metadata, metadata());
+}
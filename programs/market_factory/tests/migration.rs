@@ -0,0 +1,260 @@
+mod common;
+
+use anchor_spl::token::spl_token;
+use common::*;
+use market_factory::{
+    BetLog, BetStorage, Collateral, ErrorCode, JobStatus, Market, MarketMigrated, MarketState,
+    ResolutionJob, UserPosition, ACCOUNT_VERSION,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+    signature::{keypair_from_seed, Signer},
+};
+
+// Version 0 accounts, dumped from the program as it was before accounts
+// carried a version. The `[11; 32]` keypair created the open market with the
+// `[14; 32]` mint, and the `[12; 32]` keypair bet 1_000_000 on it with bet
+// index 0 and a blob of 64 sevens. The `[13; 32]` keypair created the other
+// market, which was enqueued for resolution at its deadline.
+const MINT: &[u8] = include_bytes!("fixtures/v0/mint.bin");
+const MARKET: &[u8] = include_bytes!("fixtures/v0/market.bin");
+const ESCROW: &[u8] = include_bytes!("fixtures/v0/escrow.bin");
+const BET_LOG: &[u8] = include_bytes!("fixtures/v0/bet_log.bin");
+const ENQUEUED_MARKET: &[u8] = include_bytes!("fixtures/v0/enqueued_market.bin");
+const ENQUEUED_ESCROW: &[u8] = include_bytes!("fixtures/v0/enqueued_escrow.bin");
+const RESOLUTION_JOB: &[u8] = include_bytes!("fixtures/v0/resolution_job.bin");
+
+const POOL: u64 = 1_000_000;
+
+/// Install an account as-is, rent exempt
+async fn load(ctx: &mut ProgramTestContext, address: Pubkey, data: &[u8], owner: Pubkey) {
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let account = Account {
+        lamports: rent.minimum_balance(data.len()),
+        data: data.to_vec(),
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&address, &account.into());
+}
+
+async fn data_len(ctx: &mut ProgramTestContext, address: Pubkey) -> usize {
+    ctx.banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap()
+        .data
+        .len()
+}
+
+#[tokio::test]
+async fn v0_accounts_migrate_in_place() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = keypair_from_seed(&[11; 32]).unwrap().pubkey();
+    let bettor = keypair_from_seed(&[12; 32]).unwrap();
+    let enqueued_creator = keypair_from_seed(&[13; 32]).unwrap().pubkey();
+    let mint = keypair_from_seed(&[14; 32]).unwrap().pubkey();
+
+    let market = market_pda(&creator);
+    let escrow = escrow_pda(&market);
+    let bet_log = bet_log_pda(&market, &bettor.pubkey(), 0);
+    let position = position_pda(&market, &bettor.pubkey());
+    let enqueued = market_pda(&enqueued_creator);
+    let enqueued_escrow = escrow_pda(&enqueued);
+    let resolution_job = resolution_job_pda(&enqueued);
+    let fixtures = [
+        (mint, MINT, spl_token::ID),
+        (market, MARKET, market_factory::ID),
+        (escrow, ESCROW, spl_token::ID),
+        (bet_log, BET_LOG, market_factory::ID),
+        (enqueued, ENQUEUED_MARKET, market_factory::ID),
+        (enqueued_escrow, ENQUEUED_ESCROW, spl_token::ID),
+        (resolution_job, RESOLUTION_JOB, market_factory::ID),
+    ];
+    for (address, data, owner) in fixtures {
+        load(&mut ctx, address, data, owner).await;
+    }
+
+    // The mint is read from the market's own escrow
+    for escrow_vault in [None, Some(enqueued_escrow)] {
+        let outcome = send(
+            &mut ctx,
+            &[migrate_market_ix(&market, escrow_vault, &[], &payer)],
+            &[],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::CollateralAccountsMismatch);
+    }
+    // A version 0 bet log needs its position alongside
+    let outcome = send(
+        &mut ctx,
+        &[migrate_market_ix(&market, Some(escrow), &[bet_log], &payer)],
+        &[],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::NotMigratable);
+
+    let logs = send_ok(
+        &mut ctx,
+        &[
+            migrate_market_ix(&market, Some(escrow), &[bet_log, position], &payer),
+            migrate_market_ix(&enqueued, Some(enqueued_escrow), &[resolution_job], &payer),
+        ],
+        &[],
+    )
+    .await;
+    let migrated: Vec<u32> = decode_events::<MarketMigrated>(&logs)
+        .iter()
+        .map(|event| event.migrated)
+        .collect();
+    assert_eq!(migrated, [2, 2]);
+    for address in [market, bet_log, enqueued, resolution_job] {
+        let len = fixtures.iter().find(|f| f.0 == address).unwrap().1.len();
+        assert_ne!(data_len(&mut ctx, address).await, len);
+    }
+
+    let migrated: Market = fetch(&mut ctx, market).await;
+    assert_eq!(migrated.version, ACCOUNT_VERSION);
+    assert_eq!(migrated.creator, creator);
+    assert_eq!(migrated.question, "Will it rain in Lisbon on Friday?");
+    assert!(migrated.state == MarketState::Open);
+    assert_eq!((migrated.escrow_vault, migrated.token_mint), (escrow, mint));
+    assert!(migrated.collateral == Collateral::Token);
+    assert!(migrated.bet_storage == BetStorage::OnChain);
+    assert_eq!((migrated.bet_count, migrated.total_pool), (1, POOL));
+    assert_eq!(migrated.position_count, 1);
+    let log: BetLog = fetch(&mut ctx, bet_log).await;
+    assert_eq!(log.version, ACCOUNT_VERSION);
+    assert_eq!((log.market, log.depositor), (market, bettor.pubkey()));
+    assert_eq!((log.amount, log.choice_hint), (POOL, 1));
+    assert_eq!(log.encrypted_blob, vec![7u8; 64]);
+    let held: UserPosition = fetch(&mut ctx, position).await;
+    assert_eq!((held.owner, held.total_staked), (bettor.pubkey(), POOL));
+
+    let migrated: Market = fetch(&mut ctx, enqueued).await;
+    assert_eq!(migrated.question, "Will the ferry run on Sunday?");
+    assert!(migrated.state == MarketState::Enqueued);
+    let job: ResolutionJob = fetch(&mut ctx, resolution_job).await;
+    assert_eq!((job.market, job.version), (enqueued, ACCOUNT_VERSION));
+    assert!(job.status == JobStatus::Pending);
+    assert_eq!((job.attempts, job.updated_at), (0, job.timestamp));
+
+    // The migrated bet refunds through its new position
+    fund(&mut ctx, &bettor.pubkey(), 10_000_000).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[withdraw_bet_ix(
+            &market,
+            &mint,
+            &bet_log,
+            &bettor.pubkey(),
+            &tokens,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_eq!(token_balance(&mut ctx, tokens).await, POOL);
+    assert_eq!(token_balance(&mut ctx, escrow).await, 0);
+    let held: UserPosition = fetch(&mut ctx, position).await;
+    assert_eq!(held.total_staked, 0);
+
+    // Running it again finds nothing to do
+    let logs = send_ok(
+        &mut ctx,
+        &[migrate_market_ix(
+            &enqueued,
+            None,
+            &[resolution_job],
+            &payer,
+        )],
+        &[],
+    )
+    .await;
+    assert_eq!(decode_events::<MarketMigrated>(&logs)[0].migrated, 0);
+}
+
+#[tokio::test]
+async fn current_accounts_are_left_alone() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let creator = new_funded_keypair(&mut ctx).await;
+    send_ok(
+        &mut ctx,
+        &[create_market_ix_with(
+            &creator.pubkey(),
+            &mint,
+            create_market_args("Already current?", deadline),
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    let created: Market = fetch(&mut ctx, market).await;
+    assert_eq!(created.version, ACCOUNT_VERSION);
+
+    let len = data_len(&mut ctx, market).await;
+    let logs = send_ok(
+        &mut ctx,
+        &[migrate_market_ix(&market, None, &[], &payer)],
+        &[],
+    )
+    .await;
+    assert_eq!(decode_events::<MarketMigrated>(&logs)[0].migrated, 0);
+    assert_eq!(data_len(&mut ctx, market).await, len);
+
+    // Only bet logs and jobs of this program can be migrated
+    for account in [mint, market] {
+        let outcome = send(
+            &mut ctx,
+            &[migrate_market_ix(&market, None, &[account], &payer)],
+            &[],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::NotMigratable);
+    }
+}
+
+#[tokio::test]
+async fn migrating_twice_with_the_same_accounts_is_harmless() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = keypair_from_seed(&[11; 32]).unwrap().pubkey();
+    let bettor = keypair_from_seed(&[12; 32]).unwrap().pubkey();
+    let mint = keypair_from_seed(&[14; 32]).unwrap().pubkey();
+
+    let market = market_pda(&creator);
+    let escrow = escrow_pda(&market);
+    let bet_log = bet_log_pda(&market, &bettor, 0);
+    let position = position_pda(&market, &bettor);
+    let fixtures = [
+        (mint, MINT, spl_token::ID),
+        (market, MARKET, market_factory::ID),
+        (escrow, ESCROW, spl_token::ID),
+        (bet_log, BET_LOG, market_factory::ID),
+    ];
+    for (address, data, owner) in fixtures {
+        load(&mut ctx, address, data, owner).await;
+    }
+
+    // The current bet log still takes its position's slot the second time
+    let migrate = || migrate_market_ix(&market, Some(escrow), &[bet_log, position], &payer);
+    let logs = send_ok(&mut ctx, &[migrate(), migrate()], &[]).await;
+    let migrated: Vec<u32> = decode_events::<MarketMigrated>(&logs)
+        .iter()
+        .map(|event| event.migrated)
+        .collect();
+    assert_eq!(migrated, [2, 0]);
+
+    let held: UserPosition = fetch(&mut ctx, position).await;
+    assert_eq!((held.owner, held.total_staked), (bettor, POOL));
+    let migrated: Market = fetch(&mut ctx, market).await;
+    assert_eq!(migrated.position_count, 1);
+}
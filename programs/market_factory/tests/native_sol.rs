@@ -0,0 +1,277 @@
+mod common;
+
+use anchor_lang::Space;
+use common::*;
+use market_factory::{
+    BetLog, Collateral, ErrorCode, Market, MarketCreated, MarketState, MxeResult, SolVault,
+    UserPosition,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Anchor's `AccountNotInitialized` framework error
+const ACCOUNT_NOT_INITIALIZED: u32 = 3012;
+
+const SOL: u64 = 1_000_000_000;
+
+async fn sol_market(ctx: &mut ProgramTestContext) -> (Keypair, Pubkey, i64) {
+    let creator = new_funded_keypair(ctx).await;
+    let deadline = now(ctx).await + 3_600;
+    let logs = send_ok(
+        ctx,
+        &[create_sol_market_ix(
+            &creator.pubkey(),
+            create_market_args("Lamports?", deadline),
+        )],
+        &[&creator],
+    )
+    .await;
+    let created = &decode_events::<MarketCreated>(&logs)[0];
+    assert!(created.collateral == Collateral::NativeSol);
+    assert_eq!(created.token_mint, Pubkey::default());
+    let market = market_pda(&creator.pubkey());
+    (creator, market, deadline)
+}
+
+/// Lamports in the vault above its own rent, i.e. the pool
+async fn vault_pool(ctx: &mut ProgramTestContext, market: &Pubkey) -> u64 {
+    let vault = sol_vault_pda(market);
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    lamports(ctx, vault).await - rent.minimum_balance(8 + SolVault::INIT_SPACE)
+}
+
+#[tokio::test]
+async fn sol_stakes_move_as_lamports() {
+    let mut ctx = start().await;
+    let (_, market, _) = sol_market(&mut ctx).await;
+    let state: Market = fetch(&mut ctx, market).await;
+    assert_eq!(state.escrow_vault, sol_vault_pda(&market));
+    let vault: SolVault = fetch(&mut ctx, sol_vault_pda(&market)).await;
+    assert_eq!(vault.market, market);
+
+    let alice = new_funded_keypair(&mut ctx).await;
+    let before = lamports(&mut ctx, alice.pubkey()).await;
+    let bet = bet_log_pda(&market, &alice.pubkey(), 0);
+    send_ok(
+        &mut ctx,
+        &[deposit_bet_sol_ix(
+            &market,
+            &alice.pubkey(),
+            0,
+            vec![1; 9],
+            SOL,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(fetch::<Market>(&mut ctx, market).await.total_pool, SOL);
+    assert_eq!(vault_pool(&mut ctx, &market).await, SOL);
+
+    // Raise, then lower the stake; each leg moves the difference in lamports
+    for amount in [3 * SOL, 2 * SOL] {
+        send_ok(
+            &mut ctx,
+            &[amend_bet_sol_ix(
+                &market,
+                &bet,
+                &alice.pubkey(),
+                vec![2; 9],
+                amount,
+            )],
+            &[&alice],
+        )
+        .await;
+        assert_eq!(fetch::<BetLog>(&mut ctx, bet).await.amount, amount);
+        assert_eq!(vault_pool(&mut ctx, &market).await, amount);
+    }
+    let position: UserPosition = fetch(&mut ctx, position_pda(&market, &alice.pubkey())).await;
+    assert_eq!(position.total_staked, 2 * SOL);
+
+    // The refund and the bet log rent go straight back to the wallet; only
+    // the position's rent is still out
+    send_ok(
+        &mut ctx,
+        &[withdraw_bet_sol_ix(&market, &bet, &alice.pubkey())],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(vault_pool(&mut ctx, &market).await, 0);
+    let position_rent = lamports(&mut ctx, position_pda(&market, &alice.pubkey())).await;
+    assert_eq!(
+        lamports(&mut ctx, alice.pubkey()).await,
+        before - position_rent
+    );
+}
+
+#[tokio::test]
+async fn sol_vault_closes_with_the_market() {
+    let mut ctx = start().await;
+    let (creator, market, deadline) = sol_market(&mut ctx).await;
+    let alice = new_funded_keypair(&mut ctx).await;
+    send_ok(
+        &mut ctx,
+        &[deposit_bet_sol_ix(
+            &market,
+            &alice.pubkey(),
+            0,
+            vec![1; 9],
+            SOL,
+        )],
+        &[&alice],
+    )
+    .await;
+
+    // Settlement takes the vault and pays the winner's wallet instead of a
+    // token account
+    warp_to_timestamp(&mut ctx, deadline).await;
+    let payer = ctx.payer.pubkey();
+    let result = MxeResult {
+        payouts: vec![mxe_payout(&alice.pubkey(), SOL)],
+        ..mxe_result(&market)
+    };
+    let before = lamports(&mut ctx, alice.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[
+            enqueue_resolution_ix(&market, &payer),
+            callback_settle_sol_ix(&market, &result, &[alice.pubkey()]),
+        ],
+        &[&mxe_authority()],
+    )
+    .await;
+    assert!(fetch::<Market>(&mut ctx, market).await.state == MarketState::Settled);
+    assert_eq!(lamports(&mut ctx, alice.pubkey()).await, before + SOL);
+    assert_eq!(vault_pool(&mut ctx, &market).await, 0);
+
+    // Paid out in full, the vault closes
+    send_ok(
+        &mut ctx,
+        &[close_escrow_sol_ix(&market, &creator.pubkey())],
+        &[&creator],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, sol_vault_pda(&market)).await);
+
+    // A payout to any other wallet is refused
+    let (creator, market, deadline) = sol_market(&mut ctx).await;
+    send_ok(
+        &mut ctx,
+        &[deposit_bet_sol_ix(
+            &market,
+            &alice.pubkey(),
+            0,
+            vec![1; 9],
+            SOL,
+        )],
+        &[&alice],
+    )
+    .await;
+    warp_to_timestamp(&mut ctx, deadline).await;
+    send_ok(&mut ctx, &[enqueue_resolution_ix(&market, &payer)], &[]).await;
+    let result = MxeResult {
+        payouts: vec![mxe_payout(&alice.pubkey(), SOL)],
+        ..mxe_result(&market)
+    };
+    let outcome = send(
+        &mut ctx,
+        &[callback_settle_sol_ix(
+            &market,
+            &result,
+            &[creator.pubkey()],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::PayoutRecipientMismatch);
+
+    // A market whose only bet was withdrawn has an empty vault
+    let (creator, market, _) = sol_market(&mut ctx).await;
+    send_ok(
+        &mut ctx,
+        &[
+            deposit_bet_sol_ix(&market, &alice.pubkey(), 0, vec![1; 9], SOL),
+            withdraw_bet_sol_ix(
+                &market,
+                &bet_log_pda(&market, &alice.pubkey(), 0),
+                &alice.pubkey(),
+            ),
+        ],
+        &[&alice],
+    )
+    .await;
+    let vault = sol_vault_pda(&market);
+    let vault_rent = lamports(&mut ctx, vault).await;
+    let before = lamports(&mut ctx, creator.pubkey()).await;
+    send_ok(
+        &mut ctx,
+        &[
+            cancel_market_ix(&market, &creator.pubkey(), &creator.pubkey()),
+            close_escrow_sol_ix(&market, &creator.pubkey()),
+        ],
+        &[&creator],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, vault).await);
+    assert_eq!(
+        lamports(&mut ctx, creator.pubkey()).await,
+        before + vault_rent
+    );
+
+    send_ok(
+        &mut ctx,
+        &[
+            close_position_ix(&market, &alice.pubkey()),
+            close_sol_market_ix(&market, &creator.pubkey()),
+        ],
+        &[&creator],
+    )
+    .await;
+    assert!(!account_exists(&mut ctx, market).await);
+}
+
+#[tokio::test]
+async fn token_accounts_do_not_fit_sol_markets() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+
+    // Token escrow accounts cannot be created for a native SOL market
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mut args = create_market_args("Both?", now(&mut ctx).await + 3_600);
+    args.collateral = Collateral::NativeSol;
+    let outcome = send(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::CollateralAccountsMismatch);
+
+    // ...and a SOL market has no token escrow to deposit into
+    let (_, market, _) = sol_market(&mut ctx).await;
+    let alice = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &alice.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+    let outcome = send(
+        &mut ctx,
+        &[deposit_bet_ix(
+            &market,
+            &mint,
+            &alice.pubkey(),
+            &tokens,
+            0,
+            vec![1; 9],
+            1,
+            10,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(
+        anchor_error_code(&outcome.result),
+        Some(ACCOUNT_NOT_INITIALIZED)
+    );
+}
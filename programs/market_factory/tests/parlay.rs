@@ -0,0 +1,377 @@
+mod common;
+
+use common::*;
+use market_factory::{ErrorCode, Market, ParlayPool, ParlaySettled};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    instruction::AccountMeta,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    mint: Pubkey,
+    deadline: i64,
+}
+
+async fn fixture() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    Fixture {
+        ctx,
+        mint,
+        deadline,
+    }
+}
+
+impl Fixture {
+    async fn market(&mut self, mint: &Pubkey, deadline: i64) -> (Keypair, Pubkey) {
+        let creator = new_funded_keypair(&mut self.ctx).await;
+        send_ok(
+            &mut self.ctx,
+            &[create_market_ix_with(
+                &creator.pubkey(),
+                mint,
+                create_market_args("Leg?", deadline),
+            )],
+            &[&creator],
+        )
+        .await;
+        let market = market_pda(&creator.pubkey());
+        (creator, market)
+    }
+
+    /// Two legs closing at the fixture deadline, in key order
+    async fn legs(&mut self) -> Vec<(Keypair, Pubkey)> {
+        let mint = self.mint;
+        let mut legs = vec![
+            self.market(&mint, self.deadline).await,
+            self.market(&mint, self.deadline).await,
+        ];
+        legs.sort_by_key(|(_, market)| *market);
+        legs
+    }
+
+    async fn bettor(&mut self, amount: u64) -> (Keypair, Pubkey) {
+        let bettor = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &bettor.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, amount).await;
+        (bettor, tokens)
+    }
+
+    async fn place(
+        &mut self,
+        pool: &Pubkey,
+        bettor: &(Keypair, Pubkey),
+        outcomes: u8,
+        amount: u64,
+    ) -> TxOutcome {
+        let (owner, tokens) = bettor;
+        send(
+            &mut self.ctx,
+            &[place_parlay_ix(
+                pool,
+                &self.mint,
+                &owner.pubkey(),
+                tokens,
+                outcomes,
+                amount,
+            )],
+            &[owner],
+        )
+        .await
+    }
+
+    async fn claim(&mut self, pool: &Pubkey, bettor: &(Keypair, Pubkey)) -> TxOutcome {
+        let (owner, tokens) = bettor;
+        send(
+            &mut self.ctx,
+            &[claim_parlay_ix(pool, &self.mint, &owner.pubkey(), tokens)],
+            &[owner],
+        )
+        .await
+    }
+
+    /// Close betting on `market` and settle it to `outcome`
+    async fn settle_leg(&mut self, market: &Pubkey, outcome: u8) {
+        let payer = self.ctx.payer.pubkey();
+        send_ok(
+            &mut self.ctx,
+            &[
+                enqueue_resolution_ix(market, &payer),
+                callback_settle_outcome_ix(market, &self.mint, Some(outcome), None, 0),
+            ],
+            &[&mxe_authority()],
+        )
+        .await;
+    }
+}
+
+#[tokio::test]
+async fn pool_legs_are_validated() {
+    let mut f = fixture().await;
+    let payer = f.ctx.payer.pubkey();
+    let mint = f.mint;
+    let (_, early) = f.market(&mint, f.deadline - 600).await;
+    let (_, late) = f.market(&mint, f.deadline).await;
+    let other_mint = create_mint(&mut f.ctx, &payer).await;
+    let (_, foreign) = f.market(&other_mint, f.deadline).await;
+    let mut sorted = [early, late];
+    sorted.sort();
+
+    let cases = [
+        vec![early],
+        vec![sorted[1], sorted[0]],
+        vec![early, early],
+        {
+            let mut legs = vec![early, foreign];
+            legs.sort();
+            legs
+        },
+    ];
+    for legs in cases {
+        let outcome = send(
+            &mut f.ctx,
+            &[create_parlay_pool_ix(&legs, &mint, &payer)],
+            &[],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::InvalidParlayLegs);
+    }
+
+    send_ok(
+        &mut f.ctx,
+        &[create_parlay_pool_ix(&sorted, &mint, &payer)],
+        &[],
+    )
+    .await;
+    let pool = parlay_pool_pda(&sorted);
+    let pool: ParlayPool = fetch(&mut f.ctx, pool).await;
+    assert_eq!(pool.legs, sorted.to_vec());
+    // Tickets close with the earliest leg
+    assert_eq!(pool.deadline, f.deadline - 600);
+    assert_eq!(
+        pool.escrow_vault,
+        parlay_escrow_pda(&parlay_pool_pda(&sorted))
+    );
+}
+
+#[tokio::test]
+async fn tickets_that_call_every_leg_split_the_pool() {
+    let mut f = fixture().await;
+    let payer = f.ctx.payer.pubkey();
+    let legs = f.legs().await;
+    let markets: Vec<Pubkey> = legs.iter().map(|(_, market)| *market).collect();
+    send_ok(
+        &mut f.ctx,
+        &[create_parlay_pool_ix(&markets, &f.mint, &payer)],
+        &[],
+    )
+    .await;
+    let pool = parlay_pool_pda(&markets);
+
+    // YES on both legs is 0b11; NO then YES is 0b10
+    let alice = f.bettor(300).await;
+    let bob = f.bettor(100).await;
+    let carol = f.bettor(400).await;
+    let outcome = f.place(&pool, &alice, 0b100, 300).await;
+    assert_error(&outcome, ErrorCode::InvalidOutcome);
+    let tickets = [(&alice, 0b11, 300), (&bob, 0b11, 100), (&carol, 0b10, 400)];
+    for (bettor, outcomes, amount) in tickets {
+        let outcome = f.place(&pool, bettor, outcomes, amount).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    }
+    let outcome = f.claim(&pool, &alice).await;
+    assert_error(&outcome, ErrorCode::ParlayNotSettled);
+
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let late = f.bettor(100).await;
+    let outcome = f.place(&pool, &late, 0b11, 100).await;
+    assert_error(&outcome, ErrorCode::DeadlinePassed);
+
+    // Every leg has to be in before the pool settles
+    f.settle_leg(&markets[0], 1).await;
+    let settle = |legs: &[Pubkey]| settle_parlay_pool_ix(&pool, legs);
+    let outcome = send(&mut f.ctx, &[settle(&markets)], &[]).await;
+    assert_error(&outcome, ErrorCode::ParlayLegsNotFinalized);
+    f.settle_leg(&markets[1], 1).await;
+    let outcome = send(&mut f.ctx, &[settle(&markets[..1])], &[]).await;
+    assert_error(&outcome, ErrorCode::InvalidParlayLegs);
+    let outcome = send(&mut f.ctx, &[settle(&markets)], &[]).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let settled = &decode_events::<ParlaySettled>(&outcome.logs)[0];
+    assert_eq!(
+        (
+            settled.winning_outcomes,
+            settled.winning_stake,
+            settled.total_staked
+        ),
+        (Some(0b11), 400, 800)
+    );
+
+    // 800 split 3:1 between the two winning tickets
+    for (bettor, paid) in [(&alice, 600), (&bob, 200), (&carol, 0)] {
+        let outcome = f.claim(&pool, bettor).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+        assert_eq!(token_balance(&mut f.ctx, bettor.1).await, paid);
+        let ticket = parlay_pda(&pool, &bettor.0.pubkey());
+        assert!(!account_exists(&mut f.ctx, ticket).await);
+    }
+    assert_eq!(token_balance(&mut f.ctx, parlay_escrow_pda(&pool)).await, 0);
+}
+
+#[tokio::test]
+async fn legs_count_only_as_their_resolver_settled_them() {
+    let mut f = fixture().await;
+    let payer = f.ctx.payer.pubkey();
+    let legs = f.legs().await;
+    let markets: Vec<Pubkey> = legs.iter().map(|(_, market)| *market).collect();
+    send_ok(
+        &mut f.ctx,
+        &[create_parlay_pool_ix(&markets, &f.mint, &payer)],
+        &[],
+    )
+    .await;
+    let pool = parlay_pool_pda(&markets);
+    let alice = f.bettor(300).await;
+    let bob = f.bettor(100).await;
+    for (bettor, outcomes, amount) in [(&alice, 0b11, 300), (&bob, 0b10, 100)] {
+        let outcome = f.place(&pool, bettor, outcomes, amount).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    }
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    send_ok(
+        &mut f.ctx,
+        &[enqueue_resolution_ix(&markets[0], &payer)],
+        &[],
+    )
+    .await;
+
+    // Alice cannot settle the first leg her way, so it does not count
+    let mut ix = callback_settle_outcome_ix(&markets[0], &f.mint, Some(1), None, 0);
+    ix.accounts[2] = AccountMeta::new_readonly(alice.0.pubkey(), true);
+    let outcome = send(&mut f.ctx, &[ix], &[&alice.0]).await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+    let outcome = send(&mut f.ctx, &[settle_parlay_pool_ix(&pool, &markets)], &[]).await;
+    assert_error(&outcome, ErrorCode::ParlayLegsNotFinalized);
+
+    // The resolver settles it NO, the way Bob called it
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_outcome_ix(
+            &markets[0],
+            &f.mint,
+            Some(0),
+            None,
+            0,
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    f.settle_leg(&markets[1], 1).await;
+    let outcome = send(&mut f.ctx, &[settle_parlay_pool_ix(&pool, &markets)], &[]).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let settled = &decode_events::<ParlaySettled>(&outcome.logs)[0];
+    assert_eq!(settled.winning_outcomes, Some(0b10));
+    for (bettor, paid) in [(&alice, 0), (&bob, 400)] {
+        let outcome = f.claim(&pool, bettor).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+        assert_eq!(token_balance(&mut f.ctx, bettor.1).await, paid);
+    }
+}
+
+#[tokio::test]
+async fn cancelled_leg_refunds_every_ticket() {
+    let mut f = fixture().await;
+    let payer = f.ctx.payer.pubkey();
+    let legs = f.legs().await;
+    let markets: Vec<Pubkey> = legs.iter().map(|(_, market)| *market).collect();
+    send_ok(
+        &mut f.ctx,
+        &[create_parlay_pool_ix(&markets, &f.mint, &payer)],
+        &[],
+    )
+    .await;
+    let pool = parlay_pool_pda(&markets);
+    let alice = f.bettor(100).await;
+    let bob = f.bettor(50).await;
+    f.place(&pool, &alice, 0b01, 100).await;
+    f.place(&pool, &bob, 0b11, 50).await;
+
+    let (creator, cancelled) = &legs[1];
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(
+            cancelled,
+            &creator.pubkey(),
+            &creator.pubkey(),
+        )],
+        &[creator],
+    )
+    .await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    f.settle_leg(&markets[0], 1).await;
+    let logs = send_ok(&mut f.ctx, &[settle_parlay_pool_ix(&pool, &markets)], &[]).await;
+    assert_eq!(
+        decode_events::<ParlaySettled>(&logs)[0].winning_outcomes,
+        None
+    );
+
+    for (bettor, stake) in [(&alice, 100), (&bob, 50)] {
+        let outcome = f.claim(&pool, bettor).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+        assert_eq!(token_balance(&mut f.ctx, bettor.1).await, stake);
+    }
+}
+
+#[tokio::test]
+async fn legs_cannot_close_under_an_unsettled_pool() {
+    let mut f = fixture().await;
+    let payer = f.ctx.payer.pubkey();
+    let legs = f.legs().await;
+    let markets: Vec<Pubkey> = legs.iter().map(|(_, market)| *market).collect();
+
+    // The pool is counted on its legs, so they have to be writable
+    let mut ix = create_parlay_pool_ix(&markets, &f.mint, &payer);
+    let leg = ix.accounts.len() - 1;
+    ix.accounts[leg] = AccountMeta::new_readonly(markets[1], false);
+    let outcome = send(&mut f.ctx, &[ix], &[]).await;
+    assert_error(&outcome, ErrorCode::InvalidParlayLegs);
+    send_ok(
+        &mut f.ctx,
+        &[create_parlay_pool_ix(&markets, &f.mint, &payer)],
+        &[],
+    )
+    .await;
+    let pool = parlay_pool_pda(&markets);
+    for market in &markets {
+        let leg: Market = fetch(&mut f.ctx, *market).await;
+        assert_eq!(leg.open_parlay_count, 1);
+    }
+
+    // A cancelled leg keeps its market until the pool has settled against it
+    let (creator, cancelled) = &legs[1];
+    let close = close_market_ix(cancelled, &creator.pubkey());
+    send_ok(
+        &mut f.ctx,
+        &[
+            cancel_market_ix(cancelled, &creator.pubkey(), &creator.pubkey()),
+            close_escrow_ix(cancelled, &creator.pubkey()),
+        ],
+        &[creator],
+    )
+    .await;
+    let outcome = send(&mut f.ctx, std::slice::from_ref(&close), &[creator]).await;
+    assert_error(&outcome, ErrorCode::AccountsStillOpen);
+
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    f.settle_leg(&markets[0], 1).await;
+    send_ok(&mut f.ctx, &[settle_parlay_pool_ix(&pool, &markets)], &[]).await;
+    let leg: Market = fetch(&mut f.ctx, markets[0]).await;
+    assert_eq!(leg.open_parlay_count, 0);
+    send_ok(&mut f.ctx, &[close], &[creator]).await;
+    assert!(!account_exists(&mut f.ctx, *cancelled).await);
+}
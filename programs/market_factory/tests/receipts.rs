@@ -0,0 +1,379 @@
+mod common;
+
+use anchor_spl::{associated_token, token::spl_token};
+use common::*;
+use market_factory::{
+    allowlist_parent, payout_leaf, BetStorage, Collateral, ErrorCode, Market, MarketState,
+    MxeResult, ReceiptRedeemed, UserPosition, MAX_JOB_ATTEMPTS,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+
+/// The associated token program's `Create` instruction
+fn create_associated_token_account(payer: &Pubkey, owner: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: associated_token::ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(
+                associated_token::get_associated_token_address(owner, mint),
+                false,
+            ),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+        ],
+        data: vec![0],
+    }
+}
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    mint: Pubkey,
+    market: Pubkey,
+    deadline: i64,
+}
+
+async fn receipt_market() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let mut args = create_market_args("Tradeable?", deadline);
+    args.receipt_tokens = true;
+    send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        mint,
+        market,
+        deadline,
+    }
+}
+
+impl Fixture {
+    async fn wallet(&mut self, balance: u64) -> (Keypair, Pubkey) {
+        let wallet = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &wallet.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, balance).await;
+        (wallet, tokens)
+    }
+
+    /// Deposit with a receipt; returns the bet log
+    async fn bet(&mut self, bettor: &Keypair, tokens: &Pubkey, amount: u64) -> Pubkey {
+        let bet_index = fetch::<Market>(&mut self.ctx, self.market).await.bet_count;
+        send_ok(
+            &mut self.ctx,
+            &[deposit_bet_receipt_ix(
+                &self.market,
+                &self.mint,
+                &bettor.pubkey(),
+                tokens,
+                bet_index,
+                amount,
+            )],
+            &[bettor],
+        )
+        .await;
+        bet_log_pda(&self.market, &bettor.pubkey(), bet_index)
+    }
+
+    /// Hand a bet's receipt from `from` to `to`
+    async fn transfer_receipt(&mut self, bet_log: &Pubkey, from: &Keypair, to: &Pubkey) {
+        let payer = self.ctx.payer.pubkey();
+        let ixs = [
+            create_associated_token_account(&payer, to, &receipt_mint_pda(bet_log)),
+            spl_token::instruction::transfer(
+                &spl_token::ID,
+                &receipt_account(bet_log, &from.pubkey()),
+                &receipt_account(bet_log, to),
+                &from.pubkey(),
+                &[],
+                1,
+            )
+            .unwrap(),
+        ];
+        send_ok(&mut self.ctx, &ixs, &[from]).await;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn redeem(
+        &mut self,
+        depositor: &Pubkey,
+        bet_index: u64,
+        holder: &Keypair,
+        holder_tokens: &Pubkey,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> TxOutcome {
+        send(
+            &mut self.ctx,
+            &[redeem_receipt_ix(
+                &self.market,
+                &self.mint,
+                depositor,
+                bet_index,
+                &holder.pubkey(),
+                holder_tokens,
+                amount,
+                proof,
+            )],
+            &[holder],
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn receipt_holder_collects_the_payout() {
+    let mut f = receipt_market().await;
+    let (alice, alice_tokens) = f.wallet(600).await;
+    let (carol, carol_tokens) = f.wallet(400).await;
+    let (bob, bob_tokens) = f.wallet(0).await;
+    let alice_bet = f.bet(&alice, &alice_tokens, 600).await;
+    f.bet(&carol, &carol_tokens, 400).await;
+
+    // One token, and nobody can mint another
+    let receipt_mint = f
+        .ctx
+        .banks_client
+        .get_account(receipt_mint_pda(&alice_bet))
+        .await
+        .unwrap()
+        .unwrap();
+    let receipt_mint = spl_token::state::Mint::unpack(&receipt_mint.data).unwrap();
+    assert_eq!(receipt_mint.supply, 1);
+    assert_eq!(receipt_mint.mint_authority, COption::None);
+
+    // The bet follows the receipt, not the depositor
+    f.transfer_receipt(&alice_bet, &alice, &bob.pubkey()).await;
+    let outcome = send(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice_bet,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::ReceiptRequired);
+
+    // Alice's bet (index 0) takes the whole pool
+    let leaves = [payout_leaf(0, 1_000), payout_leaf(1, 0)];
+    let root = allowlist_parent(&leaves[0], &leaves[1]);
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    send_ok(&mut f.ctx, &[enqueue_resolution_ix(&f.market, &payer)], &[]).await;
+
+    // A receipt market cannot settle without its payout root
+    let outcome = send(
+        &mut f.ctx,
+        &[callback_settle_ix(
+            &f.market,
+            &f.mint,
+            &mxe_result(&f.market),
+            &[],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidMxeResult);
+
+    let result = MxeResult {
+        payout_root: Some(hex::encode(root)),
+        ..mxe_result(&f.market)
+    };
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_ix(&f.market, &f.mint, &result, &[])],
+        &[&mxe_authority()],
+    )
+    .await;
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert!(market.state == MarketState::Settled);
+    assert_eq!(market.payout_root, root);
+
+    let outcome = f
+        .redeem(
+            &alice.pubkey(),
+            0,
+            &alice,
+            &alice_tokens,
+            1_000,
+            vec![leaves[1]],
+        )
+        .await;
+    assert_error(&outcome, ErrorCode::ReceiptNotHeld);
+    let outcome = f
+        .redeem(
+            &alice.pubkey(),
+            0,
+            &bob,
+            &bob_tokens,
+            1_001,
+            vec![leaves[1]],
+        )
+        .await;
+    assert_error(&outcome, ErrorCode::InvalidPayoutProof);
+
+    let outcome = f
+        .redeem(
+            &alice.pubkey(),
+            0,
+            &bob,
+            &bob_tokens,
+            1_000,
+            vec![leaves[1]],
+        )
+        .await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let redeemed = &decode_events::<ReceiptRedeemed>(&outcome.logs)[0];
+    assert_eq!((redeemed.bet_index, redeemed.amount), (0, 1_000));
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 1_000);
+    assert!(!account_exists(&mut f.ctx, alice_bet).await);
+
+    // Losing receipts redeem for nothing, which closes their bet logs too
+    let outcome = f
+        .redeem(
+            &carol.pubkey(),
+            1,
+            &carol,
+            &carol_tokens,
+            0,
+            vec![leaves[0]],
+        )
+        .await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(market.closed_bet_count, market.bet_count);
+}
+
+#[tokio::test]
+async fn receipts_refund_while_betting_is_open() {
+    let mut f = receipt_market().await;
+    let (alice, alice_tokens) = f.wallet(600).await;
+    let (bob, bob_tokens) = f.wallet(0).await;
+    let bet = f.bet(&alice, &alice_tokens, 600).await;
+    f.transfer_receipt(&bet, &alice, &bob.pubkey()).await;
+
+    let outcome = f
+        .redeem(&alice.pubkey(), 0, &bob, &bob_tokens, 0, Vec::new())
+        .await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 600);
+    assert_eq!(fetch::<Market>(&mut f.ctx, f.market).await.total_pool, 0);
+    let position: UserPosition = fetch(&mut f.ctx, position_pda(&f.market, &alice.pubkey())).await;
+    assert_eq!(position.total_staked, 0);
+
+    // Receipt markets need the receipt accounts on every deposit
+    let outcome = send(
+        &mut f.ctx,
+        &[deposit_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+            1,
+            vec![1; 9],
+            1,
+            10,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::ReceiptAccountsMismatch);
+}
+
+#[tokio::test]
+async fn receipts_refund_once_a_stuck_market_is_voided() {
+    let mut f = receipt_market().await;
+    let (alice, alice_tokens) = f.wallet(600).await;
+    let (bob, bob_tokens) = f.wallet(0).await;
+    let bet = f.bet(&alice, &alice_tokens, 600).await;
+    f.transfer_receipt(&bet, &alice, &bob.pubkey()).await;
+
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    let mxe = mxe_authority();
+    send_ok(&mut f.ctx, &[enqueue_resolution_ix(&f.market, &payer)], &[]).await;
+    for attempt in 0..MAX_JOB_ATTEMPTS {
+        send_ok(
+            &mut f.ctx,
+            &[
+                mark_job_running_ix(&f.market, &mxe.pubkey()),
+                mark_job_failed_ix(&f.market, &mxe.pubkey(), attempt as u32),
+            ],
+            &[&mxe],
+        )
+        .await;
+    }
+    send_ok(&mut f.ctx, &[void_stuck_market_ix(&f.market, None)], &[]).await;
+
+    // The stake goes to the receipt's holder, not through the position
+    let outcome = send(
+        &mut f.ctx,
+        &[refund_position_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::ReceiptRequired);
+    let outcome = f
+        .redeem(&alice.pubkey(), 0, &bob, &bob_tokens, 0, Vec::new())
+        .await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 600);
+    assert_eq!(fetch::<Market>(&mut f.ctx, f.market).await.total_pool, 0);
+    let position: UserPosition = fetch(&mut f.ctx, position_pda(&f.market, &alice.pubkey())).await;
+    assert_eq!(position.total_staked, 0);
+}
+
+#[tokio::test]
+async fn receipts_need_token_bet_logs() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    let ledger = |mut args: market_factory::instruction::CreateMarket| {
+        args.bet_storage = BetStorage::Ledger;
+        args.blob_store_uri = "https://blobs.example".to_string();
+        args
+    };
+    let native_sol = |mut args: market_factory::instruction::CreateMarket| {
+        args.collateral = Collateral::NativeSol;
+        args
+    };
+    for mode in [ledger, native_sol] {
+        let creator = new_funded_keypair(&mut ctx).await;
+        let mut args = mode(create_market_args("Receipts?", deadline));
+        args.receipt_tokens = true;
+        let ix = match args.collateral {
+            Collateral::Token => create_market_ix_with(&creator.pubkey(), &mint, args),
+            Collateral::NativeSol => create_sol_market_ix(&creator.pubkey(), args),
+        };
+        let outcome = send(&mut ctx, &[ix], &[&creator]).await;
+        assert_error(&outcome, ErrorCode::ReceiptsUnsupported);
+    }
+}
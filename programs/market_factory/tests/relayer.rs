@@ -0,0 +1,166 @@
+mod common;
+
+use common::*;
+use market_factory::{BetLog, ErrorCode, Market};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    relayer: Keypair,
+    mint: Pubkey,
+    market: Pubkey,
+}
+
+async fn market_with_relayer() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let relayer = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    send_ok(
+        &mut ctx,
+        &[create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Sponsored?",
+            deadline,
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        relayer,
+        mint,
+        market,
+    }
+}
+
+impl Fixture {
+    /// A wallet with stake tokens and no lamports at all
+    async fn token_only_wallet(&mut self) -> (Keypair, Pubkey) {
+        let wallet = Keypair::new();
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &wallet.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, 1_000).await;
+        (wallet, tokens)
+    }
+
+    async fn relay(
+        &mut self,
+        user: &Pubkey,
+        tokens: &Pubkey,
+        amount: u64,
+        authority: &Keypair,
+    ) -> TxOutcome {
+        let bet_index = fetch::<Market>(&mut self.ctx, self.market).await.bet_count;
+        let relayer = self.relayer.pubkey();
+        send(
+            &mut self.ctx,
+            &[deposit_bet_relayed_ix(
+                &self.market,
+                &self.mint,
+                user,
+                Some(tokens),
+                bet_index,
+                amount,
+                &authority.pubkey(),
+                &relayer,
+            )],
+            &[&self.relayer, authority],
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn relayer_pays_rent_for_a_signing_wallet() {
+    let mut f = market_with_relayer().await;
+    let (alice, alice_tokens) = f.token_only_wallet().await;
+    let before = lamports(&mut f.ctx, f.relayer.pubkey()).await;
+
+    let outcome = f.relay(&alice.pubkey(), &alice_tokens, 300, &alice).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+
+    let bet = bet_log_pda(&f.market, &alice.pubkey(), 0);
+    let rent = lamports(&mut f.ctx, bet).await
+        + lamports(&mut f.ctx, position_pda(&f.market, &alice.pubkey())).await;
+    assert_eq!(
+        lamports(&mut f.ctx, f.relayer.pubkey()).await,
+        before - rent
+    );
+    assert_eq!(lamports(&mut f.ctx, alice.pubkey()).await, 0);
+    assert_eq!(
+        fetch::<BetLog>(&mut f.ctx, bet).await.depositor,
+        alice.pubkey()
+    );
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 700);
+}
+
+#[tokio::test]
+async fn approved_delegate_bets_without_the_wallet() {
+    let mut f = market_with_relayer().await;
+    let (alice, alice_tokens) = f.token_only_wallet().await;
+    let relayer = f.relayer.insecure_clone();
+    approve(&mut f.ctx, &alice_tokens, &relayer.pubkey(), &alice, 500).await;
+
+    let outcome = f.relay(&alice.pubkey(), &alice_tokens, 400, &relayer).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let bet: BetLog = fetch(&mut f.ctx, bet_log_pda(&f.market, &alice.pubkey(), 0)).await;
+    assert_eq!((bet.depositor, bet.amount), (alice.pubkey(), 400));
+
+    // The token program holds the delegate to what is left of the approval
+    let outcome = f.relay(&alice.pubkey(), &alice_tokens, 101, &relayer).await;
+    assert!(outcome.result.is_err());
+
+    // Neither a stranger nor the delegate on someone else's behalf
+    let mallory = new_funded_keypair(&mut f.ctx).await;
+    let outcome = f.relay(&alice.pubkey(), &alice_tokens, 50, &mallory).await;
+    assert_error(&outcome, ErrorCode::InvalidTransferAuthority);
+    let outcome = f
+        .relay(&mallory.pubkey(), &alice_tokens, 50, &relayer)
+        .await;
+    assert_error(&outcome, ErrorCode::InvalidTransferAuthority);
+    assert_eq!(fetch::<Market>(&mut f.ctx, f.market).await.bet_count, 1);
+}
+
+#[tokio::test]
+async fn sol_stakes_need_the_wallet_signature() {
+    let mut ctx = start().await;
+    let creator = new_funded_keypair(&mut ctx).await;
+    let relayer = new_funded_keypair(&mut ctx).await;
+    let alice = new_funded_keypair(&mut ctx).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    send_ok(
+        &mut ctx,
+        &[create_sol_market_ix(
+            &creator.pubkey(),
+            create_market_args("Lamports?", deadline),
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+
+    let relayed = |authority: &Keypair| {
+        deposit_bet_relayed_ix(
+            &market,
+            &Pubkey::default(),
+            &alice.pubkey(),
+            None,
+            0,
+            1_000,
+            &authority.pubkey(),
+            &relayer.pubkey(),
+        )
+    };
+    let outcome = send(&mut ctx, &[relayed(&relayer)], &[&relayer]).await;
+    assert_error(&outcome, ErrorCode::InvalidTransferAuthority);
+    send_ok(&mut ctx, &[relayed(&alice)], &[&relayer, &alice]).await;
+    assert_eq!(fetch::<Market>(&mut ctx, market).await.total_pool, 1_000);
+}
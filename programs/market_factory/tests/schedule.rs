@@ -0,0 +1,147 @@
+mod common;
+
+use common::*;
+use market_factory::{ErrorCode, Market, MarketCreated, MarketState};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+async fn scheduled_market(
+    ctx: &mut ProgramTestContext,
+    open_time: Option<i64>,
+    deadline: i64,
+    resolve_after: Option<i64>,
+) -> (Pubkey, Pubkey) {
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(ctx).await;
+    let mint = create_mint(ctx, &payer).await;
+    let mut args = create_market_args("Scheduled?", deadline);
+    args.open_time = open_time;
+    args.resolve_after = resolve_after;
+    send_ok(
+        ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    (market_pda(&creator.pubkey()), mint)
+}
+
+async fn funded_bettor(ctx: &mut ProgramTestContext, mint: &Pubkey) -> (Keypair, Pubkey) {
+    let bettor = new_funded_keypair(ctx).await;
+    let tokens = create_token_account(ctx, mint, &bettor.pubkey()).await;
+    mint_to(ctx, mint, &tokens, 100).await;
+    (bettor, tokens)
+}
+
+#[tokio::test]
+async fn betting_starts_at_open_time() {
+    let mut ctx = start().await;
+    let open_time = now(&mut ctx).await + 600;
+    let (market, mint) = scheduled_market(&mut ctx, Some(open_time), open_time + 600, None).await;
+    let (bettor, tokens) = funded_bettor(&mut ctx, &mint).await;
+    let deposit = deposit_bet_ix(
+        &market,
+        &mint,
+        &bettor.pubkey(),
+        &tokens,
+        0,
+        vec![1; 9],
+        1,
+        10,
+    );
+
+    let outcome = send(&mut ctx, std::slice::from_ref(&deposit), &[&bettor]).await;
+    assert_error(&outcome, ErrorCode::BettingNotOpen);
+
+    warp_to_timestamp(&mut ctx, open_time).await;
+    send_ok(&mut ctx, &[deposit], &[&bettor]).await;
+    assert_eq!(fetch::<Market>(&mut ctx, market).await.bet_count, 1);
+}
+
+#[tokio::test]
+async fn resolution_waits_for_resolve_after() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let deadline = now(&mut ctx).await + 600;
+    let resolve_after = deadline + 86_400;
+    let (market, mint) = scheduled_market(&mut ctx, None, deadline, Some(resolve_after)).await;
+    let (bettor, tokens) = funded_bettor(&mut ctx, &mint).await;
+    send_ok(
+        &mut ctx,
+        &[deposit_bet_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            vec![1; 9],
+            1,
+            10,
+        )],
+        &[&bettor],
+    )
+    .await;
+
+    // Betting closes at the deadline, but resolution has to wait for the event
+    warp_to_timestamp(&mut ctx, deadline).await;
+    let outcome = send(
+        &mut ctx,
+        &[deposit_bet_ix(
+            &market,
+            &mint,
+            &bettor.pubkey(),
+            &tokens,
+            1,
+            vec![1; 9],
+            1,
+            10,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::DeadlinePassed);
+    let outcome = send(&mut ctx, &[enqueue_resolution_ix(&market, &payer)], &[]).await;
+    assert_error(&outcome, ErrorCode::ResolveTimeNotReached);
+
+    warp_to_timestamp(&mut ctx, resolve_after).await;
+    send_ok(&mut ctx, &[enqueue_resolution_ix(&market, &payer)], &[]).await;
+    assert!(fetch::<Market>(&mut ctx, market).await.state == MarketState::Enqueued);
+}
+
+#[tokio::test]
+async fn create_market_validates_timeline() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 600;
+
+    for (open_time, resolve_after) in [(Some(deadline), None), (None, Some(deadline - 1))] {
+        let mut args = create_market_args("Out of order?", deadline);
+        args.open_time = open_time;
+        args.resolve_after = resolve_after;
+        let outcome = send(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::InvalidTimeline);
+    }
+
+    // Without an explicit open time betting opens at creation
+    let mut args = create_market_args("In order?", deadline);
+    args.resolve_after = Some(deadline);
+    let logs = send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let created = &decode_events::<MarketCreated>(&logs)[0];
+    assert_eq!(created.open_time, now(&mut ctx).await);
+    assert_eq!(created.resolve_after, Some(deadline));
+}
@@ -0,0 +1,277 @@
+mod common;
+
+use common::*;
+use market_factory::{BetLog, ErrorCode, Market, SessionAuthority, SessionCreated};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Anchor's `ConstraintSeeds` and `AccountNotInitialized` framework errors
+const CONSTRAINT_SEEDS: u32 = 2006;
+const ACCOUNT_NOT_INITIALIZED: u32 = 3012;
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    mint: Pubkey,
+    markets: [Pubkey; 2],
+    owner: Keypair,
+    owner_tokens: Pubkey,
+    now: i64,
+}
+
+/// Two open markets and a wallet holding 1000 stake tokens
+async fn trader() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let now = now(&mut ctx).await;
+    let mut markets = [Pubkey::default(); 2];
+    for market in &mut markets {
+        let creator = new_funded_keypair(&mut ctx).await;
+        send_ok(
+            &mut ctx,
+            &[create_market_ix(
+                &creator.pubkey(),
+                &mint,
+                "Fast?",
+                now + 3_600,
+            )],
+            &[&creator],
+        )
+        .await;
+        *market = market_pda(&creator.pubkey());
+    }
+    let owner = new_funded_keypair(&mut ctx).await;
+    let owner_tokens = create_token_account(&mut ctx, &mint, &owner.pubkey()).await;
+    mint_to(&mut ctx, &mint, &owner_tokens, 1_000).await;
+    Fixture {
+        ctx,
+        mint,
+        markets,
+        owner,
+        owner_tokens,
+        now,
+    }
+}
+
+impl Fixture {
+    /// Session for the first market only, capped at 500, expiring in 10 minutes
+    async fn open_session(&mut self) -> Keypair {
+        let session_key = new_funded_keypair(&mut self.ctx).await;
+        let logs = send_ok(
+            &mut self.ctx,
+            &[create_session_ix(
+                &self.owner.pubkey(),
+                &self.owner_tokens,
+                &session_key.pubkey(),
+                self.now + 600,
+                500,
+                vec![self.markets[0]],
+            )],
+            &[&self.owner],
+        )
+        .await;
+        let created = &decode_events::<SessionCreated>(&logs)[0];
+        assert_eq!(created.session_key, session_key.pubkey());
+        session_key
+    }
+
+    async fn bet(&mut self, market: usize, amount: u64, session_key: &Keypair) -> TxOutcome {
+        let market = self.markets[market];
+        let bet_index = fetch::<Market>(&mut self.ctx, market).await.bet_count;
+        send(
+            &mut self.ctx,
+            &[deposit_bet_session_ix(
+                &market,
+                &self.mint,
+                &self.owner.pubkey(),
+                &self.owner_tokens,
+                bet_index,
+                amount,
+                &session_key.pubkey(),
+            )],
+            &[session_key],
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn session_key_bets_for_its_owner() {
+    let mut f = trader().await;
+    let session_key = f.open_session().await;
+
+    let outcome = f.bet(0, 300, &session_key).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let bet: BetLog = fetch(&mut f.ctx, bet_log_pda(&f.markets[0], &f.owner.pubkey(), 0)).await;
+    assert_eq!((bet.depositor, bet.amount), (f.owner.pubkey(), 300));
+    assert_eq!(token_balance(&mut f.ctx, f.owner_tokens).await, 700);
+    let session: SessionAuthority = fetch(
+        &mut f.ctx,
+        session_pda(&f.owner.pubkey(), &session_key.pubkey()),
+    )
+    .await;
+    assert_eq!((session.spent, session.spend_cap), (300, 500));
+
+    let outcome = f.bet(0, 201, &session_key).await;
+    assert_error(&outcome, ErrorCode::SessionCapExceeded);
+    let outcome = f.bet(1, 100, &session_key).await;
+    assert_error(&outcome, ErrorCode::SessionMarketNotAllowed);
+
+    // Another key cannot borrow the session
+    let stranger = new_funded_keypair(&mut f.ctx).await;
+    let mut ix = deposit_bet_session_ix(
+        &f.markets[0],
+        &f.mint,
+        &f.owner.pubkey(),
+        &f.owner_tokens,
+        1,
+        100,
+        &stranger.pubkey(),
+    );
+    let borrowed = session_pda(&f.owner.pubkey(), &stranger.pubkey());
+    for meta in ix
+        .accounts
+        .iter_mut()
+        .filter(|meta| meta.pubkey == borrowed)
+    {
+        meta.pubkey = session_pda(&f.owner.pubkey(), &session_key.pubkey());
+    }
+    let outcome = send(&mut f.ctx, &[ix], &[&stranger]).await;
+    assert_eq!(anchor_error_code(&outcome.result), Some(CONSTRAINT_SEEDS));
+}
+
+#[tokio::test]
+async fn sessions_expire_or_are_revoked() {
+    let mut f = trader().await;
+    let owner = f.owner.pubkey();
+
+    for (expires_at, markets, expected) in [
+        (f.now, vec![f.markets[0]], ErrorCode::InvalidSessionExpiry),
+        (f.now + 600, vec![], ErrorCode::InvalidSessionMarkets),
+        (
+            f.now + 600,
+            vec![f.markets[0]; 9],
+            ErrorCode::InvalidSessionMarkets,
+        ),
+    ] {
+        let outcome = send(
+            &mut f.ctx,
+            &[create_session_ix(
+                &owner,
+                &f.owner_tokens,
+                &Pubkey::new_unique(),
+                expires_at,
+                500,
+                markets,
+            )],
+            &[&f.owner],
+        )
+        .await;
+        assert_error(&outcome, expected);
+    }
+
+    let revoked = f.open_session().await;
+    let rent = lamports(&mut f.ctx, session_pda(&owner, &revoked.pubkey())).await;
+    let before = lamports(&mut f.ctx, owner).await;
+    send_ok(
+        &mut f.ctx,
+        &[revoke_session_ix(
+            &owner,
+            &f.owner_tokens,
+            &revoked.pubkey(),
+        )],
+        &[&f.owner],
+    )
+    .await;
+    assert_eq!(lamports(&mut f.ctx, owner).await, before + rent);
+    assert_eq!(token_delegate(&mut f.ctx, f.owner_tokens).await, None);
+    let outcome = f.bet(0, 100, &revoked).await;
+    assert_eq!(
+        anchor_error_code(&outcome.result),
+        Some(ACCOUNT_NOT_INITIALIZED)
+    );
+
+    let expired = f.open_session().await;
+    warp_to_timestamp(&mut f.ctx, f.now + 600).await;
+    let outcome = f.bet(0, 100, &expired).await;
+    assert_error(&outcome, ErrorCode::SessionExpired);
+}
+
+#[tokio::test]
+async fn one_approval_holds_the_token_account_at_a_time() {
+    let mut f = trader().await;
+    let owner = f.owner.pubkey();
+    let first = f.open_session().await;
+    let first_session = session_pda(&owner, &first.pubkey());
+    assert_eq!(
+        token_delegate(&mut f.ctx, f.owner_tokens).await,
+        Some(first_session)
+    );
+
+    // A second session would silently take over the first one's approval
+    let second = new_funded_keypair(&mut f.ctx).await;
+    let create_second = create_session_ix(
+        &owner,
+        &f.owner_tokens,
+        &second.pubkey(),
+        f.now + 600,
+        500,
+        vec![f.markets[0]],
+    );
+    let outcome = send(
+        &mut f.ctx,
+        std::slice::from_ref(&create_second),
+        &[&f.owner],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::DelegateInUse);
+
+    // Once the first session has spent its whole cap it no longer holds it
+    assert!(f.bet(0, 500, &first).await.result.is_ok());
+    assert_eq!(token_delegate(&mut f.ctx, f.owner_tokens).await, None);
+    send_ok(&mut f.ctx, &[create_second], &[&f.owner]).await;
+    let second_session = session_pda(&owner, &second.pubkey());
+
+    // Revoking the spent session leaves the newer approval alone
+    send_ok(
+        &mut f.ctx,
+        &[revoke_session_ix(&owner, &f.owner_tokens, &first.pubkey())],
+        &[&f.owner],
+    )
+    .await;
+    assert_eq!(
+        token_delegate(&mut f.ctx, f.owner_tokens).await,
+        Some(second_session)
+    );
+
+    // A relayer approval is protected the same way
+    send_ok(
+        &mut f.ctx,
+        &[revoke_session_ix(&owner, &f.owner_tokens, &second.pubkey())],
+        &[&f.owner],
+    )
+    .await;
+    let relayer = Pubkey::new_unique();
+    approve(&mut f.ctx, &f.owner_tokens, &relayer, &f.owner, 100).await;
+    let outcome = send(
+        &mut f.ctx,
+        &[create_session_ix(
+            &owner,
+            &f.owner_tokens,
+            &Pubkey::new_unique(),
+            f.now + 600,
+            500,
+            vec![f.markets[0]],
+        )],
+        &[&f.owner],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::DelegateInUse);
+    assert_eq!(
+        token_delegate(&mut f.ctx, f.owner_tokens).await,
+        Some(relayer)
+    );
+}
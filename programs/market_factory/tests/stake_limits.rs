@@ -0,0 +1,189 @@
+mod common;
+
+use common::*;
+use market_factory::{ErrorCode, Market, StakeLimits, UserPosition};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+async fn market_with_limits(
+    ctx: &mut ProgramTestContext,
+    stake_limits: StakeLimits,
+) -> (Pubkey, Pubkey) {
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(ctx).await;
+    let mint = create_mint(ctx, &payer).await;
+    let mut args = create_market_args("Capped?", now(ctx).await + 3_600);
+    args.stake_limits = stake_limits;
+    send_ok(
+        ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    (market_pda(&creator.pubkey()), mint)
+}
+
+async fn bettor(ctx: &mut ProgramTestContext, mint: &Pubkey) -> (Keypair, Pubkey) {
+    let bettor = new_funded_keypair(ctx).await;
+    let tokens = create_token_account(ctx, mint, &bettor.pubkey()).await;
+    mint_to(ctx, mint, &tokens, 1_000).await;
+    (bettor, tokens)
+}
+
+async fn bet(
+    ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    bettor: &Keypair,
+    tokens: &Pubkey,
+    amount: u64,
+) -> TxOutcome {
+    let Market {
+        bet_count: bet_index,
+        token_mint,
+        ..
+    } = fetch(ctx, *market).await;
+    send(
+        ctx,
+        &[deposit_bet_ix(
+            market,
+            &token_mint,
+            &bettor.pubkey(),
+            tokens,
+            bet_index,
+            vec![1; 9],
+            1,
+            amount,
+        )],
+        &[bettor],
+    )
+    .await
+}
+
+#[tokio::test]
+async fn per_bet_stake_bounds_are_enforced() {
+    let mut ctx = start().await;
+    let (market, mint) = market_with_limits(
+        &mut ctx,
+        StakeLimits {
+            min_stake: Some(10),
+            max_stake: Some(100),
+            max_wallet_exposure: None,
+        },
+    )
+    .await;
+    let (alice, tokens) = bettor(&mut ctx, &mint).await;
+
+    let outcome = bet(&mut ctx, &market, &alice, &tokens, 9).await;
+    assert_error(&outcome, ErrorCode::StakeBelowMinimum);
+    let outcome = bet(&mut ctx, &market, &alice, &tokens, 101).await;
+    assert_error(&outcome, ErrorCode::StakeAboveMaximum);
+
+    for amount in [10, 100] {
+        let outcome = bet(&mut ctx, &market, &alice, &tokens, amount).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    }
+    let position: UserPosition = fetch(&mut ctx, position_pda(&market, &alice.pubkey())).await;
+    assert_eq!(position.owner, alice.pubkey());
+    assert_eq!(position.total_staked, 110);
+    assert_eq!(fetch::<Market>(&mut ctx, market).await.position_count, 1);
+
+    // Amendments are held to the same bounds
+    let outcome = send(
+        &mut ctx,
+        &[amend_bet_ix(
+            &market,
+            &mint,
+            &bet_log_pda(&market, &alice.pubkey(), 0),
+            &alice.pubkey(),
+            &tokens,
+            vec![2; 9],
+            150,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::StakeAboveMaximum);
+}
+
+#[tokio::test]
+async fn wallet_exposure_cap_tracks_live_stake() {
+    let mut ctx = start().await;
+    let (market, mint) = market_with_limits(
+        &mut ctx,
+        StakeLimits {
+            min_stake: None,
+            max_stake: None,
+            max_wallet_exposure: Some(100),
+        },
+    )
+    .await;
+    let (alice, alice_tokens) = bettor(&mut ctx, &mint).await;
+    let (bob, bob_tokens) = bettor(&mut ctx, &mint).await;
+
+    for amount in [60, 40] {
+        let outcome = bet(&mut ctx, &market, &alice, &alice_tokens, amount).await;
+        assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    }
+    let outcome = bet(&mut ctx, &market, &alice, &alice_tokens, 1).await;
+    assert_error(&outcome, ErrorCode::WalletExposureExceeded);
+
+    // The cap is per wallet
+    let outcome = bet(&mut ctx, &market, &bob, &bob_tokens, 100).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    assert_eq!(fetch::<Market>(&mut ctx, market).await.position_count, 2);
+
+    // Withdrawing frees the withdrawn stake
+    send_ok(
+        &mut ctx,
+        &[withdraw_bet_ix(
+            &market,
+            &mint,
+            &bet_log_pda(&market, &alice.pubkey(), 0),
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    let position: UserPosition = fetch(&mut ctx, position_pda(&market, &alice.pubkey())).await;
+    assert_eq!(position.total_staked, 40);
+    let outcome = bet(&mut ctx, &market, &alice, &alice_tokens, 60).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let outcome = bet(&mut ctx, &market, &alice, &alice_tokens, 1).await;
+    assert_error(&outcome, ErrorCode::WalletExposureExceeded);
+}
+
+#[tokio::test]
+async fn create_market_rejects_inconsistent_limits() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    for stake_limits in [
+        StakeLimits {
+            min_stake: Some(50),
+            max_stake: Some(10),
+            max_wallet_exposure: None,
+        },
+        StakeLimits {
+            min_stake: Some(50),
+            max_stake: None,
+            max_wallet_exposure: Some(10),
+        },
+    ] {
+        let mut args = create_market_args("Backwards?", deadline);
+        args.stake_limits = stake_limits;
+        let outcome = send(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        assert_error(&outcome, ErrorCode::InvalidStakeLimits);
+    }
+}
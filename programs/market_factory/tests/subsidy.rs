@@ -0,0 +1,272 @@
+mod common;
+
+use anchor_lang::InstructionData;
+use common::*;
+use market_factory::{
+    ErrorCode, FeeSplit, Market, MarketCreated, MarketState, MxeResult, SubsidyReclaimed,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    sponsor: Keypair,
+    sponsor_tokens: Pubkey,
+    mint: Pubkey,
+    market: Pubkey,
+    deadline: i64,
+}
+
+/// A sponsor seeds the market with 1000; the creator takes 2% of the pool
+async fn sponsored_market() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let sponsor = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let sponsor_tokens = create_token_account(&mut ctx, &mint, &sponsor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &sponsor_tokens, 1_000).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let mut args = create_market_args("Seeded?", deadline);
+    args.subsidy = 1_000;
+    args.creator_fee_bps = 200;
+    let logs = send_ok(
+        &mut ctx,
+        &[create_subsidized_market_ix(
+            &creator.pubkey(),
+            &mint,
+            Some(&sponsor.pubkey()),
+            &sponsor_tokens,
+            args,
+        )],
+        &[&creator, &sponsor],
+    )
+    .await;
+    let created = &decode_events::<MarketCreated>(&logs)[0];
+    assert_eq!(
+        (created.subsidy, created.subsidy_sponsor),
+        (1_000, sponsor.pubkey())
+    );
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        creator,
+        sponsor,
+        sponsor_tokens,
+        mint,
+        market,
+        deadline,
+    }
+}
+
+impl Fixture {
+    async fn reclaim(&mut self, sponsor: &Keypair, sponsor_tokens: &Pubkey) -> TxOutcome {
+        send(
+            &mut self.ctx,
+            &[reclaim_subsidy_ix(
+                &self.market,
+                &self.mint,
+                &sponsor.pubkey(),
+                sponsor_tokens,
+            )],
+            &[sponsor],
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn subsidy_seeds_the_pool_without_a_bet() {
+    let mut f = sponsored_market().await;
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!((market.total_pool, market.subsidy), (1_000, 1_000));
+    assert_eq!((market.bet_count, market.position_count), (0, 0));
+    assert_eq!(
+        token_balance(&mut f.ctx, escrow_pda(&f.market)).await,
+        1_000
+    );
+
+    // Only YES is bet on, so the MXE hands NO's half back
+    let alice = new_funded_keypair(&mut f.ctx).await;
+    let alice_tokens = create_token_account(&mut f.ctx, &f.mint, &alice.pubkey()).await;
+    mint_to(&mut f.ctx, &f.mint, &alice_tokens, 600).await;
+    send_ok(
+        &mut f.ctx,
+        &[deposit_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &alice_tokens,
+            0,
+            vec![1; 9],
+            1,
+            600,
+        )],
+        &[&alice],
+    )
+    .await;
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    send_ok(&mut f.ctx, &[enqueue_resolution_ix(&f.market, &payer)], &[]).await;
+
+    // Fees come out of the 1100 in play, not the refunded half
+    let creator_tokens = create_token_account(&mut f.ctx, &f.mint, &f.creator.pubkey()).await;
+    let mxe = mxe_authority();
+    let settle = |creator_fee: u64, subsidy_refund: u64| {
+        let mut ix = callback_settle_with_fees_ix(
+            &f.market,
+            &f.mint,
+            FeeSplit::default(),
+            None,
+            Some(creator_tokens),
+            &[],
+        );
+        ix.data = market_factory::instruction::CallbackSettle {
+            mxe_result: encode_result(&MxeResult {
+                subsidy_refund,
+                ..fee_result(
+                    &f.market,
+                    &FeeSplit {
+                        creator: creator_fee,
+                        ..FeeSplit::default()
+                    },
+                )
+            }),
+            result_signature: vec![0x42; 64],
+        }
+        .data();
+        ix
+    };
+    for (creator_fee, subsidy_refund, expected) in [
+        (0, 1_001, ErrorCode::InvalidSubsidyRefund),
+        (23, 500, ErrorCode::FeeExceedsLimit),
+    ] {
+        let outcome = send(&mut f.ctx, &[settle(creator_fee, subsidy_refund)], &[&mxe]).await;
+        assert_error(&outcome, expected);
+    }
+    send_ok(&mut f.ctx, &[settle(22, 500)], &[&mxe]).await;
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert!(market.state == MarketState::Settled);
+    assert_eq!(market.subsidy_refund, 500);
+
+    let outcome = f.reclaim(&alice, &alice_tokens).await;
+    assert_error(&outcome, ErrorCode::Unauthorized);
+    let sponsor = f.sponsor.insecure_clone();
+    let sponsor_tokens = f.sponsor_tokens;
+    let outcome = f.reclaim(&sponsor, &sponsor_tokens).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let reclaimed = &decode_events::<SubsidyReclaimed>(&outcome.logs)[0];
+    assert_eq!(
+        (reclaimed.sponsor, reclaimed.amount),
+        (sponsor.pubkey(), 500)
+    );
+    assert_eq!(token_balance(&mut f.ctx, sponsor_tokens).await, 500);
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!((market.subsidy_refund, market.total_pool), (0, 1_100));
+    let outcome = f.reclaim(&sponsor, &sponsor_tokens).await;
+    assert_error(&outcome, ErrorCode::InvalidAmount);
+}
+
+#[tokio::test]
+async fn cancelled_market_returns_the_whole_subsidy() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let creator_tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    mint_to(&mut ctx, &mint, &creator_tokens, 400).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    // Without a sponsor the creator pays, and is owed the refund
+    let mut args = create_market_args("Quiet?", deadline);
+    args.subsidy = 400;
+    send_ok(
+        &mut ctx,
+        &[create_subsidized_market_ix(
+            &creator.pubkey(),
+            &mint,
+            None,
+            &creator_tokens,
+            args,
+        )],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    assert_eq!(
+        fetch::<Market>(&mut ctx, market).await.subsidy_sponsor,
+        creator.pubkey()
+    );
+    let reclaim = || reclaim_subsidy_ix(&market, &mint, &creator.pubkey(), &creator_tokens);
+    let outcome = send(&mut ctx, &[reclaim()], &[&creator]).await;
+    assert_error(&outcome, ErrorCode::MarketNotFinalized);
+
+    send_ok(
+        &mut ctx,
+        &[cancel_market_ix(
+            &market,
+            &creator.pubkey(),
+            &creator.pubkey(),
+        )],
+        &[&creator],
+    )
+    .await;
+
+    // The subsidy is owed to its sponsor, not swept to the creator
+    let outcome = send(
+        &mut ctx,
+        &[close_escrow_sweep_ix(
+            &market,
+            &mint,
+            &creator.pubkey(),
+            &creator_tokens,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::EscrowNotEmpty);
+
+    send_ok(
+        &mut ctx,
+        &[
+            reclaim(),
+            close_escrow_ix(&market, &creator.pubkey()),
+            close_market_ix(&market, &creator.pubkey()),
+        ],
+        &[&creator],
+    )
+    .await;
+    assert_eq!(token_balance(&mut ctx, creator_tokens).await, 400);
+}
+
+#[tokio::test]
+async fn lmsr_markets_take_no_subsidy() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let creator_tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    mint_to(&mut ctx, &mint, &creator_tokens, 2_000).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    let mut args = create_market_args("Both?", deadline);
+    args.lmsr_liquidity = Some(1_000);
+    args.subsidy = 100;
+    let outcome = send(
+        &mut ctx,
+        &[create_subsidized_market_ix(
+            &creator.pubkey(),
+            &mint,
+            None,
+            &creator_tokens,
+            args,
+        )],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongMarketKind);
+}
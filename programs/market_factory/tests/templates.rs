@@ -0,0 +1,219 @@
+mod common;
+
+use common::*;
+use market_factory::{
+    Collateral, ErrorCode, Market, MarketSpawned, MarketTemplate, TemplateInstance,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+const DAY: i64 = 86_400;
+const WEEK: i64 = 7 * DAY;
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    mint: Pubkey,
+    first_deadline: i64,
+}
+
+async fn fixture() -> Fixture {
+    let mut ctx = start().await;
+    let creator = new_funded_keypair(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let first_deadline = now(&mut ctx).await + DAY;
+    Fixture {
+        ctx,
+        creator,
+        mint,
+        first_deadline,
+    }
+}
+
+impl Fixture {
+    async fn create_template(
+        &mut self,
+        args: market_factory::instruction::CreateMarketTemplate,
+    ) -> TxOutcome {
+        let token_mint = (args.collateral == Collateral::Token).then_some(&self.mint);
+        send(
+            &mut self.ctx,
+            &[create_market_template_ix(
+                &self.creator.pubkey(),
+                token_mint,
+                args,
+            )],
+            &[&self.creator],
+        )
+        .await
+    }
+
+    /// Spawn instance `index` from a fresh payer, so anyone can crank it
+    async fn spawn(&mut self, template: &Pubkey, index: u32, token: bool) -> TxOutcome {
+        let payer = new_funded_keypair(&mut self.ctx).await;
+        let token_mint = token.then_some(&self.mint);
+        send(
+            &mut self.ctx,
+            &[spawn_market_ix(
+                template,
+                index,
+                token_mint,
+                &payer.pubkey(),
+            )],
+            &[&payer],
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn template_is_validated() {
+    let mut f = fixture().await;
+    let pattern = "Will BTC close above $100k in week {n}?";
+
+    let mut overlapping = create_market_template_args(pattern, f.first_deadline);
+    overlapping.cadence = DAY - 1;
+    let mut ternary = create_market_template_args(pattern, f.first_deadline);
+    ternary.outcome_count = 3;
+    let mut expensive = create_market_template_args(pattern, f.first_deadline);
+    expensive.creator_fee_bps = market_factory::MAX_CREATOR_FEE_BPS + 1;
+    let cases = [
+        (overlapping, ErrorCode::InvalidTimeline),
+        (ternary, ErrorCode::InvalidOutcomeCount),
+        (expensive, ErrorCode::InvalidFeeBps),
+        (
+            create_market_template_args(pattern, f.first_deadline - DAY),
+            ErrorCode::InvalidDeadline,
+        ),
+    ];
+    for (args, expected) in cases {
+        let outcome = f.create_template(args).await;
+        assert_error(&outcome, expected);
+    }
+
+    let outcome = f
+        .create_template(create_market_template_args(pattern, f.first_deadline))
+        .await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let template: MarketTemplate = fetch(&mut f.ctx, template_pda(&f.creator.pubkey(), 0)).await;
+    assert_eq!(template.question_pattern, pattern);
+    assert_eq!(template.token_mint, f.mint);
+    assert_eq!(template.next_deadline, f.first_deadline);
+    assert_eq!(template.spawned, 0);
+}
+
+#[tokio::test]
+async fn instances_spawn_once_the_previous_one_closes() {
+    let mut f = fixture().await;
+    let mut args =
+        create_market_template_args("Will BTC close above $100k in week {n}?", f.first_deadline);
+    args.creator_fee_bps = 100;
+    args.oracle.resolve_delay = Some(3_600);
+    let outcome = f.create_template(args).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let template = template_pda(&f.creator.pubkey(), 0);
+
+    let outcome = f.spawn(&template, 0, true).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let spawned = &decode_events::<MarketSpawned>(&outcome.logs)[0];
+    let first = template_market_pda(&template, 0);
+    assert_eq!((spawned.market, spawned.index), (first, 0));
+    let market: Market = fetch(&mut f.ctx, first).await;
+    assert_eq!(market.question, "Will BTC close above $100k in week 0?");
+    assert_eq!(market.creator, f.creator.pubkey());
+    assert_eq!(market.deadline, f.first_deadline);
+    assert_eq!(market.open_time, f.first_deadline - DAY);
+    assert_eq!(market.resolve_after, Some(f.first_deadline + 3_600));
+    assert_eq!(market.creator_fee_bps, 100);
+    assert_eq!(
+        market.template,
+        Some(TemplateInstance { template, index: 0 })
+    );
+
+    // Instances sign for their escrow with the template-derived seeds
+    let bettor = new_funded_keypair(&mut f.ctx).await;
+    let tokens = create_token_account(&mut f.ctx, &f.mint, &bettor.pubkey()).await;
+    mint_to(&mut f.ctx, &f.mint, &tokens, 500).await;
+    send_ok(
+        &mut f.ctx,
+        &[deposit_bet_ix(
+            &first,
+            &f.mint,
+            &bettor.pubkey(),
+            &tokens,
+            0,
+            vec![1u8; 32],
+            1,
+            500,
+        )],
+        &[&bettor],
+    )
+    .await;
+    send_ok(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &first,
+            &f.mint,
+            &bet_log_pda(&first, &bettor.pubkey(), 0),
+            &bettor.pubkey(),
+            &tokens,
+        )],
+        &[&bettor],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, tokens).await, 500);
+
+    let outcome = f.spawn(&template, 1, true).await;
+    assert_error(&outcome, ErrorCode::PreviousInstanceOpen);
+    warp_to_timestamp(&mut f.ctx, f.first_deadline).await;
+    let outcome = f.spawn(&template, 1, true).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let market: Market = fetch(&mut f.ctx, template_market_pda(&template, 1)).await;
+    assert_eq!(market.question, "Will BTC close above $100k in week 1?");
+    assert_eq!(market.deadline, f.first_deadline + WEEK);
+    let template: MarketTemplate = fetch(&mut f.ctx, template).await;
+    assert_eq!(template.spawned, 2);
+    assert_eq!(template.next_deadline, f.first_deadline + 2 * WEEK);
+}
+
+#[tokio::test]
+async fn late_spawn_skips_missed_instances() {
+    let mut f = fixture().await;
+    let mut args = create_market_template_args("Rain on day {n}?", f.first_deadline);
+    args.collateral = Collateral::NativeSol;
+    args.duration = DAY;
+    args.cadence = DAY;
+    let outcome = f.create_template(args).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let template = template_pda(&f.creator.pubkey(), 0);
+
+    // Nobody cranked for two and a half days
+    warp_to_timestamp(&mut f.ctx, f.first_deadline + 2 * DAY + DAY / 2).await;
+    let outcome = f.spawn(&template, 0, false).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.logs);
+    let market = template_market_pda(&template, 0);
+    let instance: Market = fetch(&mut f.ctx, market).await;
+    assert_eq!(instance.escrow_vault, sol_vault_pda(&market));
+    assert_eq!(instance.deadline, f.first_deadline + 3 * DAY);
+    assert_eq!(instance.open_time, f.first_deadline + 2 * DAY);
+
+    let bettor = new_funded_keypair(&mut f.ctx).await;
+    send_ok(
+        &mut f.ctx,
+        &[deposit_bet_sol_ix(
+            &market,
+            &bettor.pubkey(),
+            0,
+            vec![1u8; 32],
+            1_000,
+        )],
+        &[&bettor],
+    )
+    .await;
+    let instance: Market = fetch(&mut f.ctx, market).await;
+    assert_eq!(instance.total_pool, 1_000);
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use anchor_lang::InstructionData;
+use common::*;
+use market_factory::{BetLog, ErrorCode, Market, MarketCreated, TimeDecay};
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+const DECAY: TimeDecay = TimeDecay {
+    full_weight_secs: 600,
+    min_weight_bps: 2_500,
+};
+
+#[tokio::test]
+async fn time_decay_is_validated_at_creation() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+
+    let cases = [
+        (0, 0, ErrorCode::InvalidTimeDecay),
+        (0, 10_001, ErrorCode::InvalidTimeDecay),
+        // Full weight would last past the deadline
+        (3_600, 5_000, ErrorCode::InvalidTimeDecay),
+    ];
+    for (full_weight_secs, min_weight_bps, expected) in cases {
+        let creator = new_funded_keypair(&mut ctx).await;
+        let mut args = create_market_args("Early?", deadline);
+        args.time_decay = Some(TimeDecay {
+            full_weight_secs,
+            min_weight_bps,
+        });
+        let outcome = send(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        assert_error(&outcome, expected);
+    }
+
+    // LMSR prices trades itself; there is no pool to weight
+    let creator = new_funded_keypair(&mut ctx).await;
+    let creator_tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    let mut ix = create_lmsr_market_ix(
+        &creator.pubkey(),
+        &mint,
+        &creator_tokens,
+        "Priced?",
+        deadline,
+        1_000,
+    );
+    let mut args = create_market_args("Priced?", deadline);
+    args.lmsr_liquidity = Some(1_000);
+    args.time_decay = Some(DECAY);
+    ix.data = args.data();
+    let outcome = send(&mut ctx, &[ix], &[&creator]).await;
+    assert_error(&outcome, ErrorCode::WrongMarketKind);
+
+    let mut args = create_market_args("Early?", deadline);
+    args.time_decay = Some(DECAY);
+    let logs = send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    assert_eq!(
+        decode_events::<MarketCreated>(&logs)[0].time_decay,
+        Some(DECAY)
+    );
+    let market: Market = fetch(&mut ctx, market_pda(&creator.pubkey())).await;
+    assert_eq!(market.time_decay, Some(DECAY));
+}
+
+#[tokio::test]
+async fn amending_restamps_bets_under_time_decay() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, &payer).await;
+    let start_time = now(&mut ctx).await;
+    let bettor = new_funded_keypair(&mut ctx).await;
+    let tokens = create_token_account(&mut ctx, &mint, &bettor.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 1_000).await;
+
+    let mut bet_logs = Vec::new();
+    for time_decay in [Some(DECAY), None] {
+        let creator = new_funded_keypair(&mut ctx).await;
+        let mut args = create_market_args("Early?", start_time + 3_600);
+        args.time_decay = time_decay;
+        send_ok(
+            &mut ctx,
+            &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+            &[&creator],
+        )
+        .await;
+        let market = market_pda(&creator.pubkey());
+        send_ok(
+            &mut ctx,
+            &[deposit_bet_ix(
+                &market,
+                &mint,
+                &bettor.pubkey(),
+                &tokens,
+                0,
+                vec![0; 9],
+                0,
+                100,
+            )],
+            &[&bettor],
+        )
+        .await;
+        bet_logs.push(bet_log_pda(&market, &bettor.pubkey(), 0));
+    }
+    let placed_at = fetch::<BetLog>(&mut ctx, bet_logs[0]).await.timestamp;
+
+    // Switching sides late weighs like a late bet, but only where it matters
+    warp_to_timestamp(&mut ctx, placed_at + 1_800).await;
+    for bet_log in &bet_logs {
+        let market = fetch::<BetLog>(&mut ctx, *bet_log).await.market;
+        send_ok(
+            &mut ctx,
+            &[amend_bet_ix(
+                &market,
+                &mint,
+                bet_log,
+                &bettor.pubkey(),
+                &tokens,
+                vec![1; 9],
+                100,
+            )],
+            &[&bettor],
+        )
+        .await;
+    }
+    let decayed: BetLog = fetch(&mut ctx, bet_logs[0]).await;
+    let plain: BetLog = fetch(&mut ctx, bet_logs[1]).await;
+    assert_eq!(
+        (decayed.revision, decayed.timestamp),
+        (1, placed_at + 1_800)
+    );
+    assert_eq!((plain.revision, plain.timestamp), (1, placed_at));
+}
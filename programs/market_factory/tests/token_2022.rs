@@ -0,0 +1,224 @@
+mod common;
+
+use anchor_spl::token_2022::spl_token_2022::{self, extension::transfer_fee};
+use common::*;
+use market_factory::{BetDeposited, BetLog, Market, UserPosition};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Anchor's `ConstraintAddress` framework error
+const CONSTRAINT_ADDRESS: u32 = 2012;
+
+/// 1% transfer fee, uncapped
+const FEE_BPS: u16 = 100;
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    mint: Pubkey,
+    market: Pubkey,
+}
+
+async fn fee_mint_market() -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_fee_mint(&mut ctx, &payer, FEE_BPS, u64::MAX).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    send_ok(
+        &mut ctx,
+        &[with_token_2022(create_market_ix(
+            &creator.pubkey(),
+            &mint,
+            "Fee on transfer?",
+            deadline,
+        ))],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        creator,
+        mint,
+        market,
+    }
+}
+
+impl Fixture {
+    async fn bettor(&mut self) -> (Keypair, Pubkey) {
+        let bettor = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_fee_token_account(&mut self.ctx, &self.mint, &bettor.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, 10_000).await;
+        (bettor, tokens)
+    }
+
+    async fn assert_pool_matches_escrow(&mut self, expected: u64) {
+        let market: Market = fetch(&mut self.ctx, self.market).await;
+        assert_eq!(market.total_pool, expected);
+        assert_eq!(
+            token_balance(&mut self.ctx, escrow_pda(&self.market)).await,
+            expected
+        );
+    }
+}
+
+#[tokio::test]
+async fn stakes_are_recorded_net_of_transfer_fees() {
+    let mut f = fee_mint_market().await;
+    let (alice, tokens) = f.bettor().await;
+    let bet = bet_log_pda(&f.market, &alice.pubkey(), 0);
+
+    let logs = send_ok(
+        &mut f.ctx,
+        &[with_token_2022(deposit_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice.pubkey(),
+            &tokens,
+            0,
+            vec![1; 9],
+            1,
+            1_000,
+        ))],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(decode_events::<BetDeposited>(&logs)[0].amount, 990);
+    assert_eq!(fetch::<BetLog>(&mut f.ctx, bet).await.amount, 990);
+    f.assert_pool_matches_escrow(990).await;
+
+    // A top-up is also credited net of the fee
+    send_ok(
+        &mut f.ctx,
+        &[with_token_2022(amend_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &tokens,
+            vec![2; 9],
+            1_990,
+        ))],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(fetch::<BetLog>(&mut f.ctx, bet).await.amount, 1_980);
+    let position: UserPosition = fetch(&mut f.ctx, position_pda(&f.market, &alice.pubkey())).await;
+    assert_eq!(position.total_staked, 1_980);
+    f.assert_pool_matches_escrow(1_980).await;
+
+    // The refund leaves the escrow in full; the fee comes out of what arrives
+    send_ok(
+        &mut f.ctx,
+        &[with_token_2022(withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &tokens,
+        ))],
+        &[&alice],
+    )
+    .await;
+    f.assert_pool_matches_escrow(0).await;
+    assert_eq!(
+        token_balance(&mut f.ctx, tokens).await,
+        10_000 - 2_000 + 1_960
+    );
+}
+
+#[tokio::test]
+async fn deposits_must_name_the_market_mint() {
+    let mut f = fee_mint_market().await;
+    let (alice, tokens) = f.bettor().await;
+    let payer = f.ctx.payer.pubkey();
+    let other_mint = create_fee_mint(&mut f.ctx, &payer, FEE_BPS, u64::MAX).await;
+
+    let outcome = send(
+        &mut f.ctx,
+        &[with_token_2022(deposit_bet_ix(
+            &f.market,
+            &other_mint,
+            &alice.pubkey(),
+            &tokens,
+            0,
+            vec![1; 9],
+            1,
+            1_000,
+        ))],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(anchor_error_code(&outcome.result), Some(CONSTRAINT_ADDRESS));
+}
+
+#[tokio::test]
+async fn escrow_closes_once_withheld_fees_are_harvested() {
+    let mut f = fee_mint_market().await;
+    let (alice, tokens) = f.bettor().await;
+    send_ok(
+        &mut f.ctx,
+        &[
+            with_token_2022(deposit_bet_ix(
+                &f.market,
+                &f.mint,
+                &alice.pubkey(),
+                &tokens,
+                0,
+                vec![1; 9],
+                1,
+                500,
+            )),
+            with_token_2022(withdraw_bet_ix(
+                &f.market,
+                &f.mint,
+                &bet_log_pda(&f.market, &alice.pubkey(), 0),
+                &alice.pubkey(),
+                &tokens,
+            )),
+        ],
+        &[&alice],
+    )
+    .await;
+
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(&f.market, &creator, &creator)],
+        &[&f.creator],
+    )
+    .await;
+
+    // Fees withheld in the escrow block the token program's close
+    let close_escrow = with_token_2022(close_escrow_ix(&f.market, &creator));
+    let outcome = send(
+        &mut f.ctx,
+        std::slice::from_ref(&close_escrow),
+        &[&f.creator],
+    )
+    .await;
+    assert!(outcome.result.is_err());
+
+    let harvest = transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+        &spl_token_2022::ID,
+        &f.mint,
+        &[&escrow_pda(&f.market)],
+    )
+    .unwrap();
+    send_ok(
+        &mut f.ctx,
+        &[
+            harvest,
+            close_escrow,
+            close_position_ix(&f.market, &alice.pubkey()),
+            close_market_ix(&f.market, &creator),
+        ],
+        &[&f.creator],
+    )
+    .await;
+    assert!(!account_exists(&mut f.ctx, f.market).await);
+}
@@ -0,0 +1,443 @@
+mod common;
+
+use common::*;
+use market_factory::{
+    BetAmended, BetLog, BetStorage, BetWithdrawn, ErrorCode, Market, MAX_JOB_ATTEMPTS,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Anchor's `ConstraintHasOne` framework error
+const CONSTRAINT_HAS_ONE: u32 = 2001;
+
+struct Fixture {
+    ctx: ProgramTestContext,
+    creator: Keypair,
+    mint: Pubkey,
+    market: Pubkey,
+    deadline: i64,
+}
+
+async fn market_with_penalty(penalty_bps: u16) -> Fixture {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let deadline = now(&mut ctx).await + 3_600;
+    let mut args = create_market_args("Change of heart?", deadline);
+    args.withdrawal_penalty_bps = penalty_bps;
+    send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    Fixture {
+        ctx,
+        creator,
+        mint,
+        market,
+        deadline,
+    }
+}
+
+impl Fixture {
+    async fn bettor(&mut self) -> (Keypair, Pubkey) {
+        let bettor = new_funded_keypair(&mut self.ctx).await;
+        let tokens = create_token_account(&mut self.ctx, &self.mint, &bettor.pubkey()).await;
+        mint_to(&mut self.ctx, &self.mint, &tokens, 1_000).await;
+        (bettor, tokens)
+    }
+
+    async fn bet(&mut self, bettor: &Keypair, tokens: &Pubkey, amount: u64) -> Pubkey {
+        let market: Market = fetch(&mut self.ctx, self.market).await;
+        send_ok(
+            &mut self.ctx,
+            &[deposit_bet_ix(
+                &self.market,
+                &self.mint,
+                &bettor.pubkey(),
+                tokens,
+                market.bet_count,
+                vec![1; 9],
+                1,
+                amount,
+            )],
+            &[bettor],
+        )
+        .await;
+        bet_log_pda(&self.market, &bettor.pubkey(), market.bet_count)
+    }
+}
+
+#[tokio::test]
+async fn withdraw_refunds_stake_minus_penalty() {
+    let mut f = market_with_penalty(1_000).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let (bob, bob_tokens) = f.bettor().await;
+    let alice_bet = f.bet(&alice, &alice_tokens, 100).await;
+    let bob_bet = f.bet(&bob, &bob_tokens, 50).await;
+
+    let logs = send_ok(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice_bet,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    let event = &decode_events::<BetWithdrawn>(&logs)[0];
+    assert_eq!((event.refund, event.penalty), (90, 10));
+    assert!(!account_exists(&mut f.ctx, alice_bet).await);
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 990);
+
+    // The penalty stays in the pool, so the pool still matches the escrow
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(market.total_pool, 60);
+    assert_eq!(market.penalty_pool, 10);
+    assert_eq!(token_balance(&mut f.ctx, escrow_pda(&f.market)).await, 60);
+    assert_eq!(market.bet_count, 2);
+    assert_eq!(market.live_bet_count(), 1);
+
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let outcome = send(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &bob_bet,
+            &bob.pubkey(),
+            &bob_tokens,
+        )],
+        &[&bob],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::DeadlinePassed);
+}
+
+#[tokio::test]
+async fn amend_replaces_blob_and_moves_stake() {
+    let mut f = market_with_penalty(1_000).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let bet = f.bet(&alice, &alice_tokens, 100).await;
+
+    let logs = send_ok(
+        &mut f.ctx,
+        &[amend_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &alice_tokens,
+            vec![2; 9],
+            150,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(decode_events::<BetAmended>(&logs)[0].revision, 1);
+    let log: BetLog = fetch(&mut f.ctx, bet).await;
+    assert_eq!(log.encrypted_blob, vec![2; 9]);
+    assert_eq!(log.amount, 150);
+    assert_eq!(token_balance(&mut f.ctx, escrow_pda(&f.market)).await, 150);
+
+    // Lowering the stake by 100 refunds 90; the 10 penalty stays in the pool
+    send_ok(
+        &mut f.ctx,
+        &[amend_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &alice_tokens,
+            vec![3; 9],
+            50,
+        )],
+        &[&alice],
+    )
+    .await;
+    let log: BetLog = fetch(&mut f.ctx, bet).await;
+    assert_eq!((log.amount, log.revision), (50, 2));
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!(market.total_pool, 60);
+    assert_eq!(market.penalty_pool, 10);
+    assert_eq!(market.bet_count, 1);
+    assert_eq!(token_balance(&mut f.ctx, escrow_pda(&f.market)).await, 60);
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 940);
+
+    let (mallory, mallory_tokens) = f.bettor().await;
+    f.bet(&mallory, &mallory_tokens, 10).await;
+    let outcome = send(
+        &mut f.ctx,
+        &[amend_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &mallory.pubkey(),
+            &mallory_tokens,
+            vec![4; 9],
+            1,
+        )],
+        &[&mallory],
+    )
+    .await;
+    assert_eq!(anchor_error_code(&outcome.result), Some(CONSTRAINT_HAS_ONE));
+
+    let outcome = send(
+        &mut f.ctx,
+        &[amend_bet_commitment_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &alice_tokens,
+            [1; 32],
+            50,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::WrongBetStorage);
+}
+
+#[tokio::test]
+async fn penalties_are_paid_out_at_settlement() {
+    let mut f = market_with_penalty(1_000).await;
+    let payer = f.ctx.payer.pubkey();
+    let (alice, alice_tokens) = f.bettor().await;
+    let (bob, bob_tokens) = f.bettor().await;
+    let alice_bet = f.bet(&alice, &alice_tokens, 100).await;
+    f.bet(&bob, &bob_tokens, 50).await;
+    send_ok(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &alice_bet,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+
+    // The MXE is handed `penalty_pool` with the live stakes and pays it to
+    // the winner, leaving nothing behind in the escrow
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    let mut result = mxe_result(&f.market);
+    result.total_pool = market.total_pool;
+    result.payouts = vec![mxe_payout(&bob.pubkey(), 50 + market.penalty_pool)];
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    send_ok(&mut f.ctx, &[enqueue_resolution_ix(&f.market, &payer)], &[]).await;
+    send_ok(
+        &mut f.ctx,
+        &[callback_settle_ix(
+            &f.market,
+            &f.mint,
+            &result,
+            &[bob_tokens],
+        )],
+        &[&mxe_authority()],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 1_010);
+    assert_eq!(token_balance(&mut f.ctx, escrow_pda(&f.market)).await, 0);
+}
+
+#[tokio::test]
+async fn market_with_only_withdrawn_bets_can_be_cancelled_and_closed() {
+    let mut f = market_with_penalty(0).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let bet = f.bet(&alice, &alice_tokens, 100).await;
+    send_ok(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 1_000);
+
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[
+            cancel_market_ix(&f.market, &creator, &creator),
+            close_escrow_ix(&f.market, &creator),
+            close_position_ix(&f.market, &alice.pubkey()),
+            close_market_ix(&f.market, &creator),
+        ],
+        &[&f.creator],
+    )
+    .await;
+    assert!(!account_exists(&mut f.ctx, f.market).await);
+}
+
+#[tokio::test]
+async fn cancelling_without_bets_releases_penalties_to_the_creator() {
+    let mut f = market_with_penalty(1_000).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let bet = f.bet(&alice, &alice_tokens, 100).await;
+    send_ok(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+
+    // No stake is left to share the penalty, so it is the creator's
+    let creator = f.creator.pubkey();
+    send_ok(
+        &mut f.ctx,
+        &[cancel_market_ix(&f.market, &creator, &creator)],
+        &[&f.creator],
+    )
+    .await;
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!((market.penalty_pool, market.total_pool), (0, 0));
+
+    let creator_tokens = create_token_account(&mut f.ctx, &f.mint, &creator).await;
+    send_ok(
+        &mut f.ctx,
+        &[close_escrow_sweep_ix(
+            &f.market,
+            &f.mint,
+            &creator,
+            &creator_tokens,
+        )],
+        &[&f.creator],
+    )
+    .await;
+    assert_eq!(token_balance(&mut f.ctx, creator_tokens).await, 10);
+    assert_eq!(token_balance(&mut f.ctx, alice_tokens).await, 990);
+}
+
+#[tokio::test]
+async fn voided_market_refunds_penalties_with_the_stakes() {
+    let mut f = market_with_penalty(1_000).await;
+    let (alice, alice_tokens) = f.bettor().await;
+    let (bob, bob_tokens) = f.bettor().await;
+    let (carol, carol_tokens) = f.bettor().await;
+    let bet = f.bet(&alice, &alice_tokens, 100).await;
+    f.bet(&bob, &bob_tokens, 50).await;
+    f.bet(&carol, &carol_tokens, 30).await;
+    send_ok(
+        &mut f.ctx,
+        &[withdraw_bet_ix(
+            &f.market,
+            &f.mint,
+            &bet,
+            &alice.pubkey(),
+            &alice_tokens,
+        )],
+        &[&alice],
+    )
+    .await;
+
+    warp_to_timestamp(&mut f.ctx, f.deadline).await;
+    let payer = f.ctx.payer.pubkey();
+    let mxe = mxe_authority();
+    send_ok(&mut f.ctx, &[enqueue_resolution_ix(&f.market, &payer)], &[]).await;
+    for attempt in 0..MAX_JOB_ATTEMPTS {
+        send_ok(
+            &mut f.ctx,
+            &[
+                mark_job_running_ix(&f.market, &mxe.pubkey()),
+                mark_job_failed_ix(&f.market, &mxe.pubkey(), attempt as u32),
+            ],
+            &[&mxe],
+        )
+        .await;
+    }
+    send_ok(&mut f.ctx, &[void_stuck_market_ix(&f.market, None)], &[]).await;
+
+    // The penalty is pool money: Bob and Carol split it by stake, the last
+    // refund taking the rounding remainder
+    for (bettor, tokens) in [(&bob, bob_tokens), (&carol, carol_tokens)] {
+        send_ok(
+            &mut f.ctx,
+            &[refund_position_ix(
+                &f.market,
+                &f.mint,
+                &bettor.pubkey(),
+                &tokens,
+            )],
+            &[bettor],
+        )
+        .await;
+    }
+    assert_eq!(token_balance(&mut f.ctx, bob_tokens).await, 1_006);
+    assert_eq!(token_balance(&mut f.ctx, carol_tokens).await, 1_004);
+    assert_eq!(token_balance(&mut f.ctx, escrow_pda(&f.market)).await, 0);
+    let market: Market = fetch(&mut f.ctx, f.market).await;
+    assert_eq!((market.penalty_pool, market.total_pool), (0, 0));
+}
+
+#[tokio::test]
+async fn create_market_rejects_penalty_above_100_percent() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let creator = new_funded_keypair(&mut ctx).await;
+    let mint = create_mint(&mut ctx, &payer).await;
+    let mut args = create_market_args("Too harsh?", now(&mut ctx).await + 3_600);
+    args.withdrawal_penalty_bps = 10_001;
+    let outcome = send(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    assert_error(&outcome, ErrorCode::InvalidWithdrawalPenalty);
+
+    // Off-chain markets amend by swapping the commitment
+    let mut args = create_market_args("Off chain?", now(&mut ctx).await + 3_600);
+    args.bet_storage = BetStorage::OffChain;
+    args.blob_store_uri = "ipfs://bets".to_string();
+    send_ok(
+        &mut ctx,
+        &[create_market_ix_with(&creator.pubkey(), &mint, args)],
+        &[&creator],
+    )
+    .await;
+    let market = market_pda(&creator.pubkey());
+    let tokens = create_token_account(&mut ctx, &mint, &creator.pubkey()).await;
+    mint_to(&mut ctx, &mint, &tokens, 100).await;
+    send_ok(
+        &mut ctx,
+        &[
+            deposit_bet_commitment_ix(&market, &mint, &creator.pubkey(), &tokens, 0, [1; 32], 40),
+            amend_bet_commitment_ix(
+                &market,
+                &mint,
+                &bet_log_pda(&market, &creator.pubkey(), 0),
+                &creator.pubkey(),
+                &tokens,
+                [2; 32],
+                40,
+            ),
+        ],
+        &[&creator],
+    )
+    .await;
+    let log: BetLog = fetch(&mut ctx, bet_log_pda(&market, &creator.pubkey(), 0)).await;
+    assert_eq!(log.blob_hash, [2; 32]);
+}